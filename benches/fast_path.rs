@@ -0,0 +1,43 @@
+//! Compares the path-navigation fast path with full engine evaluation
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use octofhir_mcp::{fast_path, get_shared_engine};
+use serde_json::json;
+use std::hint::black_box;
+
+fn bench_path_navigation(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().expect("tokio runtime");
+    let engine = runtime
+        .block_on(get_shared_engine())
+        .expect("FHIRPath engine");
+
+    let patient = json!({
+        "resourceType": "Patient",
+        "id": "bench",
+        "name": [
+            {"use": "official", "family": "Chalmers", "given": ["Peter", "James"]},
+            {"use": "usual", "given": ["Jim"]}
+        ],
+        "telecom": [{"system": "phone", "value": "(03) 5555 6473"}],
+        "address": [{"city": "PleasantVille", "postalCode": "3999"}]
+    });
+
+    for expression in ["Patient.name.family", "Patient.address.city"] {
+        let ast = engine.parse(expression).expect("valid expression");
+        let mut group = c.benchmark_group(expression);
+
+        group.bench_function("fast_path", |b| {
+            b.iter(|| fast_path::evaluate(black_box(&ast), black_box(&patient)))
+        });
+        group.bench_function("engine", |b| {
+            b.iter(|| {
+                runtime.block_on(engine.evaluate_ast(black_box(&ast), black_box(patient.clone())))
+            })
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_path_navigation);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Build script exposing build metadata to the `/version` endpoint and engine info
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Builds without a git checkout, e.g. in Docker, can pass the commit in explicitly
+    let git_commit = std::env::var("OCTOFHIR_GIT_COMMIT")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    // Honour SOURCE_DATE_EPOCH so reproducible builds get a stable timestamp
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0)
+        });
+
+    let fhirpath_version =
+        locked_version("octofhir-fhirpath").unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=OCTOFHIR_GIT_COMMIT={git_commit}");
+    println!("cargo:rustc-env=OCTOFHIR_RUSTC_VERSION={rustc_version}");
+    println!("cargo:rustc-env=OCTOFHIR_BUILD_TIMESTAMP={build_timestamp}");
+    println!("cargo:rustc-env=OCTOFHIR_FHIRPATH_VERSION={fhirpath_version}");
+
+    println!("cargo:rerun-if-env-changed=OCTOFHIR_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let stdout = stdout.trim();
+    (output.status.success() && !stdout.is_empty()).then(|| stdout.to_string())
+}
+
+/// Version of `package` resolved in Cargo.lock
+fn locked_version(package: &str) -> Option<String> {
+    let lock = std::fs::read_to_string("Cargo.lock").ok()?;
+    let name = format!("name = \"{package}\"");
+    let mut lines = lock.lines().skip_while(|line| *line != name).skip(1);
+    let version = lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')?;
+    Some(version.to_string())
+}
@@ -5,9 +5,12 @@
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use octofhir_mcp::{server::demonstrate_tools, transport::TransportFactory};
-use tracing::{Level, info};
-use tracing_subscriber::EnvFilter;
+use octofhir_mcp::{
+    ServerConfig, server::demonstrate_tools, transport::TransportFactory,
+    validation::validate_server,
+};
+use std::path::PathBuf;
+use tracing::info;
 
 #[derive(Parser)]
 #[command(name = "octofhir-mcp")]
@@ -17,9 +20,9 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    /// Set the log level (trace, debug, info, warn, error)
-    #[arg(long, default_value = "info")]
-    log_level: String,
+    /// Set the log level (trace, debug, info, warn, error), overriding the configuration
+    #[arg(long)]
+    log_level: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -41,28 +44,32 @@ enum Commands {
     /// Show server information
     Info,
     /// Validate server configuration
-    Validate,
+    Validate {
+        /// Configuration file (JSON or TOML); defaults to $OCTOFHIR_CONFIG
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    // Initialize logging
-    let log_level = match cli.log_level.to_lowercase().as_str() {
-        "trace" => Level::TRACE,
-        "debug" => Level::DEBUG,
-        "info" => Level::INFO,
-        "warn" => Level::WARN,
-        "error" => Level::ERROR,
-        _ => Level::INFO,
+    // The configuration sizes the runtime, so it is loaded before anything async runs
+    let config_path = match &cli.command {
+        Commands::Validate { config } => config.clone(),
+        _ => None,
     };
+    let mut config = ServerConfig::load(config_path.as_deref())?;
+    if let Some(log_level) = &cli.log_level {
+        config.log_level = log_level.clone();
+        config.validate()?;
+    }
+    config.build_runtime()?.block_on(run(cli, config))
+}
 
-    let filter = EnvFilter::builder()
-        .with_default_directive(log_level.into())
-        .from_env_lossy();
-
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+async fn run(cli: Cli, config: ServerConfig) -> Result<()> {
+    // Initialize logging on stderr; stdout carries the stdio transport's protocol messages
+    octofhir_mcp::logging::init_logging(&config)?;
 
     match cli.command {
         Commands::Stdio => {
@@ -70,7 +77,7 @@ async fn main() -> Result<()> {
             info!("Protocol version: 2025-06-18");
             info!("Available tools: fhirpath_evaluate, fhirpath_parse, fhirpath_extract");
 
-            let transport = TransportFactory::create_stdio();
+            let transport = TransportFactory::create_stdio().with_config(&config);
             transport.start().await?;
         }
         Commands::Http { host, port } => {
@@ -81,7 +88,7 @@ async fn main() -> Result<()> {
             info!("Protocol version: 2025-06-18");
             info!("Available tools: fhirpath_evaluate, fhirpath_parse, fhirpath_extract");
 
-            let transport = TransportFactory::create_http(&host, port);
+            let transport = TransportFactory::create_http(&host, port).with_config(&config)?;
             transport.start().await?;
         }
         Commands::Demo => {
@@ -110,20 +117,41 @@ async fn main() -> Result<()> {
             println!("  - Comprehensive error diagnostics");
             println!("  - Performance metrics and complexity analysis");
         }
-        Commands::Validate => {
+        Commands::Validate { .. } => {
             info!("Validating server configuration...");
 
+            info!("✓ Configuration loaded: {:?}", config);
+
             // Test FHIRPath engine initialization
-            octofhir_mcp::fhirpath_engine::initialize_shared_engine().await?;
+            octofhir_mcp::fhirpath_engine::initialize_shared_engine_with_config(
+                config.engine_config(octofhir_mcp::FhirEngineConfig::default()),
+            )
+            .await?;
             info!("✓ FHIRPath engine initialized successfully");
 
-            // Test tool demonstration
-            demonstrate_tools().await?;
-            info!("✓ All tools validated successfully");
-
+            let report = validate_server(&config).await;
+            for category in &report.categories {
+                println!("{}", category.name);
+                for check in &category.checks {
+                    println!(
+                        "  {} {}: {} ({:.1}ms)",
+                        if check.passed { "✓" } else { "✗" },
+                        check.name,
+                        check.message,
+                        check.duration_ms
+                    );
+                }
+            }
+
+            if !report.passed() {
+                return Err(anyhow::anyhow!("Server validation failed"));
+            }
             info!("✓ Server configuration is valid");
         }
     }
 
+    #[cfg(feature = "otel")]
+    octofhir_mcp::metrics::telemetry::shutdown();
+
     Ok(())
 }
@@ -1,7 +1,172 @@
 //! Caching implementations for performance optimization
 
-/// Cache provider (placeholder)
-pub struct CacheProvider;
+use crate::diagnostics::EvaluationDiagnostic;
+use anyhow::{Result, anyhow};
+use octofhir_fhirpath::ExpressionNode;
+use octofhir_fhirpath::ast::{LiteralValue, MethodCallData};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Default number of parsed expressions kept by the expression cache
+pub const DEFAULT_CACHE_CAPACITY: usize = 1000;
+
+/// Default number of evaluation results kept by the result cache
+pub const DEFAULT_RESULT_CACHE_CAPACITY: usize = 256;
+
+/// Default time an evaluation result stays in the result cache
+pub const DEFAULT_RESULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Fixed-capacity map that evicts the least recently used entry on overflow
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+    /// Create a cache holding at most `capacity` entries (0 disables storage)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Look up an entry, marking it as most recently used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get_mut(key).map(|value| &*value)
+    }
+
+    /// Look up an entry for updating, marking it as most recently used
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.tick += 1;
+        let tick = self.tick;
+        let (value, last_used) = self.entries.get_mut(key)?;
+        if let Some(owned_key) = self.order.remove(last_used) {
+            self.order.insert(tick, owned_key);
+        }
+        *last_used = tick;
+        Some(value)
+    }
+
+    /// Insert an entry, returning the key evicted to make room (if any)
+    pub fn insert(&mut self, key: K, value: V) -> Option<K> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        self.tick += 1;
+        if let Some((_, last_used)) = self.entries.remove(&key) {
+            self.order.remove(&last_used);
+        }
+
+        let mut evicted = None;
+        if self.entries.len() >= self.capacity
+            && let Some((_, oldest)) = self.order.pop_first()
+        {
+            self.entries.remove(&oldest);
+            evicted = Some(oldest);
+        }
+
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+        evicted
+    }
+
+    /// Remove an entry from the cache
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (value, last_used) = self.entries.remove(key)?;
+        self.order.remove(&last_used);
+        Some(value)
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Visit every entry, in no particular order and without marking any as used
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, (value, _))| (key, value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// Snapshot of cache counters for metrics reporting
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for new ones
+    pub evictions: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that were hits, 0 when nothing was looked up
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// LRU cache of parsed FHIRPath expressions keyed by expression string
+pub struct CacheProvider {
+    expressions: Mutex<LruCache<String, Arc<ExpressionNode>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
 
 impl Default for CacheProvider {
     fn default() -> Self {
@@ -11,6 +176,478 @@ impl Default for CacheProvider {
 
 impl CacheProvider {
     pub fn new() -> Self {
-        Self
+        Self::with_capacity(DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Create an expression cache holding at most `capacity` parsed expressions
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            expressions: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Look up a parsed expression, recording a hit or miss
+    pub fn get(&self, expression: &str) -> Option<Arc<ExpressionNode>> {
+        let cached = self.expressions.lock().unwrap().get(expression).cloned();
+
+        match cached {
+            Some(ast) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(ast)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Store a parsed expression
+    pub fn insert(&self, expression: &str, ast: Arc<ExpressionNode>) {
+        let evicted = self
+            .expressions
+            .lock()
+            .unwrap()
+            .insert(expression.to_string(), ast);
+        if evicted.is_some() {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Return the cached AST for an expression, parsing and caching it on a miss.
+    /// Parse failures are not cached.
+    pub fn get_or_parse(&self, expression: &str) -> Result<Arc<ExpressionNode>> {
+        if let Some(ast) = self.get(expression) {
+            return Ok(ast);
+        }
+
+        let ast = Arc::new(
+            octofhir_fhirpath::parse(expression)
+                .map_err(|e| anyhow!("FHIRPath parse error: {}", e))?,
+        );
+        self.insert(expression, ast.clone());
+        Ok(ast)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let expressions = self.expressions.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: expressions.len(),
+            capacity: expressions.capacity(),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.expressions.lock().unwrap().clear();
+    }
+}
+
+/// Feed a JSON value into `hasher`, visiting object members in key order so that the hash
+/// does not depend on how the object's keys were ordered
+pub fn hash_json<H: Hasher>(value: &Value, hasher: &mut H) {
+    match value {
+        Value::Null => 0u8.hash(hasher),
+        Value::Bool(b) => {
+            1u8.hash(hasher);
+            b.hash(hasher);
+        }
+        Value::Number(n) => {
+            2u8.hash(hasher);
+            n.to_string().hash(hasher);
+        }
+        Value::String(s) => {
+            3u8.hash(hasher);
+            s.hash(hasher);
+        }
+        Value::Array(items) => {
+            4u8.hash(hasher);
+            items.len().hash(hasher);
+            for item in items {
+                hash_json(item, hasher);
+            }
+        }
+        Value::Object(members) => {
+            5u8.hash(hasher);
+            members.len().hash(hasher);
+            let mut members: Vec<_> = members.iter().collect();
+            members.sort_unstable_by_key(|(key, _)| *key);
+            for (key, member) in members {
+                key.hash(hasher);
+                hash_json(member, hasher);
+            }
+        }
+    }
+}
+
+/// Canonical form of a parsed expression, shared by expressions that differ only in ways
+/// that can't change their result: whitespace, redundant parentheses, and an index of `[0]`
+/// in place of `.first()`. Anything else, such as literal spellings like `1.0` and `1.00`,
+/// is kept apart.
+pub fn canonical_expression(ast: &ExpressionNode) -> String {
+    let mut ast = ast.clone();
+    normalize(&mut ast);
+    format!("{ast:?}")
+}
+
+/// Rewrite `ast` in place into the form [`canonical_expression`] renders
+fn normalize(ast: &mut ExpressionNode) {
+    match ast {
+        ExpressionNode::Literal(_)
+        | ExpressionNode::Identifier(_)
+        | ExpressionNode::Variable(_) => {}
+        ExpressionNode::Path { base, .. } => normalize(base),
+        ExpressionNode::BinaryOp(data) => {
+            normalize(&mut data.left);
+            normalize(&mut data.right);
+        }
+        ExpressionNode::UnaryOp { operand, .. } => normalize(operand),
+        ExpressionNode::FunctionCall(data) => data.args.iter_mut().for_each(normalize),
+        ExpressionNode::MethodCall(data) => {
+            normalize(&mut data.base);
+            data.args.iter_mut().for_each(normalize);
+        }
+        ExpressionNode::Index { base, index } => {
+            normalize(base);
+            normalize(index);
+        }
+        ExpressionNode::Filter { base, condition } => {
+            normalize(base);
+            normalize(condition);
+        }
+        ExpressionNode::Union { left, right } => {
+            normalize(left);
+            normalize(right);
+        }
+        ExpressionNode::TypeCheck { expression, .. }
+        | ExpressionNode::TypeCast { expression, .. } => normalize(expression),
+        ExpressionNode::Lambda(data) => normalize(&mut data.body),
+        ExpressionNode::Conditional(data) => {
+            normalize(&mut data.condition);
+            normalize(&mut data.then_expr);
+            if let Some(else_expr) = &mut data.else_expr {
+                normalize(else_expr);
+            }
+        }
+    }
+
+    // `x[0]` and `x.first()` both select the first item, or nothing when `x` is empty
+    if let ExpressionNode::Index { base, index } = ast
+        && **index == ExpressionNode::Literal(LiteralValue::Integer(0))
+    {
+        let base = std::mem::replace(&mut **base, ExpressionNode::Identifier(String::new()));
+        *ast = ExpressionNode::MethodCall(Box::new(MethodCallData {
+            base,
+            method: "first".to_string(),
+            args: Default::default(),
+        }));
+    }
+}
+
+/// Key of a cached evaluation: the expression plus everything it was evaluated with. Keys
+/// are hashed by a digest of the inputs but compared on the inputs themselves, so a digest
+/// collision can never return another evaluation's result.
+#[derive(Debug, Clone)]
+pub struct ResultKey {
+    expression: String,
+    digest: u64,
+    inputs: Arc<[Value]>,
+}
+
+impl ResultKey {
+    /// Key for `expression` evaluated with `inputs`, e.g. the resource and output options.
+    /// Pass the [`canonical_expression`] so that equivalent spellings share an entry.
+    pub fn new(expression: &str, inputs: &[&Value]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        inputs.len().hash(&mut hasher);
+        for input in inputs {
+            hash_json(input, &mut hasher);
+        }
+
+        Self {
+            expression: expression.to_string(),
+            digest: hasher.finish(),
+            inputs: inputs.iter().map(|input| (*input).clone()).collect(),
+        }
+    }
+}
+
+impl PartialEq for ResultKey {
+    fn eq(&self, other: &Self) -> bool {
+        // JSON objects compare equal regardless of member order, as they hash
+        self.digest == other.digest
+            && self.expression == other.expression
+            && self.inputs == other.inputs
+    }
+}
+
+impl Eq for ResultKey {}
+
+impl Hash for ResultKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.expression.hash(state);
+        self.digest.hash(state);
+    }
+}
+
+/// Output of a successful evaluation kept by [`ResultCache`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedEvaluation {
+    pub values: Vec<Value>,
+    pub types: Vec<String>,
+    pub diagnostics: Option<Vec<String>>,
+    pub errors: Option<Vec<EvaluationDiagnostic>>,
+}
+
+/// LRU cache of evaluation results whose entries expire after a time to live
+pub struct ResultCache {
+    results: Mutex<LruCache<ResultKey, (Instant, Arc<CachedEvaluation>)>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl ResultCache {
+    /// Create a cache holding at most `capacity` results for `ttl` each (0 disables caching)
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            results: Mutex::new(LruCache::new(capacity)),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Look up an unexpired result, recording a hit or miss
+    pub fn get(&self, key: &ResultKey) -> Option<Arc<CachedEvaluation>> {
+        let mut results = self.results.lock().unwrap();
+        if results.capacity() == 0 {
+            return None;
+        }
+
+        let cached = match results.get(key) {
+            Some((stored_at, _)) if stored_at.elapsed() >= self.ttl => {
+                results.remove(key);
+                None
+            }
+            Some((_, evaluation)) => Some(evaluation.clone()),
+            None => None,
+        };
+
+        let counter = if cached.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    /// Store the result of a successful evaluation
+    pub fn insert(&self, key: ResultKey, evaluation: CachedEvaluation) {
+        let evicted = self
+            .results
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), Arc::new(evaluation)));
+        if evicted.is_some() {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let results = self.results.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: results.len(),
+            capacity: results.capacity(),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.results.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction_order() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+
+        // Touch "a" so that "b" becomes the least recently used entry
+        assert_eq!(cache.get(&"a"), Some(&1));
+
+        assert_eq!(cache.insert("c", 3), Some("b"));
+        assert!(cache.contains(&"a"));
+        assert!(!cache.contains(&"b"));
+        assert!(cache.contains(&"c"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_lru_reinsert_does_not_evict() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+
+        assert_eq!(cache.insert("a", 10), None);
+        assert_eq!(cache.get(&"a"), Some(&10));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_repeated_expression_hits_cache() {
+        let cache = CacheProvider::with_capacity(10);
+
+        cache.get_or_parse("Patient.name.given").unwrap();
+        cache.get_or_parse("Patient.name.given").unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[test]
+    fn test_expression_cache_capacity() {
+        let cache = CacheProvider::with_capacity(2);
+
+        cache.get_or_parse("Patient.id").unwrap();
+        cache.get_or_parse("Patient.name").unwrap();
+        cache.get_or_parse("Patient.gender").unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.capacity, 2);
+        assert_eq!(stats.evictions, 1);
+        assert!(cache.get("Patient.id").is_none());
+    }
+
+    fn evaluation(value: Value) -> CachedEvaluation {
+        CachedEvaluation {
+            values: vec![value],
+            types: vec!["String".to_string()],
+            diagnostics: None,
+            errors: None,
+        }
+    }
+
+    #[test]
+    fn test_canonical_expression() {
+        let canonical =
+            |expression: &str| canonical_expression(&octofhir_fhirpath::parse(expression).unwrap());
+
+        let key = canonical("Patient.name.where(use = 'official').given");
+        for equivalent in [
+            "Patient .name.where(use='official').given",
+            "  Patient.name\n  .where( use = 'official' )\n  .given",
+            "(Patient.name).where((use = 'official')).given",
+        ] {
+            assert_eq!(canonical(equivalent), key, "{equivalent}");
+        }
+        assert_eq!(
+            canonical("Patient.name[0].given"),
+            canonical("Patient.name.first().given")
+        );
+
+        for different in [
+            "Patient.name.where(use = 'usual').given",
+            "Patient.name.where(use = 'official').family",
+            "Patient.name.given.where(use = 'official')",
+        ] {
+            assert_ne!(canonical(different), key, "{different}");
+        }
+        assert_ne!(
+            canonical("Patient.name[1]"),
+            canonical("Patient.name.first()")
+        );
+        assert_ne!(
+            canonical("Patient.name.last()"),
+            canonical("Patient.name.first()")
+        );
+        assert_ne!(canonical("1.0 + 1"), canonical("1.00 + 1"));
+        assert_ne!(canonical("1 - 2 - 3"), canonical("1 - (2 - 3)"));
+    }
+
+    #[test]
+    fn test_result_key_ignores_object_key_order() {
+        let first: Value = serde_json::from_str(
+            r#"{"resourceType": "Patient", "name": [{"family": "Doe", "given": ["J"]}]}"#,
+        )
+        .unwrap();
+        let reordered: Value = serde_json::from_str(
+            r#"{"name": [{"given": ["J"], "family": "Doe"}], "resourceType": "Patient"}"#,
+        )
+        .unwrap();
+        let changed: Value = serde_json::from_str(
+            r#"{"resourceType": "Patient", "name": [{"family": "Roe", "given": ["J"]}]}"#,
+        )
+        .unwrap();
+
+        let key = ResultKey::new("Patient.name.family", &[&first]);
+        assert_eq!(key, ResultKey::new("Patient.name.family", &[&reordered]));
+        assert_ne!(key, ResultKey::new("Patient.name.family", &[&changed]));
+        assert_ne!(key, ResultKey::new("Patient.name.given", &[&first]));
+    }
+
+    #[test]
+    fn test_result_key_digest_collision_misses() {
+        let cache = ResultCache::new(10, Duration::from_secs(60));
+        let key = ResultKey::new("Patient.id", &[&serde_json::json!({"id": "p1"})]);
+        cache.insert(key.clone(), evaluation(Value::from("p1")));
+
+        // Same digest, different resource: must not be served the other result
+        let colliding = ResultKey {
+            inputs: Arc::from([serde_json::json!({"id": "p2"})]),
+            ..key.clone()
+        };
+        assert!(cache.get(&colliding).is_none());
+        assert!(cache.get(&key).is_some());
+    }
+
+    #[test]
+    fn test_result_cache_hits_and_misses() {
+        let cache = ResultCache::new(10, Duration::from_secs(60));
+        let key = ResultKey::new("Patient.id", &[&serde_json::json!({"id": "p1"})]);
+
+        assert!(cache.get(&key).is_none());
+        cache.insert(key.clone(), evaluation(Value::from("p1")));
+        assert_eq!(cache.get(&key).unwrap().values, vec![Value::from("p1")]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+        assert_eq!(stats.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_result_cache_entries_expire() {
+        let cache = ResultCache::new(10, Duration::ZERO);
+        let key = ResultKey::new("Patient.id", &[]);
+
+        cache.insert(key.clone(), evaluation(Value::from("p1")));
+        assert!(cache.get(&key).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_parse_errors_not_cached() {
+        let cache = CacheProvider::with_capacity(10);
+
+        assert!(cache.get_or_parse("Patient.name.where(").is_err());
+        assert_eq!(cache.stats().entries, 0);
     }
 }
@@ -1,9 +1,23 @@
 //! Configuration management
 
+use crate::fhirpath_engine::{FhirEngineConfig, PackageSource};
+use crate::metrics::MonitoringConfig;
+use crate::security::{ApiKeyConfig, SecurityConfig};
+use crate::terminology::{RemoteTerminology, TerminologyResolver};
+use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Prefix of environment variables overriding configuration values
+pub const ENV_PREFIX: &str = "OCTOFHIR_";
+
+/// Environment variable naming a configuration file for [`ServerConfig::load`]
+pub const CONFIG_PATH_ENV: &str = "OCTOFHIR_CONFIG";
 
 /// Server configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ServerConfig {
     /// Server host (default: localhost)
     pub host: String,
@@ -11,14 +25,72 @@ pub struct ServerConfig {
     pub port: u16,
     /// Log level (default: info)
     pub log_level: String,
+    /// Format of log lines on stderr (default: pretty)
+    pub log_format: LogFormat,
+    /// Per-module level directives in `RUST_LOG` syntax, e.g. `octofhir_mcp=debug,hyper=warn`,
+    /// applied on top of `log_level` (default: the `RUST_LOG` environment variable)
+    pub log_filter: Option<String>,
     /// Enable HTTP transport
     pub http_transport: bool,
     /// Enable stdio transport
     pub stdio_transport: bool,
+    /// Compress HTTP responses and accept compressed request bodies (gzip, deflate)
+    pub http_compression: bool,
+    /// How long responses to tool calls with an `Idempotency-Key` are kept for replay
+    /// (0 disables idempotency keys)
+    pub idempotency_ttl_seconds: u64,
+    /// Recent messages buffered per MCP SSE stream for clients resuming with `Last-Event-ID`;
+    /// clients missing older messages get a `lagged` notification with the number missed
+    pub sse_replay_buffer: usize,
+    /// Seconds between keep-alive comments on idle SSE streams
+    pub sse_keep_alive_seconds: u64,
+    /// Close MCP sessions idle for this many seconds (sessions never expire when unset)
+    pub sse_session_timeout_seconds: Option<u64>,
     /// FHIR version to use (default: R4)
     pub fhir_version: String,
     /// Additional FHIR packages to install
     pub additional_packages: Vec<String>,
+    /// Where additional packages and the FHIR core package come from: `"offline"`,
+    /// `{ registry = "<url>" }` or `{ local_dir = "<path>" }` (default: the public package
+    /// registry)
+    pub package_source: PackageSource,
+    /// Cross-origin resource sharing for the HTTP transport (disabled when unset)
+    pub cors: Option<CorsConfig>,
+    /// Expressions evaluated at startup to prime the engine; the HTTP transport reports
+    /// not-ready until they have run
+    pub warmup_expressions: Vec<String>,
+    /// Serve administrative HTTP endpoints such as `POST /admin/metrics/reset`, which also
+    /// require the admin token
+    pub enable_admin_endpoints: bool,
+    /// File that HTTP tool calls are audited to as JSON lines, or `stdout`; auditing is off
+    /// when unset
+    pub audit_log: Option<String>,
+    /// PEM certificate chain the HTTP transport serves HTTPS with; requires `tls_key_path`
+    pub tls_cert_path: Option<PathBuf>,
+    /// PEM private key of `tls_cert_path`
+    pub tls_key_path: Option<PathBuf>,
+    /// Threads of the Tokio runtime (default: the available parallelism, which respects
+    /// container CPU limits). Evaluations run on these threads, so however many the
+    /// evaluation semaphore admits (`max_concurrent_evaluations`), at most this many make
+    /// progress at once and the others interleave with them.
+    pub worker_threads: Option<usize>,
+    /// Strip file paths, stack frames and echoed resource content from the diagnostics and
+    /// error messages returned to clients
+    pub production: bool,
+    /// API keys accepted by the HTTP transport: plain keys may call every tool, `{ key, tools }`
+    /// entries only the listed tools. Setting any enables authentication.
+    pub api_keys: Vec<ApiKeyConfig>,
+    /// Base URLs of the FHIR servers `fhirpath_evaluate_remote` may read resources from,
+    /// e.g. `https://hapi.fhir.org/baseR4`; the tool is not offered when empty
+    pub allowed_fhir_servers: Vec<String>,
+    /// Base URL of a FHIR terminology server whose `ValueSet/$expand` backs `memberOf()`;
+    /// `memberOf()` is unavailable when unset
+    pub terminology_server: Option<String>,
+    /// JSON file the HTTP transport persists request and custom metrics to, restoring them
+    /// on startup; metrics start from zero on every run when unset
+    pub metrics_snapshot_path: Option<PathBuf>,
+    /// Seconds between writes of `metrics_snapshot_path`; it is also written on shutdown
+    pub metrics_snapshot_interval_seconds: u64,
 }
 
 impl Default for ServerConfig {
@@ -27,10 +99,671 @@ impl Default for ServerConfig {
             host: "localhost".to_string(),
             port: 3000,
             log_level: "info".to_string(),
+            log_format: LogFormat::default(),
+            log_filter: None,
             http_transport: true,
             stdio_transport: true,
+            http_compression: true,
+            idempotency_ttl_seconds: crate::transport::idempotency::DEFAULT_IDEMPOTENCY_TTL
+                .as_secs(),
+            sse_replay_buffer: crate::transport::session::DEFAULT_SSE_REPLAY_BUFFER,
+            sse_keep_alive_seconds: crate::transport::http::DEFAULT_SSE_KEEP_ALIVE.as_secs(),
+            sse_session_timeout_seconds: None,
             fhir_version: "R4".to_string(),
             additional_packages: Vec::new(),
+            package_source: PackageSource::default(),
+            cors: None,
+            warmup_expressions: Vec::new(),
+            enable_admin_endpoints: false,
+            audit_log: None,
+            tls_cert_path: None,
+            tls_key_path: None,
+            worker_threads: None,
+            production: false,
+            api_keys: Vec::new(),
+            allowed_fhir_servers: Vec::new(),
+            terminology_server: None,
+            metrics_snapshot_path: None,
+            metrics_snapshot_interval_seconds: MonitoringConfig::default()
+                .snapshot_interval_seconds,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load configuration from a JSON or TOML file, chosen by extension.
+    /// Fields missing from the file keep their default values.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        let config: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content)
+                .with_context(|| format!("Invalid JSON config file {}", path.display()))?,
+            Some("toml") => toml::from_str(&content)
+                .with_context(|| format!("Invalid TOML config file {}", path.display()))?,
+            _ => {
+                return Err(anyhow!(
+                    "Unsupported config file '{}', expected a .json or .toml extension",
+                    path.display()
+                ));
+            }
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Build configuration from defaults overridden by `OCTOFHIR_*` environment variables
+    pub fn from_env() -> Result<Self> {
+        Self::default().with_env_overrides(|name| std::env::var(name).ok())
+    }
+
+    /// Load configuration layering defaults < config file < environment variables.
+    ///
+    /// The file is `path` if given, otherwise the file named by `OCTOFHIR_CONFIG`, if set.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(CONFIG_PATH_ENV).map(PathBuf::from));
+
+        let config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        config.with_env_overrides(|name| std::env::var(name).ok())
+    }
+
+    /// Build the multi-threaded runtime the server runs on, with `worker_threads` threads
+    pub fn build_runtime(&self) -> Result<tokio::runtime::Runtime> {
+        let worker_threads = match self.worker_threads {
+            Some(worker_threads) => worker_threads,
+            None => std::thread::available_parallelism().map_or(1, usize::from),
+        };
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(worker_threads)
+            .enable_all()
+            .build()
+            .context("Failed to build the Tokio runtime")
+    }
+
+    /// Security settings of the transports: `base` with the values this configuration sets.
+    /// Configured API keys replace those of `base` and turn authentication on.
+    pub fn security_config(&self, base: SecurityConfig) -> SecurityConfig {
+        let (enable_auth, api_keys) = if self.api_keys.is_empty() {
+            (base.enable_auth, base.api_keys)
+        } else {
+            (true, self.api_keys.clone())
+        };
+        SecurityConfig {
+            production: self.production,
+            enable_auth,
+            api_keys,
+            allowed_fhir_servers: self.allowed_fhir_servers.clone(),
+            ..base
+        }
+    }
+
+    /// Monitoring settings of the HTTP transport: `base` with the metrics persistence this
+    /// configuration sets, and the files whose free disk space is watched
+    pub fn monitoring_config(&self, base: MonitoringConfig) -> MonitoringConfig {
+        MonitoringConfig {
+            snapshot_path: self.metrics_snapshot_path.clone(),
+            snapshot_interval_seconds: self.metrics_snapshot_interval_seconds,
+            audit_log_path: self
+                .audit_log
+                .as_deref()
+                .filter(|sink| *sink != crate::security::audit::STDOUT_SINK)
+                .map(PathBuf::from),
+            ..base
+        }
+    }
+
+    /// Engine settings of the transports: `base` with the FHIR version, packages and
+    /// terminology server this configuration sets
+    pub fn engine_config(&self, base: FhirEngineConfig) -> FhirEngineConfig {
+        let terminology = match &self.terminology_server {
+            Some(url) => {
+                Some(Arc::new(RemoteTerminology::new(url)) as Arc<dyn TerminologyResolver>)
+            }
+            None => base.terminology,
+        };
+        FhirEngineConfig {
+            fhir_version: self.fhir_version.clone(),
+            additional_packages: self.additional_packages.clone(),
+            package_source: self.package_source.clone(),
+            terminology,
+            ..base
+        }
+    }
+
+    /// Apply `OCTOFHIR_*` overrides read through `lookup`
+    fn with_env_overrides(mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let var = |key: &str| {
+            let name = format!("{ENV_PREFIX}{key}");
+            lookup(&name).map(|value| (name, value))
+        };
+
+        if let Some((_, value)) = var("HOST") {
+            self.host = value;
+        }
+        if let Some((name, value)) = var("PORT") {
+            self.port = value.parse().map_err(|_| {
+                anyhow!("Invalid value '{value}' for {name}: expected a port number (0-65535)")
+            })?;
+        }
+        if let Some((_, value)) = var("LOG_LEVEL") {
+            self.log_level = value;
+        }
+        if let Some((name, value)) = var("LOG_FORMAT") {
+            self.log_format = match value.to_lowercase().as_str() {
+                "pretty" => LogFormat::Pretty,
+                "json" => LogFormat::Json,
+                _ => {
+                    return Err(anyhow!(
+                        "Invalid value '{value}' for {name}: expected pretty or json"
+                    ));
+                }
+            };
+        }
+        if let Some((_, value)) = var("LOG_FILTER") {
+            self.log_filter = Some(value);
+        }
+        if let Some((name, value)) = var("HTTP_TRANSPORT") {
+            self.http_transport = parse_bool(&name, &value)?;
         }
+        if let Some((name, value)) = var("STDIO_TRANSPORT") {
+            self.stdio_transport = parse_bool(&name, &value)?;
+        }
+        if let Some((name, value)) = var("HTTP_COMPRESSION") {
+            self.http_compression = parse_bool(&name, &value)?;
+        }
+        if let Some((name, value)) = var("IDEMPOTENCY_TTL_SECONDS") {
+            self.idempotency_ttl_seconds = value.parse().map_err(|_| {
+                anyhow!("Invalid value '{value}' for {name}: expected a number of seconds")
+            })?;
+        }
+        if let Some((name, value)) = var("SSE_REPLAY_BUFFER") {
+            self.sse_replay_buffer = value.parse().map_err(|_| {
+                anyhow!("Invalid value '{value}' for {name}: expected a number of messages")
+            })?;
+        }
+        if let Some((name, value)) = var("SSE_KEEP_ALIVE_SECONDS") {
+            self.sse_keep_alive_seconds = value.parse().map_err(|_| {
+                anyhow!("Invalid value '{value}' for {name}: expected a number of seconds")
+            })?;
+        }
+        if let Some((name, value)) = var("SSE_SESSION_TIMEOUT_SECONDS") {
+            self.sse_session_timeout_seconds = Some(value.parse().map_err(|_| {
+                anyhow!("Invalid value '{value}' for {name}: expected a number of seconds")
+            })?);
+        }
+        if let Some((name, value)) = var("ENABLE_ADMIN_ENDPOINTS") {
+            self.enable_admin_endpoints = parse_bool(&name, &value)?;
+        }
+        if let Some((_, value)) = var("AUDIT_LOG") {
+            self.audit_log = Some(value);
+        }
+        if let Some((_, value)) = var("TLS_CERT_PATH") {
+            self.tls_cert_path = Some(PathBuf::from(value));
+        }
+        if let Some((_, value)) = var("TLS_KEY_PATH") {
+            self.tls_key_path = Some(PathBuf::from(value));
+        }
+        if let Some((name, value)) = var("WORKER_THREADS") {
+            self.worker_threads = Some(value.parse().map_err(|_| {
+                anyhow!("Invalid value '{value}' for {name}: expected a number of threads")
+            })?);
+        }
+        if let Some((name, value)) = var("PRODUCTION") {
+            self.production = parse_bool(&name, &value)?;
+        }
+        if let Some((_, value)) = var("API_KEYS") {
+            self.api_keys = value
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(ApiKeyConfig::from)
+                .collect();
+        }
+        if let Some((_, value)) = var("ALLOWED_FHIR_SERVERS") {
+            self.allowed_fhir_servers = value
+                .split(',')
+                .map(str::trim)
+                .filter(|server| !server.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some((_, value)) = var("TERMINOLOGY_SERVER") {
+            self.terminology_server = Some(value);
+        }
+        if let Some((_, value)) = var("METRICS_SNAPSHOT_PATH") {
+            self.metrics_snapshot_path = Some(PathBuf::from(value));
+        }
+        if let Some((name, value)) = var("METRICS_SNAPSHOT_INTERVAL_SECONDS") {
+            self.metrics_snapshot_interval_seconds = value.parse().map_err(|_| {
+                anyhow!("Invalid value '{value}' for {name}: expected a number of seconds")
+            })?;
+        }
+        if let Some((_, value)) = var("FHIR_VERSION") {
+            self.fhir_version = value;
+        }
+        if let Some((_, value)) = var("ADDITIONAL_PACKAGES") {
+            self.additional_packages = value
+                .split(',')
+                .map(str::trim)
+                .filter(|package| !package.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some((_, value)) = var("PACKAGE_SOURCE") {
+            self.package_source = PackageSource::parse(&value);
+        }
+
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check that values are usable by the server
+    pub fn validate(&self) -> Result<()> {
+        const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
+
+        if !LOG_LEVELS.contains(&self.log_level.to_lowercase().as_str()) {
+            return Err(anyhow!(
+                "Invalid log_level '{}': expected one of {}",
+                self.log_level,
+                LOG_LEVELS.join(", ")
+            ));
+        }
+
+        if let Some(filter) = &self.log_filter {
+            tracing_subscriber::EnvFilter::builder()
+                .parse(filter)
+                .map_err(|e| anyhow!("Invalid log_filter '{}': {}", filter, e))?;
+        }
+
+        let versions = crate::fhirpath_engine::SUPPORTED_FHIR_VERSIONS;
+        if !versions.contains(&self.fhir_version.as_str()) {
+            return Err(anyhow!(
+                "Invalid fhir_version '{}': expected one of {}",
+                self.fhir_version,
+                versions.join(", ")
+            ));
+        }
+
+        if self.sse_replay_buffer == 0 {
+            return Err(anyhow!("Invalid sse_replay_buffer: must be at least 1"));
+        }
+
+        if self.sse_keep_alive_seconds == 0 {
+            return Err(anyhow!(
+                "Invalid sse_keep_alive_seconds: must be at least 1"
+            ));
+        }
+
+        if self.sse_session_timeout_seconds == Some(0) {
+            return Err(anyhow!(
+                "Invalid sse_session_timeout_seconds: must be at least 1"
+            ));
+        }
+
+        if self.worker_threads == Some(0) {
+            return Err(anyhow!("Invalid worker_threads: must be at least 1"));
+        }
+
+        if let Some(package) = self
+            .additional_packages
+            .iter()
+            .find(|package| !package.contains('@'))
+        {
+            return Err(anyhow!(
+                "Invalid additional package '{}': expected 'name@version'",
+                package
+            ));
+        }
+
+        if let Some(cors) = &self.cors {
+            cors.validate()?;
+        }
+
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            return Err(anyhow!(
+                "Invalid TLS config: tls_cert_path and tls_key_path must be set together"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Format of log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log pipelines
+    Json,
+}
+
+/// Cross-origin resource sharing settings for the HTTP transport
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the server; `*` allows any origin (default: `*`)
+    pub allowed_origins: Vec<String>,
+    /// Request headers browsers may send; empty allows any header
+    pub allowed_headers: Vec<String>,
+    /// Response headers exposed to browser scripts
+    pub exposed_headers: Vec<String>,
+    /// Allow cookies and authorization headers on cross-origin requests
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Check that origins and header names are valid and that wildcards are not combined
+    /// with credentials, which browsers reject
+    pub fn validate(&self) -> Result<()> {
+        if self.allowed_origins.is_empty() {
+            return Err(anyhow!(
+                "Invalid CORS config: allowed_origins cannot be empty"
+            ));
+        }
+
+        if self.allow_credentials {
+            for (field, values) in [
+                ("allowed_origins", &self.allowed_origins),
+                ("allowed_headers", &self.allowed_headers),
+                ("exposed_headers", &self.exposed_headers),
+            ] {
+                if values.iter().any(|value| value == "*") {
+                    return Err(anyhow!(
+                        "Invalid CORS config: allow_credentials cannot be combined with a \
+                         wildcard in {field}"
+                    ));
+                }
+            }
+        }
+
+        if let Some(origin) = self
+            .allowed_origins
+            .iter()
+            .find(|origin| *origin != "*" && axum::http::HeaderValue::from_str(origin).is_err())
+        {
+            return Err(anyhow!("Invalid CORS origin '{}'", origin));
+        }
+
+        if let Some(name) = self
+            .allowed_headers
+            .iter()
+            .chain(&self.exposed_headers)
+            .find(|name| {
+                *name != "*" && axum::http::HeaderName::from_bytes(name.as_bytes()).is_err()
+            })
+        {
+            return Err(anyhow!("Invalid CORS header name '{}'", name));
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(anyhow!(
+            "Invalid value '{value}' for {name}: expected true or false"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("octofhir-mcp-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_from_file_toml_and_json() {
+        let toml_path = write_config(
+            "config.toml",
+            "port = 8080\nfhir_version = \"R5\"\npackage_source = { local_dir = \"/srv/fhir\" }\n",
+        );
+        let config = ServerConfig::from_file(&toml_path).unwrap();
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.fhir_version, "R5");
+        assert_eq!(
+            config.package_source,
+            PackageSource::LocalDir(PathBuf::from("/srv/fhir"))
+        );
+        assert_eq!(config.host, "localhost");
+
+        let json_path = write_config(
+            "config.json",
+            r#"{"host": "0.0.0.0", "port": 9000,
+                "api_keys": ["admin-key", {"key": "reader-key", "tools": ["fhirpath_parse"]}]}"#,
+        );
+        let config = ServerConfig::from_file(&json_path).unwrap();
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.api_keys[0], ApiKeyConfig::from("admin-key"));
+        assert!(config.api_keys[1].scope().allows("fhirpath_parse"));
+        assert!(!config.api_keys[1].scope().allows("fhirpath_evaluate"));
+
+        let yaml_path = write_config("config.yaml", "port: 1");
+        assert!(ServerConfig::from_file(&yaml_path).is_err());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let path = write_config("config.toml", "port = 8080\nhost = \"file-host\"\n");
+        let config = ServerConfig::from_file(&path)
+            .unwrap()
+            .with_env_overrides(env(&[
+                ("OCTOFHIR_PORT", "9090"),
+                ("OCTOFHIR_HTTP_TRANSPORT", "false"),
+                ("OCTOFHIR_HTTP_COMPRESSION", "off"),
+                ("OCTOFHIR_ADDITIONAL_PACKAGES", "hl7.fhir.us.core@6.1.0, "),
+                ("OCTOFHIR_PACKAGE_SOURCE", "offline"),
+                ("OCTOFHIR_LOG_FORMAT", "JSON"),
+                ("OCTOFHIR_LOG_FILTER", "octofhir_mcp=debug"),
+                ("OCTOFHIR_PRODUCTION", "true"),
+                (
+                    "OCTOFHIR_METRICS_SNAPSHOT_PATH",
+                    "/var/lib/octofhir/metrics.json",
+                ),
+                ("OCTOFHIR_METRICS_SNAPSHOT_INTERVAL_SECONDS", "15"),
+                ("OCTOFHIR_API_KEYS", "first-key, second-key,"),
+                ("OCTOFHIR_TERMINOLOGY_SERVER", "https://tx.fhir.org/r4"),
+                (
+                    "OCTOFHIR_ALLOWED_FHIR_SERVERS",
+                    "https://hapi.fhir.org/baseR4, https://fhir.example.org/r4",
+                ),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.host, "file-host");
+        assert!(!config.http_transport);
+        assert!(!config.http_compression);
+        assert_eq!(config.additional_packages, vec!["hl7.fhir.us.core@6.1.0"]);
+        assert_eq!(config.package_source, PackageSource::Offline);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.log_filter.as_deref(), Some("octofhir_mcp=debug"));
+        assert!(config.production);
+        assert!(config.security_config(SecurityConfig::default()).production);
+        assert_eq!(
+            config.terminology_server.as_deref(),
+            Some("https://tx.fhir.org/r4")
+        );
+        assert!(
+            config
+                .engine_config(FhirEngineConfig::default())
+                .terminology
+                .is_some()
+        );
+        assert!(
+            ServerConfig::default()
+                .engine_config(FhirEngineConfig::default())
+                .terminology
+                .is_none()
+        );
+        assert_eq!(
+            config.api_keys,
+            vec![
+                ApiKeyConfig::from("first-key"),
+                ApiKeyConfig::from("second-key")
+            ]
+        );
+        let security = config.security_config(SecurityConfig {
+            enable_auth: false,
+            ..SecurityConfig::default()
+        });
+        assert!(security.enable_auth);
+        assert_eq!(security.api_keys, config.api_keys);
+        assert_eq!(
+            security.allowed_fhir_servers,
+            vec![
+                "https://hapi.fhir.org/baseR4",
+                "https://fhir.example.org/r4"
+            ]
+        );
+        assert!(
+            !ServerConfig::default()
+                .security_config(SecurityConfig {
+                    enable_auth: false,
+                    ..SecurityConfig::default()
+                })
+                .enable_auth
+        );
+        let monitoring = config.monitoring_config(MonitoringConfig::default());
+        assert_eq!(
+            monitoring.snapshot_path.as_deref(),
+            Some(Path::new("/var/lib/octofhir/metrics.json"))
+        );
+        assert_eq!(monitoring.snapshot_interval_seconds, 15);
+        assert_eq!(monitoring.audit_log_path, None);
+
+        let config = ServerConfig {
+            audit_log: Some("/var/log/octofhir/audit.jsonl".to_string()),
+            ..ServerConfig::default()
+        };
+        let monitoring = config.monitoring_config(MonitoringConfig::default());
+        assert_eq!(
+            monitoring.audit_log_path.as_deref(),
+            Some(Path::new("/var/log/octofhir/audit.jsonl"))
+        );
+        let config = ServerConfig {
+            audit_log: Some("stdout".to_string()),
+            ..ServerConfig::default()
+        };
+        assert_eq!(
+            config
+                .monitoring_config(MonitoringConfig::default())
+                .audit_log_path,
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let err = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_PORT", "not-a-port")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("OCTOFHIR_PORT"));
+
+        let err = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_FHIR_VERSION", "DSTU2")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid fhir_version 'DSTU2'"));
+
+        let err = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_SSE_KEEP_ALIVE_SECONDS", "0")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("sse_keep_alive_seconds"));
+
+        let path = write_config(
+            "config.toml",
+            "[cors]\nallowed_origins = [\"*\"]\nallow_credentials = true\n",
+        );
+        let err = ServerConfig::from_file(&path).unwrap_err();
+        assert!(err.to_string().contains("allow_credentials"));
+
+        let err = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_TLS_CERT_PATH", "cert.pem")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("tls_key_path"));
+
+        let err = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_WORKER_THREADS", "0")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("worker_threads"));
+
+        let err = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_LOG_FORMAT", "xml")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("OCTOFHIR_LOG_FORMAT"));
+
+        let err = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_LOG_FILTER", "octofhir_mcp=loud")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid log_filter"));
+
+        let path = write_config("config.toml", "port = \"eighty\"\n");
+        let err = ServerConfig::from_file(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("port"));
+    }
+
+    #[test]
+    fn test_build_runtime_with_worker_threads() {
+        let config = ServerConfig::default()
+            .with_env_overrides(env(&[("OCTOFHIR_WORKER_THREADS", "2")]))
+            .unwrap();
+        assert_eq!(config.worker_threads, Some(2));
+
+        let runtime = config.build_runtime().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+        let sum = runtime.block_on(async {
+            let tasks: Vec<_> = (1..=4)
+                .map(|i| tokio::spawn(async move { i * 10 }))
+                .collect();
+            let mut sum = 0;
+            for task in tasks {
+                sum += task.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(sum, 100);
     }
 }
@@ -0,0 +1,200 @@
+//! Custom FHIRPath functions
+//!
+//! Deployments can extend the engine with domain-specific functions, such as a `riskScore()`
+//! calculation. Functions registered on [`FhirEngineConfig`](crate::FhirEngineConfig) are
+//! added to the registry of every engine its factory creates, and `fhirpath_functions` lists
+//! them under the `custom` category.
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use octofhir_fhirpath::registry::operations::EvaluationContext;
+use octofhir_fhirpath::registry::{
+    FhirPathOperation, FhirPathRegistry, MetadataBuilder, OperationMetadata,
+    OperationSpecificMetadata, OperationType, TypeConstraint,
+};
+use octofhir_fhirpath::{FhirPathError, FhirPathValue};
+use std::{fmt, sync::Arc};
+
+/// Category `fhirpath_functions` lists custom functions under
+pub const CUSTOM_FUNCTION_CATEGORY: &str = "custom";
+
+/// A function added to the engine's standard library
+pub trait CustomFunction: Send + Sync {
+    /// Name the function is called by in expressions
+    fn name(&self) -> &str;
+
+    /// Number of arguments the function takes
+    fn arity(&self) -> usize;
+
+    /// Description listed by `fhirpath_functions`
+    fn description(&self) -> &str {
+        "Custom function"
+    }
+
+    /// Whether the function always returns the same result for the same input and arguments.
+    /// Results of expressions calling a function that reads external state are not cached.
+    fn deterministic(&self) -> bool {
+        true
+    }
+
+    /// Evaluate the function on `input`, the collection it is called on, with its evaluated
+    /// arguments. An argument holding a single item is passed as that item.
+    fn evaluate(&self, input: &FhirPathValue, args: &[FhirPathValue]) -> Result<FhirPathValue>;
+}
+
+impl fmt::Debug for dyn CustomFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.name(), self.arity())
+    }
+}
+
+/// A custom function evaluated by a closure
+pub struct FnFunction<F> {
+    name: String,
+    arity: usize,
+    description: String,
+    deterministic: bool,
+    evaluate: F,
+}
+
+impl<F> FnFunction<F>
+where
+    F: Fn(&FhirPathValue, &[FhirPathValue]) -> Result<FhirPathValue> + Send + Sync,
+{
+    /// Create a function called `name` taking `arity` arguments, evaluated by `evaluate`
+    pub fn new(name: impl Into<String>, arity: usize, evaluate: F) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            description: "Custom function".to_string(),
+            deterministic: true,
+            evaluate,
+        }
+    }
+
+    /// Set the description listed by `fhirpath_functions`
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Set whether the function always returns the same result for the same arguments
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+}
+
+impl<F> CustomFunction for FnFunction<F>
+where
+    F: Fn(&FhirPathValue, &[FhirPathValue]) -> Result<FhirPathValue> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    fn evaluate(&self, input: &FhirPathValue, args: &[FhirPathValue]) -> Result<FhirPathValue> {
+        (self.evaluate)(input, args)
+    }
+}
+
+/// Registry operation calling a custom function
+struct CustomOperation {
+    function: Arc<dyn CustomFunction>,
+    metadata: OperationMetadata,
+}
+
+impl CustomOperation {
+    fn new(function: Arc<dyn CustomFunction>) -> Self {
+        let mut metadata = MetadataBuilder::new(function.name(), OperationType::Function)
+            .description(function.description())
+            .returns(TypeConstraint::Any);
+        for i in 1..=function.arity() {
+            metadata = metadata.parameter(&format!("arg{i}"), TypeConstraint::Any, false);
+        }
+        let mut metadata = metadata.build();
+        if let OperationSpecificMetadata::Function(specific) = &mut metadata.specific {
+            specific.deterministic = function.deterministic();
+        }
+        Self { metadata, function }
+    }
+}
+
+#[async_trait]
+impl FhirPathOperation for CustomOperation {
+    fn identifier(&self) -> &str {
+        self.function.name()
+    }
+
+    fn operation_type(&self) -> OperationType {
+        OperationType::Function
+    }
+
+    fn metadata(&self) -> &OperationMetadata {
+        &self.metadata
+    }
+
+    async fn evaluate(
+        &self,
+        args: &[FhirPathValue],
+        context: &EvaluationContext,
+    ) -> octofhir_fhirpath::Result<FhirPathValue> {
+        if args.len() != self.function.arity() {
+            return Err(FhirPathError::InvalidArgumentCount {
+                function_name: self.function.name().to_string(),
+                expected: self.function.arity(),
+                actual: args.len(),
+            });
+        }
+
+        let args: Vec<FhirPathValue> = args
+            .iter()
+            .map(|arg| match arg {
+                FhirPathValue::Collection(items) if items.len() == 1 => {
+                    items.get(0).unwrap().clone()
+                }
+                arg => arg.clone(),
+            })
+            .collect();
+        self.function
+            .evaluate(&context.input, &args)
+            .map_err(|e| FhirPathError::evaluation_error(format!("{e:#}")))
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// Add `functions` to `registry`. Functions may not replace the engine's own.
+pub(crate) async fn register_custom_functions(
+    registry: &FhirPathRegistry,
+    functions: &[Arc<dyn CustomFunction>],
+) -> Result<()> {
+    for function in functions {
+        let name = function.name();
+        if registry.contains(name).await || registry.is_lambda_function(name).await {
+            return Err(anyhow!(
+                "Custom function '{}' has the name of a built-in function",
+                name
+            ));
+        }
+        registry
+            .register(CustomOperation::new(function.clone()))
+            .await
+            .map_err(|e| anyhow!("Failed to register custom function '{}': {}", name, e))?;
+    }
+    Ok(())
+}
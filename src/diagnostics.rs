@@ -0,0 +1,577 @@
+//! Structured diagnostics for failed evaluations and unparsable expressions
+//!
+//! The engine reports evaluation errors without source positions, so the offending part of
+//! the expression is located from the kind of error and the names its message mentions.
+//! A diagnostic whose cause can't be pinned to any text has no position.
+//!
+//! Parse errors are located from what the parser reports: the offset it gives, or else the
+//! token it names as found in place of what it expected. Only when it names neither is the
+//! position guessed from the token stream.
+
+use crate::fhirpath_engine::EvaluationFailed;
+use crate::lint::{Located, function_name, located_tokens};
+use crate::terminology::expand_terminology_variables;
+use crate::tools::EvaluationTimeout;
+use octofhir_fhirpath::core::EvaluationError;
+use octofhir_fhirpath::parser::{ParseError, Token, parse_expression_pratt};
+use serde::{Deserialize, Serialize};
+
+/// Operand types don't suit the operator or function they are passed to
+pub const TYPE_MISMATCH: &str = "type-mismatch";
+/// A function call failed
+pub const FUNCTION: &str = "function";
+/// An operator failed for a reason other than its operand types
+pub const OPERATOR: &str = "operator";
+/// A function was passed an unusable argument
+pub const INVALID_ARGUMENT: &str = "invalid-argument";
+/// The expression asked for something the engine can't do
+pub const INVALID_OPERATION: &str = "invalid-operation";
+/// A property doesn't exist on the type it was read from
+pub const PROPERTY_NOT_FOUND: &str = "property-not-found";
+/// An indexer went past the end of its collection
+pub const INDEX_OUT_OF_BOUNDS: &str = "index-out-of-bounds";
+/// A division or modulo by zero
+pub const DIVISION_BY_ZERO: &str = "division-by-zero";
+/// Evaluation failed at runtime for another reason
+pub const RUNTIME: &str = "runtime";
+/// Evaluation ran past its time limit
+pub const TIMEOUT: &str = "timeout";
+/// Evaluation failed outside the engine, e.g. the expression didn't parse
+pub const INTERNAL: &str = "internal";
+
+/// Why an evaluation failed, and where in the expression when that is known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationDiagnostic {
+    /// What went wrong
+    pub message: String,
+    /// Category of the error, e.g. `type-mismatch` or `timeout`
+    pub kind: String,
+    /// Byte offset where the offending text starts
+    pub start: Option<usize>,
+    /// Byte offset just past the offending text
+    pub end: Option<usize>,
+}
+
+impl EvaluationDiagnostic {
+    /// Describe `error`, raised while evaluating `expression`
+    pub fn from_error(expression: &str, error: &anyhow::Error) -> Self {
+        let Some(EvaluationFailed(cause)) = error.downcast_ref::<EvaluationFailed>() else {
+            let kind = if error.is::<EvaluationTimeout>() {
+                TIMEOUT
+            } else {
+                INTERNAL
+            };
+            return Self {
+                message: error.to_string(),
+                kind: kind.to_string(),
+                start: None,
+                end: None,
+            };
+        };
+
+        let span = located_tokens(expression).and_then(|tokens| locate(&tokens, cause));
+        Self {
+            message: cause.to_string(),
+            kind: kind(cause).to_string(),
+            start: span.map(|(start, _)| start),
+            end: span.map(|(_, end)| end),
+        }
+    }
+}
+
+fn kind(error: &EvaluationError) -> &'static str {
+    match error {
+        EvaluationError::TypeError { .. } => TYPE_MISMATCH,
+        EvaluationError::Function(_) => FUNCTION,
+        EvaluationError::Operator(_) => OPERATOR,
+        EvaluationError::InvalidArgument { .. } => INVALID_ARGUMENT,
+        EvaluationError::InvalidOperation { .. } => INVALID_OPERATION,
+        EvaluationError::PropertyNotFound { .. } => PROPERTY_NOT_FOUND,
+        EvaluationError::IndexOutOfBounds { .. } => INDEX_OUT_OF_BOUNDS,
+        EvaluationError::DivisionByZero => DIVISION_BY_ZERO,
+        EvaluationError::RuntimeError { .. } => RUNTIME,
+    }
+}
+
+/// Byte range of the text most likely responsible for `error`
+fn locate(tokens: &[Located<'_>], error: &EvaluationError) -> Option<(usize, usize)> {
+    match error {
+        EvaluationError::TypeError { .. } | EvaluationError::Operator(_) => {
+            operator(tokens, is_operator).or_else(|| mentioned_call(tokens, &error.to_string()))
+        }
+        EvaluationError::DivisionByZero => operator(tokens, |token| {
+            matches!(token, Token::Divide | Token::Div | Token::Mod)
+        }),
+        EvaluationError::PropertyNotFound { property, .. } => tokens
+            .iter()
+            .find(|located| located.token == Token::Identifier(property.as_str()))
+            .map(|located| (located.start, located.end)),
+        EvaluationError::IndexOutOfBounds { .. } => {
+            let open = tokens
+                .iter()
+                .position(|located| located.token == Token::LeftBracket)?;
+            let close = closing(tokens, open, &Token::LeftBracket, &Token::RightBracket)?;
+            Some((tokens[open].start, tokens[close].end))
+        }
+        EvaluationError::Function(message)
+        | EvaluationError::InvalidArgument { message }
+        | EvaluationError::InvalidOperation { message }
+        | EvaluationError::RuntimeError { message } => mentioned_call(tokens, message),
+    }
+}
+
+fn is_operator(token: &Token<'_>) -> bool {
+    matches!(
+        token,
+        Token::Plus
+            | Token::Minus
+            | Token::Multiply
+            | Token::Divide
+            | Token::Mod
+            | Token::Div
+            | Token::Power
+            | Token::Ampersand
+            | Token::Equal
+            | Token::NotEqual
+            | Token::LessThan
+            | Token::LessThanOrEqual
+            | Token::GreaterThan
+            | Token::GreaterThanOrEqual
+            | Token::Equivalent
+            | Token::NotEquivalent
+            | Token::And
+            | Token::Or
+            | Token::Xor
+            | Token::Implies
+            | Token::Union
+            | Token::In
+            | Token::Contains
+            | Token::Is
+            | Token::As
+    )
+}
+
+/// The first operator matching `matches`, skipping functions spelled like operators, such
+/// as `contains(...)`
+fn operator(
+    tokens: &[Located<'_>],
+    matches: impl Fn(&Token<'_>) -> bool,
+) -> Option<(usize, usize)> {
+    tokens
+        .iter()
+        .enumerate()
+        .find(|(i, located)| {
+            matches(&located.token)
+                && tokens.get(i + 1).map(|next| &next.token) != Some(&Token::LeftParen)
+        })
+        .map(|(_, located)| (located.start, located.end))
+}
+
+/// The first function call, from its name to its closing parenthesis, whose name `message`
+/// mentions
+fn mentioned_call(tokens: &[Located<'_>], message: &str) -> Option<(usize, usize)> {
+    tokens.iter().enumerate().find_map(|(i, located)| {
+        let name = function_name(&located.token)?;
+        if tokens.get(i + 1)?.token != Token::LeftParen || !mentions(message, name) {
+            return None;
+        }
+        let close = closing(tokens, i + 1, &Token::LeftParen, &Token::RightParen)?;
+        Some((located.start, tokens[close].end))
+    })
+}
+
+/// Whether `message` contains `name` as a whole word
+fn mentions(message: &str, name: &str) -> bool {
+    message.match_indices(name).any(|(at, _)| {
+        let before = message[..at].chars().next_back();
+        let after = message[at + name.len()..].chars().next();
+        [before, after]
+            .into_iter()
+            .flatten()
+            .all(|c| !c.is_alphanumeric() && c != '_')
+    })
+}
+
+/// Index of the token closing the bracket opened at `open`
+fn closing(
+    tokens: &[Located<'_>],
+    open: usize,
+    left: &Token<'_>,
+    right: &Token<'_>,
+) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, located) in tokens.iter().enumerate().skip(open) {
+        if located.token == *left {
+            depth += 1;
+        } else if located.token == *right {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Why an expression failed to parse, where, and how it might be fixed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParseDiagnostic {
+    /// The parser's message
+    pub message: String,
+    /// Byte offset of the offending text, or the length of the expression when it ends
+    /// too early
+    pub position: Option<usize>,
+    /// Byte offset just past the offending text
+    pub end: Option<usize>,
+    /// What the parser expected instead, e.g. `[",", ")"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expected: Vec<String>,
+    /// How the expression might be fixed
+    pub suggestion: Option<String>,
+}
+
+/// What went wrong while parsing, as far as the parser tells
+enum Problem<'e> {
+    /// The parser gave the offset of the error
+    At(usize),
+    /// A string literal is missing its closing quote
+    UnclosedString(usize),
+    /// The input ended where more was needed
+    EndOfInput,
+    /// A `.` is followed by the token with this debug form, or by nothing
+    AfterDot(Option<&'e str>),
+    /// A complete expression is followed by the token with this debug form
+    Trailing(&'e str),
+    /// An argument is followed by something other than `,` or `)`
+    Arguments,
+    Unknown,
+}
+
+impl<'e> Problem<'e> {
+    fn of(error: &'e ParseError) -> Self {
+        match error {
+            ParseError::UnexpectedToken { token, position } if *position == 0 => {
+                Self::from_message(token)
+            }
+            ParseError::UnexpectedToken { position, .. }
+            | ParseError::SyntaxError { position, .. }
+            | ParseError::ExpectedToken { position, .. }
+            | ParseError::InvalidLiteral { position, .. }
+            | ParseError::InvalidEscape { position, .. }
+            | ParseError::InvalidIdentifier { position, .. }
+            | ParseError::NomError { position, .. }
+            | ParseError::LazyFormatted { position, .. } => Self::At(*position),
+            ParseError::UnclosedString { position } => Self::UnclosedString(*position),
+            ParseError::UnexpectedEof | ParseError::UnexpectedEndOfInput { .. } => Self::EndOfInput,
+        }
+    }
+
+    /// The parser reports most errors at offset 0, describing them in the message instead
+    fn from_message(message: &'e str) -> Self {
+        if let Some(found) = message.strip_prefix("Expected identifier after dot: ") {
+            return Self::AfterDot(
+                found
+                    .strip_prefix("Some(")
+                    .and_then(|found| found.strip_suffix(')')),
+            );
+        }
+        if let Some(found) = message
+            .strip_prefix("Unexpected token: Some(")
+            .and_then(|found| found.strip_suffix(')'))
+        {
+            return Self::Trailing(found);
+        }
+        if message.contains("end of input") {
+            Self::EndOfInput
+        } else if message.starts_with("Expected ',' or ')'") {
+            Self::Arguments
+        } else {
+            Self::Unknown
+        }
+    }
+
+    fn expected(&self, error: &ParseError) -> Vec<String> {
+        match (self, error) {
+            (_, ParseError::ExpectedToken { expected, .. }) => vec![expected.to_string()],
+            (Self::AfterDot(_), _) => vec!["identifier".to_string()],
+            (Self::Arguments, _) => vec![",".to_string(), ")".to_string()],
+            (Self::UnclosedString(_), _) => vec!["'".to_string()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ParseDiagnostic {
+    /// Describe why `expression` doesn't parse, or `None` when it does
+    pub fn from_expression(expression: &str) -> Option<Self> {
+        let expanded = expand_terminology_variables(expression);
+        let error = parse_expression_pratt(&expanded).err()?;
+        let problem = Problem::of(&error);
+        let expected = problem.expected(&error);
+        let message = error.to_string();
+
+        // Offsets into the expanded text don't fit the expression the client sent
+        if expanded != expression {
+            return Some(Self {
+                message,
+                position: None,
+                end: None,
+                expected,
+                suggestion: None,
+            });
+        }
+
+        let tokens = located_tokens(expression).unwrap_or_default();
+        let span = locate_parse_error(expression, &tokens, &problem);
+        let suggestion = suggest(expression, &tokens, &problem, span);
+        Some(Self {
+            message,
+            position: span.map(|(start, _)| start),
+            end: span.map(|(_, end)| end),
+            expected,
+            suggestion,
+        })
+    }
+}
+
+/// Byte range of the text the parser stumbled on; empty at the end of an incomplete
+/// expression
+fn locate_parse_error(
+    expression: &str,
+    tokens: &[Located<'_>],
+    problem: &Problem<'_>,
+) -> Option<(usize, usize)> {
+    let end_of_input = (expression.len(), expression.len());
+    let span = |located: &Located<'_>| (located.start, located.end);
+    match problem {
+        Problem::At(position) => {
+            let position = (*position).min(expression.len());
+            let token_end = tokens
+                .iter()
+                .find(|located| located.start == position)
+                .map_or(position, |located| located.end);
+            Some((position, token_end))
+        }
+        // The parser reports the offset just past the opening quote
+        Problem::UnclosedString(position) => {
+            let quote = expression
+                .get(..*position)
+                .and_then(|before| before.rfind(['\'', '`']))
+                .unwrap_or(*position);
+            Some((quote, expression.len()))
+        }
+        Problem::EndOfInput | Problem::AfterDot(None) => Some(end_of_input),
+        Problem::AfterDot(Some(found)) => tokens
+            .windows(2)
+            .find(|pair| pair[0].token == Token::Dot && debug(&pair[1].token) == *found)
+            .map(|pair| span(&pair[1])),
+        // The first such token the expression before it is complete without
+        Problem::Trailing(found) => tokens
+            .iter()
+            .find(|located| {
+                debug(&located.token) == *found
+                    && parse_expression_pratt(&expression[..located.start]).is_ok()
+            })
+            .map(span),
+        Problem::Arguments | Problem::Unknown => juxtaposed(tokens)
+            .map(span)
+            .or_else(|| unclosed(tokens).map(|_| end_of_input)),
+    }
+}
+
+fn debug(token: &Token<'_>) -> String {
+    format!("{token:?}")
+}
+
+/// The first operand directly following another, such as `b` in `where(a b)`
+fn juxtaposed<'t, 'e>(tokens: &'t [Located<'e>]) -> Option<&'t Located<'e>> {
+    tokens
+        .windows(2)
+        .find(|pair| ends_operand(&pair[0].token) && starts_operand(&pair[1].token))
+        .map(|pair| &pair[1])
+}
+
+fn ends_operand(token: &Token<'_>) -> bool {
+    starts_operand(token) || matches!(token, Token::RightParen | Token::RightBracket)
+}
+
+fn starts_operand(token: &Token<'_>) -> bool {
+    function_name(token).is_some()
+        || matches!(
+            token,
+            Token::Integer(_)
+                | Token::Decimal(_)
+                | Token::String(_)
+                | Token::Boolean(_)
+                | Token::Date(_)
+                | Token::DateTime(_)
+                | Token::Time(_)
+                | Token::Quantity { .. }
+        )
+}
+
+/// The innermost bracket left open at the end of the expression
+fn unclosed<'t, 'e>(tokens: &'t [Located<'e>]) -> Option<&'t Located<'e>> {
+    let mut open = Vec::new();
+    for located in tokens {
+        match located.token {
+            Token::LeftParen | Token::LeftBracket | Token::LeftBrace => open.push(located),
+            Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    open.pop()
+}
+
+/// How to fix the expression, when the problem suggests a likely fix
+fn suggest(
+    expression: &str,
+    tokens: &[Located<'_>],
+    problem: &Problem<'_>,
+    span: Option<(usize, usize)>,
+) -> Option<String> {
+    let text = span.map(|(start, end)| &expression[start..end]);
+    let close = |located: &Located<'_>| {
+        let closing = match located.token {
+            Token::LeftBracket => ']',
+            Token::LeftBrace => '}',
+            _ => ')',
+        };
+        let opening = &expression[located.start..located.end];
+        format!(
+            "Close the '{opening}' at position {} with '{closing}'",
+            located.start
+        )
+    };
+
+    match problem {
+        Problem::UnclosedString(_) => Some("Close the string with a matching quote".to_string()),
+        Problem::EndOfInput => match (unclosed(tokens), tokens.last()) {
+            (Some(open), _) => Some(close(open)),
+            (None, Some(last)) if !ends_operand(&last.token) => Some(format!(
+                "Add an operand after '{}'",
+                &expression[last.start..last.end]
+            )),
+            _ => None,
+        },
+        Problem::AfterDot(Some(found)) if *found == "Dot" => {
+            Some("Remove the extra '.'".to_string())
+        }
+        Problem::AfterDot(_) => Some("Follow '.' with a property or function name".to_string()),
+        Problem::Trailing("RightParen") => Some("Remove the unmatched ')'".to_string()),
+        Problem::Trailing(_) => text
+            .map(|text| format!("Remove '{text}' or join it to the expression with an operator")),
+        Problem::Arguments => match (text, unclosed(tokens)) {
+            (Some(text), _) if !text.is_empty() => Some(format!(
+                "Separate arguments with ',' or join '{text}' to the argument with an operator"
+            )),
+            (_, Some(open)) => Some(close(open)),
+            _ => None,
+        },
+        Problem::At(_) | Problem::Unknown => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(expression: &str, error: EvaluationError) -> EvaluationDiagnostic {
+        EvaluationDiagnostic::from_error(expression, &EvaluationFailed(error).into())
+    }
+
+    fn located<'a>(expression: &'a str, diagnostic: &EvaluationDiagnostic) -> &'a str {
+        &expression[diagnostic.start.unwrap()..diagnostic.end.unwrap()]
+    }
+
+    #[test]
+    fn test_type_mismatch_points_at_operator() {
+        let expression = "Patient.name.given.contains('x') + 1";
+        let result = diagnostic(
+            expression,
+            EvaluationError::TypeError {
+                expected: "numeric type".to_string(),
+                actual: "Boolean".to_string(),
+            },
+        );
+        assert_eq!(result.kind, TYPE_MISMATCH);
+        // `contains(...)` is a function call here, not the membership operator
+        assert_eq!(located(expression, &result), "+");
+    }
+
+    #[test]
+    fn test_function_error_points_at_call() {
+        let expression = "Patient.name.where(use = 'official').substring(1, 'x')";
+        let result = diagnostic(
+            expression,
+            EvaluationError::Function("substring() length must be an integer".to_string()),
+        );
+        assert_eq!(result.kind, FUNCTION);
+        assert_eq!(located(expression, &result), "substring(1, 'x')");
+    }
+
+    #[test]
+    fn test_index_and_property_errors() {
+        let expression = "Patient.name[5].family";
+        let result = diagnostic(
+            expression,
+            EvaluationError::IndexOutOfBounds { index: 5, size: 1 },
+        );
+        assert_eq!(located(expression, &result), "[5]");
+
+        let result = diagnostic(
+            expression,
+            EvaluationError::PropertyNotFound {
+                property: "family".to_string(),
+                type_name: "HumanName".to_string(),
+            },
+        );
+        assert_eq!(located(expression, &result), "family");
+    }
+
+    #[test]
+    fn test_unlocatable_errors_have_no_position() {
+        let result = diagnostic(
+            "Patient.name",
+            EvaluationError::RuntimeError {
+                message: "stack overflow".to_string(),
+            },
+        );
+        assert_eq!(result.kind, RUNTIME);
+        assert_eq!((result.start, result.end), (None, None));
+
+        let result = EvaluationDiagnostic::from_error(
+            "Patient.name",
+            &EvaluationTimeout { timeout_ms: 5 }.into(),
+        );
+        assert_eq!(result.kind, TIMEOUT);
+        assert_eq!(result.start, None);
+    }
+
+    #[test]
+    fn test_parse_errors_located_from_parser_report() {
+        let located = |expression: &str| {
+            let result = ParseDiagnostic::from_expression(expression).unwrap();
+            let text = &expression[result.position.unwrap()..result.end.unwrap()];
+            (text.to_string(), result.suggestion.unwrap_or_default())
+        };
+
+        assert_eq!(
+            located("Patient.name 'x'"),
+            (
+                "'x'".to_string(),
+                "Remove ''x'' or join it to the expression with an operator".to_string()
+            )
+        );
+        assert_eq!(
+            located("Patient.name)").1,
+            "Remove the unmatched ')'".to_string()
+        );
+        assert_eq!(located("'abc").0, "'abc");
+        assert_eq!(located("name.where(a b)").0, "b");
+        assert_eq!(located("1 +").1, "Add an operand after '+'");
+        assert!(ParseDiagnostic::from_expression("Patient.name").is_none());
+    }
+}
@@ -0,0 +1,163 @@
+//! Fast path for pure path-navigation expressions
+//!
+//! Expressions such as `Patient.name.family` only navigate properties, yet going through the
+//! engine costs an evaluation permit, a pooled engine and a fresh evaluation context. For
+//! expressions made solely of identifiers and path segments, [`evaluate`] walks the resource
+//! directly using the same property lookup the engine performs, so results (including their
+//! types) are identical. Anything else returns `None` and is left to the engine.
+
+use octofhir_fhirpath::{
+    ExpressionNode, FhirPathEngine, FhirPathValue,
+    model::{Collection, JsonValue},
+    utils,
+};
+use serde_json::Value;
+
+/// Whether `node` consists only of identifiers joined by path navigation
+pub fn is_path_only(node: &ExpressionNode) -> bool {
+    match node {
+        ExpressionNode::Identifier(_) => true,
+        ExpressionNode::Path { base, .. } => is_path_only(base),
+        _ => false,
+    }
+}
+
+/// Evaluate a path-only expression against `resource`, or `None` if it needs the engine
+pub fn evaluate(node: &ExpressionNode, resource: &Value) -> Option<FhirPathValue> {
+    if !is_path_only(node) {
+        return None;
+    }
+
+    let input = FhirPathValue::from(utils::serde_to_sonic(resource).ok()?);
+    Some(FhirPathEngine::ensure_collection_result(navigate(
+        node, input,
+    )))
+}
+
+fn navigate(node: &ExpressionNode, input: FhirPathValue) -> FhirPathValue {
+    match node {
+        ExpressionNode::Identifier(identifier) => property(identifier, &input),
+        ExpressionNode::Path { base, path } => property(path, &navigate(base, input)),
+        _ => unreachable!("navigate is only called on path-only expressions"),
+    }
+}
+
+/// Mirrors the engine's identifier evaluation: a matching `resourceType` selects the input
+/// itself, collections are flattened and anything without properties yields empty
+fn property(identifier: &str, input: &FhirPathValue) -> FhirPathValue {
+    match input {
+        FhirPathValue::JsonValue(json) => {
+            if json
+                .get_property("resourceType")
+                .is_some_and(|resource_type| resource_type.as_str() == Some(identifier))
+            {
+                return input.clone();
+            }
+
+            json.get_property(identifier)
+                .map_or(FhirPathValue::Empty, |value| {
+                    FhirPathValue::from(value.into_inner())
+                })
+        }
+        FhirPathValue::Resource(resource) => {
+            if resource
+                .get_property("resourceType")
+                .is_some_and(|resource_type| {
+                    JsonValue::new(resource_type).as_str() == Some(identifier)
+                })
+            {
+                return input.clone();
+            }
+
+            resource
+                .get_property(identifier)
+                .map_or(FhirPathValue::Empty, FhirPathValue::from)
+        }
+        FhirPathValue::Collection(items) => {
+            let mut results = Vec::new();
+            for item in items.iter() {
+                match property(identifier, item) {
+                    FhirPathValue::Empty => {}
+                    FhirPathValue::Collection(sub_items) => results.extend(sub_items),
+                    other => results.push(other),
+                }
+            }
+            FhirPathValue::Collection(Collection::from(results))
+        }
+        _ => FhirPathValue::Empty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fhirpath_engine::get_shared_engine;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_fast_path_matches_engine() {
+        let engine = get_shared_engine().await.unwrap();
+        let patient = json!({
+            "resourceType": "Patient",
+            "id": "fast-path",
+            "active": true,
+            "birthDate": "1974-12-25",
+            "name": [
+                {"use": "official", "family": "Chalmers", "given": ["Peter", "James"]},
+                {"use": "usual", "given": ["Jim"]}
+            ],
+            "multipleBirthInteger": 2,
+            "contact": [{"name": {"family": "du Marché"}}]
+        });
+
+        for expression in [
+            "Patient",
+            "Patient.id",
+            "Patient.active",
+            "Patient.birthDate",
+            "Patient.name",
+            "Patient.name.family",
+            "Patient.name.given",
+            "name.given",
+            "Patient.multipleBirth",
+            "Patient.contact.name.family",
+            "Patient.telecom",
+            "Patient.name.suffix",
+            "Patient.id.value",
+            "Observation.status",
+        ] {
+            let ast = engine.parse(expression).unwrap();
+            let fast = evaluate(&ast, &patient)
+                .unwrap_or_else(|| panic!("'{expression}' should take the fast path"));
+            let slow = engine.evaluate(expression, patient.clone()).await.unwrap();
+            assert_eq!(
+                fast, slow,
+                "fast path differs from engine for '{expression}'"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_path_is_empty() {
+        let engine = get_shared_engine().await.unwrap();
+        let ast = engine.parse("Patient.name.family").unwrap();
+        let result = evaluate(&ast, &json!({"resourceType": "Patient"})).unwrap();
+
+        assert_eq!(result, FhirPathValue::Collection(Collection::new()));
+    }
+
+    #[test]
+    fn test_complex_expressions_use_engine() {
+        for expression in [
+            "Patient.name.first()",
+            "Patient.name.where(use = 'official')",
+            "Patient.name[0]",
+            "Patient.name.family | Patient.name.given",
+            "%resource.id",
+            "'literal'",
+        ] {
+            let ast = octofhir_fhirpath::parse(expression).unwrap();
+            assert!(!is_path_only(&ast), "'{expression}' is not path-only");
+        }
+    }
+}
@@ -3,18 +3,217 @@
 //! This module provides a factory for creating FHIRPath engine instances with R4 FHIR schema
 //! provider to improve performance and reduce initialization overhead across tool calls.
 
-use anyhow::{Result, anyhow};
+use crate::cache::{
+    CacheProvider, CacheStats, DEFAULT_CACHE_CAPACITY, DEFAULT_RESULT_CACHE_CAPACITY,
+    DEFAULT_RESULT_CACHE_TTL, ResultCache,
+};
+use crate::custom_functions::{CustomFunction, register_custom_functions};
+use crate::terminology::{MemberOfOperation, TerminologyResolver, expand_terminology_variables};
+use crate::trace::TraceOperation;
+use anyhow::{Context, Result, anyhow};
 use octofhir_fhir_model::provider::FhirVersion;
 use octofhir_fhirpath::{
-    FhirPathEngine, FhirPathValue,
-    model::{FhirSchemaModelProvider, ModelProvider},
+    EvaluationContext, ExpressionNode, FhirPathEngine, FhirPathRegistry, FhirPathValue,
+    core::EvaluationError,
+    model::{FhirSchemaModelProvider, ModelProvider, fhirschema_provider::FhirSchemaConfig},
     utils,
 };
-use octofhir_fhirschema::PackageSpec;
+use octofhir_fhirschema::{PackageSpec, package::PackageSpecBuilder};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{OnceCell, OwnedSemaphorePermit, Semaphore};
 use tracing::{debug, info, warn};
 
+/// FHIR versions that can be selected for evaluation
+pub const SUPPORTED_FHIR_VERSIONS: &[&str] = &["R4", "R4B", "R5"];
+
+/// Default number of evaluations admitted at once
+pub const DEFAULT_MAX_CONCURRENT_EVALUATIONS: usize = 64;
+
+/// Default time an evaluation waits for a slot before the server reports itself busy
+pub const DEFAULT_EVALUATION_QUEUE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default largest number of values returned by an evaluation
+pub const DEFAULT_MAX_RESULT_VALUES: usize = 10_000;
+
+/// Error returned when no evaluation slot frees up within the queue timeout
+#[derive(Debug, thiserror::Error)]
+#[error("Server busy: no evaluation slot available within {waited_ms}ms")]
+pub struct ServerBusy {
+    pub waited_ms: u64,
+}
+
+/// Error raised by the engine while evaluating an expression, keeping its kind
+#[derive(Debug, thiserror::Error)]
+#[error("FHIRPath evaluation error: {0}")]
+pub struct EvaluationFailed(pub EvaluationError);
+
+/// Parse a FHIR version name, rejecting versions the engine does not support
+fn parse_fhir_version(version: &str) -> Result<FhirVersion> {
+    match version {
+        "R4" => Ok(FhirVersion::R4),
+        "R4B" => Ok(FhirVersion::R4B),
+        "R5" => Ok(FhirVersion::R5),
+        _ => Err(anyhow!(
+            "Unknown FHIR version '{}'. Supported versions: {}",
+            version,
+            SUPPORTED_FHIR_VERSIONS.join(", ")
+        )),
+    }
+}
+
+/// Registry FHIR packages are downloaded from by default
+pub const DEFAULT_PACKAGE_REGISTRY: &str = "https://fs.get-ig.org/pkgs/";
+
+/// Where the packages listed in `additional_packages` and the FHIR core package come from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageSource {
+    /// Unpacked packages in this directory, one `name#version` directory each as in a FHIR
+    /// package cache
+    LocalDir(PathBuf),
+    /// Download packages from the registry at this URL
+    Registry(String),
+    /// Only packages already in the local package store, the FHIR core package included; a
+    /// missing package fails engine initialization rather than being downloaded
+    Offline,
+}
+
+impl Default for PackageSource {
+    fn default() -> Self {
+        Self::Registry(DEFAULT_PACKAGE_REGISTRY.to_string())
+    }
+}
+
+impl PackageSource {
+    /// Read a source written as `offline`, a registry URL or a local directory
+    pub fn parse(value: &str) -> Self {
+        if value.eq_ignore_ascii_case("offline") {
+            Self::Offline
+        } else if value.starts_with("http://") || value.starts_with("https://") {
+            Self::Registry(value.to_string())
+        } else {
+            Self::LocalDir(PathBuf::from(value))
+        }
+    }
+}
+
+/// Directory packages are downloaded to, one `name-version` directory each
+fn local_package_store() -> PathBuf {
+    std::env::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".fcm")
+        .join("packages")
+}
+
+/// Turn `name@version` package references into specs locating each package in `source`,
+/// with `store` as the local package store of an offline source
+fn resolve_packages(
+    packages: &[String],
+    source: &PackageSource,
+    store: &Path,
+) -> Result<Vec<PackageSpec>> {
+    packages
+        .iter()
+        .map(|package| {
+            let Some((name, version)) = package.split_once('@') else {
+                return Err(anyhow!(
+                    "Invalid package format '{}', expected 'name@version'",
+                    package
+                ));
+            };
+            resolve_package(name, version, source, store)
+        })
+        .collect()
+}
+
+/// Locate package `name` at `version` in `source`
+fn resolve_package(
+    name: &str,
+    version: &str,
+    source: &PackageSource,
+    store: &Path,
+) -> Result<PackageSpec> {
+    let spec = PackageSpecBuilder::new(name).version(version);
+    let spec = match source {
+        PackageSource::Registry(url) => spec.registry_source(Some(url.clone()), None),
+        PackageSource::LocalDir(dir) => {
+            let path = dir.join(format!("{name}#{version}"));
+            check_package_dir(&path, name, version).with_context(|| {
+                format!(
+                    "FHIR package {}@{} not found in {}",
+                    name,
+                    version,
+                    dir.display()
+                )
+            })?;
+            spec.local_source(path, false)
+        }
+        PackageSource::Offline => {
+            let path = store.join(format!("{name}-{version}"));
+            check_package_dir(&path, name, version).with_context(|| {
+                format!(
+                    "FHIR package {}@{} is not in the local package store {} and the package \
+                     source is offline",
+                    name,
+                    version,
+                    store.display()
+                )
+            })?;
+            spec.local_source(path, false)
+        }
+    };
+    Ok(spec.build())
+}
+
+/// Core package of `fhir_version`, located in `source` like the additional packages. A local
+/// directory without it leaves the core package to the default registry; offline, it must be
+/// in the local package store like every other package.
+fn resolve_core_package(
+    fhir_version: FhirVersion,
+    source: &PackageSource,
+    store: &Path,
+) -> Result<PackageSpec> {
+    let (name, version) = match fhir_version {
+        FhirVersion::R4 => ("hl7.fhir.r4.core", "4.0.1"),
+        FhirVersion::R4B => ("hl7.fhir.r4b.core", "4.3.0"),
+        FhirVersion::R5 => ("hl7.fhir.r5.core", "5.0.0"),
+    };
+    match source {
+        PackageSource::LocalDir(dir) if !dir.join(format!("{name}#{version}")).is_dir() => {
+            Ok(PackageSpec::registry(name, version))
+        }
+        _ => resolve_package(name, version, source, store),
+    }
+}
+
+/// Check that `path` holds an unpacked package whose manifest names `name` and `version`
+fn check_package_dir(path: &Path, name: &str, version: &str) -> Result<()> {
+    let manifest_path = path.join("package").join("package.json");
+    let manifest = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let manifest: Value = serde_json::from_str(&manifest)
+        .with_context(|| format!("Invalid package manifest {}", manifest_path.display()))?;
+    if manifest["name"] != name || manifest["version"] != version {
+        return Err(anyhow!(
+            "{} describes {}@{}",
+            manifest_path.display(),
+            manifest["name"].as_str().unwrap_or("?"),
+            manifest["version"].as_str().unwrap_or("?")
+        ));
+    }
+    Ok(())
+}
+
 /// Configuration for FHIRPath engine factory
 #[derive(Debug, Clone)]
 pub struct FhirEngineConfig {
@@ -22,6 +221,28 @@ pub struct FhirEngineConfig {
     pub fhir_version: String,
     /// Additional FHIR packages to install
     pub additional_packages: Vec<String>,
+    /// Where `additional_packages` are obtained from
+    pub package_source: PackageSource,
+    /// Maximum number of parsed expressions kept in the expression cache
+    pub expression_cache_capacity: usize,
+    /// Maximum number of evaluation results kept in the result cache (0 disables it)
+    pub result_cache_capacity: usize,
+    /// How long a cached evaluation result is reused
+    pub result_cache_ttl: Duration,
+    /// Number of pooled engines, which also bounds concurrent evaluations
+    pub engine_pool_size: usize,
+    /// Maximum number of evaluations admitted at once, shared by the engines of every FHIR
+    /// version; further evaluations queue for a slot
+    pub max_concurrent_evaluations: usize,
+    /// How long a queued evaluation waits for a slot before failing with [`ServerBusy`]
+    pub evaluation_queue_timeout: Duration,
+    /// Most values `fhirpath_evaluate` returns; longer results are truncated. Requests may
+    /// ask for fewer but never more.
+    pub max_result_values: usize,
+    /// Functions added to the engine's standard library
+    pub custom_functions: Vec<Arc<dyn CustomFunction>>,
+    /// Value sets `memberOf()` checks codes against; `memberOf()` is unavailable when unset
+    pub terminology: Option<Arc<dyn TerminologyResolver>>,
 }
 
 impl Default for FhirEngineConfig {
@@ -29,14 +250,178 @@ impl Default for FhirEngineConfig {
         Self {
             fhir_version: "R4".to_string(),
             additional_packages: Vec::new(),
+            package_source: PackageSource::default(),
+            expression_cache_capacity: DEFAULT_CACHE_CAPACITY,
+            result_cache_capacity: DEFAULT_RESULT_CACHE_CAPACITY,
+            result_cache_ttl: DEFAULT_RESULT_CACHE_TTL,
+            engine_pool_size: default_engine_pool_size(),
+            max_concurrent_evaluations: DEFAULT_MAX_CONCURRENT_EVALUATIONS,
+            evaluation_queue_timeout: DEFAULT_EVALUATION_QUEUE_TIMEOUT,
+            max_result_values: DEFAULT_MAX_RESULT_VALUES,
+            custom_functions: Vec::new(),
+            terminology: None,
+        }
+    }
+}
+
+impl FhirEngineConfig {
+    /// Add `function` to the engine's standard library
+    pub fn with_custom_function(mut self, function: impl CustomFunction + 'static) -> Self {
+        self.custom_functions.push(Arc::new(function));
+        self
+    }
+
+    /// Resolve the value sets of `memberOf()` with `resolver`
+    pub fn with_terminology(mut self, resolver: impl TerminologyResolver + 'static) -> Self {
+        self.terminology = Some(Arc::new(resolver));
+        self
+    }
+}
+
+/// Default engine pool size: one engine per available CPU, at least two
+fn default_engine_pool_size() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .max(2)
+}
+
+/// Pool of reusable engines sharing one model provider.
+///
+/// Engines are created on first use and handed out round-robin. A semaphore with one permit
+/// per engine bounds the number of evaluations running at once.
+pub struct EnginePool {
+    model_provider: Arc<dyn ModelProvider>,
+    custom_functions: Vec<Arc<dyn CustomFunction>>,
+    terminology: Option<Arc<dyn TerminologyResolver>>,
+    engines: Vec<OnceCell<Arc<FhirPathEngine>>>,
+    next: AtomicUsize,
+    permits: Arc<Semaphore>,
+}
+
+/// An engine checked out of the pool; the slot is released when dropped
+pub struct PooledEngine {
+    engine: Arc<FhirPathEngine>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl std::ops::Deref for PooledEngine {
+    type Target = FhirPathEngine;
+
+    fn deref(&self) -> &FhirPathEngine {
+        &self.engine
+    }
+}
+
+impl EnginePool {
+    /// Create a pool of `size` engines (at least one) using `model_provider`
+    pub fn new(model_provider: Arc<dyn ModelProvider>, size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            model_provider,
+            custom_functions: Vec::new(),
+            terminology: None,
+            engines: (0..size).map(|_| OnceCell::new()).collect(),
+            next: AtomicUsize::new(0),
+            permits: Arc::new(Semaphore::new(size)),
+        }
+    }
+
+    /// Add `functions` to the registry of every engine of the pool
+    pub fn with_custom_functions(mut self, functions: Vec<Arc<dyn CustomFunction>>) -> Self {
+        self.custom_functions = functions;
+        self
+    }
+
+    /// Give every engine of the pool `memberOf()` backed by `terminology`
+    pub fn with_terminology(mut self, terminology: Option<Arc<dyn TerminologyResolver>>) -> Self {
+        self.terminology = terminology;
+        self
+    }
+
+    /// Number of engines in the pool
+    pub fn size(&self) -> usize {
+        self.engines.len()
+    }
+
+    /// Number of engines not currently checked out
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// Create every engine of the pool up front instead of on first use
+    pub async fn initialize_all(&self) -> Result<()> {
+        for engine in &self.engines {
+            engine.get_or_try_init(|| self.create_engine()).await?;
         }
+        Ok(())
+    }
+
+    async fn create_engine(&self) -> Result<Arc<FhirPathEngine>> {
+        build_engine(
+            self.model_provider.clone(),
+            &self.custom_functions,
+            self.terminology.as_ref(),
+        )
+        .await
+        .map(Arc::new)
+    }
+
+    /// Check out an engine, waiting while all engines are in use
+    pub async fn acquire(&self) -> Result<PooledEngine> {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| anyhow!("Engine pool closed: {}", e))?;
+
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.engines.len();
+        let engine = self.engines[index]
+            .get_or_try_init(|| self.create_engine())
+            .await?
+            .clone();
+
+        Ok(PooledEngine {
+            engine,
+            _permit: permit,
+        })
+    }
+}
+
+/// Create an engine with the standard library, `custom_functions` and, given a terminology
+/// resolver, `memberOf()`. `trace()` records into the evaluation's
+/// [`TraceCollector`](crate::trace::TraceCollector) instead of printing to stdout.
+async fn build_engine(
+    model_provider: Arc<dyn ModelProvider>,
+    custom_functions: &[Arc<dyn CustomFunction>],
+    terminology: Option<&Arc<dyn TerminologyResolver>>,
+) -> Result<FhirPathEngine> {
+    let registry = octofhir_fhirpath::create_standard_registry()
+        .await
+        .map_err(|e| anyhow!("Failed to create FhirPathEngine: {}", e))?;
+    registry
+        .register(TraceOperation::new())
+        .await
+        .map_err(|e| anyhow!("Failed to register trace(): {}", e))?;
+    register_custom_functions(&registry, custom_functions).await?;
+    if let Some(terminology) = terminology {
+        registry
+            .register(MemberOfOperation::new(terminology.clone()))
+            .await
+            .map_err(|e| anyhow!("Failed to register memberOf(): {}", e))?;
     }
+    Ok(FhirPathEngine::new(Arc::new(registry), model_provider))
 }
 
 /// Factory for creating FHIRPath engine instances with configurable schema provider
 #[derive(Clone)]
 pub struct FhirPathEngineFactory {
     model_provider: Arc<dyn ModelProvider>,
+    expression_cache: Arc<CacheProvider>,
+    result_cache: Arc<ResultCache>,
+    engine_pool: Arc<EnginePool>,
+    evaluation_permits: Arc<Semaphore>,
     config: FhirEngineConfig,
 }
 
@@ -59,89 +444,235 @@ impl FhirPathEngineFactory {
         );
 
         // Parse FHIR version
-        let fhir_version = match config.fhir_version.as_str() {
-            "R4" => FhirVersion::R4,
-            "R4B" => FhirVersion::R4B,
-            "R5" => FhirVersion::R5,
-            _ => {
-                return Err(anyhow!(
-                    "Unknown FHIR version '{}'. Supported versions: R4, R4B, R5",
-                    config.fhir_version
-                ));
-            }
-        };
+        let fhir_version = parse_fhir_version(&config.fhir_version)?;
 
-        // Parse additional packages
-        let mut package_specs = Vec::new();
-        for package in &config.additional_packages {
-            if let Some((name, version)) = package.split_once('@') {
-                package_specs.push(PackageSpec::registry(name, version));
-            } else {
-                return Err(anyhow!(
-                    "Invalid package format '{}', expected 'name@version'",
-                    package
-                ));
-            }
-        }
+        // Locate every package before the provider tries to install them
+        let store = local_package_store();
+        let package_specs =
+            resolve_packages(&config.additional_packages, &config.package_source, &store)?;
+        let core_package = resolve_core_package(fhir_version, &config.package_source, &store)?;
 
         // Create FhirSchemaModelProvider - ALWAYS use real schema provider
-        let provider = if package_specs.is_empty() {
-            // Use version-specific factory methods
-            match fhir_version {
-                FhirVersion::R4 => FhirSchemaModelProvider::r4().await,
-                FhirVersion::R4B => FhirSchemaModelProvider::r4b().await,
-                FhirVersion::R5 => FhirSchemaModelProvider::r5().await,
-            }
-        } else {
-            // Use with_packages method for additional packages
-            FhirSchemaModelProvider::with_packages(package_specs).await
-        }.map_err(|e| {
+        let provider = FhirSchemaModelProvider::with_config(FhirSchemaConfig {
+            fhir_version,
+            core_package_spec: Some(core_package),
+            additional_packages: package_specs,
+            ..FhirSchemaConfig::default()
+        })
+        .await
+        .map_err(|e| {
             anyhow!("Failed to create FhirSchemaModelProvider: {}. The server requires a valid FHIR schema provider.", e)
         })?;
 
         let model_provider: Arc<dyn ModelProvider> = Arc::new(provider);
 
+        // Pooled engines are created on first use; report clashing names now instead
+        if !config.custom_functions.is_empty() {
+            build_engine(model_provider.clone(), &config.custom_functions, None).await?;
+        }
+
         info!(
             "FHIRPath engine factory initialized successfully with FHIR {} schema provider",
             config.fhir_version
         );
 
         Ok(Self {
+            engine_pool: Arc::new(
+                EnginePool::new(model_provider.clone(), config.engine_pool_size)
+                    .with_custom_functions(config.custom_functions.clone())
+                    .with_terminology(config.terminology.clone()),
+            ),
             model_provider,
+            expression_cache: Arc::new(CacheProvider::with_capacity(
+                config.expression_cache_capacity,
+            )),
+            result_cache: Arc::new(ResultCache::new(
+                config.result_cache_capacity,
+                config.result_cache_ttl,
+            )),
+            evaluation_permits: Arc::new(Semaphore::new(config.max_concurrent_evaluations.max(1))),
             config,
         })
     }
 
     /// Create a new engine instance for evaluation
     pub async fn create_engine(&self) -> Result<FhirPathEngine> {
-        FhirPathEngine::with_model_provider(self.model_provider.clone())
-            .await
-            .map_err(|e| anyhow!("Failed to create FhirPathEngine: {}", e))
+        build_engine(
+            self.model_provider.clone(),
+            &self.config.custom_functions,
+            self.config.terminology.as_ref(),
+        )
+        .await
+    }
+
+    /// Parse a FHIRPath expression into its AST, consulting the expression cache first.
+    /// `%vs-[name]` and `%ext-[name]` are replaced by their canonical URLs.
+    pub fn parse(&self, expression: &str) -> Result<Arc<ExpressionNode>> {
+        if expression.trim().is_empty() {
+            return Err(anyhow!("FHIRPath expression cannot be empty"));
+        }
+
+        self.expression_cache
+            .get_or_parse(&expand_terminology_variables(expression))
     }
 
     /// Evaluate a FHIRPath expression against a FHIR resource
     pub async fn evaluate(&self, expression: &str, resource: Value) -> Result<FhirPathValue> {
         debug!("Evaluating FHIRPath expression: {}", expression);
 
-        if expression.trim().is_empty() {
-            return Err(anyhow!("FHIRPath expression cannot be empty"));
+        let ast = self.parse(expression)?;
+        self.evaluate_ast(&ast, resource).await
+    }
+
+    /// Wait for an evaluation slot, failing with [`ServerBusy`] after the queue timeout
+    pub async fn acquire_evaluation_permit(&self) -> Result<OwnedSemaphorePermit> {
+        let timeout = self.config.evaluation_queue_timeout;
+        match tokio::time::timeout(timeout, self.evaluation_permits.clone().acquire_owned()).await {
+            Ok(permit) => permit.map_err(|e| anyhow!("Evaluation queue closed: {}", e)),
+            Err(_) => {
+                warn!("No evaluation slot available within {:?}", timeout);
+                Err(ServerBusy {
+                    waited_ms: timeout.as_millis() as u64,
+                }
+                .into())
+            }
         }
+    }
 
-        let engine = self.create_engine().await?;
+    /// Number of evaluation slots not currently in use
+    pub fn available_evaluation_permits(&self) -> usize {
+        self.evaluation_permits.available_permits()
+    }
+
+    /// Evaluate a FHIRPath expression with `model_provider` in place of the factory's, e.g.
+    /// a [`ReferenceResolver`](crate::references::ReferenceResolver) wrapping it
+    pub async fn evaluate_with_provider(
+        &self,
+        expression: &str,
+        resource: Value,
+        model_provider: Arc<dyn ModelProvider>,
+    ) -> Result<FhirPathValue> {
+        let ast = self.parse(expression)?;
+        let _permit = self.acquire_evaluation_permit().await?;
+        debug!("Evaluating FHIRPath expression: {}", expression);
+        self.evaluate_ast_in_slot(&ast, resource, Some(model_provider))
+            .await
+    }
+
+    /// Like [`evaluate_with_provider`](Self::evaluate_with_provider), but in a slot the caller
+    /// already holds from [`acquire_evaluation_permit`](Self::acquire_evaluation_permit), so
+    /// the caller decides when the slot is released
+    pub async fn evaluate_with_provider_in_slot(
+        &self,
+        expression: &str,
+        resource: Value,
+        model_provider: Arc<dyn ModelProvider>,
+    ) -> Result<FhirPathValue> {
+        debug!("Evaluating FHIRPath expression: {}", expression);
+
+        let ast = self.parse(expression)?;
+        self.evaluate_ast_in_slot(&ast, resource, Some(model_provider))
+            .await
+    }
+
+    /// Like [`evaluate_with_provider_in_slot`](Self::evaluate_with_provider_in_slot), for an
+    /// expression already parsed with [`parse`](Self::parse)
+    pub async fn evaluate_ast_with_provider_in_slot(
+        &self,
+        ast: &ExpressionNode,
+        resource: Value,
+        model_provider: Arc<dyn ModelProvider>,
+    ) -> Result<FhirPathValue> {
+        self.evaluate_ast_in_slot(ast, resource, Some(model_provider))
+            .await
+    }
+
+    /// Evaluate a parsed FHIRPath expression against a FHIR resource
+    pub async fn evaluate_ast(
+        &self,
+        ast: &ExpressionNode,
+        resource: Value,
+    ) -> Result<FhirPathValue> {
+        let _permit = self.acquire_evaluation_permit().await?;
+        self.evaluate_ast_in_slot(ast, resource, None).await
+    }
+
+    async fn evaluate_ast_in_slot(
+        &self,
+        ast: &ExpressionNode,
+        resource: Value,
+        model_provider: Option<Arc<dyn ModelProvider>>,
+    ) -> Result<FhirPathValue> {
+        let pooled = self.engine_pool.acquire().await?;
+
+        // Functions such as resolve() consult the engine's provider rather than the context's,
+        // so a custom provider needs its own engine; it shares the pooled engine's registry
+        let custom_engine;
+        let engine: &FhirPathEngine = match model_provider {
+            Some(model_provider) => {
+                custom_engine = FhirPathEngine::new(pooled.registry().clone(), model_provider)
+                    .with_config(pooled.config().clone());
+                &custom_engine
+            }
+            None => &pooled,
+        };
 
         // Convert serde_json::Value to sonic_rs::Value using octofhir-fhirpath utils
         let sonic_resource = utils::serde_to_sonic(&resource)
             .map_err(|e| anyhow!("Failed to convert resource to sonic_rs::Value: {}", e))?;
 
+        // Evaluate the cached AST directly so the engine doesn't parse the expression again.
+        // The context root is the supplied resource, which is what the evaluator binds the
+        // %resource, %rootResource and %context environment variables to, including inside
+        // where()/select() where $this moves on. The evaluator refuses to let callers override
+        // these names, so they always take precedence over a user variable called `resource`.
+        let input = FhirPathValue::from(sonic_resource);
+        let context = EvaluationContext::new(
+            input.clone(),
+            engine.registry().clone(),
+            engine.model_provider().clone(),
+        );
+
         engine
-            .evaluate(expression, sonic_resource)
+            .evaluate_ast(ast, input, &context)
             .await
+            .map(FhirPathEngine::ensure_collection_result)
             .map_err(|e| {
                 warn!("FHIRPath evaluation failed: {}", e);
-                anyhow!("FHIRPath evaluation error: {}", e)
+                EvaluationFailed(e).into()
             })
     }
 
+    /// Get the configuration this factory was created with
+    pub fn config(&self) -> &FhirEngineConfig {
+        &self.config
+    }
+
+    /// Get the model provider shared by the engines of this factory
+    pub fn model_provider(&self) -> &Arc<dyn ModelProvider> {
+        &self.model_provider
+    }
+
+    /// Get the parsed expression cache
+    pub fn expression_cache(&self) -> &CacheProvider {
+        &self.expression_cache
+    }
+
+    /// Get the cache of evaluation results, shared by the engines of every FHIR version
+    pub fn result_cache(&self) -> &ResultCache {
+        &self.result_cache
+    }
+
+    /// Get the pool of engines used for evaluation
+    pub fn engine_pool(&self) -> &EnginePool {
+        &self.engine_pool
+    }
+
+    /// Get the registry of operations available to the pooled engines
+    pub async fn registry(&self) -> Result<Arc<FhirPathRegistry>> {
+        Ok(self.engine_pool.acquire().await?.registry().clone())
+    }
+
     /// Parse a FHIRPath expression to check syntax
     pub async fn parse_expression(&self, expression: &str) -> Result<()> {
         debug!("Parsing FHIRPath expression: {}", expression);
@@ -170,22 +701,56 @@ impl FhirPathEngineFactory {
         }
     }
 
+    /// Prime the engine pool, expression cache and model provider so the first requests don't
+    /// pay for initialization: creates every pooled engine, then parses and evaluates each of
+    /// `expressions` against a minimal resource. Failures are logged and skipped; returns the
+    /// number of expressions that evaluated successfully.
+    pub async fn warm_up(&self, expressions: &[String]) -> usize {
+        let start = Instant::now();
+        if let Err(e) = self.engine_pool.initialize_all().await {
+            warn!("Warm-up could not create the engine pool: {}", e);
+        }
+
+        let resource = serde_json::json!({"resourceType": "Patient", "id": "warm-up"});
+        let mut warmed = 0;
+        for expression in expressions {
+            match self.evaluate(expression, resource.clone()).await {
+                Ok(_) => warmed += 1,
+                Err(e) => warn!("Warm-up expression '{}' failed: {}", expression, e),
+            }
+        }
+
+        info!(
+            "Warmed up FHIRPath engine with {}/{} expressions in {:?}",
+            warmed,
+            expressions.len(),
+            start.elapsed()
+        );
+        warmed
+    }
+
     /// Get engine statistics and health information
     pub async fn get_engine_info(&self) -> EngineInfo {
         EngineInfo {
             initialized: true,
             schema_provider: format!("FhirSchemaModelProvider ({})", self.config.fhir_version),
-            version: env!("CARGO_PKG_VERSION").to_string(),
+            version: FHIRPATH_VERSION.to_string(),
+            expression_cache: self.expression_cache.stats(),
         }
     }
 }
 
+/// Version of the octofhir-fhirpath crate this build evaluates expressions with
+pub const FHIRPATH_VERSION: &str = env!("OCTOFHIR_FHIRPATH_VERSION");
+
 /// Information about the FHIRPath engine instance
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct EngineInfo {
     pub initialized: bool,
     pub schema_provider: String,
+    /// Version of the octofhir-fhirpath crate
     pub version: String,
+    pub expression_cache: CacheStats,
 }
 
 /// Global shared instance of the FHIRPath engine factory
@@ -201,6 +766,51 @@ pub async fn get_shared_engine() -> Result<&'static FhirPathEngineFactory> {
         .await
 }
 
+/// Engines for FHIR versions other than the shared engine's, created on first use
+static VERSIONED_FACTORIES: LazyLock<
+    tokio::sync::Mutex<HashMap<String, &'static FhirPathEngineFactory>>,
+> = LazyLock::new(|| tokio::sync::Mutex::new(HashMap::new()));
+
+/// Get the engine factory for a FHIR version, falling back to the shared engine when
+/// no version is requested or the shared engine already uses the requested version
+pub async fn get_engine_for_version(
+    fhir_version: Option<&str>,
+) -> Result<&'static FhirPathEngineFactory> {
+    let shared = get_shared_engine().await?;
+    let Some(version) = fhir_version else {
+        return Ok(shared);
+    };
+
+    parse_fhir_version(version)?;
+    if shared.config().fhir_version == version {
+        return Ok(shared);
+    }
+
+    // Hold the lock across initialization so concurrent requests share one engine
+    let mut factories = VERSIONED_FACTORIES.lock().await;
+    if let Some(factory) = factories.get(version) {
+        return Ok(factory);
+    }
+
+    let config = FhirEngineConfig {
+        fhir_version: version.to_string(),
+        ..shared.config().clone()
+    };
+    let mut factory = FhirPathEngineFactory::with_config_async(config).await?;
+    // Evaluations for every FHIR version count against the same limit
+    factory.evaluation_permits = shared.evaluation_permits.clone();
+    factory.result_cache = shared.result_cache.clone();
+    let factory: &'static FhirPathEngineFactory = Box::leak(Box::new(factory));
+    factories.insert(version.to_string(), factory);
+
+    Ok(factory)
+}
+
+/// Get the shared engine factory if it has been initialized, without initializing it
+pub fn shared_engine_if_initialized() -> Option<&'static FhirPathEngineFactory> {
+    SHARED_FACTORY.get()
+}
+
 /// Initialize the shared FHIRPath engine factory with configuration
 pub async fn initialize_shared_engine_with_config(config: FhirEngineConfig) -> Result<()> {
     info!(
@@ -228,12 +838,141 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[tokio::test]
+    async fn test_offline_package_source_requires_local_packages() {
+        let started = Instant::now();
+        let err = FhirPathEngineFactory::with_config(FhirEngineConfig {
+            additional_packages: vec!["example.fhir.missing@9.9.9".to_string()],
+            package_source: PackageSource::Offline,
+            ..FhirEngineConfig::default()
+        })
+        .await
+        .err()
+        .expect("a missing package can't be used offline");
+        assert!(format!("{err:#}").contains("example.fhir.missing@9.9.9"));
+        assert!(format!("{err:#}").contains("offline"));
+        // Fails before the schema provider starts up
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_local_dir_package_source() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/packages");
+        let source = PackageSource::LocalDir(fixtures.clone());
+
+        let store = Path::new("/nonexistent");
+        let specs =
+            resolve_packages(&["example.fhir.test@1.0.0".to_string()], &source, store).unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(
+            (specs[0].name.as_str(), specs[0].version.as_str()),
+            ("example.fhir.test", "1.0.0")
+        );
+        assert_eq!(
+            specs[0].source,
+            octofhir_fhirschema::package::PackageSource::Local {
+                path: fixtures.join("example.fhir.test#1.0.0"),
+                watch: false,
+            }
+        );
+
+        let err =
+            resolve_packages(&["example.fhir.test@2.0.0".to_string()], &source, store).unwrap_err();
+        assert!(err.to_string().contains("not found in"));
+
+        // Without a core package in the directory, it comes from the default registry
+        let core = resolve_core_package(FhirVersion::R5, &source, store).unwrap();
+        assert_eq!(core, PackageSpec::registry("hl7.fhir.r5.core", "5.0.0"));
+    }
+
+    #[test]
+    fn test_offline_core_package_comes_from_local_store() {
+        let store = std::env::temp_dir().join(format!("octofhir-store-{}", uuid::Uuid::new_v4()));
+        let err =
+            resolve_core_package(FhirVersion::R4, &PackageSource::Offline, &store).unwrap_err();
+        assert!(format!("{err:#}").contains("hl7.fhir.r4.core@4.0.1"));
+        assert!(format!("{err:#}").contains("offline"));
+
+        let package_dir = store.join("hl7.fhir.r4.core-4.0.1").join("package");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join("package.json"),
+            r#"{"name": "hl7.fhir.r4.core", "version": "4.0.1"}"#,
+        )
+        .unwrap();
+        let core = resolve_core_package(FhirVersion::R4, &PackageSource::Offline, &store).unwrap();
+        assert_eq!(
+            core.source,
+            octofhir_fhirschema::package::PackageSource::Local {
+                path: store.join("hl7.fhir.r4.core-4.0.1"),
+                watch: false,
+            }
+        );
+        std::fs::remove_dir_all(store).unwrap();
+    }
+
+    #[test]
+    fn test_parse_package_source() {
+        assert_eq!(PackageSource::parse("offline"), PackageSource::Offline);
+        assert_eq!(
+            PackageSource::parse("https://packages.fhir.org"),
+            PackageSource::Registry("https://packages.fhir.org".to_string())
+        );
+        assert_eq!(
+            PackageSource::parse("/var/fhir/packages"),
+            PackageSource::LocalDir(PathBuf::from("/var/fhir/packages"))
+        );
+    }
+
     #[tokio::test]
     async fn test_factory_creation() {
         let factory = FhirPathEngineFactory::new().await.unwrap();
         let info = factory.get_engine_info().await;
         assert!(info.initialized);
         assert!(!info.schema_provider.is_empty());
+        // The engine crate's version from Cargo.lock, not this crate's
+        assert_ne!(info.version, "unknown");
+        assert_ne!(info.version, crate::VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_reduces_first_evaluation_latency() {
+        let expressions = vec![
+            "Patient.name.given".to_string(),
+            "Observation.value".to_string(),
+            "Patient.name.where(".to_string(),
+        ];
+        let resource = json!({
+            "resourceType": "Patient",
+            "id": "first",
+            "name": [{"given": ["John"], "family": "Doe"}]
+        });
+
+        let first_evaluation = |factory: FhirPathEngineFactory| {
+            let resource = resource.clone();
+            async move {
+                let start = Instant::now();
+                factory
+                    .evaluate("Patient.name.given", resource)
+                    .await
+                    .unwrap();
+                start.elapsed()
+            }
+        };
+
+        let cold = FhirPathEngineFactory::new().await.unwrap();
+        let baseline = first_evaluation(cold).await;
+
+        let warm = FhirPathEngineFactory::new().await.unwrap();
+        // The malformed expression is skipped without failing the warm-up
+        assert_eq!(warm.warm_up(&expressions).await, 2);
+        assert_eq!(warm.engine_pool().available(), warm.engine_pool().size());
+        let warmed = first_evaluation(warm).await;
+
+        assert!(
+            warmed < baseline,
+            "warmed evaluation took {warmed:?}, unwarmed {baseline:?}"
+        );
     }
 
     #[tokio::test]
@@ -256,6 +995,125 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_custom_functions() {
+        let double = crate::FnFunction::new("double", 1, |_input, args| match &args[0] {
+            FhirPathValue::Integer(value) => Ok(FhirPathValue::Integer(value * 2)),
+            other => Err(anyhow!(
+                "double() needs an integer, got {}",
+                other.type_name()
+            )),
+        });
+        let factory = FhirPathEngineFactory::with_config(
+            FhirEngineConfig::default().with_custom_function(double),
+        )
+        .await
+        .unwrap();
+
+        let resource = json!({"resourceType": "Patient", "id": "p1"});
+        let result = factory
+            .evaluate("double(21)", resource.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            FhirPathValue::collection(vec![FhirPathValue::Integer(42)])
+        );
+        let err = factory.evaluate("double('a')", resource).await.unwrap_err();
+        assert!(format!("{err:#}").contains("double() needs an integer"));
+
+        // Built-in functions can't be replaced
+        let count = crate::FnFunction::new("count", 0, |_input, _args| Ok(FhirPathValue::Empty));
+        let err = FhirPathEngineFactory::with_config(
+            FhirEngineConfig::default().with_custom_function(count),
+        )
+        .await
+        .err()
+        .expect("count() is built in");
+        assert!(err.to_string().contains("built-in function"));
+    }
+
+    #[tokio::test]
+    async fn test_member_of_in_memory_value_set() {
+        let gender = crate::ValueSet::new([
+            ("http://hl7.org/fhir/administrative-gender", "male"),
+            ("http://hl7.org/fhir/administrative-gender", "female"),
+        ]);
+        let factory = FhirPathEngineFactory::with_config(
+            FhirEngineConfig::default().with_terminology(
+                crate::InMemoryTerminology::new()
+                    .with_value_set("http://hl7.org/fhir/ValueSet/administrative-gender", gender),
+            ),
+        )
+        .await
+        .unwrap();
+
+        let resource = json!({
+            "resourceType": "Observation",
+            "code": {"coding": [{"system": "http://hl7.org/fhir/administrative-gender", "code": "male"}]},
+            "valueCoding": {"system": "http://example.org/other", "code": "female"},
+            "status": "unknown"
+        });
+        let member_of = |expression: &'static str| {
+            let factory = factory.clone();
+            let resource = resource.clone();
+            async move { factory.evaluate(expression, resource).await }
+        };
+
+        for (expression, expected) in [
+            (
+                "code.memberOf('http://hl7.org/fhir/ValueSet/administrative-gender')",
+                true,
+            ),
+            ("code.memberOf(%vs-administrative-gender)", true),
+            ("value.memberOf(%vs-administrative-gender)", false),
+            ("status.memberOf(%vs-administrative-gender)", false),
+        ] {
+            assert_eq!(
+                member_of(expression).await.unwrap(),
+                FhirPathValue::collection(vec![FhirPathValue::Boolean(expected)]),
+                "{expression}"
+            );
+        }
+
+        let err = member_of("code.memberOf('http://example.org/ValueSet/unknown')")
+            .await
+            .unwrap_err();
+        assert!(err.is::<EvaluationFailed>());
+        assert!(format!("{err:#}").contains("could not be resolved"));
+    }
+
+    #[tokio::test]
+    async fn test_environment_variables_bind_to_resource() {
+        let factory = FhirPathEngineFactory::new().await.unwrap();
+        let resource = json!({
+            "resourceType": "Patient",
+            "id": "p1",
+            "name": [{"use": "official", "family": "Doe"}, {"use": "nickname", "family": "D"}]
+        });
+
+        for expression in ["%resource.id", "%context.id", "%rootResource.id"] {
+            let result = factory
+                .evaluate(expression, resource.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                serde_json::to_value(&result).unwrap(),
+                json!(["p1"]),
+                "{expression}"
+            );
+        }
+
+        let result = factory
+            .evaluate(
+                "name.where(%resource.id = 'p1' and use = 'official').family",
+                resource,
+            )
+            .await
+            .unwrap();
+        assert_eq!(serde_json::to_value(&result).unwrap(), json!(["Doe"]));
+    }
+
     #[tokio::test]
     async fn test_empty_expression_error() {
         let factory = FhirPathEngineFactory::new().await.unwrap();
@@ -291,6 +1149,173 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_evaluate_uses_expression_cache() {
+        let factory = FhirPathEngineFactory::new().await.unwrap();
+        let resource = json!({"resourceType": "Patient", "id": "cached"});
+
+        factory
+            .evaluate("Patient.id", resource.clone())
+            .await
+            .unwrap();
+        factory.evaluate("Patient.id", resource).await.unwrap();
+
+        let stats = factory.expression_cache().stats();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_engine_for_version() {
+        let r4 = get_engine_for_version(Some("R4")).await.unwrap();
+        let r5 = get_engine_for_version(Some("R5")).await.unwrap();
+
+        assert_eq!(r4.config().fhir_version, "R4");
+        assert_eq!(r5.config().fhir_version, "R5");
+        assert!(std::ptr::eq(
+            r5,
+            get_engine_for_version(Some("R5")).await.unwrap()
+        ));
+
+        // MedicationRequest.medication is a choice type in R4 but a CodeableReference in R5
+        let r4_resource = json!({
+            "resourceType": "MedicationRequest",
+            "medicationCodeableConcept": {"text": "aspirin"}
+        });
+        let r5_resource = json!({
+            "resourceType": "MedicationRequest",
+            "medication": {"concept": {"text": "aspirin"}}
+        });
+
+        let r4_result = r4
+            .evaluate("MedicationRequest.medication.text", r4_resource)
+            .await
+            .unwrap();
+        let r5_result = r5
+            .evaluate("MedicationRequest.medication.concept.text", r5_resource)
+            .await
+            .unwrap();
+        assert_eq!(format!("{r4_result:?}"), format!("{r5_result:?}"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_fhir_version() {
+        let Err(err) = get_engine_for_version(Some("R3")).await else {
+            panic!("R3 should be rejected");
+        };
+        assert!(err.to_string().contains("Supported versions: R4, R4B, R5"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_evaluations_use_pool() {
+        const CALLS: u32 = 32;
+
+        let factory = Arc::new(
+            FhirPathEngineFactory::with_config(FhirEngineConfig {
+                engine_pool_size: 4,
+                ..FhirEngineConfig::default()
+            })
+            .await
+            .unwrap(),
+        );
+        let resource = json!({"resourceType": "Patient", "name": [{"given": ["Ann"]}]});
+        let pool = factory.engine_pool();
+        let timeout = std::time::Duration::from_secs(30);
+
+        // Every engine can be checked out at once, so evaluations run side by side
+        let mut checked_out = Vec::new();
+        for _ in 0..4 {
+            let engine = tokio::time::timeout(timeout, pool.acquire())
+                .await
+                .expect("a free engine should be handed out")
+                .unwrap();
+            checked_out.push(engine);
+        }
+        assert_eq!(pool.available(), 0);
+        for (i, engine) in checked_out.iter().enumerate() {
+            for other in &checked_out[i + 1..] {
+                assert!(!std::ptr::eq::<FhirPathEngine>(&**engine, &**other));
+            }
+        }
+
+        // Another checkout waits until an engine is returned
+        let short = std::time::Duration::from_millis(50);
+        assert!(tokio::time::timeout(short, pool.acquire()).await.is_err());
+        checked_out.pop();
+        let engine = tokio::time::timeout(timeout, pool.acquire())
+            .await
+            .expect("the returned engine should be handed out")
+            .unwrap();
+        drop(engine);
+        drop(checked_out);
+        assert_eq!(pool.available(), 4);
+
+        let tasks: Vec<_> = (0..CALLS)
+            .map(|_| {
+                let factory = factory.clone();
+                let resource = resource.clone();
+                tokio::spawn(async move { factory.evaluate("Patient.name.given", resource).await })
+            })
+            .collect();
+        for task in tasks {
+            let result = tokio::time::timeout(timeout, task)
+                .await
+                .expect("evaluations should not deadlock")
+                .unwrap();
+            assert!(result.is_ok());
+        }
+        assert_eq!(pool.available(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_evaluations_queue_for_permits() {
+        let factory = Arc::new(
+            FhirPathEngineFactory::with_config(FhirEngineConfig {
+                max_concurrent_evaluations: 1,
+                ..FhirEngineConfig::default()
+            })
+            .await
+            .unwrap(),
+        );
+        let resource = json!({"resourceType": "Patient", "name": [{"given": ["Ann"]}]});
+
+        // Hold the only slot so the evaluations below have to queue
+        let permit = factory.acquire_evaluation_permit().await.unwrap();
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let factory = factory.clone();
+                let resource = resource.clone();
+                tokio::spawn(async move { factory.evaluate("Patient.name.given", resource).await })
+            })
+            .collect();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(tasks.iter().all(|task| !task.is_finished()));
+
+        drop(permit);
+        for task in tasks {
+            assert!(task.await.unwrap().is_ok());
+        }
+        assert_eq!(factory.available_evaluation_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_server_busy_after_queue_timeout() {
+        let factory = FhirPathEngineFactory::with_config(FhirEngineConfig {
+            max_concurrent_evaluations: 1,
+            evaluation_queue_timeout: Duration::from_millis(20),
+            ..FhirEngineConfig::default()
+        })
+        .await
+        .unwrap();
+
+        let _permit = factory.acquire_evaluation_permit().await.unwrap();
+        let err = factory
+            .evaluate("Patient.id", json!({"resourceType": "Patient"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ServerBusy>().unwrap().waited_ms, 20);
+    }
+
     #[tokio::test]
     async fn test_engine_creation() {
         let factory = FhirPathEngineFactory::new().await.unwrap();
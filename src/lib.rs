@@ -5,22 +5,34 @@
 
 pub mod cache;
 pub mod config;
+pub mod custom_functions;
+pub mod diagnostics;
+pub mod fast_path;
 pub mod fhirpath_engine;
+pub mod lint;
+pub mod logging;
 pub mod metrics;
 pub mod prompts;
+pub mod references;
+pub mod remote;
 pub mod resources;
 pub mod security;
 pub mod server;
+pub mod terminology;
 pub mod tools;
+pub mod trace;
 pub mod transport;
+pub mod validation;
 
 // Re-export main types
 pub use config::ServerConfig;
+pub use custom_functions::{CustomFunction, FnFunction};
 pub use fhirpath_engine::{
-    FhirEngineConfig, FhirPathEngineFactory, get_shared_engine, initialize_shared_engine,
-    initialize_shared_engine_with_config,
+    FhirEngineConfig, FhirPathEngineFactory, PackageSource, get_shared_engine,
+    initialize_shared_engine, initialize_shared_engine_with_config,
 };
 pub use server::{FhirPathToolRouter, demonstrate_tools, start_sdk_server};
+pub use terminology::{InMemoryTerminology, RemoteTerminology, TerminologyResolver, ValueSet};
 pub use transport::TransportFactory;
 
 /// Current version of the MCP server
@@ -0,0 +1,328 @@
+//! Correctness and style lints for FHIRPath expressions
+//!
+//! Lints work on the token stream rather than the AST so each one can point at the exact
+//! source text it concerns. They flag expressions that are valid but likely don't do what
+//! the author meant; an expression that fails to tokenize has no lints.
+
+use octofhir_fhirpath::parser::{Token, tokenizer::tokenize};
+use serde::{Deserialize, Serialize};
+
+/// `where(...)` whose condition is always true
+pub const REDUNDANT_FILTER: &str = "redundant-filter";
+/// `first()` applied to the result of `first()`
+pub const REDUNDANT_FIRST: &str = "redundant-first";
+/// `=` or `!=` against a string whose case or surrounding whitespace likely shouldn't matter
+pub const STRING_EQUALITY: &str = "string-equality";
+/// Comparison of a collection-producing call with a single literal value
+pub const COLLECTION_COMPARISON: &str = "collection-comparison";
+
+/// Functions whose result is usually a collection of several items
+const COLLECTION_FUNCTIONS: &[&str] = &[
+    "where",
+    "select",
+    "repeat",
+    "children",
+    "descendants",
+    "distinct",
+    "tail",
+    "skip",
+    "take",
+    "ofType",
+    "union",
+    "combine",
+];
+
+/// A likely mistake in an expression
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lint {
+    /// Identifier of the rule that produced the lint, e.g. `redundant-filter`
+    pub rule: String,
+    /// What is wrong and how to fix it
+    pub message: String,
+    /// Byte offset where the offending text starts
+    pub start: usize,
+    /// Byte offset just past the offending text
+    pub end: usize,
+}
+
+impl Lint {
+    fn new(rule: &str, message: impl Into<String>, start: usize, end: usize) -> Self {
+        Self {
+            rule: rule.to_string(),
+            message: message.into(),
+            start,
+            end,
+        }
+    }
+}
+
+/// A token with its exact byte range in the expression
+pub(crate) struct Located<'a> {
+    pub(crate) token: Token<'a>,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+/// Lint `expression`, returning lints in source order
+pub fn lint(expression: &str) -> Vec<Lint> {
+    let Some(tokens) = located_tokens(expression) else {
+        return Vec::new();
+    };
+
+    let mut lints = Vec::new();
+    for (i, current) in tokens.iter().enumerate() {
+        match &current.token {
+            Token::Where
+                if is_call(&tokens, i, &[Token::True])
+                    || is_call(&tokens, i, &[Token::Boolean(true)]) =>
+            {
+                lints.push(Lint::new(
+                    REDUNDANT_FILTER,
+                    "where(true) keeps every item; remove it",
+                    current.start,
+                    tokens[i + 3].end,
+                ));
+            }
+            Token::First
+                if is_call(&tokens, i, &[])
+                    && i >= 4
+                    && tokens[i - 1].token == Token::Dot
+                    && tokens[i - 2].token == Token::RightParen
+                    && tokens[i - 3].token == Token::LeftParen
+                    && tokens[i - 4].token == Token::First =>
+            {
+                lints.push(Lint::new(
+                    REDUNDANT_FIRST,
+                    "first() already returns at most one item; the second first() is redundant",
+                    tokens[i - 4].start,
+                    tokens[i + 2].end,
+                ));
+            }
+            Token::Equal | Token::NotEqual => {
+                lints.extend(string_equality(&tokens, i));
+                lints.extend(collection_comparison(&tokens, i));
+            }
+            Token::LessThan
+            | Token::LessThanOrEqual
+            | Token::GreaterThan
+            | Token::GreaterThanOrEqual => {
+                lints.extend(collection_comparison(&tokens, i));
+            }
+            _ => {}
+        }
+    }
+
+    lints.sort_by_key(|lint| lint.start);
+    lints
+}
+
+/// Tokenize `expression`, deriving each token's start from the end of the previous one since
+/// the tokenizer only reports end offsets exactly
+pub(crate) fn located_tokens(expression: &str) -> Option<Vec<Located<'_>>> {
+    let mut previous_end = 0;
+    let tokens = tokenize(expression).ok()?;
+
+    Some(
+        tokens
+            .into_iter()
+            .map(|spanned| {
+                let gap = expression
+                    .get(previous_end..spanned.end)
+                    .unwrap_or_default();
+                let start = previous_end + (gap.len() - gap.trim_start().len());
+                previous_end = spanned.end;
+                Located {
+                    token: spanned.value,
+                    start,
+                    end: spanned.end,
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Whether the function name at `i` is followed by `(`, exactly `args`, then `)`
+fn is_call(tokens: &[Located<'_>], i: usize, args: &[Token<'_>]) -> bool {
+    let Some(rest) = tokens.get(i + 1..i + 3 + args.len()) else {
+        return false;
+    };
+    rest[0].token == Token::LeftParen
+        && rest[1..=args.len()]
+            .iter()
+            .zip(args)
+            .all(|(located, arg)| located.token == *arg)
+        && rest[args.len() + 1].token == Token::RightParen
+}
+
+fn is_literal(token: &Token<'_>) -> bool {
+    matches!(
+        token,
+        Token::Integer(_)
+            | Token::Decimal(_)
+            | Token::String(_)
+            | Token::Boolean(_)
+            | Token::True
+            | Token::False
+            | Token::Date(_)
+            | Token::DateTime(_)
+            | Token::Time(_)
+            | Token::Quantity { .. }
+    )
+}
+
+/// Name of a function token, including those the tokenizer treats as keywords
+pub(crate) fn function_name<'a>(token: &Token<'a>) -> Option<&'a str> {
+    Some(match token {
+        Token::Identifier(name) => name,
+        Token::Where => "where",
+        Token::Select => "select",
+        Token::Distinct => "distinct",
+        Token::Tail => "tail",
+        Token::Skip => "skip",
+        Token::Take => "take",
+        Token::OfType => "ofType",
+        Token::First => "first",
+        Token::Last => "last",
+        Token::All => "all",
+        Token::Empty => "empty",
+        _ => return None,
+    })
+}
+
+/// Flag `=`/`!=` at `op` when either side is a string literal with uppercase letters or
+/// surrounding whitespace, where equivalence (`~`) is usually what's wanted
+fn string_equality(tokens: &[Located<'_>], op: usize) -> Option<Lint> {
+    let neighbours = [op.checked_sub(1), Some(op + 1)];
+    let literal = neighbours
+        .into_iter()
+        .flatten()
+        .filter_map(|i| tokens.get(i))
+        .find(|located| match located.token {
+            Token::String(value) => value.chars().any(char::is_uppercase) || value.trim() != value,
+            _ => false,
+        })?;
+
+    let (operator, suggestion) = match tokens[op].token {
+        Token::Equal => ("=", "~"),
+        _ => ("!=", "!~"),
+    };
+    Some(Lint::new(
+        STRING_EQUALITY,
+        format!(
+            "'{operator}' compares strings exactly, including case and whitespace; \
+             use '{suggestion}' for equivalence"
+        ),
+        tokens[op].start.min(literal.start),
+        tokens[op].end.max(literal.end),
+    ))
+}
+
+/// Flag a comparison at `op` between a call producing a collection and a literal, which is
+/// empty rather than true or false when the collection has more than one item
+fn collection_comparison(tokens: &[Located<'_>], op: usize) -> Option<Lint> {
+    let literal = tokens.get(op + 1).filter(|next| is_literal(&next.token))?;
+    let close = op.checked_sub(1)?;
+    if tokens[close].token != Token::RightParen {
+        return None;
+    }
+
+    // Walk back to the parenthesis opening the call's arguments
+    let mut depth = 0usize;
+    let mut open = close;
+    loop {
+        match tokens[open].token {
+            Token::RightParen => depth += 1,
+            Token::LeftParen => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        open = open.checked_sub(1)?;
+    }
+
+    let name_token = &tokens[open.checked_sub(1)?];
+    let name = function_name(&name_token.token)?;
+    if !COLLECTION_FUNCTIONS.contains(&name) {
+        return None;
+    }
+
+    Some(Lint::new(
+        COLLECTION_COMPARISON,
+        format!(
+            "{name}() can return several items, and comparing them to a single value yields \
+             empty; use exists(...), first() or all(...) instead"
+        ),
+        name_token.start,
+        literal.end,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(expression: &str) -> Vec<String> {
+        lint(expression).into_iter().map(|lint| lint.rule).collect()
+    }
+
+    #[test]
+    fn test_redundant_filter() {
+        let expression = "Patient.name.where(true).given";
+        let lints = lint(expression);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, REDUNDANT_FILTER);
+        assert_eq!(&expression[lints[0].start..lints[0].end], "where(true)");
+
+        assert!(rules("Patient.name.where(use = 'official')").is_empty());
+    }
+
+    #[test]
+    fn test_redundant_first() {
+        let expression = "Patient.name.first().first().given";
+        let lints = lint(expression);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, REDUNDANT_FIRST);
+        assert_eq!(&expression[lints[0].start..lints[0].end], "first().first()");
+    }
+
+    #[test]
+    fn test_collection_comparison() {
+        let expression = "Patient.name.select(given) = 'john'";
+        let lints = lint(expression);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, COLLECTION_COMPARISON);
+        assert_eq!(
+            &expression[lints[0].start..lints[0].end],
+            "select(given) = 'john'"
+        );
+
+        assert_eq!(
+            rules("Observation.component.where(code.exists()).count() > 2"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            rules("Patient.name.where(given.exists()) != 1"),
+            vec![COLLECTION_COMPARISON]
+        );
+    }
+
+    #[test]
+    fn test_string_equality() {
+        let expression = "Patient.name.family = 'Smith'";
+        let lints = lint(expression);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, STRING_EQUALITY);
+        assert_eq!(&expression[lints[0].start..lints[0].end], "= 'Smith'");
+
+        assert!(rules("Patient.telecom.system = 'phone'").is_empty());
+        assert!(rules("Patient.name.family ~ 'Smith'").is_empty());
+    }
+
+    #[test]
+    fn test_unparseable_expression_has_no_lints() {
+        assert!(lint("Patient.name = 'unterminated").is_empty());
+    }
+}
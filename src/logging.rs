@@ -0,0 +1,192 @@
+//! Logging setup
+//!
+//! Logs go to stderr, since stdout carries the stdio transport's protocol messages. The
+//! level, per-module directives and line format come from [`ServerConfig`].
+
+use crate::config::{LogFormat, ServerConfig};
+use anyhow::{Result, anyhow};
+use tracing::Subscriber;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Filter admitting events at `log_level` and above, refined per module by `log_filter`,
+/// or by `RUST_LOG` when `log_filter` is unset.
+///
+/// A bare level in the directives (`RUST_LOG=debug`) replaces `log_level`. Invalid
+/// `log_filter` directives are a configuration error; invalid `RUST_LOG` directives are
+/// skipped.
+pub fn env_filter(config: &ServerConfig) -> Result<EnvFilter> {
+    let env_directives = std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default();
+    filter_from(config, &env_directives)
+}
+
+fn filter_from(config: &ServerConfig, env_directives: &str) -> Result<EnvFilter> {
+    let level: LevelFilter = config
+        .log_level
+        .parse()
+        .map_err(|_| anyhow!("Invalid log_level '{}'", config.log_level))?;
+    let builder = EnvFilter::builder().with_default_directive(level.into());
+
+    let (filter, directives) = match &config.log_filter {
+        Some(filter) => (
+            builder
+                .parse(filter)
+                .map_err(|e| anyhow!("Invalid log filter '{}': {}", filter, e))?,
+            filter.as_str(),
+        ),
+        None => (builder.parse_lossy(env_directives), env_directives),
+    };
+    // The default directive only applies when there are no valid directives at all, but
+    // modules the directives don't name still log at `log_level` unless a bare level is set
+    let sets_level = directives
+        .split(',')
+        .any(|directive| directive.trim().parse::<LevelFilter>().is_ok());
+    Ok(if sets_level {
+        filter
+    } else {
+        filter.add_directive(level.into())
+    })
+}
+
+/// Layer writing events to `writer` in the configured `log_format`
+pub fn fmt_layer<S, W>(config: &ServerConfig, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match config.log_format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Install the global subscriber logging to stderr as configured
+pub fn init_logging(config: &ServerConfig) -> Result<()> {
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    let registry = tracing_subscriber::registry()
+        .with(env_filter(config)?)
+        .with(fmt_layer(config, std::io::stderr));
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(crate::metrics::telemetry::otlp_layer()?);
+
+    registry
+        .try_init()
+        .map_err(|e| anyhow!("Failed to initialize logging: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct CapturingWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'writer> MakeWriter<'writer> for CapturingWriter {
+        type Writer = Self;
+
+        fn make_writer(&'writer self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_log_lines() {
+        let config = ServerConfig {
+            log_format: LogFormat::Json,
+            log_filter: Some("octofhir_mcp::logging=debug,other=error".to_string()),
+            ..ServerConfig::default()
+        };
+        let writer = CapturingWriter::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(env_filter(&config).unwrap())
+            .with(fmt_layer(&config, writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!(expression = "Patient.id", "Evaluated");
+            tracing::debug!(target: "other", "Filtered out");
+            tracing::info!(target: "unlisted", "Logged at the default level");
+            tracing::debug!(target: "unlisted", "Below the default level");
+        });
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "DEBUG");
+        assert_eq!(lines[0]["target"], "octofhir_mcp::logging::tests");
+        assert_eq!(lines[0]["fields"]["expression"], "Patient.id");
+        assert_eq!(lines[1]["level"], "INFO");
+        assert_eq!(lines[1]["target"], "unlisted");
+    }
+
+    /// Targets among `targets` whose debug events `filter` admits
+    fn enabled(filter: EnvFilter, targets: &[&str]) -> Vec<String> {
+        let writer = CapturingWriter::default();
+        let config = ServerConfig {
+            log_format: LogFormat::Json,
+            ..ServerConfig::default()
+        };
+        let subscriber = tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt_layer(&config, writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            for target in targets {
+                match *target {
+                    "app" => tracing::debug!(target: "app", "debug"),
+                    "other" => tracing::debug!(target: "other", "debug"),
+                    _ => unreachable!(),
+                }
+            }
+        });
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        output
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["target"].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_rust_log_directives() {
+        let config = ServerConfig::default();
+        let targets = ["app", "other"];
+
+        // A bare level replaces the configured one
+        let filter = filter_from(&config, "debug").unwrap();
+        assert_eq!(enabled(filter, &targets).len(), 2);
+
+        // Invalid directives are skipped instead of failing startup
+        let filter = filter_from(&config, "app=debug,other=lolwut").unwrap();
+        assert_eq!(enabled(filter, &targets), vec!["\"app\""]);
+
+        let filter = filter_from(&config, "=").unwrap();
+        assert!(enabled(filter, &targets).is_empty());
+
+        // An explicit log_filter is still validated
+        let config = ServerConfig {
+            log_filter: Some("app=lolwut".to_string()),
+            ..ServerConfig::default()
+        };
+        assert!(filter_from(&config, "").is_err());
+    }
+}
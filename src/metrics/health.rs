@@ -1,7 +1,11 @@
 use anyhow::Result;
+use octofhir_fhir_model::provider::ValueReflection;
+use octofhir_fhirpath::model::ModelProvider;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    io,
+    path::{Path, PathBuf},
     sync::{
         Arc, RwLock,
         atomic::{AtomicU64, AtomicUsize, Ordering},
@@ -113,6 +117,38 @@ impl Default for PerformanceMetrics {
     }
 }
 
+/// Latency and error statistics for a single tool
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolMetrics {
+    pub total_requests: u64,
+    pub average_response_time_ms: f64,
+    pub p95_response_time_ms: f64,
+    pub error_rate_percent: f64,
+}
+
+/// Base resource type whose definition must resolve for the core specification to be loaded
+const BASE_SPEC_PROBE_TYPE: &str = "Patient";
+
+/// Representative canonical resources of commonly installed implementation guides
+const WELL_KNOWN_PACKAGE_CANONICALS: &[(&str, &[&str])] = &[
+    (
+        "hl7.fhir.us.core",
+        &["http://hl7.org/fhir/us/core/StructureDefinition/us-core-patient"],
+    ),
+    (
+        "hl7.fhir.uv.ips",
+        &["http://hl7.org/fhir/uv/ips/StructureDefinition/Patient-uv-ips"],
+    ),
+    (
+        "hl7.fhir.uv.sdc",
+        &["http://hl7.org/fhir/uv/sdc/StructureDefinition/sdc-questionnaire"],
+    ),
+    (
+        "hl7.fhir.uv.ipa",
+        &["http://hl7.org/fhir/uv/ipa/StructureDefinition/ipa-patient"],
+    ),
+];
+
 #[derive(Debug, Clone)]
 pub struct MonitoringConfig {
     pub enable_health_checks: bool,
@@ -120,8 +156,25 @@ pub struct MonitoringConfig {
     pub metrics_retention_hours: u32,
     pub health_check_interval_seconds: u64,
     pub memory_threshold_mb: f64,
+    /// Multiple of `memory_threshold_mb` above which memory usage is critical: health is
+    /// unhealthy and the HTTP transport turns away new evaluation requests
+    pub memory_critical_multiplier: f64,
     pub response_time_threshold_ms: f64,
     pub error_rate_threshold_percent: f64,
+    /// Upper bounds (in seconds) of the response time histogram buckets, in ascending order
+    pub response_time_buckets_seconds: Vec<f64>,
+    /// Canonical URLs expected to resolve once a FHIR package is loaded, keyed by package name
+    pub package_canonicals: HashMap<String, Vec<String>>,
+    /// JSON file counters are persisted to and restored from, so they survive restarts
+    pub snapshot_path: Option<PathBuf>,
+    /// How often counters are written to `snapshot_path`
+    pub snapshot_interval_seconds: u64,
+    /// File tool calls are audited to, watched for free disk space like `snapshot_path`
+    pub audit_log_path: Option<PathBuf>,
+    /// Free space on a persistence path's filesystem below which health is degraded
+    pub disk_space_warning_mb: f64,
+    /// Free space on a persistence path's filesystem below which health is unhealthy
+    pub disk_space_critical_mb: f64,
 }
 
 impl Default for MonitoringConfig {
@@ -132,12 +185,152 @@ impl Default for MonitoringConfig {
             metrics_retention_hours: 24,
             health_check_interval_seconds: 30,
             memory_threshold_mb: 512.0,
+            memory_critical_multiplier: 1.5,
             response_time_threshold_ms: 1000.0,
             error_rate_threshold_percent: 5.0,
+            response_time_buckets_seconds: vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ],
+            package_canonicals: WELL_KNOWN_PACKAGE_CANONICALS
+                .iter()
+                .map(|(package, canonicals)| {
+                    let canonicals = canonicals.iter().map(|url| url.to_string()).collect();
+                    (package.to_string(), canonicals)
+                })
+                .collect(),
+            snapshot_path: None,
+            snapshot_interval_seconds: 60,
+            audit_log_path: None,
+            disk_space_warning_mb: 1024.0,
+            disk_space_critical_mb: 100.0,
+        }
+    }
+}
+
+/// Response time distribution of every request since startup or the last reset
+#[derive(Debug, Clone, Serialize)]
+pub struct ResponseTimeHistogram {
+    /// Cumulative sample count for each bucket upper bound (seconds), excluding `+Inf`
+    pub buckets: Vec<(f64, u64)>,
+    pub sum_seconds: f64,
+    pub count: u64,
+}
+
+/// Cumulative response time histogram: one counter per bucket and a running sum, so the
+/// exported series only ever grow, as Prometheus expects of a histogram
+#[derive(Debug)]
+struct HistogramCounters {
+    /// Finite upper bounds in seconds, ascending
+    bounds: Vec<f64>,
+    /// Requests per bucket, the last one above every bound
+    buckets: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+impl HistogramCounters {
+    fn new(bounds_seconds: &[f64]) -> Self {
+        let mut bounds = bounds_seconds.to_vec();
+        bounds.retain(|bound| bound.is_finite());
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        bounds.dedup();
+
+        Self {
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds,
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, response_time_ms: f64) {
+        let seconds = response_time_ms / 1000.0;
+        let bucket = self.bounds.partition_point(|&bound| bound < seconds);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(
+            (response_time_ms * 1000.0).round() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    fn snapshot(&self) -> ResponseTimeHistogram {
+        let mut cumulative = 0;
+        let mut buckets = Vec::with_capacity(self.bounds.len());
+        for (bound, count) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            buckets.push((*bound, cumulative));
+        }
+        // The total is read from the buckets so `+Inf` always matches the count
+        let count = cumulative + self.buckets[self.bounds.len()].load(Ordering::Relaxed);
+
+        ResponseTimeHistogram {
+            buckets,
+            sum_seconds: self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+            count,
+        }
+    }
+
+    fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.sum_micros.store(0, Ordering::Relaxed);
+    }
+
+    fn persisted(&self) -> PersistedHistogram {
+        PersistedHistogram {
+            bounds_seconds: self.bounds.clone(),
+            buckets: self
+                .buckets
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Add persisted counts, unless they were taken with different bucket bounds
+    fn restore(&self, persisted: &PersistedHistogram) {
+        if persisted.bounds_seconds != self.bounds || persisted.buckets.len() != self.buckets.len()
+        {
+            tracing::warn!("Not restoring the response time histogram: bucket bounds changed");
+            return;
+        }
+        for (bucket, count) in self.buckets.iter().zip(&persisted.buckets) {
+            bucket.fetch_add(*count, Ordering::Relaxed);
         }
+        self.sum_micros
+            .fetch_add(persisted.sum_micros, Ordering::Relaxed);
     }
 }
 
+/// Per-bucket (non-cumulative) histogram counts, the last bucket above every bound
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PersistedHistogram {
+    pub bounds_seconds: Vec<f64>,
+    pub buckets: Vec<u64>,
+    pub sum_micros: u64,
+}
+
+/// Recent latency samples and the error count behind averages, percentiles and error rates
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestSamples {
+    pub response_times_ms: Vec<f64>,
+    pub errors: u64,
+}
+
+/// Request counters of a [`HealthMonitor`], written to the metrics snapshot
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedRequests {
+    pub total_requests: u64,
+    pub tool_requests: HashMap<String, u64>,
+    pub requests: RequestSamples,
+    pub tool_samples: HashMap<String, RequestSamples>,
+    pub histogram: Option<PersistedHistogram>,
+}
+
+/// Latency samples kept for percentile calculations
+const MAX_RESPONSE_TIME_SAMPLES: usize = 1000;
+
 #[derive(Debug)]
 struct RequestMetrics {
     response_times: Vec<f64>,
@@ -157,9 +350,9 @@ impl RequestMetrics {
     fn add_request(&mut self, response_time_ms: f64, is_error: bool) {
         let now = Instant::now();
 
-        // Add response time (keep only last 1000 for percentile calculations)
+        // Add response time (keep only the latest samples for percentile calculations)
         self.response_times.push(response_time_ms);
-        if self.response_times.len() > 1000 {
+        if self.response_times.len() > MAX_RESPONSE_TIME_SAMPLES {
             self.response_times.remove(0);
         }
 
@@ -174,6 +367,26 @@ impl RequestMetrics {
             .retain(|&time| now.duration_since(time) <= Duration::from_secs(60));
     }
 
+    fn samples(&self) -> RequestSamples {
+        RequestSamples {
+            response_times_ms: self.response_times.clone(),
+            errors: self.error_count,
+        }
+    }
+
+    /// Prepend samples from a previous run, keeping the latest ones. Requests per minute
+    /// only cover requests since startup.
+    fn restore(&mut self, samples: &RequestSamples) {
+        let mut response_times = samples.response_times_ms.clone();
+        response_times.append(&mut self.response_times);
+        let excess = response_times
+            .len()
+            .saturating_sub(MAX_RESPONSE_TIME_SAMPLES);
+        response_times.drain(..excess);
+        self.response_times = response_times;
+        self.error_count += samples.errors;
+    }
+
     fn calculate_percentile(&self, percentile: f64) -> f64 {
         if self.response_times.is_empty() {
             return 0.0;
@@ -205,29 +418,86 @@ impl RequestMetrics {
     }
 }
 
+/// Readiness check on the shared FHIRPath engine
+pub const ENGINE_CHECK: &str = "engine";
+
+fn check_engine_availability() -> HealthCheck {
+    match crate::fhirpath_engine::shared_engine_if_initialized() {
+        Some(factory) => HealthCheck::healthy(format!(
+            "FHIRPath engine available, {} evaluation slots free",
+            factory.available_evaluation_permits()
+        )),
+        None => HealthCheck::unhealthy("FHIRPath engine not initialized"),
+    }
+}
+
 pub struct HealthMonitor {
     config: MonitoringConfig,
     start_time: Instant,
     version: String,
     health_checks: Arc<TokioRwLock<HashMap<String, HealthCheck>>>,
+    readiness_checks: Arc<TokioRwLock<HashMap<String, HealthCheck>>>,
     request_metrics: Arc<RwLock<RequestMetrics>>,
+    response_time_histogram: HistogramCounters,
+    tool_metrics: Arc<RwLock<HashMap<String, (u64, RequestMetrics)>>>,
     total_requests: AtomicU64,
     active_connections: AtomicUsize,
+    /// Measures the memory used by the process, in MB
+    memory_probe: fn() -> f64,
+    /// Memory usage in MB as of the last sample, as `f64` bits
+    sampled_memory_mb: AtomicU64,
 }
 
 impl HealthMonitor {
     pub fn new(config: MonitoringConfig, version: String) -> Self {
         Self {
+            response_time_histogram: HistogramCounters::new(&config.response_time_buckets_seconds),
             config,
             start_time: Instant::now(),
             version,
             health_checks: Arc::new(TokioRwLock::new(HashMap::new())),
+            readiness_checks: Arc::new(TokioRwLock::new(HashMap::new())),
             request_metrics: Arc::new(RwLock::new(RequestMetrics::new())),
+            tool_metrics: Arc::new(RwLock::new(HashMap::new())),
             total_requests: AtomicU64::new(0),
             active_connections: AtomicUsize::new(0),
+            memory_probe: process_memory_usage_mb,
+            sampled_memory_mb: AtomicU64::new(process_memory_usage_mb().to_bits()),
         }
     }
 
+    /// Measure memory usage with `probe` instead of reading it from the process
+    pub fn with_memory_probe(mut self, probe: fn() -> f64) -> Self {
+        self.set_memory_probe(probe);
+        self
+    }
+
+    pub(super) fn set_memory_probe(&mut self, probe: fn() -> f64) {
+        self.memory_probe = probe;
+        self.sample_memory_usage();
+    }
+
+    /// Measure memory usage, keeping it for [`critical_memory_usage_mb`]
+    ///
+    /// [`critical_memory_usage_mb`]: Self::critical_memory_usage_mb
+    pub fn sample_memory_usage(&self) -> f64 {
+        let memory_mb = (self.memory_probe)();
+        self.sampled_memory_mb
+            .store(memory_mb.to_bits(), Ordering::Relaxed);
+        memory_mb
+    }
+
+    /// Memory usage in MB when the last sample was above the critical threshold, `None`
+    /// otherwise. Only reads the sample, so it is cheap enough to call for every request.
+    pub fn critical_memory_usage_mb(&self) -> Option<f64> {
+        let memory_mb = f64::from_bits(self.sampled_memory_mb.load(Ordering::Relaxed));
+        (memory_mb > self.memory_critical_mb()).then_some(memory_mb)
+    }
+
+    fn memory_critical_mb(&self) -> f64 {
+        self.config.memory_threshold_mb * self.config.memory_critical_multiplier
+    }
+
     pub async fn get_health_status(&self) -> HealthResponse {
         let checks = self.health_checks.read().await.clone();
         let overall_status = self.calculate_overall_status(&checks).await;
@@ -236,15 +506,18 @@ impl HealthMonitor {
         HealthResponse {
             status: overall_status,
             timestamp: SystemTime::now(),
-            uptime_seconds: self.start_time.elapsed().as_secs(),
+            uptime_seconds: self.uptime().as_secs(),
             version: self.version.clone(),
             checks,
             metrics,
         }
     }
 
+    /// Whether the server can take traffic: the shared engine is up and every readiness check
+    /// passes. System health checks such as memory usage don't gate readiness.
     pub async fn get_readiness_status(&self) -> ReadinessResponse {
-        let checks = self.health_checks.read().await.clone();
+        let mut checks = self.readiness_checks.read().await.clone();
+        checks.insert(ENGINE_CHECK.to_string(), check_engine_availability());
         let ready = checks.values().all(|check| check.status.is_healthy());
 
         ReadinessResponse {
@@ -270,11 +543,22 @@ impl HealthMonitor {
         }
     }
 
+    /// Response times of every request, in the configured buckets
+    pub fn get_response_time_histogram(&self) -> ResponseTimeHistogram {
+        self.response_time_histogram.snapshot()
+    }
+
     pub async fn update_health_check(&self, name: impl Into<String>, check: HealthCheck) {
         let name = name.into();
         self.health_checks.write().await.insert(name, check);
     }
 
+    /// Record a check that must pass before the server reports ready, e.g. startup warm-up
+    pub async fn update_readiness_check(&self, name: impl Into<String>, check: HealthCheck) {
+        let name = name.into();
+        self.readiness_checks.write().await.insert(name, check);
+    }
+
     pub async fn run_system_health_checks(&self) -> Result<()> {
         let start_time = Instant::now();
 
@@ -283,6 +567,11 @@ impl HealthMonitor {
         self.update_health_check("fhirpath_library", fhirpath_check)
             .await;
 
+        // FHIR package availability check
+        let packages_check = self.check_fhir_packages().await;
+        self.update_health_check("fhir_packages", packages_check)
+            .await;
+
         // Memory usage check
         let memory_check = self.check_memory_usage();
         self.update_health_check("memory_usage", memory_check).await;
@@ -296,6 +585,12 @@ impl HealthMonitor {
         self.update_health_check("performance", performance_check)
             .await;
 
+        // Disk space check, only while something is persisted
+        if !self.persistence_paths().is_empty() {
+            let disk_check = self.check_disk_space();
+            self.update_health_check("disk_space", disk_check).await;
+        }
+
         let duration = start_time.elapsed();
         tracing::debug!("Health checks completed in {}ms", duration.as_millis());
 
@@ -304,12 +599,104 @@ impl HealthMonitor {
 
     pub fn record_request(&self, response_time_ms: f64, is_error: bool) {
         self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.response_time_histogram.observe(response_time_ms);
         self.request_metrics
             .write()
             .unwrap()
             .add_request(response_time_ms, is_error);
     }
 
+    /// Record a single tool execution, tracked separately from the global request metrics
+    pub fn record_tool_request(&self, tool_name: &str, response_time_ms: f64, is_error: bool) {
+        let mut tools = self.tool_metrics.write().unwrap();
+        let (total, metrics) = tools
+            .entry(tool_name.to_string())
+            .or_insert_with(|| (0, RequestMetrics::new()));
+        *total += 1;
+        metrics.add_request(response_time_ms, is_error);
+    }
+
+    /// Request totals, latency samples, error counts and the histogram, globally and per tool
+    pub fn persisted_requests(&self) -> PersistedRequests {
+        let request_metrics = self.request_metrics.read().unwrap();
+        let tools = self.tool_metrics.read().unwrap();
+
+        PersistedRequests {
+            total_requests: self.total_requests.load(Ordering::Relaxed),
+            tool_requests: tools
+                .iter()
+                .map(|(name, (total, _))| (name.clone(), *total))
+                .collect(),
+            requests: request_metrics.samples(),
+            tool_samples: tools
+                .iter()
+                .map(|(name, (_, metrics))| (name.clone(), metrics.samples()))
+                .collect(),
+            histogram: Some(self.response_time_histogram.persisted()),
+        }
+    }
+
+    /// Add counters carried over from a previous run
+    pub fn restore_requests(&self, persisted: &PersistedRequests) {
+        let mut request_metrics = self.request_metrics.write().unwrap();
+        let mut tools = self.tool_metrics.write().unwrap();
+
+        self.total_requests
+            .fetch_add(persisted.total_requests, Ordering::Relaxed);
+        request_metrics.restore(&persisted.requests);
+        if let Some(histogram) = &persisted.histogram {
+            self.response_time_histogram.restore(histogram);
+        }
+
+        for (name, restored) in &persisted.tool_requests {
+            let (total, _) = tools
+                .entry(name.clone())
+                .or_insert_with(|| (0, RequestMetrics::new()));
+            *total += restored;
+        }
+        for (name, samples) in &persisted.tool_samples {
+            let (_, metrics) = tools
+                .entry(name.clone())
+                .or_insert_with(|| (0, RequestMetrics::new()));
+            metrics.restore(samples);
+        }
+    }
+
+    /// Zero request totals, latency samples and per-tool metrics. Uptime is kept, as is the
+    /// active connection gauge since those connections are still open.
+    pub fn reset_counters(&self) {
+        let mut request_metrics = self.request_metrics.write().unwrap();
+        let mut tools = self.tool_metrics.write().unwrap();
+        self.total_requests.store(0, Ordering::Relaxed);
+        *request_metrics = RequestMetrics::new();
+        self.response_time_histogram.reset();
+        tools.clear();
+    }
+
+    /// Time since the monitor was created
+    pub fn uptime(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
+    pub fn get_tool_metrics(&self) -> HashMap<String, ToolMetrics> {
+        self.tool_metrics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, (total, metrics))| {
+                (
+                    name.clone(),
+                    ToolMetrics {
+                        total_requests: *total,
+                        average_response_time_ms: metrics.average_response_time(),
+                        p95_response_time_ms: metrics.calculate_percentile(95.0),
+                        error_rate_percent: metrics.error_rate_percent(),
+                    },
+                )
+            })
+            .collect()
+    }
+
     pub fn increment_active_connections(&self) {
         self.active_connections.fetch_add(1, Ordering::Relaxed);
     }
@@ -366,11 +753,89 @@ impl HealthMonitor {
         }
     }
 
+    /// Check that the base specification and each configured FHIR package resolve through the
+    /// shared engine's model provider
+    pub async fn check_fhir_packages(&self) -> HealthCheck {
+        let start_time = Instant::now();
+
+        match crate::fhirpath_engine::get_shared_engine().await {
+            Ok(factory) => {
+                self.check_packages_with(
+                    factory.model_provider().as_ref(),
+                    &factory.config().additional_packages,
+                )
+                .await
+            }
+            Err(e) => HealthCheck::unhealthy(format!("Engine factory access failed: {e}")),
+        }
+        .with_duration(start_time.elapsed())
+    }
+
+    async fn check_packages_with(
+        &self,
+        provider: &dyn ModelProvider,
+        packages: &[String],
+    ) -> HealthCheck {
+        let base_spec_loaded = provider
+            .get_type_reflection(BASE_SPEC_PROBE_TYPE)
+            .await
+            .is_some();
+
+        let mut missing = Vec::new();
+        for package in packages {
+            let name = package
+                .split_once('@')
+                .map_or(package.as_str(), |(name, _)| name);
+            let canonicals = self
+                .config
+                .package_canonicals
+                .get(name)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            // A package without known canonical resources cannot be verified
+            let mut resolved = !canonicals.is_empty();
+            for canonical in canonicals {
+                if !canonical_resolves(provider, canonical).await {
+                    resolved = false;
+                    break;
+                }
+            }
+            if !resolved {
+                missing.push(package.as_str());
+            }
+        }
+
+        let missing_message = format!("unresolvable FHIR packages: {}", missing.join(", "));
+        if !base_spec_loaded {
+            let mut message = format!(
+                "Base FHIR {:?} specification not available",
+                provider.fhir_version()
+            );
+            if !missing.is_empty() {
+                message.push_str(&format!("; {missing_message}"));
+            }
+            HealthCheck::unhealthy(message)
+        } else if !missing.is_empty() {
+            HealthCheck::degraded(format!(
+                "{} of {} {}",
+                missing.len(),
+                packages.len(),
+                missing_message
+            ))
+        } else {
+            HealthCheck::healthy(format!(
+                "Base specification and {} FHIR packages available",
+                packages.len()
+            ))
+        }
+    }
+
     fn check_memory_usage(&self) -> HealthCheck {
         let start_time = Instant::now();
         let memory_mb = self.get_memory_usage_mb();
 
-        if memory_mb > self.config.memory_threshold_mb * 1.5 {
+        if memory_mb > self.memory_critical_mb() {
             HealthCheck::unhealthy(format!("High memory usage: {memory_mb:.1}MB"))
                 .with_duration(start_time.elapsed())
         } else if memory_mb > self.config.memory_threshold_mb {
@@ -423,30 +888,136 @@ impl HealthMonitor {
         }
     }
 
+    /// Files the server persists to: the metrics snapshot and the audit log
+    fn persistence_paths(&self) -> Vec<&Path> {
+        [&self.config.snapshot_path, &self.config.audit_log_path]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    /// Check the free space left for persisted files against the configured thresholds
+    pub fn check_disk_space(&self) -> HealthCheck {
+        self.check_disk_space_with(available_space)
+    }
+
+    fn check_disk_space_with(&self, probe: impl Fn(&Path) -> io::Result<u64>) -> HealthCheck {
+        let start_time = Instant::now();
+
+        // Report the path with the least free space
+        let mut lowest: Option<(&Path, f64)> = None;
+        for path in self.persistence_paths() {
+            let free_mb = match probe(path) {
+                Ok(bytes) => bytes as f64 / (1024.0 * 1024.0),
+                Err(e) => {
+                    return HealthCheck::unhealthy(format!(
+                        "Cannot read free disk space for {}: {e}",
+                        path.display()
+                    ))
+                    .with_duration(start_time.elapsed());
+                }
+            };
+            if lowest.is_none_or(|(_, lowest_mb)| free_mb < lowest_mb) {
+                lowest = Some((path, free_mb));
+            }
+        }
+        let Some((path, free_mb)) = lowest else {
+            return HealthCheck::healthy("Nothing is persisted to disk")
+                .with_duration(start_time.elapsed());
+        };
+
+        if free_mb < self.config.disk_space_critical_mb {
+            HealthCheck::unhealthy(format!(
+                "Disk almost full: {free_mb:.1}MB free for {}",
+                path.display()
+            ))
+            .with_duration(start_time.elapsed())
+        } else if free_mb < self.config.disk_space_warning_mb {
+            HealthCheck::degraded(format!(
+                "Disk space low: {free_mb:.1}MB free for {}",
+                path.display()
+            ))
+            .with_duration(start_time.elapsed())
+        } else {
+            HealthCheck::healthy(format!(
+                "Disk space sufficient: {free_mb:.1}MB free for {}",
+                path.display()
+            ))
+            .with_duration(start_time.elapsed())
+        }
+    }
+
     fn get_memory_usage_mb(&self) -> f64 {
-        // Simple memory usage approximation
-        // In production, you might want to use a system monitoring library like `sysinfo`
-        // For now, return a reasonable approximation based on process info
-        // This is a placeholder - in production you'd use system metrics
+        self.sample_memory_usage()
+    }
+}
 
-        // Try to get memory usage from /proc/self/status on Linux-like systems
-        #[cfg(target_os = "linux")]
-        {
-            if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
-                for line in status.lines() {
-                    if line.starts_with("VmRSS:") {
-                        if let Some(kb_str) = line.split_whitespace().nth(1) {
-                            if let Ok(kb) = kb_str.parse::<f64>() {
-                                return kb / 1024.0; // Convert KB to MB
-                            }
-                        }
-                    }
+fn process_memory_usage_mb() -> f64 {
+    // Simple memory usage approximation
+    // In production, you might want to use a system monitoring library like `sysinfo`
+    // For now, return a reasonable approximation based on process info
+    // This is a placeholder - in production you'd use system metrics
+
+    // Try to get memory usage from /proc/self/status on Linux-like systems
+    #[cfg(target_os = "linux")]
+    {
+        if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+            for line in status.lines() {
+                if line.starts_with("VmRSS:")
+                    && let Some(kb_str) = line.split_whitespace().nth(1)
+                    && let Ok(kb) = kb_str.parse::<f64>()
+                {
+                    return kb / 1024.0; // Convert KB to MB
                 }
             }
         }
+    }
 
-        // Fallback approximation
-        32.0 // MB
+    // Fallback approximation
+    32.0 // MB
+}
+
+/// Free bytes on the filesystem `path` is written to. The file may not exist yet, so its
+/// closest existing ancestor is probed.
+fn available_space(path: &Path) -> io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."));
+    fs4::available_space(existing)
+}
+
+/// Check whether the model provider can resolve the resource definition at `canonical`
+async fn canonical_resolves(provider: &dyn ModelProvider, canonical: &str) -> bool {
+    provider
+        .validate_conformance(&CanonicalProbe, canonical)
+        .await
+        .is_ok_and(|result| result.is_valid)
+}
+
+/// Empty value used to look up a profile through the conformance API
+struct CanonicalProbe;
+
+impl ValueReflection for CanonicalProbe {
+    fn type_name(&self) -> String {
+        "Resource".to_string()
+    }
+
+    fn get_property(&self, _name: &str) -> Option<Box<dyn ValueReflection>> {
+        None
+    }
+
+    fn has_property(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn property_names(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn to_debug_string(&self) -> String {
+        "CanonicalProbe".to_string()
     }
 }
 
@@ -486,6 +1057,29 @@ mod tests {
         assert!((metrics.error_rate_percent() - 33.33).abs() < 0.1);
     }
 
+    #[test]
+    fn test_response_time_histogram_counts_every_request() {
+        let histogram = HistogramCounters::new(&[1.0, 0.1, f64::INFINITY, 0.175, 0.1]);
+        for ms in [100.0, 200.0, 150.0, 5000.0] {
+            histogram.observe(ms);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.buckets, vec![(0.1, 1), (0.175, 2), (1.0, 3)]);
+        assert_eq!(snapshot.count, 4);
+        assert!((snapshot.sum_seconds - 5.45).abs() < 1e-9);
+
+        // Unlike the latency samples, buckets keep counting past the retained window
+        for _ in 0..1500 {
+            histogram.observe(1.0);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 1504);
+        assert_eq!(snapshot.buckets[0], (0.1, 1501));
+
+        histogram.reset();
+        assert_eq!(histogram.snapshot().count, 0);
+    }
+
     #[tokio::test]
     async fn test_health_monitor_creation() {
         let config = MonitoringConfig::default();
@@ -495,23 +1089,129 @@ mod tests {
         assert_eq!(health.version, "test-0.1.0");
     }
 
+    #[test]
+    fn test_memory_critical_threshold() {
+        let config = MonitoringConfig {
+            memory_threshold_mb: 100.0,
+            memory_critical_multiplier: 2.0,
+            ..MonitoringConfig::default()
+        };
+        let monitor = HealthMonitor::new(config.clone(), "test-0.1.0".to_string())
+            .with_memory_probe(|| 150.0);
+        assert_eq!(monitor.critical_memory_usage_mb(), None);
+        assert_eq!(monitor.check_memory_usage().status, HealthStatus::Degraded);
+
+        let monitor =
+            HealthMonitor::new(config, "test-0.1.0".to_string()).with_memory_probe(|| 250.0);
+        assert_eq!(monitor.critical_memory_usage_mb(), Some(250.0));
+        assert_eq!(monitor.check_memory_usage().status, HealthStatus::Unhealthy);
+    }
+
     #[tokio::test]
     async fn test_readiness_check() {
         let config = MonitoringConfig::default();
         let monitor = HealthMonitor::new(config, "test-0.1.0".to_string());
 
-        // Initially should be ready (no checks registered)
+        // Ready once the engine is up and no readiness checks are registered
+        crate::fhirpath_engine::get_shared_engine().await.unwrap();
         let readiness = monitor.get_readiness_status().await;
         assert!(readiness.ready);
+        assert!(readiness.checks[ENGINE_CHECK].status.is_healthy());
 
-        // Add a failing check
+        // A failing health check doesn't make the server unready
         monitor
-            .update_health_check("test", HealthCheck::unhealthy("Test failure"))
+            .update_health_check("memory_usage", HealthCheck::degraded("High memory usage"))
+            .await;
+        assert!(monitor.get_readiness_status().await.ready);
+
+        // A failing readiness check does
+        monitor
+            .update_readiness_check("test", HealthCheck::unhealthy("Test failure"))
             .await;
         let readiness = monitor.get_readiness_status().await;
         assert!(!readiness.ready);
     }
 
+    #[tokio::test]
+    async fn test_fhir_packages_check_reports_bogus_package() {
+        let monitor = HealthMonitor::new(MonitoringConfig::default(), "test-0.1.0".to_string());
+        let factory = crate::fhirpath_engine::get_shared_engine().await.unwrap();
+
+        let check = monitor
+            .check_packages_with(
+                factory.model_provider().as_ref(),
+                &["bogus.package@0.0.1".to_string()],
+            )
+            .await;
+
+        assert!(matches!(
+            check.status,
+            HealthStatus::Degraded | HealthStatus::Unhealthy
+        ));
+        assert!(check.message.contains("bogus.package@0.0.1"));
+    }
+
+    #[test]
+    fn test_disk_space_check_thresholds() {
+        let monitor = HealthMonitor::new(
+            MonitoringConfig {
+                snapshot_path: Some(PathBuf::from("/var/lib/octofhir/metrics.json")),
+                audit_log_path: Some(PathBuf::from("/var/log/octofhir/audit.jsonl")),
+                disk_space_warning_mb: 500.0,
+                disk_space_critical_mb: 50.0,
+                ..MonitoringConfig::default()
+            },
+            "test-0.1.0".to_string(),
+        );
+        const MB: u64 = 1024 * 1024;
+
+        let check = monitor.check_disk_space_with(|_| Ok(2048 * MB));
+        assert_eq!(check.status, HealthStatus::Healthy);
+
+        // The fuller filesystem decides
+        let check = monitor.check_disk_space_with(|path| {
+            Ok(if path.starts_with("/var/log") {
+                200 * MB
+            } else {
+                2048 * MB
+            })
+        });
+        assert_eq!(check.status, HealthStatus::Degraded);
+        assert!(check.message.contains("audit.jsonl"));
+
+        let check = monitor.check_disk_space_with(|_| Ok(10 * MB));
+        assert_eq!(check.status, HealthStatus::Unhealthy);
+
+        let check = monitor.check_disk_space_with(|_| Err(io::ErrorKind::NotFound.into()));
+        assert_eq!(check.status, HealthStatus::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn test_disk_space_check_only_with_persistence() {
+        let monitor = HealthMonitor::new(MonitoringConfig::default(), "test-0.1.0".to_string());
+        monitor.run_system_health_checks().await.unwrap();
+        assert!(
+            !monitor
+                .get_health_status()
+                .await
+                .checks
+                .contains_key("disk_space")
+        );
+
+        let monitor = HealthMonitor::new(
+            MonitoringConfig {
+                snapshot_path: Some(std::env::temp_dir().join("octofhir-metrics.json")),
+                disk_space_warning_mb: 0.0,
+                disk_space_critical_mb: 0.0,
+                ..MonitoringConfig::default()
+            },
+            "test-0.1.0".to_string(),
+        );
+        monitor.run_system_health_checks().await.unwrap();
+        let checks = monitor.get_health_status().await.checks;
+        assert_eq!(checks["disk_space"].status, HealthStatus::Healthy);
+    }
+
     #[test]
     fn test_monitoring_config_defaults() {
         let config = MonitoringConfig::default();
@@ -1,14 +1,18 @@
 //! Metrics and observability implementations
 
 pub mod health;
+pub mod profile;
+#[cfg(feature = "otel")]
+pub mod telemetry;
 
-use anyhow::Result;
-use health::{
-    HealthMonitor, HealthResponse, MonitoringConfig, PerformanceMetrics, ReadinessResponse,
-};
-use serde::Serialize;
+use crate::cache::CacheStats;
+use crate::security::AuthFailureReason;
+use anyhow::{Context, Result};
+use health::{HealthMonitor, HealthResponse, PerformanceMetrics, ReadinessResponse, ToolMetrics};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    path::Path,
     sync::{
         Arc,
         atomic::{AtomicU64, Ordering},
@@ -17,36 +21,286 @@ use std::{
 };
 use tokio::sync::RwLock;
 
-pub use health::{HealthCheck, HealthStatus};
+pub use health::{
+    HealthCheck, HealthStatus, MonitoringConfig, PersistedHistogram, PersistedRequests,
+    RequestSamples,
+};
+
+/// Longest resource type name given its own per-type metrics
+const MAX_RESOURCE_TYPE_LENGTH: usize = 64;
+
+/// Name under which the parsed expression cache is reported
+pub const EXPRESSION_CACHE: &str = "expression";
+/// Name under which the evaluation result cache is reported
+pub const RESULT_CACHE: &str = "result";
 
 #[derive(Debug, Clone, Serialize)]
 pub struct MetricsSnapshot {
     pub timestamp: std::time::SystemTime,
     pub performance: PerformanceMetrics,
+    pub tools: HashMap<String, ToolMetrics>,
+    /// Counters of each cache by name; always present, and zero while caching is disabled
+    pub caches: BTreeMap<String, CacheMetrics>,
     pub custom_metrics: HashMap<String, f64>,
 }
 
+/// Effectiveness of one cache
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: u64,
+    /// Fraction of lookups answered from the cache
+    pub hit_rate: f64,
+}
+
+impl From<&CacheStats> for CacheMetrics {
+    fn from(stats: &CacheStats) -> Self {
+        Self {
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+            entries: stats.entries as u64,
+            hit_rate: stats.hit_rate(),
+        }
+    }
+}
+
+/// How often memory usage is sampled for admission checks
+pub const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counters written to [`MonitoringConfig::snapshot_path`] and restored on startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PersistedMetrics {
+    pub timestamp: Option<std::time::SystemTime>,
+    #[serde(flatten)]
+    pub requests: PersistedRequests,
+    pub custom_metrics: HashMap<String, u64>,
+}
+
+impl<'de> Deserialize<'de> for PersistedMetrics {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // `#[serde(flatten)]` buffers fields in a form that can't hold serde_json's
+        // arbitrary-precision numbers, so the flattened counters are read from a `Value`
+        #[derive(Deserialize)]
+        struct Snapshot {
+            timestamp: Option<std::time::SystemTime>,
+            #[serde(default)]
+            custom_metrics: HashMap<String, u64>,
+        }
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let snapshot = Snapshot::deserialize(&value).map_err(serde::de::Error::custom)?;
+        let requests = PersistedRequests::deserialize(&value).map_err(serde::de::Error::custom)?;
+        Ok(Self {
+            timestamp: snapshot.timestamp,
+            requests,
+            custom_metrics: snapshot.custom_metrics,
+        })
+    }
+}
+
+impl PersistedMetrics {
+    /// Read a snapshot file; a missing file yields empty counters
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("Invalid metrics snapshot {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to read metrics snapshot {}", path.display())),
+        }
+    }
+
+    /// Write the snapshot to a temporary file and rename it over `path`, so readers never
+    /// see a partially written file
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = std::path::PathBuf::from(temp_name);
+
+        tokio::fs::write(&temp_path, content)
+            .await
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct PrometheusMetrics {
     pub content_type: String,
     pub data: String,
 }
 
+/// Text format of the metrics endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetricsFormat {
+    /// Prometheus text format 0.0.4
+    #[default]
+    Prometheus,
+    /// OpenMetrics 1.0.0 text format
+    OpenMetrics,
+}
+
+impl MetricsFormat {
+    /// The format an `Accept` header asks for. OpenMetrics is chosen only when it is accepted
+    /// with at least the quality of `text/plain`; anything else gets the Prometheus format.
+    pub fn from_accept(accept: &str) -> Self {
+        let quality = |media_type: &str| {
+            accept
+                .split(',')
+                .filter_map(|range| {
+                    let mut params = range.split(';').map(str::trim);
+                    if !params.next()?.eq_ignore_ascii_case(media_type) {
+                        return None;
+                    }
+                    Some(
+                        params
+                            .find_map(|param| param.strip_prefix("q="))
+                            .and_then(|q| q.parse::<f32>().ok())
+                            .unwrap_or(1.0),
+                    )
+                })
+                .reduce(f32::max)
+        };
+
+        match (
+            quality("application/openmetrics-text"),
+            quality("text/plain"),
+        ) {
+            (Some(openmetrics), text)
+                if openmetrics > 0.0 && openmetrics >= text.unwrap_or(0.0) =>
+            {
+                Self::OpenMetrics
+            }
+            _ => Self::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+            Self::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Metric families being written in one text format. Names are given as in the Prometheus
+/// format; OpenMetrics counter families drop the `_total` suffix their samples carry.
+struct Exposition {
+    format: MetricsFormat,
+    data: String,
+}
+
+impl Exposition {
+    /// Name of the family `name` belongs to
+    fn family_name<'a>(&self, name: &'a str, kind: &str) -> &'a str {
+        match (self.format, kind) {
+            (MetricsFormat::OpenMetrics, "counter") => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        }
+    }
+
+    /// Write the HELP and TYPE lines of a family, and in OpenMetrics its unit when the name
+    /// ends with one
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let family = self.family_name(name, kind);
+        self.data
+            .push_str(&format!("# HELP {family} {help}\n# TYPE {family} {kind}\n"));
+        if self.format == MetricsFormat::OpenMetrics
+            && let Some(unit) = ["seconds", "ms", "mb", "percent"]
+                .into_iter()
+                .find(|unit| family.ends_with(&format!("_{unit}")))
+        {
+            self.data.push_str(&format!("# UNIT {family} {unit}\n"));
+        }
+    }
+
+    fn sample(&mut self, name: &str, kind: &str, labels: &str, value: impl std::fmt::Display) {
+        let name = match (self.format, kind) {
+            (MetricsFormat::OpenMetrics, "counter") => {
+                format!("{}_total", self.family_name(name, kind))
+            }
+            _ => name.to_string(),
+        };
+        self.data.push_str(&format!("{name}{labels} {value}\n"));
+    }
+
+    /// Write a family with a single unlabelled sample
+    fn metric(&mut self, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+        self.family(name, kind, help);
+        self.sample(name, kind, "", value);
+    }
+}
+
 pub struct MetricsProvider {
     health_monitor: Arc<HealthMonitor>,
     custom_metrics: Arc<RwLock<HashMap<String, AtomicU64>>>,
+    caches: RwLock<BTreeMap<String, CacheMetrics>>,
     config: MonitoringConfig,
 }
 
 impl MetricsProvider {
+    /// Create a provider, resuming counters from `config.snapshot_path` when set. A snapshot
+    /// that cannot be read is logged and ignored.
     pub fn new(config: MonitoringConfig, version: String) -> Self {
+        let health_monitor = HealthMonitor::new(config.clone(), version);
+        let mut custom_metrics = HashMap::new();
+
+        if let Some(path) = &config.snapshot_path {
+            match PersistedMetrics::load(path) {
+                Ok(snapshot) => {
+                    health_monitor.restore_requests(&snapshot.requests);
+                    custom_metrics = snapshot
+                        .custom_metrics
+                        .into_iter()
+                        .map(|(name, value)| (name, AtomicU64::new(value)))
+                        .collect();
+                }
+                Err(e) => tracing::warn!("Not restoring metrics: {:#}", e),
+            }
+        }
+
         Self {
-            health_monitor: Arc::new(HealthMonitor::new(config.clone(), version)),
-            custom_metrics: Arc::new(RwLock::new(HashMap::new())),
+            health_monitor: Arc::new(health_monitor),
+            custom_metrics: Arc::new(RwLock::new(custom_metrics)),
+            caches: RwLock::new(
+                [EXPRESSION_CACHE, RESULT_CACHE]
+                    .into_iter()
+                    .map(|name| (name.to_string(), CacheMetrics::default()))
+                    .collect(),
+            ),
             config,
         }
     }
 
+    /// Measure memory usage with `probe` instead of reading it from the process. Must be
+    /// called before the provider is shared, e.g. by starting persistence.
+    pub fn with_memory_probe(mut self, probe: fn() -> f64) -> Self {
+        Arc::get_mut(&mut self.health_monitor)
+            .expect("memory probe is set before the health monitor is shared")
+            .set_memory_probe(probe);
+        self
+    }
+
+    /// Sample memory usage every [`MEMORY_SAMPLE_INTERVAL`], so admission checks read a
+    /// recent value instead of measuring it per request
+    pub fn start_memory_sampling(&self) {
+        let health_monitor = self.health_monitor.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MEMORY_SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+                health_monitor.sample_memory_usage();
+            }
+        });
+    }
+
     pub async fn get_health_status(&self) -> HealthResponse {
         if self.config.enable_health_checks {
             // Run health checks before returning status
@@ -72,62 +326,156 @@ impl MetricsProvider {
         MetricsSnapshot {
             timestamp: std::time::SystemTime::now(),
             performance,
+            tools: self.health_monitor.get_tool_metrics(),
+            caches: self.caches.read().await.clone(),
             custom_metrics,
         }
     }
 
+    /// Metrics in the Prometheus text format
     pub async fn get_prometheus_metrics(&self) -> PrometheusMetrics {
+        self.get_text_metrics(MetricsFormat::Prometheus).await
+    }
+
+    /// Metrics in the text format `format`
+    pub async fn get_text_metrics(&self, format: MetricsFormat) -> PrometheusMetrics {
         let performance = self.get_performance_metrics();
         let custom_metrics = self.get_custom_metrics().await;
 
-        let mut prometheus_data = String::new();
+        let mut exposition = Exposition {
+            format,
+            data: String::new(),
+        };
 
         // Performance metrics
-        prometheus_data.push_str(&format!(
-            "# HELP octofhir_requests_total Total number of requests\n# TYPE octofhir_requests_total counter\noctofhir_requests_total {}\n",
-            performance.total_requests
-        ));
-
-        prometheus_data.push_str(&format!(
-            "# HELP octofhir_requests_per_minute Current requests per minute\n# TYPE octofhir_requests_per_minute gauge\noctofhir_requests_per_minute {}\n",
-            performance.requests_per_minute
-        ));
-
-        prometheus_data.push_str(&format!(
-            "# HELP octofhir_response_time_avg_ms Average response time in milliseconds\n# TYPE octofhir_response_time_avg_ms gauge\noctofhir_response_time_avg_ms {}\n",
-            performance.average_response_time_ms
-        ));
-
-        prometheus_data.push_str(&format!(
-            "# HELP octofhir_response_time_p95_ms 95th percentile response time in milliseconds\n# TYPE octofhir_response_time_p95_ms gauge\noctofhir_response_time_p95_ms {}\n",
-            performance.p95_response_time_ms
-        ));
+        exposition.metric(
+            "octofhir_requests_total",
+            "counter",
+            "Total number of requests",
+            performance.total_requests,
+        );
+        exposition.metric(
+            "octofhir_requests_per_minute",
+            "gauge",
+            "Current requests per minute",
+            performance.requests_per_minute,
+        );
+        exposition.metric(
+            "octofhir_response_time_avg_ms",
+            "gauge",
+            "Average response time in milliseconds",
+            performance.average_response_time_ms,
+        );
+        exposition.metric(
+            "octofhir_response_time_p95_ms",
+            "gauge",
+            "95th percentile response time in milliseconds",
+            performance.p95_response_time_ms,
+        );
 
-        prometheus_data.push_str(&format!(
-            "# HELP octofhir_error_rate_percent Error rate percentage\n# TYPE octofhir_error_rate_percent gauge\noctofhir_error_rate_percent {}\n",
-            performance.error_rate_percent
+        // Response time histogram
+        let histogram = self.health_monitor.get_response_time_histogram();
+        exposition.family(
+            "octofhir_response_time_seconds",
+            "histogram",
+            "Response time distribution in seconds",
+        );
+        for (bound, count) in &histogram.buckets {
+            exposition.sample(
+                "octofhir_response_time_seconds_bucket",
+                "histogram",
+                &format!("{{le=\"{bound}\"}}"),
+                count,
+            );
+        }
+        exposition.data.push_str(&format!(
+            "octofhir_response_time_seconds_bucket{{le=\"+Inf\"}} {count}\noctofhir_response_time_seconds_sum {sum}\noctofhir_response_time_seconds_count {count}\n",
+            count = histogram.count,
+            sum = histogram.sum_seconds
         ));
 
-        prometheus_data.push_str(&format!(
-            "# HELP octofhir_active_connections Current active connections\n# TYPE octofhir_active_connections gauge\noctofhir_active_connections {}\n",
-            performance.active_connections
-        ));
+        exposition.metric(
+            "octofhir_error_rate_percent",
+            "gauge",
+            "Error rate percentage",
+            performance.error_rate_percent,
+        );
+        exposition.metric(
+            "octofhir_active_connections",
+            "gauge",
+            "Current active connections",
+            performance.active_connections,
+        );
+        exposition.metric(
+            "octofhir_memory_usage_mb",
+            "gauge",
+            "Memory usage in megabytes",
+            performance.memory_usage_mb,
+        );
 
-        prometheus_data.push_str(&format!(
-            "# HELP octofhir_memory_usage_mb Memory usage in megabytes\n# TYPE octofhir_memory_usage_mb gauge\noctofhir_memory_usage_mb {}\n",
-            performance.memory_usage_mb
-        ));
+        // Cache effectiveness, one series per cache
+        let caches = self.caches.read().await;
+        let mut push_cache_metric =
+            |name: &str, kind: &str, help: &str, value: fn(&CacheMetrics) -> f64| {
+                let name = format!("octofhir_{name}");
+                exposition.family(&name, kind, help);
+                for (cache_name, cache) in caches.iter() {
+                    exposition.sample(
+                        &name,
+                        kind,
+                        &format!("{{cache=\"{cache_name}\"}}"),
+                        value(cache),
+                    );
+                }
+            };
+        push_cache_metric(
+            "cache_hits",
+            "counter",
+            "Cache lookups answered from the cache",
+            |cache| cache.hits as f64,
+        );
+        push_cache_metric(
+            "cache_misses",
+            "counter",
+            "Cache lookups not found in the cache",
+            |cache| cache.misses as f64,
+        );
+        push_cache_metric(
+            "cache_evictions",
+            "counter",
+            "Entries evicted to make room for new ones",
+            |cache| cache.evictions as f64,
+        );
+        push_cache_metric(
+            "cache_entries",
+            "gauge",
+            "Entries currently cached",
+            |cache| cache.entries as f64,
+        );
+        push_cache_metric(
+            "cache_hit_rate",
+            "gauge",
+            "Fraction of cache lookups answered from the cache",
+            |cache| cache.hit_rate,
+        );
+        drop(caches);
 
         // Custom metrics
         for (name, value) in custom_metrics {
-            prometheus_data.push_str(&format!(
-                "# HELP octofhir_{name} Custom metric {name}\n# TYPE octofhir_{name} gauge\noctofhir_{name} {value}\n"
-            ));
+            exposition.metric(
+                &format!("octofhir_{name}"),
+                "gauge",
+                &format!("Custom metric {name}"),
+                value,
+            );
         }
 
+        if format == MetricsFormat::OpenMetrics {
+            exposition.data.push_str("# EOF\n");
+        }
         PrometheusMetrics {
-            content_type: "text/plain; version=0.0.4; charset=utf-8".to_string(),
-            data: prometheus_data,
+            content_type: format.content_type().to_string(),
+            data: exposition.data,
         }
     }
 
@@ -138,6 +486,17 @@ impl MetricsProvider {
         }
     }
 
+    /// Record a tool execution in both the global and the per-tool metrics
+    pub fn record_tool_request(&self, tool_name: &str, response_time: Duration, is_error: bool) {
+        if self.config.enable_metrics {
+            let response_time_ms = response_time.as_secs_f64() * 1000.0;
+            self.health_monitor
+                .record_request(response_time_ms, is_error);
+            self.health_monitor
+                .record_tool_request(tool_name, response_time_ms, is_error);
+        }
+    }
+
     pub fn increment_active_connections(&self) {
         if self.config.enable_metrics {
             self.health_monitor.increment_active_connections();
@@ -174,6 +533,81 @@ impl MetricsProvider {
             .store(value, Ordering::Relaxed);
     }
 
+    /// Publish counters of the cache called `name`, e.g. [`RESULT_CACHE`]
+    pub async fn record_cache_metrics(&self, name: &str, stats: &CacheStats) {
+        self.caches
+            .write()
+            .await
+            .insert(name.to_string(), CacheMetrics::from(stats));
+    }
+
+    /// Publish expression cache counters as custom metrics
+    pub async fn record_cache_stats(&self, stats: &CacheStats) {
+        self.record_cache_metrics(EXPRESSION_CACHE, stats).await;
+        self.set_custom_metric("expression_cache_hits", stats.hits)
+            .await;
+        self.set_custom_metric("expression_cache_misses", stats.misses)
+            .await;
+        self.set_custom_metric("expression_cache_entries", stats.entries as u64)
+            .await;
+    }
+
+    /// Count a rejected request under `auth_failures_<reason>`
+    pub async fn record_auth_failure(&self, reason: AuthFailureReason) {
+        self.increment_custom_metric(&format!("auth_failures_{reason}"), 1)
+            .await;
+    }
+
+    /// Count an evaluation under `evaluations_by_type_<ResourceType>` and add its latency to
+    /// `evaluation_time_us_by_type_<ResourceType>`, so the average per type is their ratio.
+    /// Types that aren't plain alphanumeric names are counted as `unknown`, keeping metric
+    /// names valid whatever the client sends.
+    pub async fn record_evaluation_by_type(&self, resource_type: &str, elapsed: Duration) {
+        let resource_type = if !resource_type.is_empty()
+            && resource_type.len() <= MAX_RESOURCE_TYPE_LENGTH
+            && resource_type.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            resource_type
+        } else {
+            "unknown"
+        };
+
+        self.increment_custom_metric(&format!("evaluations_by_type_{resource_type}"), 1)
+            .await;
+        self.increment_custom_metric(
+            &format!("evaluation_time_us_by_type_{resource_type}"),
+            elapsed.as_micros() as u64,
+        )
+        .await;
+    }
+
+    /// Publish evaluation result cache counters and hit rate as custom metrics
+    pub async fn record_result_cache_stats(&self, stats: &CacheStats) {
+        self.record_cache_metrics(RESULT_CACHE, stats).await;
+        self.set_custom_metric("result_cache_hits", stats.hits)
+            .await;
+        self.set_custom_metric("result_cache_misses", stats.misses)
+            .await;
+        self.set_custom_metric("result_cache_entries", stats.entries as u64)
+            .await;
+        self.set_custom_metric(
+            "result_cache_hit_rate_percent",
+            (stats.hit_rate() * 100.0).round() as u64,
+        )
+        .await;
+    }
+
+    /// Zero request counters, per-tool metrics and custom metrics, e.g. between load test
+    /// runs. Uptime, the active connection gauge and cache counters, which mirror the caches
+    /// themselves, are preserved.
+    pub async fn reset(&self) {
+        // Hold the custom metrics lock across both resets so a concurrent snapshot never
+        // mixes old custom metrics with reset request counters
+        let mut custom_metrics = self.custom_metrics.write().await;
+        self.health_monitor.reset_counters();
+        custom_metrics.clear();
+    }
+
     pub async fn get_custom_metrics(&self) -> HashMap<String, f64> {
         let metrics = self.custom_metrics.read().await;
         metrics
@@ -186,6 +620,12 @@ impl MetricsProvider {
         self.health_monitor.update_health_check(name, check).await;
     }
 
+    pub async fn update_readiness_check(&self, name: impl Into<String>, check: HealthCheck) {
+        self.health_monitor
+            .update_readiness_check(name, check)
+            .await;
+    }
+
     pub async fn start_periodic_health_checks(&self) -> Result<()> {
         if !self.config.enable_health_checks {
             return Ok(());
@@ -210,11 +650,71 @@ impl MetricsProvider {
         Ok(())
     }
 
+    /// Write counters to the configured snapshot file, if any
+    pub async fn persist_snapshot(&self) -> Result<()> {
+        match &self.config.snapshot_path {
+            Some(path) => {
+                persisted_metrics(&self.health_monitor, &self.custom_metrics)
+                    .await
+                    .save(path)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Persist counters every `snapshot_interval_seconds` when a snapshot path is configured
+    pub async fn start_periodic_persistence(&self) -> Result<()> {
+        let Some(path) = self.config.snapshot_path.clone() else {
+            return Ok(());
+        };
+
+        let health_monitor = self.health_monitor.clone();
+        let custom_metrics = self.custom_metrics.clone();
+        let interval_seconds = self.config.snapshot_interval_seconds.max(1);
+        tracing::info!(
+            "Persisting metrics to {} every {}s",
+            path.display(),
+            interval_seconds
+        );
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds));
+
+            loop {
+                interval.tick().await;
+
+                let snapshot = persisted_metrics(&health_monitor, &custom_metrics).await;
+                if let Err(e) = snapshot.save(&path).await {
+                    tracing::warn!("Failed to persist metrics: {:#}", e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
     pub fn health_monitor(&self) -> &HealthMonitor {
         &self.health_monitor
     }
 }
 
+async fn persisted_metrics(
+    health_monitor: &HealthMonitor,
+    custom_metrics: &RwLock<HashMap<String, AtomicU64>>,
+) -> PersistedMetrics {
+    PersistedMetrics {
+        timestamp: Some(std::time::SystemTime::now()),
+        requests: health_monitor.persisted_requests(),
+        custom_metrics: custom_metrics
+            .read()
+            .await
+            .iter()
+            .map(|(name, value)| (name.clone(), value.load(Ordering::Relaxed)))
+            .collect(),
+    }
+}
+
 impl Default for MetricsProvider {
     fn default() -> Self {
         Self::new(
@@ -262,6 +762,337 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_metrics_format_from_accept() {
+        for (accept, format) in [
+            ("", MetricsFormat::Prometheus),
+            ("*/*", MetricsFormat::Prometheus),
+            ("text/plain; version=0.0.4", MetricsFormat::Prometheus),
+            (
+                "application/openmetrics-text; version=1.0.0",
+                MetricsFormat::OpenMetrics,
+            ),
+            // What Prometheus itself sends
+            (
+                "application/openmetrics-text;version=1.0.0,application/openmetrics-text;\
+                 version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1",
+                MetricsFormat::OpenMetrics,
+            ),
+            (
+                "application/openmetrics-text;q=0.2, text/plain",
+                MetricsFormat::Prometheus,
+            ),
+            (
+                "application/openmetrics-text;q=0",
+                MetricsFormat::Prometheus,
+            ),
+        ] {
+            assert_eq!(MetricsFormat::from_accept(accept), format, "{accept}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openmetrics_metrics() {
+        let provider = MetricsProvider::default();
+        provider.record_request(Duration::from_millis(20), false);
+        provider.increment_custom_metric("test_metric", 10).await;
+
+        let metrics = provider.get_text_metrics(MetricsFormat::OpenMetrics).await;
+        assert_eq!(
+            metrics.content_type,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8"
+        );
+        assert!(metrics.data.ends_with("# EOF\n"));
+        assert!(
+            metrics
+                .data
+                .contains("# TYPE octofhir_requests counter\noctofhir_requests_total 1\n")
+        );
+        assert!(
+            metrics
+                .data
+                .contains("octofhir_cache_hits_total{cache=\"result\"} 0\n")
+        );
+        assert!(
+            metrics
+                .data
+                .contains("# UNIT octofhir_response_time_seconds seconds\n")
+        );
+        assert!(metrics.data.contains("octofhir_test_metric 10\n"));
+
+        let prometheus = provider.get_prometheus_metrics().await;
+        assert!(!prometheus.data.contains("# EOF"));
+        assert!(!prometheus.data.contains("# UNIT"));
+        assert!(
+            prometheus
+                .data
+                .contains("octofhir_cache_hits{cache=\"result\"} 0\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_response_time_histogram() {
+        let config = MonitoringConfig {
+            response_time_buckets_seconds: vec![0.5, 0.05, 0.1],
+            ..MonitoringConfig::default()
+        };
+        let provider = MetricsProvider::new(config, "test".to_string());
+        for ms in [10, 60, 80, 200, 2000] {
+            provider.record_request(Duration::from_millis(ms), false);
+        }
+
+        let prometheus = provider.get_prometheus_metrics().await;
+        assert!(
+            prometheus
+                .data
+                .contains("# TYPE octofhir_response_time_seconds histogram")
+        );
+
+        let buckets: Vec<(String, u64)> = prometheus
+            .data
+            .lines()
+            .filter_map(|line| line.strip_prefix("octofhir_response_time_seconds_bucket{le=\""))
+            .map(|rest| {
+                let (le, count) = rest.split_once("\"} ").expect("well-formed bucket line");
+                (le.to_string(), count.parse().expect("integer bucket count"))
+            })
+            .collect();
+
+        let bounds: Vec<&str> = buckets.iter().map(|(le, _)| le.as_str()).collect();
+        assert_eq!(bounds, vec!["0.05", "0.1", "0.5", "+Inf"]);
+        assert!(buckets.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        assert_eq!(buckets.last().unwrap().1, 5);
+        assert!(
+            prometheus
+                .data
+                .contains("octofhir_response_time_seconds_count 5\n")
+        );
+        assert!(
+            prometheus
+                .data
+                .contains("octofhir_response_time_seconds_sum 2.35\n")
+        );
+
+        // Existing gauges are still exported
+        assert!(prometheus.data.contains("octofhir_response_time_avg_ms"));
+        assert!(prometheus.data.contains("octofhir_response_time_p95_ms"));
+    }
+
+    #[tokio::test]
+    async fn test_cache_stats_metrics() {
+        let provider = MetricsProvider::default();
+        let stats = CacheStats {
+            hits: 3,
+            misses: 1,
+            evictions: 0,
+            entries: 1,
+            capacity: 10,
+        };
+
+        provider.record_cache_stats(&stats).await;
+
+        let metrics = provider.get_custom_metrics().await;
+        assert_eq!(metrics.get("expression_cache_hits"), Some(&3.0));
+        assert_eq!(metrics.get("expression_cache_misses"), Some(&1.0));
+    }
+
+    #[tokio::test]
+    async fn test_result_cache_stats_metrics() {
+        let provider = MetricsProvider::default();
+        let stats = CacheStats {
+            hits: 3,
+            misses: 1,
+            evictions: 0,
+            entries: 1,
+            capacity: 10,
+        };
+
+        provider.record_result_cache_stats(&stats).await;
+
+        let metrics = provider.get_custom_metrics().await;
+        assert_eq!(metrics.get("result_cache_hits"), Some(&3.0));
+        assert_eq!(metrics.get("result_cache_hit_rate_percent"), Some(&75.0));
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_cache_metrics() {
+        let provider = MetricsProvider::default();
+
+        // Reported as zero before any cache has been looked at
+        let prometheus = provider.get_prometheus_metrics().await;
+        assert!(
+            prometheus
+                .data
+                .contains("# TYPE octofhir_cache_hits counter")
+        );
+        assert!(
+            prometheus
+                .data
+                .contains("octofhir_cache_hits{cache=\"result\"} 0\n")
+        );
+        let snapshot = provider.get_metrics_snapshot().await;
+        assert_eq!(snapshot.caches[EXPRESSION_CACHE], CacheMetrics::default());
+
+        let cache = crate::cache::CacheProvider::with_capacity(1);
+        cache.get_or_parse("Patient.id").unwrap();
+        cache.get_or_parse("Patient.id").unwrap();
+        cache.get_or_parse("Patient.id").unwrap();
+        cache.get_or_parse("Patient.name").unwrap();
+        provider.record_cache_stats(&cache.stats()).await;
+
+        let prometheus = provider.get_prometheus_metrics().await;
+        for line in [
+            "octofhir_cache_hits{cache=\"expression\"} 2\n",
+            "octofhir_cache_misses{cache=\"expression\"} 2\n",
+            "octofhir_cache_evictions{cache=\"expression\"} 1\n",
+            "octofhir_cache_entries{cache=\"expression\"} 1\n",
+            "octofhir_cache_hit_rate{cache=\"expression\"} 0.5\n",
+            "octofhir_cache_hits{cache=\"result\"} 0\n",
+        ] {
+            assert!(prometheus.data.contains(line), "missing {line}");
+        }
+
+        let snapshot = provider.get_metrics_snapshot().await;
+        assert_eq!(snapshot.caches[EXPRESSION_CACHE].evictions, 1);
+        assert_eq!(snapshot.caches[RESULT_CACHE].hit_rate, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_evaluations_by_type_metrics() {
+        let provider = MetricsProvider::default();
+
+        provider
+            .record_evaluation_by_type("Patient", Duration::from_millis(2))
+            .await;
+        provider
+            .record_evaluation_by_type("Patient", Duration::from_millis(4))
+            .await;
+        provider
+            .record_evaluation_by_type("unknown", Duration::from_millis(1))
+            .await;
+        provider
+            .record_evaluation_by_type("Not a type!", Duration::from_millis(1))
+            .await;
+
+        let metrics = provider.get_custom_metrics().await;
+        assert_eq!(metrics.get("evaluations_by_type_Patient"), Some(&2.0));
+        assert_eq!(
+            metrics.get("evaluation_time_us_by_type_Patient"),
+            Some(&6000.0)
+        );
+        assert_eq!(metrics.get("evaluations_by_type_unknown"), Some(&2.0));
+    }
+
+    #[tokio::test]
+    async fn test_per_tool_metrics() {
+        let provider = MetricsProvider::default();
+
+        provider.record_tool_request("fhirpath_evaluate", Duration::from_millis(10), false);
+        provider.record_tool_request("fhirpath_evaluate", Duration::from_millis(30), false);
+        provider.record_tool_request("fhirpath_extract", Duration::from_millis(400), true);
+
+        let snapshot = provider.get_metrics_snapshot().await;
+        assert_eq!(snapshot.performance.total_requests, 3);
+
+        let evaluate = &snapshot.tools["fhirpath_evaluate"];
+        assert_eq!(evaluate.total_requests, 2);
+        assert!((evaluate.average_response_time_ms - 20.0).abs() < 1e-6);
+        assert_eq!(evaluate.error_rate_percent, 0.0);
+
+        let extract = &snapshot.tools["fhirpath_extract"];
+        assert_eq!(extract.total_requests, 1);
+        assert!((extract.p95_response_time_ms - 400.0).abs() < 1e-6);
+        assert_eq!(extract.error_rate_percent, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_restored_from_snapshot() {
+        let dir =
+            std::env::temp_dir().join(format!("octofhir-mcp-metrics-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_for = |dir: &Path| MonitoringConfig {
+            snapshot_path: Some(dir.join("metrics.json")),
+            ..MonitoringConfig::default()
+        };
+        let config = config_for(&dir);
+
+        let provider = MetricsProvider::new(config.clone(), "test".to_string());
+        provider.record_request(Duration::from_millis(5), false);
+        provider.record_tool_request("fhirpath_evaluate", Duration::from_millis(10), false);
+        provider.record_tool_request("fhirpath_evaluate", Duration::from_millis(30), true);
+        provider
+            .increment_custom_metric("bundles_processed", 4)
+            .await;
+        provider.persist_snapshot().await.unwrap();
+        assert!(!dir.join("metrics.json.tmp").exists());
+
+        let restored = MetricsProvider::new(config, "test".to_string());
+        let snapshot = restored.get_metrics_snapshot().await;
+        assert_eq!(snapshot.performance.total_requests, 3);
+        assert_eq!(
+            snapshot.performance.error_rate_percent,
+            provider.get_performance_metrics().error_rate_percent
+        );
+        assert_eq!(snapshot.performance.average_response_time_ms, 15.0);
+        let evaluate = &snapshot.tools["fhirpath_evaluate"];
+        assert_eq!(evaluate.total_requests, 2);
+        assert_eq!(evaluate.error_rate_percent, 50.0);
+        assert_eq!(evaluate.average_response_time_ms, 20.0);
+        assert_eq!(snapshot.custom_metrics["bundles_processed"], 4.0);
+        assert_eq!(
+            restored
+                .health_monitor()
+                .get_response_time_histogram()
+                .count,
+            3
+        );
+
+        restored.record_request(Duration::from_millis(5), false);
+        assert_eq!(restored.get_performance_metrics().total_requests, 4);
+
+        // Snapshots written before errors and latency were persisted still restore totals
+        std::fs::write(
+            dir.join("metrics.json"),
+            r#"{"timestamp": null, "total_requests": 7, "tool_requests": {"fhirpath_parse": 2}}"#,
+        )
+        .unwrap();
+        let legacy = MetricsProvider::new(config_for(&dir), "test".to_string());
+        assert_eq!(legacy.get_performance_metrics().total_requests, 7);
+        assert_eq!(
+            legacy.health_monitor().get_tool_metrics()["fhirpath_parse"].total_requests,
+            2
+        );
+
+        // A corrupt snapshot is ignored rather than failing startup
+        std::fs::write(dir.join("metrics.json"), "not json").unwrap();
+        let fresh = MetricsProvider::new(config_for(&dir), "test".to_string());
+        assert_eq!(fresh.get_performance_metrics().total_requests, 0);
+    }
+
+    #[tokio::test]
+    async fn test_reset_metrics() {
+        let provider = MetricsProvider::default();
+        provider.record_request(Duration::from_millis(5), true);
+        provider.record_tool_request("fhirpath_evaluate", Duration::from_millis(10), false);
+        provider
+            .increment_custom_metric("bundles_processed", 4)
+            .await;
+        let uptime = provider.health_monitor().uptime();
+
+        provider.reset().await;
+
+        let snapshot = provider.get_metrics_snapshot().await;
+        assert_eq!(snapshot.performance.total_requests, 0);
+        assert_eq!(snapshot.performance.error_rate_percent, 0.0);
+        assert_eq!(snapshot.performance.requests_per_minute, 0.0);
+        assert!(snapshot.tools.is_empty());
+        assert!(snapshot.custom_metrics.is_empty());
+        assert!(provider.health_monitor().uptime() >= uptime);
+
+        provider.record_request(Duration::from_millis(5), false);
+        assert_eq!(provider.get_performance_metrics().total_requests, 1);
+    }
+
     #[test]
     fn test_request_recording() {
         let provider = MetricsProvider::default();
@@ -0,0 +1,147 @@
+//! Per-expression evaluation statistics
+//!
+//! Every `fhirpath_evaluate` call is recorded under its trimmed expression, whichever
+//! transport it came through. Only the most recently used expressions are kept.
+
+use crate::cache::LruCache;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+/// Distinct expressions profiled before the least recently used one is dropped
+pub const DEFAULT_PROFILED_EXPRESSIONS: usize = 1000;
+
+/// Latest evaluation times kept per expression for the p95
+const LATENCY_SAMPLES: usize = 100;
+
+static EXPRESSION_PROFILER: LazyLock<ExpressionProfiler> =
+    LazyLock::new(|| ExpressionProfiler::new(DEFAULT_PROFILED_EXPRESSIONS));
+
+/// The profiler fed by `fhirpath_evaluate`
+pub fn expression_profiler() -> &'static ExpressionProfiler {
+    &EXPRESSION_PROFILER
+}
+
+#[derive(Debug, Default)]
+struct ExpressionStats {
+    calls: u64,
+    errors: u64,
+    total_time_ms: f64,
+    recent_times_ms: VecDeque<f64>,
+}
+
+/// Statistics of one expression
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExpressionProfile {
+    pub expression: String,
+    pub calls: u64,
+    pub total_time_ms: f64,
+    pub average_time_ms: f64,
+    /// Over the latest evaluations only
+    pub p95_time_ms: f64,
+    pub error_rate_percent: f64,
+}
+
+/// Call counts, latency and error rate per expression, bounded to the most recently
+/// evaluated expressions
+pub struct ExpressionProfiler {
+    expressions: Mutex<LruCache<String, ExpressionStats>>,
+}
+
+impl ExpressionProfiler {
+    /// Create a profiler tracking at most `capacity` expressions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            expressions: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Record one evaluation of `expression`
+    pub fn record(&self, expression: &str, elapsed: Duration, is_error: bool) {
+        let expression = expression.trim();
+        let mut expressions = self.expressions.lock().unwrap();
+        if !expressions.contains(expression) {
+            expressions.insert(expression.to_string(), ExpressionStats::default());
+        }
+        let Some(stats) = expressions.get_mut(expression) else {
+            // A capacity of zero disables profiling
+            return;
+        };
+
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+        stats.calls += 1;
+        stats.errors += u64::from(is_error);
+        stats.total_time_ms += elapsed_ms;
+        if stats.recent_times_ms.len() == LATENCY_SAMPLES {
+            stats.recent_times_ms.pop_front();
+        }
+        stats.recent_times_ms.push_back(elapsed_ms);
+    }
+
+    /// Statistics of every tracked expression, the most total time first
+    pub fn profile(&self) -> Vec<ExpressionProfile> {
+        let expressions = self.expressions.lock().unwrap();
+        let mut profile: Vec<ExpressionProfile> = expressions
+            .iter()
+            .map(|(expression, stats)| {
+                let mut times: Vec<f64> = stats.recent_times_ms.iter().copied().collect();
+                times.sort_by(f64::total_cmp);
+                let p95 = times
+                    .get(((times.len().saturating_sub(1)) as f64 * 0.95) as usize)
+                    .copied()
+                    .unwrap_or_default();
+                ExpressionProfile {
+                    expression: expression.clone(),
+                    calls: stats.calls,
+                    total_time_ms: stats.total_time_ms,
+                    average_time_ms: stats.total_time_ms / stats.calls as f64,
+                    p95_time_ms: p95,
+                    error_rate_percent: stats.errors as f64 * 100.0 / stats.calls as f64,
+                }
+            })
+            .collect();
+        profile.sort_by(|a, b| b.total_time_ms.total_cmp(&a.total_time_ms));
+        profile
+    }
+
+    /// Forget every expression
+    pub fn clear(&self) {
+        self.expressions.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_per_expression() {
+        let profiler = ExpressionProfiler::new(2);
+        for ms in [10, 20, 30] {
+            profiler.record("Patient.id", Duration::from_millis(ms), false);
+        }
+        profiler.record(" Patient.id\n", Duration::from_millis(40), true);
+        profiler.record("Patient.name", Duration::from_millis(500), false);
+
+        let profile = profiler.profile();
+        assert_eq!(profile.len(), 2);
+        assert_eq!(profile[0].expression, "Patient.name");
+        let id = &profile[1];
+        assert_eq!((id.expression.as_str(), id.calls), ("Patient.id", 4));
+        assert!((id.average_time_ms - 25.0).abs() < 1e-9);
+        assert!((id.p95_time_ms - 30.0).abs() < 1e-9);
+        assert_eq!(id.error_rate_percent, 25.0);
+
+        // The least recently evaluated expression makes way for a new one
+        profiler.record("Patient.gender", Duration::from_millis(1), false);
+        let expressions: Vec<String> = profiler
+            .profile()
+            .into_iter()
+            .map(|profile| profile.expression)
+            .collect();
+        assert_eq!(expressions, vec!["Patient.name", "Patient.gender"]);
+    }
+}
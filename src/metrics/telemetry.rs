@@ -0,0 +1,100 @@
+//! OpenTelemetry export of tracing spans (enabled by the `otel` feature)
+//!
+//! Tool executions are recorded as `tracing` spans; this module bridges them to an
+//! OpenTelemetry tracer so they can be correlated with HTTP requests in a tracing backend.
+
+use anyhow::{Result, anyhow};
+use opentelemetry_sdk::trace::Tracer;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Build a tracing layer exporting spans through `tracer`
+pub fn otel_layer<S>(tracer: Tracer) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+/// Install an OTLP (gRPC) span pipeline and build a layer for it.
+///
+/// The collector endpoint is read from `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// (default `http://localhost:4317`). Must be called within a Tokio runtime.
+pub fn otlp_layer<S>() -> Result<OpenTelemetryLayer<S, Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| anyhow!("Failed to install OpenTelemetry pipeline: {}", e))?;
+
+    Ok(otel_layer(tracer))
+}
+
+/// Flush pending spans and shut down the global tracer provider
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{EvaluateParams, fhirpath_evaluate};
+    use futures_util::future::BoxFuture;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Debug, Clone, Default)]
+    struct CapturingExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for CapturingExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_span_exported() {
+        let exporter = CapturingExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber =
+            tracing_subscriber::registry().with(otel_layer(provider.tracer("octofhir-mcp-test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        fhirpath_evaluate(EvaluateParams {
+            expression: "Patient.name.given".to_string(),
+            resource: json!({"resourceType": "Patient", "name": [{"given": ["Ann", "Marie"]}]}),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        provider.force_flush();
+
+        let spans = exporter.0.lock().unwrap();
+        let span = spans
+            .iter()
+            .find(|span| span.name == "fhirpath_evaluate")
+            .expect("tool span exported");
+        let attribute = |key: &str| {
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+
+        assert_eq!(attribute("expression_length").as_deref(), Some("18"));
+        assert_eq!(attribute("resource_type").as_deref(), Some("Patient"));
+        assert_eq!(attribute("result_count").as_deref(), Some("2"));
+        assert!(attribute("duration_ms").is_some());
+    }
+}
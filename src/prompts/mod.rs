@@ -2,7 +2,76 @@
 //!
 //! Prompts provide templated interactions for common FHIRPath patterns
 
-/// Prompt provider (placeholder)
+use anyhow::{Result, anyhow};
+use rmcp::model::{
+    GetPromptResult, JsonObject, Prompt, PromptArgument, PromptMessage, PromptMessageRole,
+};
+use serde_json::Value;
+
+/// A prompt argument: name, description and whether it must be provided
+type ArgumentSpec = (&'static str, &'static str, bool);
+
+/// A prompt template with `{argument}` placeholders
+struct PromptTemplate {
+    name: &'static str,
+    description: &'static str,
+    arguments: &'static [ArgumentSpec],
+    template: &'static str,
+}
+
+const PROMPTS: &[PromptTemplate] = &[
+    PromptTemplate {
+        name: "explain_expression",
+        description: "Explain what a FHIRPath expression does, step by step",
+        arguments: &[
+            ("expression", "The FHIRPath expression to explain", true),
+            (
+                "resource_type",
+                "FHIR resource type the expression is evaluated against",
+                false,
+            ),
+        ],
+        template: "Explain the following FHIRPath expression step by step, evaluated against a \
+                   {resource_type} resource:\n\n{expression}\n\nDescribe what each path segment \
+                   and function does, what the result collection contains, and any edge cases \
+                   such as empty collections or multiple values.",
+    },
+    PromptTemplate {
+        name: "write_expression",
+        description: "Write a FHIRPath expression that extracts the requested data",
+        arguments: &[
+            (
+                "resource_type",
+                "FHIR resource type to extract data from",
+                true,
+            ),
+            ("goal", "What data the expression should extract", true),
+        ],
+        template: "Write a FHIRPath expression against a {resource_type} resource that extracts: \
+                   {goal}\n\nUse the fhirpath_evaluate tool to check the expression against an \
+                   example resource and explain the final expression.",
+    },
+    PromptTemplate {
+        name: "debug_expression",
+        description: "Find out why a FHIRPath expression does not return the expected result",
+        arguments: &[
+            (
+                "expression",
+                "The FHIRPath expression that misbehaves",
+                true,
+            ),
+            ("expected", "The result the expression should return", false),
+        ],
+        template: "The FHIRPath expression below does not return what I expect:\n\n{expression}\n\n\
+                   Expected result: {expected}\n\nUse the fhirpath_parse and fhirpath_evaluate \
+                   tools to find the problem and suggest a corrected expression.",
+    },
+];
+
+/// Value used for optional arguments that were not provided
+const UNSPECIFIED_ARGUMENT: &str = "(not specified)";
+
+/// Prompt provider serving the curated FHIRPath authoring prompts
 pub struct PromptProvider;
 
 impl Default for PromptProvider {
@@ -15,4 +84,101 @@ impl PromptProvider {
     pub fn new() -> Self {
         Self
     }
+
+    /// List all available prompts
+    pub fn list_prompts(&self) -> Vec<Prompt> {
+        PROMPTS
+            .iter()
+            .map(|prompt| Prompt {
+                name: prompt.name.to_string(),
+                description: Some(prompt.description.to_string()),
+                arguments: Some(
+                    prompt
+                        .arguments
+                        .iter()
+                        .map(|(name, description, required)| PromptArgument {
+                            name: name.to_string(),
+                            description: Some(description.to_string()),
+                            required: Some(*required),
+                        })
+                        .collect(),
+                ),
+            })
+            .collect()
+    }
+
+    /// Render a prompt, substituting the provided arguments into its template
+    pub fn get_prompt(
+        &self,
+        name: &str,
+        arguments: Option<&JsonObject>,
+    ) -> Result<GetPromptResult> {
+        let prompt = PROMPTS
+            .iter()
+            .find(|prompt| prompt.name == name)
+            .ok_or_else(|| anyhow!("Unknown prompt: {}", name))?;
+
+        let mut text = prompt.template.to_string();
+        for (arg_name, _, required) in prompt.arguments {
+            let value = match arguments.and_then(|args| args.get(*arg_name)) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Null) | None if *required => {
+                    return Err(anyhow!(
+                        "Missing required argument '{}' for prompt '{}'",
+                        arg_name,
+                        name
+                    ));
+                }
+                Some(Value::Null) | None => UNSPECIFIED_ARGUMENT.to_string(),
+                Some(other) => other.to_string(),
+            };
+            text = text.replace(&format!("{{{arg_name}}}"), &value);
+        }
+
+        Ok(GetPromptResult {
+            description: Some(prompt.description.to_string()),
+            messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::PromptMessageContent;
+    use serde_json::json;
+
+    fn message_text(result: &GetPromptResult) -> &str {
+        match &result.messages[0].content {
+            PromptMessageContent::Text { text } => text,
+            other => panic!("unexpected prompt content: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_list_prompts() {
+        let prompts = PromptProvider::new().list_prompts();
+        assert!(!prompts.is_empty());
+        assert!(prompts.iter().any(|p| p.name == "explain_expression"));
+    }
+
+    #[test]
+    fn test_get_prompt_substitutes_arguments() {
+        let args = json!({"expression": "Patient.name.given.first()"});
+        let result = PromptProvider::new()
+            .get_prompt("explain_expression", args.as_object())
+            .unwrap();
+
+        let text = message_text(&result);
+        assert!(text.contains("Patient.name.given.first()"));
+        assert!(!text.contains("{expression}"));
+        assert!(!text.contains("{resource_type}"));
+    }
+
+    #[test]
+    fn test_get_prompt_errors() {
+        let provider = PromptProvider::new();
+        assert!(provider.get_prompt("unknown", None).is_err());
+        assert!(provider.get_prompt("write_expression", None).is_err());
+    }
 }
@@ -0,0 +1,334 @@
+//! FHIR reference resolution for `resolve()`
+//!
+//! The engine resolves references through its model provider, which only looks inside the
+//! evaluated resource. [`ReferenceResolver`] wraps the provider so `resolve()` also finds
+//! resources supplied alongside the expression: contained resources first, then bundle
+//! entries by `fullUrl` or `ResourceType/id`. References that still can't be resolved yield
+//! empty and are recorded so callers can report them.
+
+use async_trait::async_trait;
+use octofhir_fhir_model::{conformance::ConformanceResult, constraints::ConstraintInfo};
+use octofhir_fhirpath::{
+    FhirPathValue,
+    model::{
+        ModelProvider,
+        provider::{
+            BoxedValueWithMetadata, ExpressionAnalysis, FhirVersion, ModelError,
+            NavigationValidation, PrimitiveExtensionData, ReferenceComponents, ResolutionContext,
+            SearchParameter, StructureDefinition, TypeReflectionInfo, ValueReflection,
+        },
+    },
+    utils,
+};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// Model provider that resolves references against supplied resources
+#[derive(Debug)]
+pub struct ReferenceResolver {
+    inner: Arc<dyn ModelProvider>,
+    contained: Vec<Value>,
+    bundle_entries: Vec<Value>,
+    unresolved: Mutex<Vec<String>>,
+}
+
+impl ReferenceResolver {
+    /// Wrap `inner`, resolving references against `contained` resources and `bundle` entries
+    pub fn new(
+        inner: Arc<dyn ModelProvider>,
+        contained: Vec<Value>,
+        bundle: Option<Value>,
+    ) -> Self {
+        let bundle_entries = match bundle {
+            Some(Value::Object(mut bundle)) => match bundle.remove("entry") {
+                Some(Value::Array(entries)) => entries,
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+
+        Self {
+            inner,
+            contained,
+            bundle_entries,
+            unresolved: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// References `resolve()` was asked for but could not find, in first-seen order
+    pub fn unresolved(&self) -> Vec<String> {
+        self.unresolved.lock().unwrap().clone()
+    }
+
+    /// Find the supplied resource a reference points to
+    fn find(&self, reference: &str) -> Option<&Value> {
+        if let Some(id) = reference.strip_prefix('#') {
+            return self
+                .contained
+                .iter()
+                .find(|resource| resource.get("id").and_then(Value::as_str) == Some(id));
+        }
+
+        self.contained
+            .iter()
+            .find(|resource| matches_reference(resource, reference))
+            .or_else(|| {
+                self.bundle_entries.iter().find_map(|entry| {
+                    let resource = entry.get("resource")?;
+                    let full_url = entry.get("fullUrl").and_then(Value::as_str);
+                    (full_url == Some(reference) || matches_reference(resource, reference))
+                        .then_some(resource)
+                })
+            })
+    }
+
+    fn record_unresolved(&self, reference: &str) {
+        let mut unresolved = self.unresolved.lock().unwrap();
+        if !unresolved.iter().any(|seen| seen == reference) {
+            unresolved.push(reference.to_string());
+        }
+    }
+}
+
+/// Whether `reference` is `ResourceType/id`, or an absolute URL ending with it, for `resource`
+fn matches_reference(resource: &Value, reference: &str) -> bool {
+    let (Some(resource_type), Some(id)) = (
+        resource.get("resourceType").and_then(Value::as_str),
+        resource.get("id").and_then(Value::as_str),
+    ) else {
+        return false;
+    };
+
+    let relative = format!("{resource_type}/{id}");
+    reference == relative || reference.ends_with(&format!("/{relative}"))
+}
+
+#[async_trait]
+impl ModelProvider for ReferenceResolver {
+    async fn get_type_reflection(&self, type_name: &str) -> Option<TypeReflectionInfo> {
+        self.inner.get_type_reflection(type_name).await
+    }
+
+    async fn get_element_reflection(
+        &self,
+        type_name: &str,
+        element_path: &str,
+    ) -> Option<TypeReflectionInfo> {
+        self.inner
+            .get_element_reflection(type_name, element_path)
+            .await
+    }
+
+    async fn get_property_type(
+        &self,
+        parent_type: &str,
+        property: &str,
+    ) -> Option<TypeReflectionInfo> {
+        self.inner.get_property_type(parent_type, property).await
+    }
+
+    async fn get_structure_definition(&self, type_name: &str) -> Option<StructureDefinition> {
+        self.inner.get_structure_definition(type_name).await
+    }
+
+    async fn validate_conformance(
+        &self,
+        value: &dyn ValueReflection,
+        profile_url: &str,
+    ) -> Result<ConformanceResult, ModelError> {
+        self.inner.validate_conformance(value, profile_url).await
+    }
+
+    async fn get_constraints(&self, type_name: &str) -> Vec<ConstraintInfo> {
+        self.inner.get_constraints(type_name).await
+    }
+
+    async fn resolve_reference(
+        &self,
+        reference_url: &str,
+        context: &dyn ResolutionContext,
+    ) -> Option<Box<dyn ValueReflection>> {
+        self.inner.resolve_reference(reference_url, context).await
+    }
+
+    async fn resolve_reference_in_context(
+        &self,
+        reference_url: &str,
+        root_resource: &FhirPathValue,
+        current_resource: Option<&FhirPathValue>,
+    ) -> Option<FhirPathValue> {
+        // The evaluated resource's own contained resources and bundle entries come first
+        if let Some(resolved) = self
+            .inner
+            .resolve_reference_in_context(reference_url, root_resource, current_resource)
+            .await
+        {
+            return Some(resolved);
+        }
+
+        match self
+            .find(reference_url)
+            .and_then(|resource| utils::serde_to_sonic(resource).ok())
+        {
+            Some(resource) => Some(FhirPathValue::resource_from_json(resource)),
+            None => {
+                self.record_unresolved(reference_url);
+                None
+            }
+        }
+    }
+
+    async fn resolve_in_bundle(
+        &self,
+        reference_url: &str,
+        bundle: &FhirPathValue,
+    ) -> Option<FhirPathValue> {
+        self.inner.resolve_in_bundle(reference_url, bundle).await
+    }
+
+    async fn resolve_in_contained(
+        &self,
+        reference_url: &str,
+        containing_resource: &FhirPathValue,
+    ) -> Option<FhirPathValue> {
+        self.inner
+            .resolve_in_contained(reference_url, containing_resource)
+            .await
+    }
+
+    async fn resolve_external_reference(&self, reference_url: &str) -> Option<FhirPathValue> {
+        self.inner.resolve_external_reference(reference_url).await
+    }
+
+    fn parse_reference_url(&self, reference_url: &str) -> Option<ReferenceComponents> {
+        self.inner.parse_reference_url(reference_url)
+    }
+
+    fn get_base_fhir_url(&self) -> Option<String> {
+        self.inner.get_base_fhir_url()
+    }
+
+    async fn analyze_expression(&self, expression: &str) -> Result<ExpressionAnalysis, ModelError> {
+        self.inner.analyze_expression(expression).await
+    }
+
+    async fn box_value_with_metadata(
+        &self,
+        value: &dyn ValueReflection,
+        type_name: &str,
+    ) -> Result<BoxedValueWithMetadata, ModelError> {
+        self.inner.box_value_with_metadata(value, type_name).await
+    }
+
+    async fn extract_primitive_extensions(
+        &self,
+        value: &dyn ValueReflection,
+        element_path: &str,
+    ) -> Option<PrimitiveExtensionData> {
+        self.inner
+            .extract_primitive_extensions(value, element_path)
+            .await
+    }
+
+    async fn find_extensions_by_url(
+        &self,
+        value: &FhirPathValue,
+        parent_resource: &FhirPathValue,
+        element_path: Option<&str>,
+        url: &str,
+    ) -> Vec<FhirPathValue> {
+        self.inner
+            .find_extensions_by_url(value, parent_resource, element_path, url)
+            .await
+    }
+
+    async fn get_search_params(&self, resource_type: &str) -> Vec<SearchParameter> {
+        self.inner.get_search_params(resource_type).await
+    }
+
+    async fn is_resource_type(&self, type_name: &str) -> bool {
+        self.inner.is_resource_type(type_name).await
+    }
+
+    fn fhir_version(&self) -> FhirVersion {
+        self.inner.fhir_version()
+    }
+
+    async fn is_subtype_of(&self, child_type: &str, parent_type: &str) -> bool {
+        self.inner.is_subtype_of(child_type, parent_type).await
+    }
+
+    async fn is_type_compatible(&self, resource_type: &str, target_type: &str) -> bool {
+        self.inner
+            .is_type_compatible(resource_type, target_type)
+            .await
+    }
+
+    async fn validates_resource_against_profile(
+        &self,
+        resource: &FhirPathValue,
+        profile_url: &str,
+    ) -> Result<bool, ModelError> {
+        self.inner
+            .validates_resource_against_profile(resource, profile_url)
+            .await
+    }
+
+    async fn get_properties(&self, type_name: &str) -> Vec<(String, TypeReflectionInfo)> {
+        self.inner.get_properties(type_name).await
+    }
+
+    async fn get_base_type(&self, type_name: &str) -> Option<String> {
+        self.inner.get_base_type(type_name).await
+    }
+
+    async fn validate_navigation_path(
+        &self,
+        type_name: &str,
+        path: &str,
+    ) -> Result<NavigationValidation, ModelError> {
+        self.inner.validate_navigation_path(type_name, path).await
+    }
+
+    fn extract_type_name(&self, type_arg: &FhirPathValue) -> Result<String, ModelError> {
+        self.inner.extract_type_name(type_arg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use octofhir_fhirpath::MockModelProvider;
+    use serde_json::json;
+
+    fn resolver() -> ReferenceResolver {
+        ReferenceResolver::new(
+            Arc::new(MockModelProvider::new()),
+            vec![json!({"resourceType": "Practitioner", "id": "dr"})],
+            Some(json!({
+                "resourceType": "Bundle",
+                "entry": [{
+                    "fullUrl": "urn:uuid:6d1b6c3e",
+                    "resource": {"resourceType": "Patient", "id": "p1"}
+                }]
+            })),
+        )
+    }
+
+    #[test]
+    fn test_find_references() {
+        let resolver = resolver();
+        let id = |reference: &str| {
+            resolver
+                .find(reference)
+                .and_then(|resource| resource["id"].as_str())
+        };
+
+        assert_eq!(id("#dr"), Some("dr"));
+        assert_eq!(id("Practitioner/dr"), Some("dr"));
+        assert_eq!(id("urn:uuid:6d1b6c3e"), Some("p1"));
+        assert_eq!(id("Patient/p1"), Some("p1"));
+        assert_eq!(id("https://example.org/fhir/Patient/p1"), Some("p1"));
+        assert_eq!(id("Patient/p2"), None);
+    }
+}
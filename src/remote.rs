@@ -0,0 +1,162 @@
+//! Fetching of resources from FHIR servers for `fhirpath_evaluate_remote`
+//!
+//! Only servers on the allow list of the security configuration are contacted; the base URL
+//! is checked by [`validate_fhir_server_base`] before anything is fetched. Redirects are not
+//! followed, so an allowed server can't send the request on to another host, and responses
+//! larger than `max_resource_size` are abandoned rather than read into memory.
+//!
+//! [`validate_fhir_server_base`]: crate::security::validation::InputValidator::validate_fhir_server_base
+
+use crate::security::ResourceTooLarge;
+use anyhow::Result;
+use reqwest::Url;
+use serde_json::Value;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Longest id a FHIR resource may have
+const MAX_ID_LENGTH: usize = 64;
+
+/// Error returned when `resource_ref` is not a `ResourceType/id` reference
+#[derive(Debug, thiserror::Error)]
+#[error("resource_ref must be a reference of the form ResourceType/id, got '{0}'")]
+pub struct InvalidResourceRef(pub String);
+
+/// Error returned when a resource could not be fetched from a FHIR server
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to fetch {reference} from {base}: {reason}")]
+pub struct RemoteFetchFailed {
+    pub reference: String,
+    pub base: String,
+    pub reason: String,
+}
+
+/// Split a `ResourceType/id` reference, rejecting anything else such as search URLs or
+/// paths that would leave the server's base
+pub fn parse_resource_ref(reference: &str) -> Result<(&str, &str), InvalidResourceRef> {
+    let invalid = || InvalidResourceRef(reference.to_string());
+    let (resource_type, id) = reference.split_once('/').ok_or_else(invalid)?;
+
+    let type_valid = resource_type.starts_with(|c: char| c.is_ascii_uppercase())
+        && resource_type.chars().all(|c| c.is_ascii_alphanumeric());
+    let id_valid = !id.is_empty()
+        && id.len() <= MAX_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && id != "."
+        && id != "..";
+    if type_valid && id_valid {
+        Ok((resource_type, id))
+    } else {
+        Err(invalid())
+    }
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("HTTP client configuration is valid")
+    })
+}
+
+/// Read the resource `reference` from the FHIR server at `base`, giving up after `timeout`.
+/// Responses over `max_size` bytes fail with [`ResourceTooLarge`].
+pub async fn fetch_resource(
+    base: &Url,
+    reference: &str,
+    timeout: Duration,
+    max_size: usize,
+) -> Result<Value> {
+    let (resource_type, id) = parse_resource_ref(reference)?;
+    let failed = |reason: String| RemoteFetchFailed {
+        reference: reference.to_string(),
+        base: base.to_string(),
+        reason,
+    };
+
+    let url = format!(
+        "{}/{resource_type}/{id}",
+        base.as_str().trim_end_matches('/')
+    );
+    let mut response = client()
+        .get(url)
+        .header(reqwest::header::ACCEPT, "application/fhir+json")
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| match e.is_timeout() {
+            true => failed(format!("no response within {}ms", timeout.as_millis())),
+            false => failed(e.to_string()),
+        })?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(failed(format!("server returned {status}")).into());
+    }
+    // Checked up front when announced; chunked responses are counted as they arrive
+    if let Some(length) = response.content_length()
+        && length > max_size as u64
+    {
+        return Err(ResourceTooLarge {
+            size: usize::try_from(length).unwrap_or(usize::MAX),
+            max: max_size,
+        }
+        .into());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| failed(format!("reading the response failed: {e}")))?
+    {
+        if body.len() + chunk.len() > max_size {
+            return Err(ResourceTooLarge {
+                size: body.len() + chunk.len(),
+                max: max_size,
+            }
+            .into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let resource: Value =
+        serde_json::from_slice(&body).map_err(|e| failed(format!("response is not JSON: {e}")))?;
+
+    match resource["resourceType"].as_str() {
+        Some(found) if found == resource_type => Ok(resource),
+        Some(found) => Err(failed(format!("server returned a {found}")).into()),
+        None => Err(failed("response is not a FHIR resource".to_string()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resource_ref() {
+        assert_eq!(
+            parse_resource_ref("Patient/123").unwrap(),
+            ("Patient", "123")
+        );
+        assert_eq!(
+            parse_resource_ref("Observation/a-b.c").unwrap(),
+            ("Observation", "a-b.c")
+        );
+        for invalid in [
+            "Patient",
+            "Patient/",
+            "patient/1",
+            "Patient/../metadata",
+            "Patient/1/_history/2",
+            "Patient?name=x",
+            "Patient/1?_format=xml",
+            "../Patient/1",
+        ] {
+            assert!(parse_resource_ref(invalid).is_err(), "{invalid}");
+        }
+    }
+}
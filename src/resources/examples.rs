@@ -1,6 +1,8 @@
 //! FHIR example resources
 
-/// FHIR example provider (placeholder)
+use serde_json::{Value, json};
+
+/// FHIR example provider serving sample resources to evaluate expressions against
 pub struct ExampleProvider;
 
 impl Default for ExampleProvider {
@@ -13,4 +15,84 @@ impl ExampleProvider {
     pub fn new() -> Self {
         Self
     }
+
+    /// Resource types with an available example
+    pub fn resource_types(&self) -> &'static [&'static str] {
+        &["Patient", "Observation", "Condition", "Encounter"]
+    }
+
+    /// Get the example resource for a resource type
+    pub fn get_example(&self, resource_type: &str) -> Option<Value> {
+        let example = match resource_type {
+            "Patient" => json!({
+                "resourceType": "Patient",
+                "id": "example",
+                "active": true,
+                "name": [
+                    {"use": "official", "family": "Chalmers", "given": ["Peter", "James"]},
+                    {"use": "usual", "given": ["Jim"]}
+                ],
+                "telecom": [
+                    {"system": "phone", "value": "(03) 5555 6473", "use": "work"}
+                ],
+                "gender": "male",
+                "birthDate": "1974-12-25",
+                "address": [
+                    {"use": "home", "line": ["534 Erewhon St"], "city": "PleasantVille", "postalCode": "3999"}
+                ]
+            }),
+            "Observation" => json!({
+                "resourceType": "Observation",
+                "id": "example",
+                "status": "final",
+                "category": [{
+                    "coding": [{
+                        "system": "http://terminology.hl7.org/CodeSystem/observation-category",
+                        "code": "vital-signs"
+                    }]
+                }],
+                "code": {
+                    "coding": [{"system": "http://loinc.org", "code": "29463-7", "display": "Body Weight"}]
+                },
+                "subject": {"reference": "Patient/example"},
+                "effectiveDateTime": "2016-03-28",
+                "valueQuantity": {
+                    "value": 185,
+                    "unit": "lbs",
+                    "system": "http://unitsofmeasure.org",
+                    "code": "[lb_av]"
+                }
+            }),
+            "Condition" => json!({
+                "resourceType": "Condition",
+                "id": "example",
+                "clinicalStatus": {
+                    "coding": [{
+                        "system": "http://terminology.hl7.org/CodeSystem/condition-clinical",
+                        "code": "active"
+                    }]
+                },
+                "code": {
+                    "coding": [{"system": "http://snomed.info/sct", "code": "39065001", "display": "Burn of ear"}],
+                    "text": "Burnt Ear"
+                },
+                "subject": {"reference": "Patient/example"},
+                "onsetDateTime": "2012-05-24"
+            }),
+            "Encounter" => json!({
+                "resourceType": "Encounter",
+                "id": "example",
+                "status": "finished",
+                "class": {
+                    "system": "http://terminology.hl7.org/CodeSystem/v3-ActCode",
+                    "code": "AMB"
+                },
+                "subject": {"reference": "Patient/example"},
+                "period": {"start": "2015-01-17T16:00:00+10:00", "end": "2015-01-17T16:30:00+10:00"}
+            }),
+            _ => return None,
+        };
+
+        Some(example)
+    }
 }
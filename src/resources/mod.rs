@@ -4,3 +4,141 @@
 
 pub mod examples;
 pub mod schemas;
+
+use anyhow::{Result, anyhow};
+use examples::ExampleProvider;
+use rmcp::model::{AnnotateAble, RawResource, ReadResourceResult, Resource, ResourceContents};
+use schemas::SchemaProvider;
+
+/// URI prefix for resource schema summaries
+pub const SCHEMA_URI_PREFIX: &str = "fhir://schema/";
+/// URI prefix for example resources
+pub const EXAMPLE_URI_PREFIX: &str = "fhir://example/";
+
+const JSON_MIME_TYPE: &str = "application/json";
+const FHIR_JSON_MIME_TYPE: &str = "application/fhir+json";
+
+/// Resource provider combining the schema and example providers
+pub struct ResourceProvider {
+    schemas: SchemaProvider,
+    examples: ExampleProvider,
+}
+
+impl Default for ResourceProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResourceProvider {
+    pub fn new() -> Self {
+        Self {
+            schemas: SchemaProvider::new(),
+            examples: ExampleProvider::new(),
+        }
+    }
+
+    /// List all schema and example resources
+    pub fn list_resources(&self) -> Vec<Resource> {
+        let schemas = self
+            .schemas
+            .resource_types()
+            .map(|(resource_type, description)| RawResource {
+                uri: format!("{SCHEMA_URI_PREFIX}{resource_type}"),
+                name: format!("{resource_type} schema"),
+                description: Some(description.to_string()),
+                mime_type: Some(JSON_MIME_TYPE.to_string()),
+                size: None,
+            });
+
+        let examples = self
+            .examples
+            .resource_types()
+            .iter()
+            .map(|resource_type| RawResource {
+                uri: format!("{EXAMPLE_URI_PREFIX}{resource_type}"),
+                name: format!("{resource_type} example"),
+                description: Some(format!("Example {resource_type} resource")),
+                mime_type: Some(FHIR_JSON_MIME_TYPE.to_string()),
+                size: None,
+            });
+
+        schemas
+            .chain(examples)
+            .map(|resource| resource.no_annotation())
+            .collect()
+    }
+
+    /// Read a resource by URI
+    pub fn read_resource(&self, uri: &str) -> Result<ReadResourceResult> {
+        let (content, mime_type) = if let Some(resource_type) = uri.strip_prefix(SCHEMA_URI_PREFIX)
+        {
+            (self.schemas.get_schema(resource_type), JSON_MIME_TYPE)
+        } else if let Some(resource_type) = uri.strip_prefix(EXAMPLE_URI_PREFIX) {
+            (
+                self.examples.get_example(resource_type),
+                FHIR_JSON_MIME_TYPE,
+            )
+        } else {
+            (None, JSON_MIME_TYPE)
+        };
+
+        let content = content.ok_or_else(|| anyhow!("Resource not found: {}", uri))?;
+
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri: uri.to_string(),
+                mime_type: Some(mime_type.to_string()),
+                text: serde_json::to_string_pretty(&content)?,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_resources() {
+        let resources = ResourceProvider::new().list_resources();
+        assert!(resources.iter().any(|r| r.uri == "fhir://schema/Patient"));
+        assert!(
+            resources
+                .iter()
+                .any(|r| r.uri == "fhir://example/Observation")
+        );
+    }
+
+    #[test]
+    fn test_read_schema_resource() {
+        let result = ResourceProvider::new()
+            .read_resource("fhir://schema/Patient")
+            .unwrap();
+
+        let ResourceContents::TextResourceContents {
+            mime_type, text, ..
+        } = &result.contents[0]
+        else {
+            panic!("expected text contents");
+        };
+        assert_eq!(mime_type.as_deref(), Some("application/json"));
+
+        let schema: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(schema["resourceType"], "Patient");
+        assert!(
+            schema["elements"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|e| e["path"] == "Patient.birthDate")
+        );
+    }
+
+    #[test]
+    fn test_read_unknown_resource() {
+        let provider = ResourceProvider::new();
+        assert!(provider.read_resource("fhir://schema/Unknown").is_err());
+        assert!(provider.read_resource("file:///etc/passwd").is_err());
+    }
+}
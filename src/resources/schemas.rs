@@ -1,6 +1,99 @@
 //! FHIR schema resources
 
-/// FHIR schema provider (placeholder)
+use serde_json::{Value, json};
+
+/// Element summary: name, type and cardinality
+type ElementSpec = (&'static str, &'static str, &'static str);
+
+/// Top-level elements of commonly queried FHIR R4 resources
+const SCHEMAS: &[(&str, &str, &[ElementSpec])] = &[
+    (
+        "Patient",
+        "Demographics and administrative information about a person receiving care",
+        &[
+            ("id", "id", "0..1"),
+            ("identifier", "Identifier", "0..*"),
+            ("active", "boolean", "0..1"),
+            ("name", "HumanName", "0..*"),
+            ("telecom", "ContactPoint", "0..*"),
+            ("gender", "code", "0..1"),
+            ("birthDate", "date", "0..1"),
+            ("deceased[x]", "boolean | dateTime", "0..1"),
+            ("address", "Address", "0..*"),
+            ("maritalStatus", "CodeableConcept", "0..1"),
+            ("contact", "BackboneElement", "0..*"),
+            ("generalPractitioner", "Reference", "0..*"),
+            ("managingOrganization", "Reference", "0..1"),
+        ],
+    ),
+    (
+        "Observation",
+        "Measurements and simple assertions made about a patient or subject",
+        &[
+            ("id", "id", "0..1"),
+            ("identifier", "Identifier", "0..*"),
+            ("status", "code", "1..1"),
+            ("category", "CodeableConcept", "0..*"),
+            ("code", "CodeableConcept", "1..1"),
+            ("subject", "Reference", "0..1"),
+            ("encounter", "Reference", "0..1"),
+            (
+                "effective[x]",
+                "dateTime | Period | Timing | instant",
+                "0..1",
+            ),
+            ("issued", "instant", "0..1"),
+            (
+                "value[x]",
+                "Quantity | CodeableConcept | string | boolean | integer | Range | Ratio | \
+                 SampledData | time | dateTime | Period",
+                "0..1",
+            ),
+            ("interpretation", "CodeableConcept", "0..*"),
+            ("referenceRange", "BackboneElement", "0..*"),
+            ("component", "BackboneElement", "0..*"),
+        ],
+    ),
+    (
+        "Condition",
+        "A clinical condition, problem, diagnosis or other health matter",
+        &[
+            ("id", "id", "0..1"),
+            ("identifier", "Identifier", "0..*"),
+            ("clinicalStatus", "CodeableConcept", "0..1"),
+            ("verificationStatus", "CodeableConcept", "0..1"),
+            ("category", "CodeableConcept", "0..*"),
+            ("severity", "CodeableConcept", "0..1"),
+            ("code", "CodeableConcept", "0..1"),
+            ("subject", "Reference", "1..1"),
+            ("encounter", "Reference", "0..1"),
+            (
+                "onset[x]",
+                "dateTime | Age | Period | Range | string",
+                "0..1",
+            ),
+            ("recordedDate", "dateTime", "0..1"),
+        ],
+    ),
+    (
+        "Encounter",
+        "An interaction between a patient and healthcare providers",
+        &[
+            ("id", "id", "0..1"),
+            ("identifier", "Identifier", "0..*"),
+            ("status", "code", "1..1"),
+            ("class", "Coding", "1..1"),
+            ("type", "CodeableConcept", "0..*"),
+            ("subject", "Reference", "0..1"),
+            ("participant", "BackboneElement", "0..*"),
+            ("period", "Period", "0..1"),
+            ("reasonCode", "CodeableConcept", "0..*"),
+            ("serviceProvider", "Reference", "0..1"),
+        ],
+    ),
+];
+
+/// FHIR schema provider serving element summaries for common resource types
 pub struct SchemaProvider;
 
 impl Default for SchemaProvider {
@@ -13,4 +106,35 @@ impl SchemaProvider {
     pub fn new() -> Self {
         Self
     }
+
+    /// Resource types with an available schema, with a short description of each
+    pub fn resource_types(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+        SCHEMAS
+            .iter()
+            .map(|(resource_type, description, _)| (*resource_type, *description))
+    }
+
+    /// Get the schema summary for a resource type
+    pub fn get_schema(&self, resource_type: &str) -> Option<Value> {
+        let (name, description, elements) =
+            SCHEMAS.iter().find(|(name, _, _)| *name == resource_type)?;
+
+        let elements: Vec<Value> = elements
+            .iter()
+            .map(|(element, element_type, cardinality)| {
+                json!({
+                    "path": format!("{name}.{element}"),
+                    "type": element_type,
+                    "cardinality": cardinality,
+                })
+            })
+            .collect();
+
+        Some(json!({
+            "resourceType": name,
+            "fhirVersion": "R4",
+            "description": description,
+            "elements": elements,
+        }))
+    }
 }
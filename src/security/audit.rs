@@ -0,0 +1,241 @@
+//! Append-only audit trail of tool calls
+//!
+//! Each call is written as one JSON line naming the caller, the tool, the expressions it
+//! evaluated and the outcome. Resource payloads are never recorded: they may contain
+//! protected health information, so only the resource type is kept.
+//!
+//! Records are handed over a channel to a dedicated writer thread, so auditing never blocks
+//! the async runtime on file I/O. The thread writes buffered records out every
+//! [`FLUSH_INTERVAL`] and when the last handle to the log is dropped.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+/// Sink name selecting standard output instead of a file
+pub const STDOUT_SINK: &str = "stdout";
+
+/// How often the writer thread writes buffered records out
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Argument fields holding expressions, recorded verbatim
+const EXPRESSION_FIELDS: &[&str] = &["expression", "expression_a", "expression_b"];
+
+/// A single audited tool call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    /// Authenticated caller; absent when authentication is disabled
+    pub subject: Option<String>,
+    pub tool: String,
+    /// Expressions from the call arguments, including `fhirpath_transform` mapping values
+    pub expressions: Vec<String>,
+    /// `resourceType` of the evaluated resource; the resource itself is never recorded
+    pub resource_type: Option<String>,
+    pub correlation_id: Option<String>,
+    pub success: bool,
+    /// JSON-RPC error code of a failed call
+    pub error_code: Option<i32>,
+}
+
+impl AuditRecord {
+    /// Describe a call to `tool` with `arguments`, keeping only what is safe to log
+    pub fn new(
+        tool: &str,
+        arguments: &Value,
+        subject: Option<String>,
+        correlation_id: Option<String>,
+    ) -> Self {
+        let mut expressions: Vec<String> = EXPRESSION_FIELDS
+            .iter()
+            .filter_map(|field| arguments.get(field).and_then(Value::as_str))
+            .map(str::to_string)
+            .collect();
+        if let Some(mapping) = arguments.get("mapping").and_then(Value::as_object) {
+            expressions.extend(
+                mapping
+                    .values()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string),
+            );
+        }
+
+        Self {
+            timestamp: Utc::now(),
+            subject,
+            tool: tool.to_string(),
+            expressions,
+            resource_type: arguments
+                .get("resource")
+                .map(|resource| crate::tools::resource_type(resource).to_string()),
+            correlation_id,
+            success: true,
+            error_code: None,
+        }
+    }
+
+    /// Mark the call as failed with `error_code`
+    pub fn failed(mut self, error_code: i32) -> Self {
+        self.success = false;
+        self.error_code = Some(error_code);
+        self
+    }
+}
+
+/// Buffered newline-delimited JSON writer for [`AuditRecord`]s. Clones share the sink.
+#[derive(Clone)]
+pub struct AuditLog {
+    commands: mpsc::Sender<Command>,
+}
+
+/// Work for the writer thread
+enum Command {
+    Record(Box<AuditRecord>),
+    /// Write buffered records out, then signal the sender
+    Flush(mpsc::Sender<()>),
+}
+
+impl AuditLog {
+    /// Open `sink`: [`STDOUT_SINK`] or the path of a file that records are appended to
+    pub fn open(sink: &str) -> Result<Self> {
+        if sink == STDOUT_SINK {
+            return Self::from_writer(std::io::stdout());
+        }
+
+        let path = Path::new(sink);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        Self::from_writer(file)
+    }
+
+    /// Write records to `writer` from a new writer thread
+    pub fn from_writer(writer: impl Write + Send + 'static) -> Result<Self> {
+        let (commands, received) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || write_records(BufWriter::new(writer), received))
+            .context("Failed to start the audit log writer")?;
+        Ok(Self { commands })
+    }
+
+    /// Queue `record` for writing; never blocks. Failures are logged rather than failing the
+    /// audited call.
+    pub fn record(&self, record: AuditRecord) {
+        if self
+            .commands
+            .send(Command::Record(Box::new(record)))
+            .is_err()
+        {
+            tracing::warn!("Failed to write audit record: the audit log writer has stopped");
+        }
+    }
+
+    /// Write every record queued so far to the sink, blocking until it is written
+    pub fn flush(&self) {
+        let (done, written) = mpsc::channel();
+        if self.commands.send(Command::Flush(done)).is_err() || written.recv().is_err() {
+            tracing::warn!("Failed to flush audit log: the audit log writer has stopped");
+        }
+    }
+}
+
+/// Body of the writer thread, which runs until every [`AuditLog`] handle is dropped
+fn write_records(mut writer: BufWriter<impl Write>, commands: mpsc::Receiver<Command>) {
+    let flush = |writer: &mut BufWriter<_>| {
+        if let Err(e) = writer.flush() {
+            tracing::warn!("Failed to flush audit log: {}", e);
+        }
+    };
+
+    loop {
+        match commands.recv_timeout(FLUSH_INTERVAL) {
+            Ok(Command::Record(record)) => {
+                let result = serde_json::to_writer(&mut writer, &record)
+                    .map_err(std::io::Error::from)
+                    .and_then(|()| writer.write_all(b"\n"));
+                if let Err(e) = result {
+                    tracing::warn!("Failed to write audit record: {}", e);
+                }
+            }
+            Ok(Command::Flush(done)) => {
+                flush(&mut writer);
+                let _ = done.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => flush(&mut writer),
+            Err(RecvTimeoutError::Disconnected) => {
+                flush(&mut writer);
+                return;
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_audit_record_omits_resource() {
+        let arguments = json!({
+            "expression": "Patient.name.given",
+            "resource": {"resourceType": "Patient", "name": [{"given": ["Secret"]}]}
+        });
+        let record = AuditRecord::new(
+            "fhirpath_evaluate",
+            &arguments,
+            Some("client-1".to_string()),
+            None,
+        )
+        .failed(-32000);
+
+        assert_eq!(record.expressions, vec!["Patient.name.given"]);
+        assert_eq!(record.resource_type.as_deref(), Some("Patient"));
+        assert_eq!(record.error_code, Some(-32000));
+        assert!(!serde_json::to_string(&record).unwrap().contains("Secret"));
+
+        let arguments = json!({"mapping": {"family": "name.family"}, "resource": {}});
+        let record = AuditRecord::new("fhirpath_transform", &arguments, None, None);
+        assert_eq!(record.expressions, vec!["name.family"]);
+        assert!(record.success);
+    }
+
+    #[test]
+    fn test_audit_log_appends_to_file() {
+        let path =
+            std::env::temp_dir().join(format!("octofhir-audit-{}.ndjson", uuid::Uuid::new_v4()));
+        let sink = path.to_str().unwrap();
+        let record = AuditRecord::new("fhirpath_parse", &json!({"expression": "id"}), None, None);
+
+        for _ in 0..2 {
+            let log = AuditLog::open(sink).unwrap();
+            log.record(record.clone());
+            log.flush();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<AuditRecord> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records, vec![record.clone(), record]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
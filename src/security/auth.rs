@@ -1,24 +1,164 @@
-use anyhow::{Result, anyhow};
-use jsonwebtoken::{DecodingKey, TokenData, Validation, decode};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, TokenData, Validation, decode, errors::ErrorKind as JwtErrorKind,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use uuid::Uuid;
 
+/// Why a request was rejected, recorded in logs and metrics but never sent to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthFailureReason {
+    MissingHeader,
+    /// The header is not `Bearer <credential>` or the token cannot be decoded
+    MalformedHeader,
+    UnknownApiKey,
+    /// The token has expired, is not valid yet or was issued by an unexpected issuer
+    ExpiredToken,
+    /// The token signature does not verify, or no JWT secret is configured to verify it
+    InvalidSignature,
+    /// The caller authenticated but may not call the requested tool
+    OutOfScope,
+}
+
+impl AuthFailureReason {
+    /// Stable name used as a log field and metric suffix
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthFailureReason::MissingHeader => "missing_header",
+            AuthFailureReason::MalformedHeader => "malformed_header",
+            AuthFailureReason::UnknownApiKey => "unknown_api_key",
+            AuthFailureReason::ExpiredToken => "expired_token",
+            AuthFailureReason::InvalidSignature => "invalid_signature",
+            AuthFailureReason::OutOfScope => "out_of_scope",
+        }
+    }
+}
+
+impl fmt::Display for AuthFailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error returned when a request fails authentication
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct AuthError {
+    pub reason: AuthFailureReason,
+    message: String,
+}
+
+impl AuthError {
+    pub fn new(reason: AuthFailureReason, message: impl Into<String>) -> Self {
+        Self {
+            reason,
+            message: message.into(),
+        }
+    }
+}
+
+type Result<T, E = AuthError> = std::result::Result<T, E>;
+
+/// Tools a caller is allowed to invoke
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolScope {
+    All,
+    Tools(HashSet<String>),
+}
+
+impl ToolScope {
+    pub fn allows(&self, tool: &str) -> bool {
+        match self {
+            ToolScope::All => true,
+            ToolScope::Tools(tools) => tools.contains(tool),
+        }
+    }
+}
+
+/// An API key as written in configuration: a plain key granting every tool, or a key with
+/// the tools it may call. Its `Debug` output shows a digest prefix instead of the key.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ApiKeyConfig {
+    Key(String),
+    Scoped { key: String, tools: Vec<String> },
+}
+
+impl ApiKeyConfig {
+    pub fn key(&self) -> &str {
+        match self {
+            ApiKeyConfig::Key(key) | ApiKeyConfig::Scoped { key, .. } => key,
+        }
+    }
+
+    pub fn scope(&self) -> ToolScope {
+        match self {
+            ApiKeyConfig::Key(_) => ToolScope::All,
+            ApiKeyConfig::Scoped { tools, .. } => ToolScope::Tools(tools.iter().cloned().collect()),
+        }
+    }
+}
+
+impl std::fmt::Debug for ApiKeyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let key = format!("api_key:{}", key_id(self.key()));
+        match self {
+            ApiKeyConfig::Key(_) => f.debug_tuple("Key").field(&key).finish(),
+            ApiKeyConfig::Scoped { tools, .. } => f
+                .debug_struct("Scoped")
+                .field("key", &key)
+                .field("tools", tools)
+                .finish(),
+        }
+    }
+}
+
+/// Hex SHA-256 digest of an API key
+fn key_digest(api_key: &str) -> String {
+    format!("{:x}", Sha256::digest(api_key.as_bytes()))
+}
+
+/// Short identifier of an API key for subjects and logs, revealing nothing of the key
+fn key_id(api_key: &str) -> String {
+    key_digest(api_key)[..8].to_string()
+}
+
+impl From<String> for ApiKeyConfig {
+    fn from(key: String) -> Self {
+        ApiKeyConfig::Key(key)
+    }
+}
+
+impl From<&str> for ApiKeyConfig {
+    fn from(key: &str) -> Self {
+        ApiKeyConfig::Key(key.to_string())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AuthConfig {
     pub enable_auth: bool,
-    pub api_keys: HashSet<String>,
+    /// Accepted API keys with the tools each may call
+    pub api_keys: HashMap<String, ToolScope>,
     pub jwt_secret: Option<String>,
+    /// Required `iss` claim of JWTs; any issuer is accepted when unset
+    pub jwt_issuer: Option<String>,
     pub enable_request_logging: bool,
+    /// Token required by administrative endpoints; they are disabled when unset
+    pub admin_token: Option<String>,
 }
 
 impl Default for AuthConfig {
     fn default() -> Self {
         Self {
             enable_auth: true,
-            api_keys: HashSet::new(),
+            api_keys: HashMap::new(),
             jwt_secret: None,
+            jwt_issuer: None,
             enable_request_logging: true,
+            admin_token: None,
         }
     }
 }
@@ -27,8 +167,12 @@ impl Default for AuthConfig {
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
-    pub iat: usize,
-    pub iss: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +180,19 @@ pub struct AuthenticatedRequest {
     pub request_id: Uuid,
     pub authenticated_by: AuthMethod,
     pub subject: String,
+    /// Tools the caller may invoke
+    pub scope: ToolScope,
+}
+
+impl AuthenticatedRequest {
+    /// Stable identity of the caller for per-client state such as rate limits and idempotency
+    /// keys: API keys are identified by a digest of the whole key, other callers by subject
+    pub fn client_id(&self) -> String {
+        match &self.authenticated_by {
+            AuthMethod::ApiKey(api_key) => format!("api_key:{}", key_digest(api_key)),
+            _ => format!("subject:{}", self.subject),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -60,17 +217,22 @@ impl Authenticator {
                 request_id: Uuid::new_v4(),
                 authenticated_by: AuthMethod::Bypass,
                 subject: "local".to_string(),
+                scope: ToolScope::All,
             });
         }
 
-        if self.config.api_keys.contains(api_key) {
+        if let Some(scope) = self.config.api_keys.get(api_key) {
             Ok(AuthenticatedRequest {
                 request_id: Uuid::new_v4(),
                 authenticated_by: AuthMethod::ApiKey(api_key.to_string()),
-                subject: format!("api_key:{}", &api_key[..8]),
+                subject: format!("api_key:{}", key_id(api_key)),
+                scope: scope.clone(),
             })
         } else {
-            Err(anyhow!("Invalid API key"))
+            Err(AuthError::new(
+                AuthFailureReason::UnknownApiKey,
+                "Invalid API key",
+            ))
         }
     }
 
@@ -80,26 +242,62 @@ impl Authenticator {
                 request_id: Uuid::new_v4(),
                 authenticated_by: AuthMethod::Bypass,
                 subject: "local".to_string(),
+                scope: ToolScope::All,
             });
         }
 
-        let jwt_secret = self
-            .config
-            .jwt_secret
-            .as_ref()
-            .ok_or_else(|| anyhow!("JWT secret not configured"))?;
+        let jwt_secret = self.config.jwt_secret.as_ref().ok_or_else(|| {
+            AuthError::new(
+                AuthFailureReason::InvalidSignature,
+                "JWT secret not configured",
+            )
+        })?;
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_nbf = true;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = &self.config.jwt_issuer {
+            validation.set_issuer(&[issuer]);
+        }
 
         let token_data: TokenData<Claims> = decode::<Claims>(
             token,
             &DecodingKey::from_secret(jwt_secret.as_ref()),
-            &Validation::default(),
+            &validation,
         )
-        .map_err(|e| anyhow!("JWT validation failed: {}", e))?;
+        .map_err(|e| {
+            let (reason, message) = match e.kind() {
+                JwtErrorKind::ExpiredSignature => {
+                    (AuthFailureReason::ExpiredToken, "token has expired".into())
+                }
+                JwtErrorKind::ImmatureSignature => (
+                    AuthFailureReason::ExpiredToken,
+                    "token is not valid yet".into(),
+                ),
+                JwtErrorKind::InvalidIssuer => {
+                    (AuthFailureReason::ExpiredToken, "unexpected issuer".into())
+                }
+                JwtErrorKind::InvalidSignature => (
+                    AuthFailureReason::InvalidSignature,
+                    "invalid signature".into(),
+                ),
+                JwtErrorKind::InvalidAlgorithm => (
+                    AuthFailureReason::MalformedHeader,
+                    "unsupported algorithm, expected HS256".into(),
+                ),
+                _ => (
+                    AuthFailureReason::MalformedHeader,
+                    format!("malformed token: {e}"),
+                ),
+            };
+            AuthError::new(reason, format!("JWT validation failed: {message}"))
+        })?;
 
         Ok(AuthenticatedRequest {
             request_id: Uuid::new_v4(),
             authenticated_by: AuthMethod::JwtToken(token_data.claims.clone()),
             subject: token_data.claims.sub.clone(),
+            scope: ToolScope::All,
         })
     }
 
@@ -111,15 +309,28 @@ impl Authenticator {
                 self.authenticate_api_key(bearer_token)
             }
         } else {
-            Err(anyhow!("Invalid authorization header format"))
+            Err(AuthError::new(
+                AuthFailureReason::MalformedHeader,
+                "Invalid authorization header format",
+            ))
         }
     }
 
+    /// Whether `token` is the configured admin token. Always false when none is configured,
+    /// even with authentication disabled.
+    pub fn is_admin_token(&self, token: &str) -> bool {
+        // Comparing digests keeps the comparison time independent of how much of the token matches
+        self.config.admin_token.as_ref().is_some_and(|admin_token| {
+            Sha256::digest(admin_token.as_bytes()) == Sha256::digest(token.as_bytes())
+        })
+    }
+
     pub fn bypass_for_stdio(&self) -> AuthenticatedRequest {
         AuthenticatedRequest {
             request_id: Uuid::new_v4(),
             authenticated_by: AuthMethod::Bypass,
             subject: "stdio".to_string(),
+            scope: ToolScope::All,
         }
     }
 
@@ -135,24 +346,165 @@ mod tests {
     #[test]
     fn test_api_key_authentication() {
         let mut config = AuthConfig::default();
-        config.api_keys.insert("test-key-123".to_string());
+        config
+            .api_keys
+            .insert("test-key-123".to_string(), ToolScope::All);
         let auth = Authenticator::new(config);
 
         let result = auth.authenticate_api_key("test-key-123");
-        assert!(result.is_ok());
+        assert_eq!(result.unwrap().scope, ToolScope::All);
 
         let result = auth.authenticate_api_key("invalid-key");
-        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().reason, AuthFailureReason::UnknownApiKey);
+
+        // Short and multi-byte keys get a subject too
+        let mut config = AuthConfig::default();
+        for key in ["short", "ключ-доступа"] {
+            config.api_keys.insert(key.to_string(), ToolScope::All);
+        }
+        let auth = Authenticator::new(config);
+        for key in ["short", "ключ-доступа"] {
+            let subject = auth.authenticate_api_key(key).unwrap().subject;
+            assert_eq!(subject.len(), "api_key:".len() + 8);
+            assert!(!subject.contains(key));
+        }
+
+        // Keys sharing a prefix are still different clients
+        let mut config = AuthConfig::default();
+        for key in ["shared-prefix-a", "shared-prefix-b"] {
+            config.api_keys.insert(key.to_string(), ToolScope::All);
+        }
+        let auth = Authenticator::new(config);
+        let first = auth.authenticate_api_key("shared-prefix-a").unwrap();
+        let second = auth.authenticate_api_key("shared-prefix-b").unwrap();
+        assert_ne!(first.client_id(), second.client_id());
+        assert!(!first.client_id().contains("shared-prefix"));
+
+        let result = auth.parse_authorization_header("Basic dGVzdDp0ZXN0");
+        assert_eq!(
+            result.unwrap_err().reason,
+            AuthFailureReason::MalformedHeader
+        );
+    }
+
+    #[test]
+    fn test_api_key_config_parsing() {
+        let keys: Vec<ApiKeyConfig> = serde_json::from_value(serde_json::json!([
+            "full-access-key",
+            {"key": "read-only-key", "tools": ["fhirpath_evaluate"]}
+        ]))
+        .unwrap();
+
+        assert_eq!(keys[0].key(), "full-access-key");
+        assert_eq!(keys[0].scope(), ToolScope::All);
+        assert_eq!(keys[1].key(), "read-only-key");
+        assert!(!format!("{keys:?}").contains("-key"));
+        assert!(keys[1].scope().allows("fhirpath_evaluate"));
+        assert!(!keys[1].scope().allows("fhirpath_transform"));
     }
 
     #[test]
     fn test_disabled_auth() {
-        let mut config = AuthConfig::default();
-        config.enable_auth = false;
+        let config = AuthConfig {
+            enable_auth: false,
+            ..AuthConfig::default()
+        };
         let auth = Authenticator::new(config);
 
         let result = auth.authenticate_api_key("any-key");
         assert!(result.is_ok());
+        assert!(!auth.is_admin_token("any-key"));
+    }
+
+    #[test]
+    fn test_admin_token() {
+        let mut config = AuthConfig::default();
+        config
+            .api_keys
+            .insert("regular-api-key".to_string(), ToolScope::All);
+        config.admin_token = Some("admin-token".to_string());
+        let auth = Authenticator::new(config);
+
+        assert!(auth.is_admin_token("admin-token"));
+        assert!(!auth.is_admin_token("regular-api-key"));
+        assert!(!auth.is_admin_token("admin-token-suffix"));
+        assert!(auth.authenticate_api_key("admin-token").is_err());
+    }
+
+    const SECRET: &str = "test-secret";
+
+    fn jwt_authenticator() -> Authenticator {
+        Authenticator::new(AuthConfig {
+            jwt_secret: Some(SECRET.to_string()),
+            jwt_issuer: Some("octofhir".to_string()),
+            ..AuthConfig::default()
+        })
+    }
+
+    fn sign(claims: &Claims, secret: &str) -> String {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_ref()),
+        )
+        .unwrap()
+    }
+
+    fn claims(exp_offset_secs: i64) -> Claims {
+        let now = jsonwebtoken::get_current_timestamp() as i64;
+        Claims {
+            sub: "user-42".to_string(),
+            exp: (now + exp_offset_secs) as usize,
+            nbf: None,
+            iat: Some(now as usize),
+            iss: Some("octofhir".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_valid_jwt() {
+        let token = sign(&claims(3600), SECRET);
+        let result = jwt_authenticator()
+            .parse_authorization_header(&format!("Bearer {token}"))
+            .unwrap();
+
+        assert_eq!(result.subject, "user-42");
+        assert!(matches!(result.authenticated_by, AuthMethod::JwtToken(_)));
+    }
+
+    #[test]
+    fn test_expired_jwt() {
+        let token = sign(&claims(-3600), SECRET);
+        let err = jwt_authenticator().authenticate_jwt(&token).unwrap_err();
+        assert!(err.to_string().contains("expired"));
+        assert_eq!(err.reason, AuthFailureReason::ExpiredToken);
+    }
+
+    #[test]
+    fn test_jwt_with_wrong_secret() {
+        let token = sign(&claims(3600), "other-secret");
+        let err = jwt_authenticator().authenticate_jwt(&token).unwrap_err();
+        assert!(err.to_string().contains("invalid signature"));
+        assert_eq!(err.reason, AuthFailureReason::InvalidSignature);
+    }
+
+    #[test]
+    fn test_jwt_claim_checks() {
+        let auth = jwt_authenticator();
+
+        let mut wrong_issuer = claims(3600);
+        wrong_issuer.iss = Some("someone-else".to_string());
+        assert!(auth.authenticate_jwt(&sign(&wrong_issuer, SECRET)).is_err());
+
+        let mut not_yet_valid = claims(7200);
+        not_yet_valid.nbf = Some(not_yet_valid.exp - 60);
+        let err = auth
+            .authenticate_jwt(&sign(&not_yet_valid, SECRET))
+            .unwrap_err();
+        assert!(err.to_string().contains("not valid yet"));
+
+        let err = auth.authenticate_jwt("eyJnot-a-token").unwrap_err();
+        assert!(err.to_string().contains("malformed"));
     }
 
     #[test]
@@ -1,21 +1,45 @@
 //! Security and authentication implementations
 
+pub mod audit;
 pub mod auth;
+pub mod rate_limit;
 pub mod validation;
 
+use anyhow::{Result, anyhow};
 use auth::{AuthConfig, Authenticator};
 use std::collections::HashSet;
+use std::sync::OnceLock;
 use validation::{InputValidator, ValidationConfig};
 
 #[derive(Debug, Clone)]
 pub struct SecurityConfig {
     pub enable_auth: bool,
-    pub api_keys: Vec<String>,
+    /// Accepted API keys; plain keys may call every tool
+    pub api_keys: Vec<ApiKeyConfig>,
     pub jwt_secret: Option<String>,
+    /// Required `iss` claim of JWTs; any issuer is accepted when unset
+    pub jwt_issuer: Option<String>,
     pub max_expression_length: usize,
     pub max_expression_depth: usize,
     pub max_resource_size: usize,
+    /// Deepest nesting of objects and arrays accepted in a FHIR resource
+    pub max_resource_depth: usize,
     pub enable_request_logging: bool,
+    /// Requests allowed per client per minute on the HTTP transport (0 disables limiting)
+    pub rate_limit_per_minute: u32,
+    /// FHIRPath functions expressions may call; every function is allowed when unset
+    pub allowed_functions: Option<HashSet<String>>,
+    /// FHIRPath functions expressions may never call
+    pub denied_functions: HashSet<String>,
+    /// Token for administrative HTTP endpoints such as `/admin/shutdown`, distinct from the
+    /// API keys; those endpoints are disabled when unset
+    pub admin_token: Option<String>,
+    /// Base URLs of the FHIR servers `fhirpath_evaluate_remote` may fetch resources from,
+    /// e.g. `https://hapi.fhir.org/baseR4`; remote evaluation is disabled when empty
+    pub allowed_fhir_servers: Vec<String>,
+    /// Strip file paths, stack frames and echoed resource content from the diagnostics and
+    /// error messages tools return; they are returned in full when unset
+    pub production: bool,
 }
 
 impl Default for SecurityConfig {
@@ -24,10 +48,18 @@ impl Default for SecurityConfig {
             enable_auth: true,
             api_keys: vec![],
             jwt_secret: None,
+            jwt_issuer: None,
             max_expression_length: 1000,
             max_expression_depth: 10,
             max_resource_size: 1024 * 1024, // 1MB
+            max_resource_depth: 64,
             enable_request_logging: true,
+            rate_limit_per_minute: 600,
+            allowed_functions: None,
+            denied_functions: HashSet::new(),
+            admin_token: None,
+            allowed_fhir_servers: Vec::new(),
+            production: false,
         }
     }
 }
@@ -35,27 +67,39 @@ impl Default for SecurityConfig {
 pub struct SecurityProvider {
     authenticator: Authenticator,
     validator: InputValidator,
+    production: bool,
 }
 
 impl SecurityProvider {
     pub fn new(config: SecurityConfig) -> Self {
         let auth_config = AuthConfig {
             enable_auth: config.enable_auth,
-            api_keys: config.api_keys.into_iter().collect::<HashSet<_>>(),
+            api_keys: config
+                .api_keys
+                .iter()
+                .map(|api_key| (api_key.key().to_string(), api_key.scope()))
+                .collect(),
             jwt_secret: config.jwt_secret.clone(),
+            jwt_issuer: config.jwt_issuer.clone(),
             enable_request_logging: config.enable_request_logging,
+            admin_token: config.admin_token.clone(),
         };
 
         let validation_config = ValidationConfig {
             max_expression_length: config.max_expression_length,
             max_expression_depth: config.max_expression_depth,
             max_resource_size: config.max_resource_size,
+            max_resource_depth: config.max_resource_depth,
+            allowed_functions: config.allowed_functions.clone(),
+            denied_functions: config.denied_functions.clone(),
+            allowed_fhir_servers: config.allowed_fhir_servers.clone(),
             ..ValidationConfig::default()
         };
 
         Self {
             authenticator: Authenticator::new(auth_config),
             validator: InputValidator::new(validation_config),
+            production: config.production,
         }
     }
 
@@ -66,7 +110,31 @@ impl SecurityProvider {
     pub fn validator(&self) -> &InputValidator {
         &self.validator
     }
+
+    /// Whether diagnostics are sanitized before they leave the server
+    pub fn production(&self) -> bool {
+        self.production
+    }
+}
+
+/// Global security provider used by the tool functions, shared by all transports
+static SHARED_SECURITY: OnceLock<SecurityProvider> = OnceLock::new();
+
+/// Get the global security provider, created with the default configuration if not initialized
+pub fn shared_security_provider() -> &'static SecurityProvider {
+    SHARED_SECURITY.get_or_init(|| SecurityProvider::new(SecurityConfig::default()))
+}
+
+/// Initialize the global security provider with configuration
+pub fn initialize_shared_security(config: SecurityConfig) -> Result<()> {
+    SHARED_SECURITY
+        .set(SecurityProvider::new(config))
+        .map_err(|_| anyhow!("Shared security provider already initialized"))
 }
 
-pub use auth::{AuthMethod, AuthenticatedRequest};
-pub use validation::RequestSanitizer;
+pub use audit::{AuditLog, AuditRecord};
+pub use auth::{
+    ApiKeyConfig, AuthError, AuthFailureReason, AuthMethod, AuthenticatedRequest, ToolScope,
+};
+pub use rate_limit::RateLimiter;
+pub use validation::{FhirServerNotAllowed, RequestSanitizer, ResourceTooDeep, ResourceTooLarge};
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+
+/// A single client's token bucket
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token-bucket rate limiter keyed by client identity (subject or IP address)
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    buckets: Arc<RwLock<HashMap<String, TokenBucket>>>,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `requests_per_minute` requests per client, with bursts
+    /// of up to the same number of requests
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self {
+            capacity: requests_per_minute as f64,
+            refill_per_second: requests_per_minute as f64 / 60.0,
+            buckets: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Take a token for `key`, returning how long to wait before retrying if none are left
+    pub async fn check(&self, key: &str) -> Result<(), Duration> {
+        if self.refill_per_second <= 0.0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.write().await;
+        let bucket = buckets.entry(key.to_string()).or_insert(TokenBucket {
+            tokens: self.capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.refill_per_second;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+
+    /// Drop buckets that have not been used for at least `max_idle`
+    pub async fn prune(&self, max_idle: Duration) {
+        let now = Instant::now();
+        self.buckets
+            .write()
+            .await
+            .retain(|_, bucket| now.duration_since(bucket.last_refill) < max_idle);
+    }
+
+    /// Number of clients currently tracked
+    pub async fn tracked_clients(&self) -> usize {
+        self.buckets.read().await.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rate_limiter_exhausts_bucket() {
+        let limiter = RateLimiter::new(2);
+
+        assert!(limiter.check("client").await.is_ok());
+        assert!(limiter.check("client").await.is_ok());
+
+        let retry_after = limiter.check("client").await.unwrap_err();
+        assert!(retry_after > Duration::ZERO);
+
+        // Other clients have their own bucket
+        assert!(limiter.check("other").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_prunes_idle_buckets() {
+        let limiter = RateLimiter::new(10);
+        limiter.check("client").await.unwrap();
+        assert_eq!(limiter.tracked_clients().await, 1);
+
+        limiter.prune(Duration::ZERO).await;
+        assert_eq!(limiter.tracked_clients().await, 0);
+    }
+}
@@ -1,4 +1,7 @@
+use crate::tools::ComplexityMetrics;
 use anyhow::{Result, anyhow};
+use octofhir_fhirpath::ExpressionNode;
+use reqwest::Url;
 use serde_json::Value;
 use std::collections::HashSet;
 
@@ -7,8 +10,16 @@ pub struct ValidationConfig {
     pub max_expression_length: usize,
     pub max_expression_depth: usize,
     pub max_resource_size: usize,
+    /// Deepest nesting of objects and arrays accepted in a resource
+    pub max_resource_depth: usize,
     pub enable_expression_blacklist: bool,
     pub blacklisted_functions: HashSet<String>,
+    /// Only these functions may be called when set
+    pub allowed_functions: Option<HashSet<String>>,
+    /// Functions that may never be called, e.g. `trace`
+    pub denied_functions: HashSet<String>,
+    /// Base URLs of the FHIR servers resources may be fetched from; none when empty
+    pub allowed_fhir_servers: Vec<String>,
 }
 
 impl Default for ValidationConfig {
@@ -23,12 +34,53 @@ impl Default for ValidationConfig {
             max_expression_length: 1000,
             max_expression_depth: 10,
             max_resource_size: 1024 * 1024, // 1MB
+            max_resource_depth: 64,
             enable_expression_blacklist: true,
             blacklisted_functions,
+            allowed_functions: None,
+            denied_functions: HashSet::new(),
+            allowed_fhir_servers: Vec::new(),
         }
     }
 }
 
+/// Error returned when a FHIR resource exceeds the configured size limit
+#[derive(Debug, thiserror::Error)]
+#[error("FHIR resource too large: {size} > {max} bytes")]
+pub struct ResourceTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+/// Error returned when a FHIR resource nests deeper than the configured limit
+#[derive(Debug, thiserror::Error)]
+#[error("FHIR resource too deeply nested: depth {depth} > {max}")]
+pub struct ResourceTooDeep {
+    pub depth: usize,
+    pub max: usize,
+}
+
+/// Error returned when a FHIR server is not on the allow list
+#[derive(Debug, thiserror::Error)]
+#[error("FHIR server '{base}' is not in the allowed FHIR servers")]
+pub struct FhirServerNotAllowed {
+    pub base: String,
+}
+
+/// Whether `url` is `allowed` or below it: same origin, and a path equal to or under the
+/// allowed path
+fn within_base(allowed: &Url, url: &Url) -> bool {
+    let allowed_path = allowed.path().trim_end_matches('/');
+    let path = url.path().trim_end_matches('/');
+    allowed.scheme() == url.scheme()
+        && allowed.host_str() == url.host_str()
+        && allowed.port_or_known_default() == url.port_or_known_default()
+        && (path == allowed_path
+            || path
+                .strip_prefix(allowed_path)
+                .is_some_and(|rest| rest.starts_with('/')))
+}
+
 pub struct InputValidator {
     config: ValidationConfig,
 }
@@ -51,76 +103,161 @@ impl InputValidator {
             return Err(anyhow!("FHIRPath expression cannot be empty"));
         }
 
-        let depth = self.calculate_expression_depth(expression);
-        if depth > self.config.max_expression_depth {
-            return Err(anyhow!(
-                "FHIRPath expression too complex: depth {} > {}",
-                depth,
-                self.config.max_expression_depth
-            ));
-        }
+        self.validate_expression_depth(expression)?;
 
         if self.config.enable_expression_blacklist {
             self.check_blacklisted_functions(expression)?;
         }
 
+        self.validate_function_usage(expression)?;
+
         Ok(self.sanitize_expression(expression))
     }
 
-    pub fn validate_fhir_resource(&self, resource: &Value) -> Result<Value> {
-        let resource_str = serde_json::to_string(resource)
-            .map_err(|e| anyhow!("Failed to serialize resource: {}", e))?;
-
-        if resource_str.len() > self.config.max_resource_size {
-            return Err(anyhow!(
-                "FHIR resource too large: {} > {}",
-                resource_str.len(),
-                self.config.max_resource_size
-            ));
+    /// Reject expressions nested deeper than `max_expression_depth`: brackets in the source
+    /// text, which are checked before parsing so deep grouping can't exhaust the parser's
+    /// stack, and the parsed expression, where function arguments and operands nest but path
+    /// steps don't. Brackets in string literals, delimited identifiers and comments don't
+    /// count.
+    ///
+    /// Expressions that fail to parse pass the tree check; the engine reports the syntax
+    /// error.
+    pub fn validate_expression_depth(&self, expression: &str) -> Result<()> {
+        let max = self.config.max_expression_depth;
+        let too_deep =
+            |depth: usize| anyhow!("FHIRPath expression too complex: depth {} > {}", depth, max);
+
+        let depth = bracket_depth(expression);
+        if depth > max {
+            return Err(too_deep(depth));
         }
 
-        if !resource.is_object() {
-            return Err(anyhow!("FHIR resource must be a JSON object"));
+        let Ok(ast) = octofhir_fhirpath::parse(expression) else {
+            return Ok(());
+        };
+        let depth = nesting_depth(&ast);
+        if depth > max {
+            return Err(too_deep(depth));
         }
-
-        self.validate_json_structure(resource)?;
-        Ok(self.sanitize_resource(resource.clone()))
+        Ok(())
     }
 
-    fn calculate_expression_depth(&self, expression: &str) -> usize {
-        let mut depth: usize = 0;
-        let mut max_depth: usize = 0;
-        let mut in_quotes = false;
-        let mut escape_next = false;
+    /// Whether any FHIR server is allowed, i.e. remote evaluation is enabled
+    pub fn remote_evaluation_enabled(&self) -> bool {
+        !self.config.allowed_fhir_servers.is_empty()
+    }
 
-        for ch in expression.chars() {
-            if escape_next {
-                escape_next = false;
-                continue;
+    /// Parse a FHIR server base URL, rejecting servers outside `allowed_fhir_servers` so
+    /// remote evaluation can't be pointed at internal services
+    pub fn validate_fhir_server_base(&self, base: &str) -> Result<Url> {
+        let url = Url::parse(base)
+            .map_err(|e| anyhow!("Invalid FHIR server base URL '{}': {}", base, e))?;
+        let plain = matches!(url.scheme(), "http" | "https")
+            && url.username().is_empty()
+            && url.password().is_none()
+            && url.query().is_none()
+            && url.fragment().is_none();
+        let allowed = plain
+            && self
+                .config
+                .allowed_fhir_servers
+                .iter()
+                .filter_map(|allowed| Url::parse(allowed).ok())
+                .any(|allowed| within_base(&allowed, &url));
+        if !allowed {
+            return Err(FhirServerNotAllowed {
+                base: base.to_string(),
             }
+            .into());
+        }
+        Ok(url)
+    }
 
-            match ch {
-                '\\' => escape_next = true,
-                '\'' | '"' => in_quotes = !in_quotes,
-                '(' | '[' | '{' if !in_quotes => {
-                    depth += 1;
-                    max_depth = max_depth.max(depth);
-                }
-                ')' | ']' | '}' if !in_quotes => {
-                    depth = depth.saturating_sub(1);
-                }
-                _ => {}
+    /// Reject expressions calling a denied function or one outside the allow list
+    ///
+    /// Expressions that fail to parse pass this check; the engine reports the syntax error.
+    pub fn validate_function_usage(&self, expression: &str) -> Result<()> {
+        if self.config.allowed_functions.is_none() && self.config.denied_functions.is_empty() {
+            return Ok(());
+        }
+
+        let Ok(ast) = octofhir_fhirpath::parse(expression) else {
+            return Ok(());
+        };
+
+        for function in ComplexityMetrics::from_ast(&ast).functions {
+            if self.config.denied_functions.contains(&function) {
+                return Err(anyhow!("FHIRPath function '{}' is not permitted", function));
+            }
+            if let Some(allowed) = &self.config.allowed_functions
+                && !allowed.contains(&function)
+            {
+                return Err(anyhow!(
+                    "FHIRPath function '{}' is not in the allowed functions",
+                    function
+                ));
             }
         }
 
-        max_depth
+        Ok(())
     }
 
-    fn check_blacklisted_functions(&self, expression: &str) -> Result<()> {
-        let expression_lower = expression.to_lowercase();
+    /// Largest accepted serialized resource, in bytes
+    pub fn max_resource_size(&self) -> usize {
+        self.config.max_resource_size
+    }
+
+    /// Check the serialized size of a resource against `max_resource_size`, returning the size
+    pub fn check_resource_size(&self, resource: &Value) -> Result<usize, ResourceTooLarge> {
+        let size = serde_json::to_vec(resource)
+            .map(|bytes| bytes.len())
+            .unwrap_or(0);
+        let max = self.config.max_resource_size;
+        if size > max {
+            return Err(ResourceTooLarge { size, max });
+        }
+        Ok(size)
+    }
+
+    /// Check the nesting depth of a resource against `max_resource_depth`. The walk is
+    /// iterative, so this is safe to run before anything that recurses into the value.
+    pub fn check_resource_depth(&self, resource: &Value) -> Result<(), ResourceTooDeep> {
+        let depth = resource_depth(resource);
+        let max = self.config.max_resource_depth;
+        if depth > max {
+            return Err(ResourceTooDeep { depth, max });
+        }
+        Ok(())
+    }
 
-        for blacklisted in &self.config.blacklisted_functions {
-            if expression_lower.contains(&blacklisted.to_lowercase()) {
+    pub fn validate_fhir_resource(&self, resource: &Value) -> Result<Value> {
+        // Checked first: the size check and structure walk below recurse into the value
+        self.check_resource_depth(resource)?;
+        self.check_resource_size(resource)?;
+
+        if !resource.is_object() {
+            return Err(anyhow!("FHIR resource must be a JSON object"));
+        }
+
+        self.validate_json_structure(resource)?;
+        Ok(self.sanitize_resource(resource.clone()))
+    }
+
+    /// Reject expressions calling a blacklisted function, whatever its case
+    ///
+    /// Expressions that fail to parse pass this check; the engine reports the syntax error.
+    fn check_blacklisted_functions(&self, expression: &str) -> Result<()> {
+        let Ok(ast) = octofhir_fhirpath::parse(expression) else {
+            return Ok(());
+        };
+
+        for function in ComplexityMetrics::from_ast(&ast).functions {
+            if let Some(blacklisted) = self
+                .config
+                .blacklisted_functions
+                .iter()
+                .find(|blacklisted| blacklisted.eq_ignore_ascii_case(&function))
+            {
                 return Err(anyhow!(
                     "FHIRPath expression contains blacklisted function: {}",
                     blacklisted
@@ -158,10 +295,8 @@ impl InputValidator {
                     self.validate_json_structure(item)?;
                 }
             }
-            Value::String(s) => {
-                if s.len() > 100000 {
-                    return Err(anyhow!("JSON string too long: {}", s.len()));
-                }
+            Value::String(s) if s.len() > 100000 => {
+                return Err(anyhow!("JSON string too long: {}", s.len()));
             }
             _ => {}
         }
@@ -197,6 +332,25 @@ impl InputValidator {
     }
 }
 
+/// Deepest nesting of objects and arrays in `value`, counting the outermost container as 1.
+/// Walks with an explicit stack so adversarially deep values cannot overflow the call stack.
+fn resource_depth(value: &Value) -> usize {
+    let mut max_depth = 0;
+    let mut stack = vec![(value, 1)];
+
+    while let Some((value, depth)) = stack.pop() {
+        let children: Box<dyn Iterator<Item = &Value>> = match value {
+            Value::Object(obj) => Box::new(obj.values()),
+            Value::Array(arr) => Box::new(arr.iter()),
+            _ => continue,
+        };
+        max_depth = max_depth.max(depth);
+        stack.extend(children.map(|child| (child, depth + 1)));
+    }
+
+    max_depth
+}
+
 pub struct RequestSanitizer;
 
 impl RequestSanitizer {
@@ -214,11 +368,196 @@ impl RequestSanitizer {
             .join(" ")
     }
 
+    /// Scrub a diagnostic returned to clients of a production server: stack frames are
+    /// dropped, file paths replaced by `<path>` and JSON echoed from the resource by
+    /// `<redacted>`. Without `production` the diagnostic is returned unchanged.
+    pub fn sanitize_diagnostic(diagnostic: &str, production: bool) -> String {
+        if !production {
+            return diagnostic.to_string();
+        }
+
+        let lines: Vec<String> = diagnostic
+            .lines()
+            .filter(|line| !is_stack_frame(line))
+            .map(|line| {
+                line.split(' ')
+                    .map(redact_path)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        redact_objects(&lines.join("\n"))
+    }
+
     pub fn create_correlation_id() -> String {
         uuid::Uuid::new_v4().to_string()
     }
 }
 
+/// Deepest nesting of `()`, `[]` and `{}` in `expression`, outside string literals, delimited
+/// identifiers and comments
+fn bracket_depth(expression: &str) -> usize {
+    let mut depth = 0usize;
+    let mut deepest = 0;
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '`' => {
+                while let Some(next) = chars.next() {
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == c {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars
+                    .by_ref()
+                    .take_while(|&next| next != '\n')
+                    .for_each(drop);
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = '\0';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            '(' | '[' | '{' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    deepest
+}
+
+/// Nesting depth of the expression rooted at `node`: every function argument, operand and
+/// index or filter is a level below the expression holding it, while the steps of a path or
+/// chain of method calls stay on the same level
+fn nesting_depth(node: &ExpressionNode) -> usize {
+    let deepest = |nodes: &mut dyn Iterator<Item = &ExpressionNode>| {
+        nodes.map(nesting_depth).max().unwrap_or(0)
+    };
+    match node {
+        ExpressionNode::Literal(_)
+        | ExpressionNode::Identifier(_)
+        | ExpressionNode::Variable(_) => 1,
+        ExpressionNode::Path { base, .. } => nesting_depth(base),
+        ExpressionNode::FunctionCall(data) => 1 + deepest(&mut data.args.iter()),
+        ExpressionNode::MethodCall(data) => {
+            nesting_depth(&data.base).max(1 + deepest(&mut data.args.iter()))
+        }
+        ExpressionNode::Index { base, index } => nesting_depth(base).max(1 + nesting_depth(index)),
+        ExpressionNode::Filter { base, condition } => {
+            nesting_depth(base).max(1 + nesting_depth(condition))
+        }
+        ExpressionNode::BinaryOp(data) => {
+            1 + nesting_depth(&data.left).max(nesting_depth(&data.right))
+        }
+        ExpressionNode::Union { left, right } => 1 + nesting_depth(left).max(nesting_depth(right)),
+        ExpressionNode::UnaryOp { operand, .. } => 1 + nesting_depth(operand),
+        ExpressionNode::TypeCheck { expression, .. }
+        | ExpressionNode::TypeCast { expression, .. } => 1 + nesting_depth(expression),
+        ExpressionNode::Lambda(data) => nesting_depth(&data.body),
+        ExpressionNode::Conditional(data) => {
+            1 + deepest(
+                &mut [&data.condition, &data.then_expr]
+                    .into_iter()
+                    .chain(data.else_expr.as_deref()),
+            )
+        }
+    }
+}
+
+/// Lines of a backtrace, e.g. `   3: octofhir_mcp::tools::call_tool` or `at src/tools.rs:12`
+fn is_stack_frame(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    let indented = trimmed.len() < line.len();
+    let numbered = trimmed
+        .split_once(": ")
+        .is_some_and(|(index, _)| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()));
+    trimmed.starts_with("stack backtrace:")
+        || (indented && (numbered || trimmed.starts_with("at ")))
+}
+
+/// `word` with a file path in it replaced by `<path>`, keeping surrounding punctuation
+fn redact_path(word: &str) -> String {
+    let path = word
+        .trim_start_matches(|c: char| "'\"`([".contains(c))
+        .trim_end_matches(|c: char| "'\"`()[],;:.".contains(c));
+    let is_path = !path.contains("://")
+        && ((["/", "./", "../", "~/"]
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+            && path.chars().any(char::is_alphanumeric))
+            || path.get(1..3).is_some_and(|drive| {
+                drive == ":\\" && path.starts_with(|c: char| c.is_ascii_alphabetic())
+            })
+            || (path.contains('/') && path.contains(".rs")));
+    if is_path {
+        word.replacen(path, "<path>", 1)
+    } else {
+        word.to_string()
+    }
+}
+
+/// `text` with every JSON object in it replaced by `<redacted>`.
+///
+/// Only brace spans holding a quoted key (`{"name": ...}`) count as JSON objects, so FHIRPath
+/// source such as the empty collection literal `{}` is kept as written.
+fn redact_objects(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut span = String::new();
+    let mut depth = 0usize;
+    for c in text.chars() {
+        match c {
+            '{' => {
+                depth += 1;
+                span.push(c);
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                span.push(c);
+                if depth == 0 {
+                    if is_json_object(&span) {
+                        redacted.push_str("<redacted>");
+                    } else {
+                        redacted.push_str(&span);
+                    }
+                    span.clear();
+                }
+            }
+            _ if depth == 0 => redacted.push(c),
+            _ => span.push(c),
+        }
+    }
+    // An unbalanced `{` may be the start of a truncated object; keep its data out as well
+    if !span.is_empty() {
+        if span.contains('"') {
+            redacted.push_str("<redacted>");
+        } else {
+            redacted.push_str(&span);
+        }
+    }
+    redacted
+}
+
+/// Whether a balanced `{...}` span starts with a quoted key, as a serialized JSON object does
+fn is_json_object(span: &str) -> bool {
+    let inner = span[1..span.len() - 1].trim_start();
+    inner.starts_with('"')
+        && inner[1..]
+            .split_once('"')
+            .is_some_and(|(_, rest)| rest.trim_start().starts_with(':'))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -237,6 +576,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn test_expression_depth_validation() {
         let mut config = ValidationConfig::default();
         config.max_expression_depth = 2;
@@ -249,6 +589,58 @@ mod tests {
         assert!(validator.validate_fhirpath_expression(deep_expr).is_err());
     }
 
+    #[test]
+    fn test_expression_depth_at_limit() {
+        let config = ValidationConfig {
+            max_expression_depth: 5,
+            ..ValidationConfig::default()
+        };
+        let validator = InputValidator::new(config);
+
+        // where() > where() > `=` > `+` > 1
+        let at_limit = "name.where(given.where(1 + 2 = 3))";
+        assert!(validator.validate_expression_depth(at_limit).is_ok());
+
+        let over_limit = "name.where(given.where(1 + 2 + 3 = 6))";
+        let err = validator.validate_expression_depth(over_limit).unwrap_err();
+        assert!(err.to_string().contains("depth 6 > 5"), "{err}");
+
+        // Nested calls, where the brackets reach the limit too
+        let at_limit = "name.where(given.where(family.where(use.where(text))))";
+        assert!(validator.validate_expression_depth(at_limit).is_ok());
+        let over_limit = "name.where(given.where(family.where(use.where(text.where(id)))))";
+        assert!(validator.validate_expression_depth(over_limit).is_err());
+
+        // Path steps and chained calls don't nest
+        let path = ["name"; 20].join(".");
+        assert!(validator.validate_expression_depth(&path).is_ok());
+        let chained = "name.given.first().lower().upper().length().toString().length()";
+        assert!(validator.validate_expression_depth(chained).is_ok());
+
+        // Every operator is a level, even without any function calls
+        let chained = ["1"; 6].join(" + ");
+        let err = validator.validate_expression_depth(&chained).unwrap_err();
+        assert!(err.to_string().contains("depth 6 > 5"), "{err}");
+        assert!(
+            validator
+                .validate_expression_depth(&["1"; 4].join(" + "))
+                .is_ok()
+        );
+
+        // Grouping is rejected before parsing, however little it adds to the tree
+        let grouped = format!("{}1{}", "(".repeat(6), ")".repeat(6));
+        let err = validator.validate_expression_depth(&grouped).unwrap_err();
+        assert!(err.to_string().contains("depth 6 > 5"), "{err}");
+        let grouped = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert!(validator.validate_expression_depth(&grouped).is_err());
+        assert!(validator.validate_expression_depth("((1)) + [2]").is_ok());
+
+        // Brackets inside strings, delimited identifiers and comments are not nesting
+        let quoted = "name.where(family = '((((((') and `a(((((`.exists()) // ((((((";
+        assert!(validator.validate_expression_depth(quoted).is_ok());
+        assert!(validator.validate_fhirpath_expression(quoted).is_ok());
+    }
+
     #[test]
     fn test_blacklisted_functions() {
         let config = ValidationConfig::default();
@@ -259,6 +651,65 @@ mod tests {
 
         let unsafe_expr = "eval('malicious code')";
         assert!(validator.validate_fhirpath_expression(unsafe_expr).is_err());
+
+        // Only calls count, not elements sharing a blacklisted name
+        let element = "Coding.where(system = 'http://loinc.org').code";
+        assert!(validator.validate_fhirpath_expression(element).is_ok());
+        assert!(
+            validator
+                .validate_fhirpath_expression("name.System()")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_denied_function_rejected() {
+        let config = ValidationConfig {
+            denied_functions: HashSet::from(["trace".to_string()]),
+            ..ValidationConfig::default()
+        };
+        let validator = InputValidator::new(config);
+
+        assert!(
+            validator
+                .validate_function_usage("Patient.name.first()")
+                .is_ok()
+        );
+
+        let error = validator
+            .validate_function_usage("Patient.name.trace('names').first()")
+            .unwrap_err();
+        assert!(error.to_string().contains("'trace'"));
+        assert!(
+            validator
+                .validate_fhirpath_expression("Patient.name.trace('names')")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_allowed_functions_exclude_where() {
+        let config = ValidationConfig {
+            allowed_functions: Some(HashSet::from(["first".to_string(), "exists".to_string()])),
+            ..ValidationConfig::default()
+        };
+        let validator = InputValidator::new(config);
+
+        assert!(
+            validator
+                .validate_function_usage("Patient.name.first()")
+                .is_ok()
+        );
+        assert!(
+            validator
+                .validate_function_usage("Patient.name.exists()")
+                .is_ok()
+        );
+
+        let error = validator
+            .validate_function_usage("Patient.name.where(use = 'official')")
+            .unwrap_err();
+        assert!(error.to_string().contains("'where'"));
     }
 
     #[test]
@@ -274,6 +725,34 @@ mod tests {
         assert!(validator.validate_fhir_resource(&large_resource).is_err());
     }
 
+    #[test]
+    fn test_resource_depth_validation() {
+        let config = ValidationConfig {
+            max_resource_depth: 5,
+            ..ValidationConfig::default()
+        };
+        let validator = InputValidator::new(config);
+
+        // An extension chain `depth` containers deep: the resource, then alternating
+        // `extension` arrays and extension objects
+        let nested = |depth: usize| {
+            let mut value = json!({"url": "http://example.org/leaf", "valueString": "x"});
+            for level in 2..depth {
+                value = if level % 2 == 0 {
+                    json!([value])
+                } else {
+                    json!({"url": "http://example.org/nested", "extension": value})
+                };
+            }
+            json!({"resourceType": "Basic", "extension": value})
+        };
+        assert_eq!(resource_depth(&nested(5)), 5);
+        assert!(validator.validate_fhir_resource(&nested(5)).is_ok());
+
+        let err = validator.validate_fhir_resource(&nested(6)).unwrap_err();
+        assert!(err.to_string().contains("depth 6 > 5"));
+    }
+
     #[test]
     fn test_error_message_sanitization() {
         let detailed_error = "JWT token validation failed with secret key abc123";
@@ -286,4 +765,87 @@ mod tests {
         // Note: The error message sanitization intentionally leaves some technical details for debugging
         // while removing sensitive information like 'JWT' -> 'token'
     }
+
+    #[test]
+    fn test_diagnostic_sanitization() {
+        let diagnostic = "Evaluation error: failed to read /opt/octofhir/packages/r4/index.json \
+            for {\"resourceType\": \"Patient\", \"name\": [{\"family\": \"Smith\"}]}
+stack backtrace:
+   0: octofhir_mcp::tools::call_tool
+             at ./src/tools.rs:3794:5";
+
+        let sanitized = RequestSanitizer::sanitize_diagnostic(diagnostic, true);
+        assert_eq!(
+            sanitized,
+            "Evaluation error: failed to read <path> for <redacted>"
+        );
+        assert_eq!(
+            RequestSanitizer::sanitize_diagnostic(diagnostic, false),
+            diagnostic
+        );
+
+        // Not file paths
+        let diagnostic = "Reference Patient/123 to http://hl7.org/fhir/Patient: 4 / 2";
+        assert_eq!(
+            RequestSanitizer::sanitize_diagnostic(diagnostic, true),
+            diagnostic
+        );
+        assert_eq!(
+            RequestSanitizer::sanitize_diagnostic("in 'C:\\octofhir\\config.toml'.", true),
+            "in '<path>'."
+        );
+
+        // FHIRPath collection literals are not JSON objects
+        let diagnostic = "Cannot compare {} with { } in name.where(given = {})";
+        assert_eq!(
+            RequestSanitizer::sanitize_diagnostic(diagnostic, true),
+            diagnostic
+        );
+        assert_eq!(
+            RequestSanitizer::sanitize_diagnostic("got {} and {\"id\" : 1}", true),
+            "got {} and <redacted>"
+        );
+    }
+
+    #[test]
+    fn test_fhir_server_allow_list() {
+        let validator = InputValidator::new(ValidationConfig {
+            allowed_fhir_servers: vec!["https://fhir.example.org/r4/".to_string()],
+            ..ValidationConfig::default()
+        });
+
+        for allowed in [
+            "https://fhir.example.org/r4",
+            "https://FHIR.example.org:443/r4/",
+            "https://fhir.example.org/r4/tenant-a",
+        ] {
+            assert!(
+                validator.validate_fhir_server_base(allowed).is_ok(),
+                "{allowed}"
+            );
+        }
+        for denied in [
+            "http://fhir.example.org/r4",
+            "https://fhir.example.org/r4b",
+            "https://fhir.example.org",
+            "https://fhir.example.org:8443/r4",
+            "https://fhir.example.org.evil.com/r4",
+            "https://user@fhir.example.org/r4",
+            "https://fhir.example.org/r4?x=1",
+            "file:///etc/passwd",
+        ] {
+            assert!(
+                validator.validate_fhir_server_base(denied).is_err(),
+                "{denied}"
+            );
+        }
+
+        // Remote evaluation is off without an allow list
+        let validator = InputValidator::new(ValidationConfig::default());
+        assert!(
+            validator
+                .validate_fhir_server_base("https://fhir.example.org/r4")
+                .is_err()
+        );
+    }
 }
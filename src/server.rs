@@ -4,221 +4,596 @@
 //! rmcp SDK for protocol handling and transport management.
 
 use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
 use rmcp::{
     ErrorData, RoleServer, ServerHandler,
     model::{
-        CallToolRequestParam, CallToolResult, Content, ErrorCode, ListToolsResult,
-        PaginatedRequestParam, ServerCapabilities, ServerInfo, Tool,
+        CallToolRequestParam, CallToolResult, ErrorCode, ExperimentalCapabilities,
+        GetPromptRequestParam, GetPromptResult, Implementation, ListPromptsResult,
+        ListResourcesResult, ListToolsResult, PaginatedRequestParam, ProgressNotificationParam,
+        ProgressToken, ReadResourceRequestParam, ReadResourceResult, ServerCapabilities,
+        ServerInfo, Tool,
     },
     service::RequestContext,
 };
 use schemars::{JsonSchema, SchemaGenerator};
+use serde::Serialize;
 use serde_json::{Value, json};
+use std::time::{Duration, Instant};
 use tracing::{debug, info};
 
+use crate::prompts::PromptProvider;
+use crate::resources::ResourceProvider;
+use crate::security::{AuditLog, AuditRecord, AuthenticatedRequest, SecurityConfig};
 // Import our tool functions
 use crate::tools::{
-    AnalyzeParams, EvaluateParams, ExtractParams, ParseParams, fhirpath_analyze, fhirpath_evaluate,
-    fhirpath_extract, fhirpath_parse,
+    AnalyzeParams, BundleFilterParams, CompareParams, DEPRECATIONS, Deprecation, DiffParams,
+    EvaluateParams, EvaluateRemoteParams, ExplainParams, ExtractParams, FormatParams,
+    FunctionsParams, MultiResourceParams, ParseParams, TransformParams, authorize_tool, call_tool,
+    fhirpath_bundle_filter, fhirpath_compare, fhirpath_diff, fhirpath_evaluate,
+    fhirpath_evaluate_multi, fhirpath_evaluate_remote, fhirpath_explain, fhirpath_extract,
+    fhirpath_format, fhirpath_functions, fhirpath_parse, fhirpath_transform, with_correlation_id,
 };
+use crate::transport::http::CORRELATION_ID_HEADER;
+
+/// Number of items returned per page by the list methods
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Name of the experimental capability advertising [`ServerLimits`] in the `initialize`
+/// response; clients that don't know it ignore it
+pub const LIMITS_CAPABILITY: &str = "octofhir/limits";
+
+/// Operational limits advertised to clients so they can avoid requests that would be rejected
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerLimits {
+    /// Longest accepted FHIRPath expression, in characters
+    pub max_expression_length: usize,
+    /// Largest accepted FHIR resource, in bytes of serialized JSON
+    pub max_resource_size: usize,
+    /// FHIR version expressions are evaluated with unless a tool call selects another
+    pub fhir_version: String,
+    /// FHIR versions tool calls may select
+    pub supported_fhir_versions: Vec<String>,
+}
+
+impl ServerLimits {
+    /// Limits enforced under `security` when serving `fhir_version` by default
+    pub fn new(security: &SecurityConfig, fhir_version: impl Into<String>) -> Self {
+        Self {
+            max_expression_length: security.max_expression_length,
+            max_resource_size: security.max_resource_size,
+            fhir_version: fhir_version.into(),
+            supported_fhir_versions: crate::fhirpath_engine::SUPPORTED_FHIR_VERSIONS
+                .iter()
+                .map(|version| version.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        Self::new(
+            &SecurityConfig::default(),
+            crate::config::ServerConfig::default().fhir_version,
+        )
+    }
+}
+
+/// Name, version and instructions the server reports to clients in the `initialize` response
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerIdentity {
+    /// Server name (default: `octofhir-mcp`)
+    pub server_name: String,
+    /// Server version (default: this crate's version)
+    pub server_version: String,
+    /// Guidance on using the server shown to LLM clients
+    pub instructions: String,
+}
+
+impl Default for ServerIdentity {
+    fn default() -> Self {
+        Self {
+            server_name: env!("CARGO_PKG_NAME").to_string(),
+            server_version: crate::VERSION.to_string(),
+            instructions: "FHIRPath evaluation tools for FHIR resources using OctoFHIR engine"
+                .to_string(),
+        }
+    }
+}
 
 /// FHIRPath Tools Server using rmcp SDK
-#[derive(Debug, Clone, Default)]
-pub struct FhirPathToolServer;
+#[derive(Debug, Clone)]
+pub struct FhirPathToolServer {
+    page_size: usize,
+    limits: ServerLimits,
+    identity: ServerIdentity,
+    audit_log: Option<AuditLog>,
+    progress_interval: Option<Duration>,
+}
+
+impl Default for FhirPathToolServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl FhirPathToolServer {
     pub fn new() -> Self {
-        Self
+        Self {
+            page_size: DEFAULT_PAGE_SIZE,
+            limits: ServerLimits::default(),
+            identity: ServerIdentity::default(),
+            audit_log: None,
+            progress_interval: None,
+        }
+    }
+
+    /// Report `identity` in the `initialize` response instead of the OctoFHIR defaults
+    pub fn with_identity(mut self, identity: ServerIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Advertise `limits` in the `initialize` response
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Record every tool call to `audit_log`
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Send a `notifications/progress` notification every `interval` while a tool call runs, so
+    /// the client knows a long evaluation is still alive
+    pub fn with_progress_interval(mut self, interval: Duration) -> Self {
+        self.progress_interval = Some(interval);
+        self
+    }
+
+    /// Set the number of items returned per page by the list methods (at least one)
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
     }
+
+    /// List one page of tools, starting at the request's cursor
+    pub fn list_tools_page(
+        &self,
+        request: Option<PaginatedRequestParam>,
+    ) -> Result<ListToolsResult, ErrorData> {
+        let (tools, next_cursor) = paginate(tool_definitions()?, request, self.page_size)?;
+        Ok(ListToolsResult { tools, next_cursor })
+    }
+
+    /// List one page of prompts, starting at the request's cursor
+    pub fn list_prompts_page(
+        &self,
+        request: Option<PaginatedRequestParam>,
+    ) -> Result<ListPromptsResult, ErrorData> {
+        let (prompts, next_cursor) = paginate(
+            PromptProvider::new().list_prompts(),
+            request,
+            self.page_size,
+        )?;
+        Ok(ListPromptsResult {
+            prompts,
+            next_cursor,
+        })
+    }
+
+    /// List one page of resources, starting at the request's cursor
+    pub fn list_resources_page(
+        &self,
+        request: Option<PaginatedRequestParam>,
+    ) -> Result<ListResourcesResult, ErrorData> {
+        let (resources, next_cursor) = paginate(
+            ResourceProvider::new().list_resources(),
+            request,
+            self.page_size,
+        )?;
+        Ok(ListResourcesResult {
+            resources,
+            next_cursor,
+        })
+    }
+}
+
+/// Encode a list offset as an opaque cursor
+fn encode_cursor(offset: usize) -> String {
+    URL_SAFE_NO_PAD.encode(offset.to_string())
+}
+
+/// Decode a cursor produced by [`encode_cursor`], rejecting offsets past `len`
+fn decode_cursor(cursor: &str, len: usize) -> Result<usize, ErrorData> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|offset| offset.parse::<usize>().ok())
+        .filter(|offset| *offset <= len)
+        .ok_or_else(|| ErrorData::invalid_params(format!("Invalid cursor '{cursor}'"), None))
+}
+
+/// Take the page of `items` starting at the request's cursor, with the cursor of the next page
+fn paginate<T>(
+    items: Vec<T>,
+    request: Option<PaginatedRequestParam>,
+    page_size: usize,
+) -> Result<(Vec<T>, Option<String>), ErrorData> {
+    let offset = match request.and_then(|request| request.cursor) {
+        Some(cursor) => decode_cursor(&cursor, items.len())?,
+        None => 0,
+    };
+    let end = offset.saturating_add(page_size).min(items.len());
+    let next_cursor = (end < items.len()).then(|| encode_cursor(end));
+
+    let page = items.into_iter().skip(offset).take(end - offset).collect();
+    Ok((page, next_cursor))
 }
 
 impl ServerHandler for FhirPathToolServer {
     fn get_info(&self) -> ServerInfo {
+        let mut experimental = ExperimentalCapabilities::new();
+        if let Ok(Value::Object(limits)) = serde_json::to_value(&self.limits) {
+            experimental.insert(LIMITS_CAPABILITY.to_string(), limits);
+        }
+
         ServerInfo {
-            instructions: Some(
-                "FHIRPath evaluation tools for FHIR resources using OctoFHIR engine".to_string(),
-            ),
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            server_info: Implementation {
+                name: self.identity.server_name.clone(),
+                version: self.identity.server_version.clone(),
+            },
+            instructions: Some(self.identity.instructions.clone()),
+            capabilities: ServerCapabilities::builder()
+                .enable_experimental_with(experimental)
+                .enable_tools()
+                .enable_prompts()
+                .enable_resources()
+                .build(),
             ..Default::default()
         }
     }
 
     async fn list_tools(
         &self,
-        _request: Option<PaginatedRequestParam>,
+        request: Option<PaginatedRequestParam>,
         _context: RequestContext<RoleServer>,
     ) -> Result<ListToolsResult, ErrorData> {
-        let tools = vec![
-            Tool {
-                name: "fhirpath_evaluate".into(),
-                description: Some("Evaluate FHIRPath expressions against FHIR resources with performance metrics".into()),
-                input_schema: std::sync::Arc::new(
-                    serde_json::to_value(EvaluateParams::json_schema(&mut SchemaGenerator::default()))
-                        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
-                        .as_object()
-                        .unwrap()
-                        .clone()
-                ),
-                output_schema: None,
-                annotations: None,
-            },
-            Tool {
-                name: "fhirpath_parse".into(),
-                description: Some("Parse and validate FHIRPath expressions with detailed syntax analysis".into()),
-                input_schema: std::sync::Arc::new(
-                    serde_json::to_value(ParseParams::json_schema(&mut SchemaGenerator::default()))
-                        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
-                        .as_object()
-                        .unwrap()
-                        .clone()
-                ),
-                output_schema: None,
-                annotations: None,
-            },
-            Tool {
-                name: "fhirpath_extract".into(),
-                description: Some("Extract data from FHIR resources using FHIRPath with flexible formatting".into()),
-                input_schema: std::sync::Arc::new(
-                    serde_json::to_value(ExtractParams::json_schema(&mut SchemaGenerator::default()))
-                        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
-                        .as_object()
-                        .unwrap()
-                        .clone()
-                ),
-                output_schema: None,
-                annotations: None,
-            },
-            Tool {
-                name: "fhirpath_analyze".into(),
-                description: Some("Analyze FHIRPath expressions providing detailed information about syntax, performance, and usage".into()),
-                input_schema: std::sync::Arc::new(
-                    serde_json::to_value(AnalyzeParams::json_schema(&mut SchemaGenerator::default()))
-                        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
-                        .as_object()
-                        .unwrap()
-                        .clone()
-                ),
-                output_schema: None,
-                annotations: None,
-            },
-        ];
+        self.list_tools_page(request)
+    }
 
-        Ok(ListToolsResult {
-            tools,
-            next_cursor: None,
-        })
+    async fn list_prompts(
+        &self,
+        request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, ErrorData> {
+        self.list_prompts_page(request)
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, ErrorData> {
+        PromptProvider::new()
+            .get_prompt(&request.name, request.arguments.as_ref())
+            .map_err(|e| ErrorData::invalid_params(e.to_string(), None))
+    }
+
+    async fn list_resources(
+        &self,
+        request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, ErrorData> {
+        self.list_resources_page(request)
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, ErrorData> {
+        ResourceProvider::new()
+            .read_resource(&request.uri)
+            .map_err(|e| ErrorData::new(ErrorCode::METHOD_NOT_FOUND, e.to_string(), None))
     }
 
     async fn call_tool(
         &self,
         request: CallToolRequestParam,
-        _context: RequestContext<RoleServer>,
+        context: RequestContext<RoleServer>,
     ) -> Result<CallToolResult, ErrorData> {
-        match request.name.as_ref() {
-            "fhirpath_evaluate" => {
-                let args_map = request.arguments.unwrap_or_default();
-                let args = Value::Object(args_map);
-                let params: EvaluateParams = serde_json::from_value(args).map_err(|e| {
-                    ErrorData::invalid_params(
-                        format!("Invalid parameters for fhirpath_evaluate: {e}"),
-                        None,
-                    )
-                })?;
-                let result = fhirpath_evaluate(params).await.map_err(|e| {
-                    ErrorData::internal_error(format!("Evaluation failed: {e}"), None)
-                })?;
-                let json_result = serde_json::to_value(result).map_err(|e| {
-                    ErrorData::internal_error(format!("Serialization failed: {e}"), None)
-                })?;
-                Ok(CallToolResult {
-                    content: vec![Content::text(json_result.to_string())],
-                    is_error: Some(false),
-                    structured_content: None,
-                })
-            }
-            "fhirpath_parse" => {
-                let args_map = request.arguments.unwrap_or_default();
-                let args = Value::Object(args_map);
-                let params: ParseParams = serde_json::from_value(args).map_err(|e| {
-                    ErrorData::new(
-                        ErrorCode::INVALID_PARAMS,
-                        format!("Invalid parameters for fhirpath_parse: {e}"),
-                        None,
-                    )
-                })?;
-                let result = fhirpath_parse(params).await.map_err(|e| {
-                    ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Parsing failed: {e}"),
-                        None,
-                    )
-                })?;
-                let json_result = serde_json::to_value(result).map_err(|e| {
-                    ErrorData::internal_error(format!("Serialization failed: {e}"), None)
-                })?;
-                Ok(CallToolResult {
-                    content: vec![Content::text(json_result.to_string())],
-                    is_error: Some(false),
-                    structured_content: None,
-                })
-            }
-            "fhirpath_extract" => {
-                let args_map = request.arguments.unwrap_or_default();
-                let args = Value::Object(args_map);
-                let params: ExtractParams = serde_json::from_value(args).map_err(|e| {
-                    ErrorData::new(
-                        ErrorCode::INVALID_PARAMS,
-                        format!("Invalid parameters for fhirpath_extract: {e}"),
-                        None,
-                    )
-                })?;
-                let result = fhirpath_extract(params).await.map_err(|e| {
-                    ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Extraction failed: {e}"),
-                        None,
+        let parts = context.extensions.get::<axum::http::request::Parts>();
+
+        // Over HTTP, the auth middleware stores the caller on the underlying request
+        if let Some(authenticated) =
+            parts.and_then(|parts| parts.extensions.get::<AuthenticatedRequest>())
+        {
+            authorize_tool(&request.name, &authenticated.scope)?;
+        }
+
+        let arguments = Value::Object(request.arguments.unwrap_or_default());
+        let correlation_id = parts
+            .and_then(|parts| parts.headers.get(CORRELATION_ID_HEADER))
+            .and_then(|value| value.to_str().ok());
+        let audit = self.audit_log.as_ref().map(|audit_log| {
+            let subject = parts
+                .and_then(|parts| parts.extensions.get::<AuthenticatedRequest>())
+                .map(|authenticated| authenticated.subject.clone());
+            let record = AuditRecord::new(
+                &request.name,
+                &arguments,
+                subject,
+                correlation_id.map(str::to_string),
+            );
+            (audit_log, record)
+        });
+        let call = async {
+            match correlation_id {
+                Some(correlation_id) => {
+                    with_correlation_id(
+                        correlation_id.to_string(),
+                        call_tool(&request.name, arguments),
                     )
-                })?;
-                let json_result = serde_json::to_value(result).map_err(|e| {
-                    ErrorData::internal_error(format!("Serialization failed: {e}"), None)
-                })?;
-                Ok(CallToolResult {
-                    content: vec![Content::text(json_result.to_string())],
-                    is_error: Some(false),
-                    structured_content: None,
-                })
+                    .await
+                }
+                None => call_tool(&request.name, arguments).await,
             }
-            "fhirpath_analyze" => {
-                let args_map = request.arguments.unwrap_or_default();
-                let args = Value::Object(args_map);
-                let params: AnalyzeParams = serde_json::from_value(args).map_err(|e| {
-                    ErrorData::new(
-                        ErrorCode::INVALID_PARAMS,
-                        format!("Invalid parameters for fhirpath_analyze: {e}"),
-                        None,
-                    )
-                })?;
-                let result = fhirpath_analyze(params).await.map_err(|e| {
-                    ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Analysis failed: {e}"),
-                        None,
-                    )
-                })?;
-                let json_result = serde_json::to_value(result).map_err(|e| {
-                    ErrorData::internal_error(format!("Serialization failed: {e}"), None)
-                })?;
-                Ok(CallToolResult {
-                    content: vec![Content::text(json_result.to_string())],
-                    is_error: Some(false),
-                    structured_content: None,
-                })
+        };
+        let result = match self.progress_interval {
+            Some(interval) => report_progress(call, interval, &context).await,
+            None => call.await,
+        };
+        if let Some((audit_log, record)) = audit {
+            audit_log.record(match &result {
+                Ok(_) => record,
+                Err(error) => record.failed(error.code()),
+            });
+        }
+        // Typed for clients that read structured content; the text copy is for the others
+        Ok(CallToolResult::structured(result?))
+    }
+}
+
+/// Drive `call` to completion, notifying the client of the elapsed time every `interval`.
+/// Progress is reported under the request's progress token, or its id when it has none.
+async fn report_progress<F: std::future::Future>(
+    call: F,
+    interval: Duration,
+    context: &RequestContext<RoleServer>,
+) -> F::Output {
+    let started = Instant::now();
+    let token = context
+        .meta
+        .get_progress_token()
+        .unwrap_or_else(|| ProgressToken(context.id.clone()));
+    let mut ticks = tokio::time::interval_at((started + interval).into(), interval);
+    tokio::pin!(call);
+
+    loop {
+        tokio::select! {
+            output = &mut call => return output,
+            _ = ticks.tick() => {
+                let elapsed = started.elapsed().as_secs_f64();
+                let notification = ProgressNotificationParam {
+                    progress_token: token.clone(),
+                    progress: elapsed,
+                    total: None,
+                    message: Some(format!(
+                        "Request {} still running after {:.1}s",
+                        context.id, elapsed
+                    )),
+                };
+                if let Err(e) = context.peer.notify_progress(notification).await {
+                    debug!("Failed to send progress notification: {}", e);
+                }
             }
-            _ => Err(ErrorData::new(
-                ErrorCode::METHOD_NOT_FOUND,
-                format!("Unknown tool: {}", request.name),
-                None,
-            )),
+        }
+    }
+}
+
+/// Tools offered to clients. `fhirpath_evaluate_remote` is left out while no FHIR server is
+/// allowed, since every call to it would be rejected.
+pub(crate) fn tool_definitions() -> Result<Vec<Tool>, ErrorData> {
+    let mut tools = all_tool_definitions()?;
+    if !crate::security::shared_security_provider()
+        .validator()
+        .remote_evaluation_enabled()
+    {
+        tools.retain(|tool| tool.name != "fhirpath_evaluate_remote");
+    }
+    Ok(tools)
+}
+
+/// Definitions of all tools served by [`FhirPathToolServer`]
+fn all_tool_definitions() -> Result<Vec<Tool>, ErrorData> {
+    let mut tools = vec![
+        Tool {
+            name: "fhirpath_evaluate".into(),
+            description: Some("Evaluate FHIRPath expressions against FHIR resources with performance metrics".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(EvaluateParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_evaluate_remote".into(),
+            description: Some("Evaluate a FHIRPath expression against a resource read by reference (e.g. `Patient/123`) from an allowed FHIR server".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(EvaluateRemoteParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_parse".into(),
+            description: Some("Parse and validate FHIRPath expressions with detailed syntax analysis".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(ParseParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_extract".into(),
+            description: Some("Extract data from FHIR resources using FHIRPath with flexible formatting".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(ExtractParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_analyze".into(),
+            description: Some("Analyze FHIRPath expressions providing detailed information about syntax, performance, and usage".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(AnalyzeParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_diff".into(),
+            description: Some("Compare the results of a FHIRPath expression evaluated against two versions of a FHIR resource".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(DiffParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_compare".into(),
+            description: Some("Check whether two FHIRPath expressions produce identical results (values and types) across a set of sample FHIR resources".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(CompareParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_evaluate_multi".into(),
+            description: Some("Evaluate one FHIRPath expression against each of several FHIR resources, returning per-resource results in input order; a failure on one resource doesn't affect the others".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(MultiResourceParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_transform".into(),
+            description: Some("Project a FHIR resource into a custom JSON object using one FHIRPath expression per output field".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(TransformParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_bundle_filter".into(),
+            description: Some("Return the resources of given types or type glob patterns (e.g. `*Request`) from a FHIR Bundle, including nested Bundles, grouped by type and optionally mapped through a FHIRPath expression".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(BundleFilterParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_explain".into(),
+            description: Some("Explain a FHIRPath expression step by step, showing the collection size after each navigation, function and operator".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(ExplainParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_functions".into(),
+            description: Some("List the FHIRPath functions supported by the engine with their arity and category".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(FunctionsParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: "fhirpath_format".into(),
+            description: Some("Format a FHIRPath expression canonically, with consistent spacing around operators and in function calls".into()),
+            input_schema: std::sync::Arc::new(
+                serde_json::to_value(FormatParams::json_schema(&mut SchemaGenerator::default()))
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+    ];
+
+    describe_deprecations(&mut tools, DEPRECATIONS);
+    Ok(tools)
+}
+
+/// Append the message of each of `deprecations` to the description of its tool, so clients
+/// learn about deprecations before calling, not only from the warnings
+pub(crate) fn describe_deprecations(tools: &mut [Tool], deprecations: &[Deprecation]) {
+    for tool in tools {
+        for deprecation in deprecations.iter().filter(|d| d.tool == tool.name) {
+            let description = tool.description.get_or_insert_default().to_mut();
+            description.push_str(&format!(". {}", deprecation.message()));
         }
     }
 }
@@ -245,6 +620,60 @@ impl FhirPathToolRouter {
         let result = fhirpath_extract(params).await?;
         Ok(serde_json::to_value(result)?)
     }
+
+    /// Compares an expression's results on two versions of a resource
+    pub async fn fhirpath_diff(&self, params: DiffParams) -> Result<Value> {
+        let result = fhirpath_diff(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Checks whether two expressions produce the same results across sample resources
+    pub async fn fhirpath_compare(&self, params: CompareParams) -> Result<Value> {
+        let result = fhirpath_compare(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Evaluates one expression against each of several resources
+    pub async fn fhirpath_evaluate_multi(&self, params: MultiResourceParams) -> Result<Value> {
+        let result = fhirpath_evaluate_multi(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Evaluates an expression against a resource read from an allowed FHIR server
+    pub async fn fhirpath_evaluate_remote(&self, params: EvaluateRemoteParams) -> Result<Value> {
+        let result = fhirpath_evaluate_remote(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Returns the resources of given types in a Bundle, optionally evaluating an expression on each
+    pub async fn fhirpath_bundle_filter(&self, params: BundleFilterParams) -> Result<Value> {
+        let result = fhirpath_bundle_filter(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Traces a FHIRPath expression's evaluation step by step
+    pub async fn fhirpath_explain(&self, params: ExplainParams) -> Result<Value> {
+        let result = fhirpath_explain(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Projects a FHIR resource into a custom object using one FHIRPath expression per field
+    pub async fn fhirpath_transform(&self, params: TransformParams) -> Result<Value> {
+        let result = fhirpath_transform(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Lists the FHIRPath functions supported by the engine
+    pub async fn fhirpath_functions(&self, params: FunctionsParams) -> Result<Value> {
+        let result = fhirpath_functions(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
+
+    /// Formats a FHIRPath expression canonically
+    pub async fn fhirpath_format(&self, params: FormatParams) -> Result<Value> {
+        let result = fhirpath_format(params).await?;
+        Ok(serde_json::to_value(result)?)
+    }
 }
 
 /// Start the MCP server with proper rmcp SDK integration
@@ -285,8 +714,7 @@ pub async fn demonstrate_tools() -> Result<()> {
             "resourceType": "Patient",
             "name": [{"given": ["John"], "family": "Doe"}]
         }),
-        context: None,
-        timeout_ms: None,
+        ..Default::default()
     };
 
     let result = _router.fhirpath_evaluate(eval_params).await?;
@@ -309,6 +737,105 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_server_capabilities() {
+        let info = FhirPathToolServer::new().get_info();
+        assert!(info.capabilities.tools.is_some());
+        assert!(info.capabilities.prompts.is_some());
+        assert!(info.capabilities.resources.is_some());
+    }
+
+    #[test]
+    fn test_server_identity() {
+        let info = FhirPathToolServer::new().get_info();
+        assert_eq!(info.server_info.name, "octofhir-mcp");
+        assert_eq!(info.server_info.version, crate::VERSION);
+
+        let info = FhirPathToolServer::new()
+            .with_identity(ServerIdentity {
+                server_name: "acme-fhirpath".to_string(),
+                server_version: "2.1.0".to_string(),
+                instructions: "Use these tools for Acme patient records".to_string(),
+            })
+            .get_info();
+        assert_eq!(info.server_info.name, "acme-fhirpath");
+        assert_eq!(info.server_info.version, "2.1.0");
+        assert_eq!(
+            info.instructions.as_deref(),
+            Some("Use these tools for Acme patient records")
+        );
+    }
+
+    #[test]
+    fn test_server_capabilities_advertise_limits() {
+        let security = SecurityConfig {
+            max_expression_length: 250,
+            ..SecurityConfig::default()
+        };
+        let info = FhirPathToolServer::new()
+            .with_limits(ServerLimits::new(&security, "R5"))
+            .get_info();
+
+        let initialize = serde_json::to_value(&info).unwrap();
+        let limits = &initialize["capabilities"]["experimental"][LIMITS_CAPABILITY];
+        assert_eq!(limits["max_expression_length"], 250);
+        assert_eq!(limits["max_resource_size"], security.max_resource_size);
+        assert_eq!(limits["fhir_version"], "R5");
+        assert_eq!(
+            limits["supported_fhir_versions"],
+            json!(["R4", "R4B", "R5"])
+        );
+        // Standard capabilities are unaffected
+        assert!(initialize["capabilities"]["tools"].is_object());
+    }
+
+    #[test]
+    fn test_remote_evaluation_hidden_without_allowed_servers() {
+        let listed = |tools: Vec<Tool>| {
+            tools
+                .iter()
+                .any(|tool| tool.name == "fhirpath_evaluate_remote")
+        };
+        assert!(listed(all_tool_definitions().unwrap()));
+        // No test configures the shared security provider with an allowed FHIR server
+        assert!(!listed(tool_definitions().unwrap()));
+    }
+
+    #[test]
+    fn test_list_tools_pagination() {
+        let server = FhirPathToolServer::new().with_page_size(2);
+        let total = tool_definitions().unwrap().len();
+
+        let mut names = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = server
+                .list_tools_page(Some(PaginatedRequestParam { cursor }))
+                .unwrap();
+            assert!(page.tools.len() <= 2);
+            names.extend(page.tools.into_iter().map(|tool| tool.name.to_string()));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        let mut unique = names.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(names.len(), total);
+        assert_eq!(unique.len(), total);
+
+        for cursor in ["not base64!", "YWJj", &encode_cursor(total + 1)] {
+            let err = server
+                .list_tools_page(Some(PaginatedRequestParam {
+                    cursor: Some(cursor.to_string()),
+                }))
+                .unwrap_err();
+            assert_eq!(err.code, ErrorCode::INVALID_PARAMS);
+        }
+    }
+
     #[tokio::test]
     async fn test_tool_router_functionality() {
         // Test that the tool router works correctly
@@ -328,8 +855,7 @@ mod tests {
                 "resourceType": "Patient",
                 "name": [{"family": "Smith"}]
             }),
-            context: None,
-            timeout_ms: None,
+            ..Default::default()
         };
 
         let result = router.fhirpath_evaluate(eval_params).await;
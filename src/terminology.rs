@@ -0,0 +1,585 @@
+//! Terminology support for FHIRPath evaluation
+//!
+//! With a [`TerminologyResolver`] set on [`FhirEngineConfig`](crate::FhirEngineConfig), the
+//! engine gains `memberOf(valueset)`, which tests a code, Coding or CodeableConcept against
+//! the codes of a value set. Value sets come from an [`InMemoryTerminology`] map or are
+//! expanded by a FHIR terminology server through [`RemoteTerminology`]. A value set the
+//! resolver doesn't know fails the evaluation with a diagnostic naming it.
+//!
+//! The `%vs-[name]` and `%ext-[name]` environment variables are expanded to the canonical
+//! URLs of HL7 value sets and extensions by [`expand_terminology_variables`] before parsing,
+//! as the engine's parser doesn't accept `-` in variable names.
+
+use crate::cache::LruCache;
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use octofhir_fhirpath::registry::operations::EvaluationContext;
+use octofhir_fhirpath::registry::{
+    FhirPathOperation, MetadataBuilder, OperationMetadata, OperationType, TypeConstraint,
+};
+use octofhir_fhirpath::{FhirPathError, FhirPathValue, utils};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Canonical URL prefix `%vs-[name]` expands to
+pub const VALUE_SET_URL_PREFIX: &str = "http://hl7.org/fhir/ValueSet/";
+
+/// Canonical URL prefix `%ext-[name]` expands to
+pub const EXTENSION_URL_PREFIX: &str = "http://hl7.org/fhir/StructureDefinition/";
+
+/// Default number of expansions a [`RemoteTerminology`] keeps
+pub const DEFAULT_EXPANSION_CACHE_CAPACITY: usize = 256;
+
+/// Default time a [`RemoteTerminology`] keeps an expansion
+pub const DEFAULT_EXPANSION_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// Longest a [`RemoteTerminology`] remembers that a value set is unknown, so one published
+/// later is picked up
+pub const UNKNOWN_VALUE_SET_TTL: Duration = Duration::from_secs(60);
+
+/// Default time a [`RemoteTerminology`] waits for each `$expand` response
+pub const DEFAULT_TERMINOLOGY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Codes requested per `$expand` page
+const EXPANSION_PAGE_SIZE: usize = 1000;
+
+/// Most `$expand` pages read for one value set
+const MAX_EXPANSION_PAGES: usize = 100;
+
+/// Codes of an expanded value set
+#[derive(Debug, Clone, Default)]
+pub struct ValueSet {
+    codes: HashSet<(String, String)>,
+}
+
+impl ValueSet {
+    /// Create a value set of `(system, code)` pairs
+    pub fn new<S, C>(codes: impl IntoIterator<Item = (S, C)>) -> Self
+    where
+        S: Into<String>,
+        C: Into<String>,
+    {
+        Self {
+            codes: codes
+                .into_iter()
+                .map(|(system, code)| (system.into(), code.into()))
+                .collect(),
+        }
+    }
+
+    /// Whether the value set has `code` from `system`, or from any system when `system` is
+    /// `None` (a bare `code` value carries no system)
+    pub fn contains(&self, system: Option<&str>, code: &str) -> bool {
+        match system {
+            Some(system) => self.codes.contains(&(system.to_string(), code.to_string())),
+            None => self.codes.iter().any(|(_, candidate)| candidate == code),
+        }
+    }
+
+    /// Number of codes in the value set
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    /// Whether the value set has no codes
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+}
+
+/// Source of value set expansions for `memberOf()`
+#[async_trait]
+pub trait TerminologyResolver: Send + Sync {
+    /// Expand the value set with canonical `url`, or `None` if it is unknown
+    async fn value_set(&self, url: &str) -> Result<Option<Arc<ValueSet>>>;
+}
+
+impl fmt::Debug for dyn TerminologyResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TerminologyResolver")
+    }
+}
+
+/// Value sets supplied up front, keyed by canonical URL
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryTerminology {
+    value_sets: HashMap<String, Arc<ValueSet>>,
+}
+
+impl InMemoryTerminology {
+    /// Create a resolver without value sets
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `value_set` under the canonical `url`
+    pub fn with_value_set(mut self, url: impl Into<String>, value_set: ValueSet) -> Self {
+        self.value_sets.insert(url.into(), Arc::new(value_set));
+        self
+    }
+}
+
+#[async_trait]
+impl TerminologyResolver for InMemoryTerminology {
+    async fn value_set(&self, url: &str) -> Result<Option<Arc<ValueSet>>> {
+        Ok(self.value_sets.get(url).cloned())
+    }
+}
+
+/// An expansion, or `None` for an unknown value set, with the time it was fetched
+type CachedExpansion = (Instant, Option<Arc<ValueSet>>);
+
+/// Value sets expanded by a FHIR terminology server's `ValueSet/$expand` operation.
+///
+/// Paged expansions are read page by page. Expansions are kept in a bounded LRU cache for a
+/// time to live; value sets the server doesn't know are remembered for at most
+/// [`UNKNOWN_VALUE_SET_TTL`], and failed requests not at all.
+pub struct RemoteTerminology {
+    base_url: String,
+    client: reqwest::Client,
+    timeout: Duration,
+    ttl: Duration,
+    expansions: Mutex<LruCache<String, CachedExpansion>>,
+}
+
+impl RemoteTerminology {
+    /// Create a resolver for the terminology server at `base_url`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            timeout: DEFAULT_TERMINOLOGY_TIMEOUT,
+            ttl: DEFAULT_EXPANSION_CACHE_TTL,
+            expansions: Mutex::new(LruCache::new(DEFAULT_EXPANSION_CACHE_CAPACITY)),
+        }
+    }
+
+    /// Keep at most `capacity` expansions for `ttl` each (a capacity of 0 disables caching)
+    pub fn with_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.expansions = Mutex::new(LruCache::new(capacity));
+        self.ttl = ttl;
+        self
+    }
+
+    /// Fail an expansion when a response takes longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn expand(&self, url: &str) -> Result<Option<Arc<ValueSet>>> {
+        let mut codes = Vec::new();
+        let mut offset = 0;
+        for _ in 0..MAX_EXPANSION_PAGES {
+            let Some(expansion) = self.expand_page(url, offset).await? else {
+                return Ok(None);
+            };
+            let expansion = &expansion["expansion"];
+            let entries = expansion["contains"].as_array().map_or(0, Vec::len);
+            collect_expansion_codes(&expansion["contains"], &mut codes);
+            offset += entries;
+
+            // Without a total the server returned the whole expansion
+            let remaining = expansion["total"]
+                .as_u64()
+                .is_some_and(|total| (offset as u64) < total);
+            if !remaining || entries == 0 {
+                return Ok(Some(Arc::new(ValueSet::new(codes))));
+            }
+        }
+        Err(anyhow!(
+            "Expansion of value set '{url}' has more than {MAX_EXPANSION_PAGES} pages"
+        ))
+    }
+
+    /// One page of the expansion, starting at code `offset`; `None` if the value set is unknown
+    async fn expand_page(&self, url: &str, offset: usize) -> Result<Option<Value>> {
+        let failed = || format!("Failed to expand value set '{url}'");
+        let response = self
+            .client
+            .get(format!("{}/ValueSet/$expand", self.base_url))
+            .query(&[
+                ("url", url),
+                ("offset", &offset.to_string()),
+                ("count", &EXPANSION_PAGE_SIZE.to_string()),
+            ])
+            .header(reqwest::header::ACCEPT, "application/fhir+json")
+            .timeout(self.timeout)
+            .send()
+            .await
+            .with_context(failed)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let expansion = response
+            .error_for_status()
+            .with_context(failed)?
+            .json()
+            .await
+            .with_context(|| format!("Invalid expansion of value set '{url}'"))?;
+        Ok(Some(expansion))
+    }
+
+    fn cached(&self, url: &str) -> Option<Option<Arc<ValueSet>>> {
+        let mut expansions = self.expansions.lock().unwrap();
+        let (stored_at, value_set) = expansions.get(url)?;
+        let ttl = match value_set {
+            Some(_) => self.ttl,
+            None => self.ttl.min(UNKNOWN_VALUE_SET_TTL),
+        };
+        if stored_at.elapsed() < ttl {
+            return Some(value_set.clone());
+        }
+        expansions.remove(url);
+        None
+    }
+}
+
+/// Collect the `(system, code)` pairs of `expansion.contains`, including nested entries
+fn collect_expansion_codes(contains: &Value, codes: &mut Vec<(String, String)>) {
+    for entry in contains.as_array().into_iter().flatten() {
+        if let (Some(system), Some(code)) = (entry["system"].as_str(), entry["code"].as_str()) {
+            codes.push((system.to_string(), code.to_string()));
+        }
+        collect_expansion_codes(&entry["contains"], codes);
+    }
+}
+
+#[async_trait]
+impl TerminologyResolver for RemoteTerminology {
+    async fn value_set(&self, url: &str) -> Result<Option<Arc<ValueSet>>> {
+        if let Some(value_set) = self.cached(url) {
+            return Ok(value_set);
+        }
+
+        let value_set = self.expand(url).await?;
+        self.expansions
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), (Instant::now(), value_set.clone()));
+        Ok(value_set)
+    }
+}
+
+/// Registry operation implementing `memberOf(valueset)` with a terminology resolver
+pub(crate) struct MemberOfOperation {
+    resolver: Arc<dyn TerminologyResolver>,
+    metadata: OperationMetadata,
+}
+
+impl MemberOfOperation {
+    pub(crate) fn new(resolver: Arc<dyn TerminologyResolver>) -> Self {
+        Self {
+            resolver,
+            metadata: MetadataBuilder::new("memberOf", OperationType::Function)
+                .description("Whether the code, Coding or CodeableConcept is in the value set")
+                .parameter("valueset", TypeConstraint::Any, false)
+                .returns(TypeConstraint::Any)
+                .build(),
+        }
+    }
+}
+
+/// The `(system, code)` pairs of a code, Coding or CodeableConcept
+fn codings(item: &Value) -> Vec<(Option<&str>, &str)> {
+    match item {
+        Value::String(code) => vec![(None, code.as_str())],
+        Value::Object(object) => match object.get("coding") {
+            Some(coding) => coding
+                .as_array()
+                .into_iter()
+                .flatten()
+                .flat_map(codings)
+                .collect(),
+            None => object
+                .get("code")
+                .and_then(Value::as_str)
+                .map(|code| (object.get("system").and_then(Value::as_str), code))
+                .into_iter()
+                .collect(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+#[async_trait]
+impl FhirPathOperation for MemberOfOperation {
+    fn identifier(&self) -> &str {
+        "memberOf"
+    }
+
+    fn operation_type(&self) -> OperationType {
+        OperationType::Function
+    }
+
+    fn metadata(&self) -> &OperationMetadata {
+        &self.metadata
+    }
+
+    async fn evaluate(
+        &self,
+        args: &[FhirPathValue],
+        context: &EvaluationContext,
+    ) -> octofhir_fhirpath::Result<FhirPathValue> {
+        let url = match args {
+            [FhirPathValue::String(url)] => url.to_string(),
+            [FhirPathValue::Collection(items)] if items.len() == 1 => match items.get(0) {
+                Some(FhirPathValue::String(url)) => url.to_string(),
+                _ => {
+                    return Err(FhirPathError::evaluation_error(
+                        "memberOf() needs a value set URL",
+                    ));
+                }
+            },
+            [_] => {
+                return Err(FhirPathError::evaluation_error(
+                    "memberOf() needs a value set URL",
+                ));
+            }
+            _ => {
+                return Err(FhirPathError::InvalidArgumentCount {
+                    function_name: "memberOf".to_string(),
+                    expected: 1,
+                    actual: args.len(),
+                });
+            }
+        };
+
+        let item = match &context.input {
+            FhirPathValue::Empty => return Ok(FhirPathValue::Empty),
+            FhirPathValue::Collection(items) => match items.len() {
+                0 => return Ok(FhirPathValue::Empty),
+                1 => items.get(0).unwrap().clone(),
+                _ => {
+                    return Err(FhirPathError::evaluation_error(
+                        "memberOf() needs a single code, Coding or CodeableConcept",
+                    ));
+                }
+            },
+            item => item.clone(),
+        };
+
+        let value_set = self
+            .resolver
+            .value_set(&url)
+            .await
+            .map_err(|e| FhirPathError::evaluation_error(format!("{e:#}")))?
+            .ok_or_else(|| {
+                FhirPathError::evaluation_error(format!("Value set '{url}' could not be resolved"))
+            })?;
+        let item = utils::fhir_value_to_serde(&item).map_err(FhirPathError::evaluation_error)?;
+        Ok(FhirPathValue::Boolean(
+            codings(&item)
+                .into_iter()
+                .any(|(system, code)| value_set.contains(system, code)),
+        ))
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// Replace `%vs-[name]` and `%ext-[name]` (also written `` %`vs-[name]` `` or
+/// `%'vs-[name]'`) with string literals of their canonical URLs, leaving string literals
+/// and comments untouched
+pub fn expand_terminology_variables(expression: &str) -> Cow<'_, str> {
+    if !expression.contains("vs-") && !expression.contains("ext-") {
+        return Cow::Borrowed(expression);
+    }
+
+    let mut expanded = String::with_capacity(expression.len());
+    let mut rest = expression;
+    while let Some(c) = rest.chars().next() {
+        // Copy string literals, delimited identifiers and comments verbatim
+        let verbatim_end = match c {
+            '\'' | '`' | '"' => Some(closing_quote(rest, c)),
+            '/' if rest.starts_with("//") => Some(rest.find('\n').unwrap_or(rest.len())),
+            '/' if rest.starts_with("/*") => {
+                Some(rest.find("*/").map_or(rest.len(), |end| end + 2))
+            }
+            '%' => {
+                if let Some((url, len)) = terminology_variable(&rest[1..]) {
+                    expanded.push('\'');
+                    expanded.push_str(&url);
+                    expanded.push('\'');
+                    rest = &rest[1 + len..];
+                    continue;
+                }
+                None
+            }
+            _ => None,
+        };
+
+        let len = verbatim_end.unwrap_or(c.len_utf8());
+        expanded.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    Cow::Owned(expanded)
+}
+
+/// Length of the quoted text at the start of `text` including both quotes, honouring
+/// backslash escapes
+fn closing_quote(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// The canonical URL of the terminology variable `text` starts with (after the `%`) and
+/// the variable's length
+fn terminology_variable(text: &str) -> Option<(String, usize)> {
+    let (name, len) = match text.chars().next()? {
+        quote @ ('`' | '\'' | '"') => {
+            let end = text[1..].find(quote)? + 1;
+            (&text[1..end], end + 1)
+        }
+        _ => {
+            let len = text
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+                .unwrap_or(text.len());
+            (&text[..len], len)
+        }
+    };
+
+    let url = if let Some(id) = name.strip_prefix("vs-") {
+        format!("{VALUE_SET_URL_PREFIX}{id}")
+    } else if let Some(id) = name.strip_prefix("ext-") {
+        format!("{EXTENSION_URL_PREFIX}{id}")
+    } else {
+        return None;
+    };
+    (!url.ends_with('/')).then_some((url, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serve `$expand` for one value set of five codes, two per page, counting requests.
+    /// `ValueSet/slow` never answers in time.
+    async fn terminology_server(requests: Arc<AtomicUsize>) -> String {
+        use axum::{Json, Router, extract::Query, http::StatusCode, routing::get};
+
+        let expand = move |Query(params): Query<HashMap<String, String>>| {
+            let requests = requests.clone();
+            async move {
+                requests.fetch_add(1, Ordering::SeqCst);
+                match params["url"].as_str() {
+                    "http://example.org/ValueSet/colors" => {
+                        let offset: usize = params["offset"].parse().unwrap();
+                        let contains: Vec<Value> = ["red", "green", "blue", "cyan", "black"]
+                            .iter()
+                            .skip(offset)
+                            .take(2)
+                            .map(|code| {
+                                serde_json::json!({"system": "http://example.org/colors", "code": code})
+                            })
+                            .collect();
+                        Ok(Json(serde_json::json!({
+                            "resourceType": "ValueSet",
+                            "expansion": {"total": 5, "offset": offset, "contains": contains}
+                        })))
+                    }
+                    "http://example.org/ValueSet/slow" => {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        Err(StatusCode::GATEWAY_TIMEOUT)
+                    }
+                    _ => Err(StatusCode::NOT_FOUND),
+                }
+            }
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/fhir", listener.local_addr().unwrap());
+        let router = Router::new().route("/fhir/ValueSet/$expand", get(expand));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        base
+    }
+
+    #[tokio::test]
+    async fn test_remote_terminology_pages_and_caches_expansions() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let base = terminology_server(requests.clone()).await;
+        let terminology = RemoteTerminology::new(&base);
+
+        let colors = "http://example.org/ValueSet/colors";
+        let value_set = terminology.value_set(colors).await.unwrap().unwrap();
+        assert_eq!(value_set.len(), 5);
+        assert!(value_set.contains(Some("http://example.org/colors"), "black"));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // Served from the cache
+        terminology.value_set(colors).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let unknown = "http://example.org/ValueSet/unknown";
+        assert!(terminology.value_set(unknown).await.unwrap().is_none());
+        assert!(terminology.value_set(unknown).await.unwrap().is_none());
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // Expired entries are fetched again, unknown value sets included
+        let uncached = RemoteTerminology::new(&base).with_cache(8, Duration::ZERO);
+        for _ in 0..2 {
+            assert!(uncached.value_set(unknown).await.unwrap().is_none());
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 6);
+
+        // The least recently used expansion is evicted
+        let small = RemoteTerminology::new(&base).with_cache(1, DEFAULT_EXPANSION_CACHE_TTL);
+        small.value_set(colors).await.unwrap();
+        small.value_set(unknown).await.unwrap();
+        small.value_set(colors).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 6 + 3 + 1 + 3);
+    }
+
+    #[tokio::test]
+    async fn test_remote_terminology_timeout() {
+        let base = terminology_server(Arc::new(AtomicUsize::new(0))).await;
+        let terminology = RemoteTerminology::new(base).with_timeout(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let err = terminology
+            .value_set("http://example.org/ValueSet/slow")
+            .await
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(format!("{err:#}").contains("Failed to expand value set"));
+    }
+
+    #[test]
+    fn test_expand_terminology_variables() {
+        assert_eq!(
+            expand_terminology_variables("code.memberOf(%vs-administrative-gender)"),
+            "code.memberOf('http://hl7.org/fhir/ValueSet/administrative-gender')"
+        );
+        assert_eq!(
+            expand_terminology_variables("extension(%`ext-patient-birthPlace`).exists()"),
+            "extension('http://hl7.org/fhir/StructureDefinition/patient-birthPlace').exists()"
+        );
+        // String literals, comments and other variables are left alone
+        for expression in [
+            "'%vs-x' = name",
+            "name // %vs-x",
+            "%resource.id - %context.id",
+            "Patient.name",
+        ] {
+            assert_eq!(expand_terminology_variables(expression), expression);
+        }
+    }
+}
@@ -6,24 +6,144 @@
 
 use anyhow::{Result, anyhow};
 use num_traits::cast::ToPrimitive;
-use octofhir_fhirpath::FhirPathValue;
+use octofhir_fhirpath::model::TemporalPrecision;
+use octofhir_fhirpath::registry::operations::{
+    CdaOperations, CollectionOperations, ConversionOperations, DateTimeOperations, FhirOperations,
+    LogicalOperations, MathOperations, StringOperations, TypeOperations, UtilityOperations,
+};
+use octofhir_fhirpath::registry::{FunctionMetadata, OperationSpecificMetadata, OperationType};
+use octofhir_fhirpath::{
+    BinaryOperator, ExpressionNode, FhirPathRegistry, FhirPathValue, LiteralValue, ModelProvider,
+    UnaryOperator, utils,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use std::collections::HashMap;
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{Instrument, Span, field};
+
+use crate::cache::{CachedEvaluation, ResultKey, canonical_expression};
+use crate::custom_functions::CUSTOM_FUNCTION_CATEGORY;
+use crate::diagnostics::{EvaluationDiagnostic, ParseDiagnostic};
+use crate::fhirpath_engine::ServerBusy;
+use crate::lint::Lint;
+use crate::references::ReferenceResolver;
+use crate::remote::InvalidResourceRef;
+use crate::security::{
+    FhirServerNotAllowed, RequestSanitizer, ResourceTooDeep, ResourceTooLarge, ToolScope,
+};
+use crate::trace::TraceCollector;
 
 /// Input parameters for FHIRPath evaluation
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct EvaluateParams {
     /// The FHIRPath expression to evaluate
     pub expression: String,
     /// The FHIR resource to evaluate against (JSON)
     pub resource: Value,
-    /// Optional context variables
+    /// Optional context variables. `%resource`, `%rootResource` and `%context` are always
+    /// bound to `resource` and take precedence over variables with those names.
     pub context: Option<HashMap<String, Value>>,
     /// Optional timeout in milliseconds (default: 5000ms)
     pub timeout_ms: Option<u64>,
+    /// Optional FHIR version to evaluate against (R4, R4B, R5; default: server version)
+    pub fhir_version: Option<String>,
+    /// How values are returned (default: plain)
+    #[serde(default)]
+    pub output: Option<OutputFormat>,
+    /// Resources `resolve()` may return, matched by `#id` or `ResourceType/id` before the bundle
+    #[serde(default)]
+    pub contained_resources: Option<Vec<Value>>,
+    /// Bundle whose entries `resolve()` may return, matched by `fullUrl` or `ResourceType/id`
+    #[serde(default)]
+    pub bundle: Option<Value>,
+    /// FHIR type of `resource` when it is not a resource, e.g. `HumanName`; the expression then
+    /// navigates from that element (`given.first()` or `HumanName.given.first()`) and no
+    /// `resourceType` is required
+    #[serde(default)]
+    pub root_type: Option<String>,
+    /// Most values to return, capped by the server's limit (default: the server's limit)
+    #[serde(default)]
+    pub max_values: Option<usize>,
+    /// Return each value as `{type, value, ...}` without lossy conversion: decimals as strings
+    /// keeping every digit, including those written in `resource`, temporal values with their
+    /// precision. Overrides `output`.
+    #[serde(default)]
+    pub lossless: bool,
+    /// Expression selecting the part of `resource` to evaluate against, e.g.
+    /// `Bundle.entry.resource.where(resourceType = 'Patient')`. `expression` is then evaluated
+    /// with each selected item as its context and the results are concatenated.
+    #[serde(default)]
+    pub focus_path: Option<String>,
+    /// Evaluate afresh instead of reusing a cached result, replacing the cached entry with the
+    /// new result. Over HTTP, a `Cache-Control: no-cache` request header sets this.
+    #[serde(default)]
+    pub bypass_cache: bool,
+    /// Record the evaluation's trace and return it in the result's `trace` (default: none).
+    /// Traced evaluations don't use the result cache.
+    #[serde(default)]
+    pub trace: Option<TraceLevel>,
+}
+
+/// How much of an evaluation `fhirpath_evaluate` traces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceLevel {
+    /// No trace
+    #[default]
+    None,
+    /// The values passed to `trace()` calls in the expression
+    Summary,
+    /// `trace()` calls plus the value of every intermediate step, as `fhirpath_explain`
+    /// breaks the expression down. Steps are evaluated against `resource` within what is left
+    /// of `timeout_ms`, so they are left out when `focus_path` is set and stop once time runs
+    /// out.
+    Full,
+}
+
+/// A value recorded while tracing an evaluation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// `trace` for `trace()` calls, otherwise the kind of step as in `fhirpath_explain`
+    pub operation: String,
+    /// Name given to `trace()`, or what the step applies, e.g. `name` or `where(...)`
+    pub name: String,
+    /// The traced collection, cut to the evaluation's `max_values`
+    pub values: Vec<Value>,
+    /// Size of the traced collection when `values` was cut
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_count: Option<usize>,
+}
+
+impl TraceEntry {
+    fn new(
+        operation: impl Into<String>,
+        name: String,
+        value: FhirPathValue,
+        convert: fn(&FhirPathValue) -> Value,
+        max_values: usize,
+    ) -> Self {
+        let collection = fhirpath_value_to_collection(value);
+        Self {
+            operation: operation.into(),
+            name,
+            values: collection.iter().take(max_values).map(convert).collect(),
+            total_count: (collection.len() > max_values).then_some(collection.len()),
+        }
+    }
+}
+
+/// Representation of evaluated values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Values as plain JSON; quantities become `{value, unit}` and dates strings
+    #[default]
+    Plain,
+    /// Each value as `{type, value, ...}`, keeping temporal precision and quantity codes
+    Typed,
 }
 
 /// Result of FHIRPath evaluation
@@ -39,6 +159,25 @@ pub struct EvaluateResult {
     pub expression_info: ExpressionInfo,
     /// Any evaluation errors or warnings
     pub diagnostics: Option<Vec<String>>,
+    /// Whether the expression failed to parse or evaluate, as opposed to matching nothing
+    #[serde(default)]
+    pub evaluation_failed: bool,
+    /// Evaluation errors with their kind and, when it could be found, the offending range
+    /// of the expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<EvaluationDiagnostic>>,
+    /// Whether `values` and `types` were cut short at the result limit
+    #[serde(default)]
+    pub truncated: bool,
+    /// Number of values the expression produced, present when the result was truncated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_count: Option<usize>,
+    /// Correlation ID of the request, to match diagnostics with server logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Values recorded during evaluation, present when `trace` was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Vec<TraceEntry>>,
 }
 
 /// Performance metrics for evaluation
@@ -50,6 +189,9 @@ pub struct PerformanceMetrics {
     pub parse_time_ms: f64,
     /// Evaluation time in milliseconds
     pub evaluation_time_ms: f64,
+    /// Whether the result was served from the result cache
+    #[serde(default)]
+    pub cache_hit: bool,
 }
 
 /// Information about the evaluated expression
@@ -63,6 +205,25 @@ pub struct ExpressionInfo {
     pub ast_node_count: Option<usize>,
 }
 
+/// Input parameters for evaluating an expression against a resource read from a FHIR server
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EvaluateRemoteParams {
+    /// Base URL of the FHIR server, e.g. `https://hapi.fhir.org/baseR4`. Only servers on the
+    /// server's allow list can be used.
+    pub fhir_server_base: String,
+    /// Reference of the resource to evaluate against, e.g. `Patient/123`
+    pub resource_ref: String,
+    /// The FHIRPath expression to evaluate
+    pub expression: String,
+    /// Optional timeout in milliseconds for fetching the resource and for evaluating the
+    /// expression, each (default: 5000ms)
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Optional FHIR version to evaluate against (R4, R4B, R5; default: server version)
+    #[serde(default)]
+    pub fhir_version: Option<String>,
+}
+
 /// Input parameters for FHIRPath parsing
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ParseParams {
@@ -79,6 +240,9 @@ pub struct ParseResult {
     pub valid: bool,
     /// Any parsing errors
     pub errors: Vec<String>,
+    /// Where parsing failed and how the expression might be fixed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub error_details: Vec<ParseDiagnostic>,
     /// Expression metadata
     pub metadata: ExpressionMetadata,
     /// Optional AST representation
@@ -99,14 +263,18 @@ pub struct ExpressionMetadata {
 }
 
 /// Input parameters for FHIRPath extraction
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct ExtractParams {
     /// The FHIRPath expression for extraction
     pub expression: String,
     /// The FHIR resource to extract from (JSON)
     pub resource: Value,
-    /// Output format (values, paths, structured)
+    /// Output format (values, paths, structured, jsonpatch)
     pub format: Option<String>,
+    /// Optional timeout in milliseconds (default: 5000ms)
+    pub timeout_ms: Option<u64>,
+    /// Optional FHIR version to extract with (R4, R4B, R5; default: server version)
+    pub fhir_version: Option<String>,
 }
 
 /// Result of FHIRPath extraction
@@ -129,6 +297,31 @@ pub struct ExtractionMetadata {
     pub value_types: Vec<String>,
     /// Execution time in milliseconds
     pub execution_time_ms: f64,
+    /// Indices of values whose location could not be traced back to the resource
+    /// (computed values); their paths use the `result[i]` placeholder
+    pub computed_paths: Vec<usize>,
+}
+
+/// A chunk of values streamed by [`fhirpath_extract_stream`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractChunk {
+    /// Position of the chunk in the stream
+    pub index: usize,
+    /// Extracted values, in result order
+    pub values: Vec<Value>,
+    /// Type of each value
+    pub types: Vec<String>,
+}
+
+/// Summary sent after the last chunk of a streamed extraction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractStreamSummary {
+    /// Total number of values extracted
+    pub value_count: usize,
+    /// Number of chunks sent
+    pub chunk_count: usize,
+    /// Execution time in milliseconds
+    pub execution_time_ms: f64,
 }
 
 /// Input parameters for FHIRPath expression analysis
@@ -164,6 +357,259 @@ pub struct AnalyzeResult {
     pub syntax: SyntaxAnalysis,
     /// Optional detailed AST
     pub ast: Option<Value>,
+    /// Likely mistakes such as `where(true)` or comparing a collection to a single value
+    pub lints: Vec<Lint>,
+}
+
+/// Input parameters for comparing an expression's results on two resources
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DiffParams {
+    /// The FHIRPath expression to evaluate on both resources
+    pub expression: String,
+    /// The original FHIR resource (JSON)
+    pub left_resource: Value,
+    /// The changed FHIR resource (JSON)
+    pub right_resource: Value,
+}
+
+/// Result of comparing evaluation results
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffResult {
+    /// Values produced only for the left resource (removed)
+    pub only_in_left: Vec<DiffValue>,
+    /// Values produced only for the right resource (added)
+    pub only_in_right: Vec<DiffValue>,
+    /// Values produced for both resources
+    pub common: Vec<DiffValue>,
+    /// Whether the results differ
+    pub changed: bool,
+    /// Execution time in milliseconds
+    pub execution_time_ms: f64,
+}
+
+/// A single value in a diff, with its type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffValue {
+    pub value: Value,
+    #[serde(rename = "type")]
+    pub value_type: String,
+}
+
+/// Input parameters for checking whether two expressions are equivalent
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CompareParams {
+    /// The first FHIRPath expression
+    pub expression_a: String,
+    /// The second FHIRPath expression
+    pub expression_b: String,
+    /// Sample FHIR resources (JSON) to evaluate both expressions against
+    pub resources: Vec<Value>,
+}
+
+/// Result of comparing two expressions across sample resources
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompareResult {
+    /// Whether both expressions produced identical results for every resource
+    pub equivalent: bool,
+    /// Per-resource comparison, in input order
+    pub results: Vec<CompareEntry>,
+    /// Execution time in milliseconds
+    pub execution_time_ms: f64,
+}
+
+/// Results of both expressions for a single resource
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompareEntry {
+    /// Position of the resource in the input
+    pub index: usize,
+    /// Whether both expressions produced the same values, types and order
+    pub equivalent: bool,
+    /// Values produced by `expression_a`
+    pub result_a: Vec<DiffValue>,
+    /// Values produced by `expression_b`
+    pub result_b: Vec<DiffValue>,
+}
+
+/// Input parameters for evaluating one expression against several resources
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MultiResourceParams {
+    /// The FHIRPath expression to evaluate
+    pub expression: String,
+    /// FHIR resources (JSON) to evaluate the expression against, each on its own
+    pub resources: Vec<Value>,
+}
+
+/// Results of one expression across several resources
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MultiResourceResult {
+    /// Per-resource results, in input order
+    pub results: Vec<MultiResourceEntry>,
+    /// Number of resources the expression failed on
+    pub error_count: usize,
+    /// Execution time in milliseconds
+    pub execution_time_ms: f64,
+}
+
+/// Result of the expression for a single resource
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MultiResourceEntry {
+    /// Position of the resource in the input
+    pub index: usize,
+    /// Values produced for this resource; empty when evaluation failed
+    pub values: Vec<DiffValue>,
+    /// Why evaluation failed for this resource
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Input parameters for projecting a resource into a custom shape
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransformParams {
+    /// The FHIR resource to transform (JSON)
+    pub resource: Value,
+    /// Output field names mapped to the FHIRPath expression producing each field
+    pub mapping: HashMap<String, String>,
+}
+
+/// Result of a resource transformation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransformResult {
+    /// The assembled object: `null` for empty results, a scalar for single values,
+    /// an array otherwise
+    pub output: serde_json::Map<String, Value>,
+    /// Execution time in milliseconds
+    pub execution_time_ms: f64,
+}
+
+/// Input parameters for picking the resources of given types out of a Bundle
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BundleFilterParams {
+    /// The FHIR Bundle to search (JSON). Bundles nested in its entries are searched too.
+    pub bundle: Value,
+    /// Resource types to return, e.g. `["Patient", "Observation"]`. Glob patterns match
+    /// several types: `*` matches any run of characters, `?` one character and `[...]` one of
+    /// the listed characters, so `Service*` and `*Request` select families of types and `*`
+    /// every resource.
+    pub resource_types: Vec<String>,
+    /// Optional FHIRPath expression evaluated on each matching resource; its values are
+    /// returned in place of the resource
+    #[serde(default)]
+    pub expression: Option<String>,
+}
+
+/// Resources of the requested types found in a Bundle
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleFilterResult {
+    /// Each matching resource type mapped to its resources in Bundle order, or to the array
+    /// of expression values per resource when `expression` was given. Types requested by
+    /// exact name are present even when no resource has them.
+    pub resources: BTreeMap<String, Vec<Value>>,
+    /// Number of matching resources across all types
+    pub match_count: usize,
+    /// Execution time in milliseconds
+    pub execution_time_ms: f64,
+}
+
+/// Input parameters for tracing the evaluation of an expression
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ExplainParams {
+    /// The FHIRPath expression to explain
+    pub expression: String,
+    /// The FHIR resource to evaluate against (JSON)
+    pub resource: Value,
+}
+
+/// Input parameters for listing the functions supported by the engine
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct FunctionsParams {
+    /// Only list functions in this category, e.g. `collection` or `string`
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// Functions supported by the engine
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FunctionsResult {
+    /// Supported functions, sorted by name
+    pub functions: Vec<FunctionInfo>,
+    /// Number of functions listed
+    pub total_count: usize,
+}
+
+/// A function supported by the engine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    /// Operation group the function belongs to, e.g. `collection` or `string`
+    pub category: String,
+    pub description: String,
+    /// Fewest arguments the function accepts
+    pub min_arity: usize,
+    /// Most arguments the function accepts; `None` when variadic
+    pub max_arity: Option<usize>,
+    /// Whether arguments are evaluated once per input item, as in `where`
+    pub supports_lambda: bool,
+    /// Whether the same input always gives the same result
+    pub deterministic: bool,
+}
+
+/// Input parameters for formatting an expression
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FormatParams {
+    /// The FHIRPath expression to format
+    pub expression: String,
+    /// Spacing of the formatted expression (default: standard)
+    #[serde(default)]
+    pub style: Option<FormatStyle>,
+}
+
+/// Spacing of a formatted expression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FormatStyle {
+    /// One space around operators and after commas, e.g. `name.where(use = 'official')`
+    #[default]
+    Standard,
+    /// Spaces only where words need them, e.g. `name.where(use='official')`
+    Compact,
+}
+
+/// Canonical form of an expression
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FormatResult {
+    /// The formatted expression, or the original one when it could not be formatted
+    pub formatted: String,
+    /// Whether `formatted` differs from the original expression
+    pub changed: bool,
+    /// Why the expression could not be formatted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Step-by-step evaluation trace of an expression
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExplainResult {
+    /// Evaluation steps in execution order; the last step produces the final result
+    pub steps: Vec<ExplainStep>,
+    /// The final result values
+    pub values: Vec<Value>,
+    /// Execution time in milliseconds
+    pub execution_time_ms: f64,
+}
+
+/// One path navigation, function application or operator in an evaluation trace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExplainStep {
+    /// Position of the step in the trace
+    pub step: usize,
+    /// Kind of step: navigate, filter, function, index, operator or value
+    pub operation: String,
+    /// What the step applies, e.g. `name` or `where(...)`
+    pub description: String,
+    /// Size of the collection the step is applied to, for steps in an invocation chain
+    pub input_count: Option<usize>,
+    /// Size of the collection the step produces
+    pub output_count: usize,
 }
 
 /// Expression analysis information
@@ -177,10 +623,126 @@ pub struct ExpressionAnalysis {
     pub path_segments: usize,
     /// Number of function calls
     pub function_count: usize,
+    /// Number of predicate (filtering/projection) function calls
+    pub predicate_count: usize,
+    /// Maximum nesting depth of function calls
+    pub ast_depth: usize,
     /// Whether the expression uses collections
     pub uses_collections: bool,
 }
 
+/// Structural metrics computed by walking a parsed expression tree
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ComplexityMetrics {
+    /// Maximum nesting depth of function calls (1 for plain navigation)
+    pub ast_depth: usize,
+    /// Total number of AST nodes
+    pub node_count: usize,
+    /// Number of function and method calls
+    pub function_count: usize,
+    /// Number of calls taking a per-item predicate or projection, plus filters
+    pub predicate_count: usize,
+    /// Number of member navigation segments
+    pub path_segments: usize,
+    /// Number of operators, including `is`, `as` and union
+    pub operator_count: usize,
+    /// Number of indexer expressions
+    pub index_count: usize,
+    /// Names of the called functions, sorted and deduplicated
+    pub functions: Vec<String>,
+}
+
+/// Functions returning a subset of their input, so each result keeps its element location
+const SUBSETTING_FUNCTIONS: &[&str] = &[
+    "where", "first", "last", "tail", "skip", "take", "single", "distinct", "ofType",
+];
+
+/// Functions whose argument is evaluated once per input item
+const PREDICATE_FUNCTIONS: &[&str] = &["where", "select", "all", "exists", "repeat", "aggregate"];
+
+impl ComplexityMetrics {
+    /// Compute metrics for a parsed expression
+    pub fn from_ast(node: &ExpressionNode) -> Self {
+        let mut metrics = Self::default();
+        metrics.ast_depth = metrics.visit(node).max(1);
+        metrics.functions.sort();
+        metrics.functions.dedup();
+        metrics
+    }
+
+    /// Accumulate counts for `node` and its children, returning its function nesting depth
+    fn visit(&mut self, node: &ExpressionNode) -> usize {
+        self.node_count += 1;
+
+        match node {
+            ExpressionNode::Literal(_) | ExpressionNode::Variable(_) => 0,
+            ExpressionNode::Identifier(_) => {
+                self.path_segments += 1;
+                0
+            }
+            ExpressionNode::Path { base, .. } => {
+                self.path_segments += 1;
+                self.visit(base)
+            }
+            ExpressionNode::BinaryOp(data) => {
+                self.operator_count += 1;
+                self.visit(&data.left).max(self.visit(&data.right))
+            }
+            ExpressionNode::Union { left, right } => {
+                self.operator_count += 1;
+                self.visit(left).max(self.visit(right))
+            }
+            ExpressionNode::UnaryOp { operand, .. } => {
+                self.operator_count += 1;
+                self.visit(operand)
+            }
+            ExpressionNode::TypeCheck { expression, .. }
+            | ExpressionNode::TypeCast { expression, .. } => {
+                self.operator_count += 1;
+                self.visit(expression)
+            }
+            ExpressionNode::FunctionCall(data) => {
+                self.record_call(&data.name, !data.args.is_empty());
+                1 + self.visit_all(data.args.iter())
+            }
+            ExpressionNode::MethodCall(data) => {
+                self.record_call(&data.method, !data.args.is_empty());
+                let base_depth = self.visit(&data.base);
+                base_depth.max(1 + self.visit_all(data.args.iter()))
+            }
+            ExpressionNode::Conditional(data) => {
+                self.record_call("iif", true);
+                let mut depth = self.visit(&data.condition).max(self.visit(&data.then_expr));
+                if let Some(else_expr) = &data.else_expr {
+                    depth = depth.max(self.visit(else_expr));
+                }
+                1 + depth
+            }
+            ExpressionNode::Index { base, index } => {
+                self.index_count += 1;
+                self.visit(base).max(self.visit(index))
+            }
+            ExpressionNode::Filter { base, condition } => {
+                self.predicate_count += 1;
+                self.visit(base).max(self.visit(condition))
+            }
+            ExpressionNode::Lambda(data) => self.visit(&data.body),
+        }
+    }
+
+    fn visit_all<'a>(&mut self, nodes: impl Iterator<Item = &'a ExpressionNode>) -> usize {
+        nodes.map(|node| self.visit(node)).max().unwrap_or(0)
+    }
+
+    fn record_call(&mut self, name: &str, has_args: bool) {
+        self.function_count += 1;
+        if has_args && PREDICATE_FUNCTIONS.contains(&name) {
+            self.predicate_count += 1;
+        }
+        self.functions.push(name.to_string());
+    }
+}
+
 /// Performance prediction
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PerformancePrediction {
@@ -207,6 +769,15 @@ pub struct SyntaxAnalysis {
 
 // Helper functions for value conversion and type analysis
 
+/// The conversion of result values requested by `params`
+fn value_converter(params: &EvaluateParams) -> fn(&FhirPathValue) -> Value {
+    match params.output.unwrap_or_default() {
+        _ if params.lossless => fhirpath_value_to_lossless_json,
+        OutputFormat::Plain => fhirpath_value_to_json,
+        OutputFormat::Typed => fhirpath_value_to_typed_json,
+    }
+}
+
 /// Convert FhirPathValue to JSON Value for serialization
 fn fhirpath_value_to_json(value: &FhirPathValue) -> Value {
     match value {
@@ -233,6 +804,170 @@ fn fhirpath_value_to_json(value: &FhirPathValue) -> Value {
     }
 }
 
+/// UCUM code system of quantity units
+const UCUM_SYSTEM: &str = "http://unitsofmeasure.org";
+
+/// Convert a FhirPathValue to a `{type, value, ...}` object that keeps its FHIR type:
+/// temporal values add `precision`, quantities add UCUM `system` and `code`
+fn fhirpath_value_to_typed_json(value: &FhirPathValue) -> Value {
+    match value {
+        FhirPathValue::Date(d) => json!({
+            "type": "date",
+            "value": d.to_string(),
+            "precision": temporal_precision(d.precision),
+        }),
+        FhirPathValue::DateTime(dt) => json!({
+            "type": "dateTime",
+            "value": dt.to_string(),
+            "precision": temporal_precision(dt.precision),
+        }),
+        FhirPathValue::Time(t) => json!({
+            "type": "time",
+            "value": t.to_string(),
+            "precision": temporal_precision(t.precision),
+        }),
+        FhirPathValue::Quantity(q) => {
+            let mut quantity = json!({
+                "type": "Quantity",
+                "value": q.value.to_f64(),
+            });
+            if let Some(unit) = &q.unit {
+                quantity["unit"] = json!(unit);
+                quantity["system"] = json!(UCUM_SYSTEM);
+                quantity["code"] = json!(unit);
+            }
+            quantity
+        }
+        FhirPathValue::Collection(items) => {
+            Value::Array(items.iter().map(fhirpath_value_to_typed_json).collect())
+        }
+        FhirPathValue::Resource(_) | FhirPathValue::JsonValue(_) => {
+            // Elements taken from the resource keep their JSON form
+            let json = serde_json::from_str(&value.to_string())
+                .unwrap_or_else(|_| json!(value.to_string()));
+            let type_name = match &json {
+                Value::Bool(_) => "boolean",
+                Value::Number(n) if n.is_i64() => "integer",
+                Value::Number(_) => "decimal",
+                Value::String(_) => "string",
+                Value::Object(obj) => obj
+                    .get("resourceType")
+                    .and_then(Value::as_str)
+                    .unwrap_or("Element"),
+                _ => "Element",
+            };
+            json!({ "type": type_name, "value": json })
+        }
+        _ => json!({
+            "type": get_type_description(value),
+            "value": fhirpath_value_to_json(value),
+        }),
+    }
+}
+
+/// Convert a FhirPathValue like [`fhirpath_value_to_typed_json`], but with decimals, including
+/// quantity values, as strings so no digits are lost to floating point
+fn fhirpath_value_to_lossless_json(value: &FhirPathValue) -> Value {
+    match value {
+        FhirPathValue::Decimal(d) => json!({ "type": "decimal", "value": d.to_string() }),
+        FhirPathValue::Quantity(q) => {
+            let mut quantity = fhirpath_value_to_typed_json(value);
+            quantity["value"] = json!(q.value.to_string());
+            quantity
+        }
+        FhirPathValue::Collection(items) => {
+            Value::Array(items.iter().map(fhirpath_value_to_lossless_json).collect())
+        }
+        _ => {
+            let mut typed = fhirpath_value_to_typed_json(value);
+            // Decimals taken from the resource keep the digits of their JSON form
+            if typed["type"] == "decimal"
+                && let Some(number) = typed["value"].as_number()
+            {
+                typed["value"] = json!(number.to_string());
+            }
+            typed
+        }
+    }
+}
+
+/// Digits of the decimals in `resources` as written in their JSON text, keyed by the digits
+/// the engine reads them as. The engine goes through `f64`, dropping trailing zeros and digits
+/// past its precision; results equal to exactly one decimal of the input get its digits back.
+/// Numbers that read the same but are written differently (`1.5` and `1.50`) are left out.
+fn decimal_digits<'a>(resources: impl IntoIterator<Item = &'a Value>) -> HashMap<String, String> {
+    fn collect(value: &Value, digits: &mut HashMap<String, Option<String>>) {
+        match value {
+            Value::Number(number) => {
+                let text = number.to_string();
+                if text.contains('.')
+                    && !text.contains(['e', 'E'])
+                    && let Ok(sonic) = utils::serde_to_sonic(value)
+                    && let FhirPathValue::Decimal(read) = FhirPathValue::from(sonic)
+                {
+                    digits
+                        .entry(read.to_string())
+                        .and_modify(|known| {
+                            if known.as_ref() != Some(&text) {
+                                *known = None;
+                            }
+                        })
+                        .or_insert_with(|| Some(text));
+                }
+            }
+            Value::Array(items) => items.iter().for_each(|item| collect(item, digits)),
+            Value::Object(fields) => fields.values().for_each(|field| collect(field, digits)),
+            _ => {}
+        }
+    }
+
+    let mut digits = HashMap::new();
+    for resource in resources {
+        collect(resource, &mut digits);
+    }
+    digits
+        .into_iter()
+        .filter_map(|(bits, text)| Some((bits, text?)))
+        .collect()
+}
+
+/// Replace the `value` of lossless decimals and quantities with the digits the input wrote
+/// them with, see [`decimal_digits`]
+fn restore_decimal_digits(values: &mut [Value], digits: &HashMap<String, String>) {
+    for value in values {
+        match value {
+            Value::Array(items) => restore_decimal_digits(items, digits),
+            Value::Object(fields)
+                if matches!(
+                    fields.get("type").and_then(Value::as_str),
+                    Some("decimal" | "Quantity")
+                ) =>
+            {
+                if let Some(restored) = fields
+                    .get("value")
+                    .and_then(Value::as_str)
+                    .and_then(|read| digits.get(read))
+                {
+                    fields.insert("value".to_string(), json!(restored));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn temporal_precision(precision: TemporalPrecision) -> &'static str {
+    match precision {
+        TemporalPrecision::Year => "year",
+        TemporalPrecision::Month => "month",
+        TemporalPrecision::Day => "day",
+        TemporalPrecision::Hour => "hour",
+        TemporalPrecision::Minute => "minute",
+        TemporalPrecision::Second => "second",
+        TemporalPrecision::Millisecond => "millisecond",
+    }
+}
+
 /// Get type description for a FhirPathValue
 fn get_type_description(value: &FhirPathValue) -> String {
     match value {
@@ -260,17 +995,129 @@ fn get_type_description(value: &FhirPathValue) -> String {
     }
 }
 
-/// Assess expression complexity based on the expression string
-fn assess_complexity(expression: &str) -> String {
-    let length = expression.len();
-    let function_count = expression.matches('(').count();
-    let operator_count = expression
-        .matches(&['=', '!', '<', '>', '&', '|'][..])
-        .count();
+/// Serialize a parsed FHIRPath expression tree into a stable JSON structure.
+///
+/// Every node carries a `type`; named nodes add `name`, operators add `operator`,
+/// literals add `literal_type` and `value`, and sub-expressions are listed in
+/// evaluation order under `children`.
+fn expression_to_json(node: &ExpressionNode) -> Value {
+    match node {
+        ExpressionNode::Literal(literal) => literal_to_json(literal),
+        ExpressionNode::Identifier(name) => json!({
+            "type": "Identifier",
+            "name": name
+        }),
+        ExpressionNode::Path { base, path } => json!({
+            "type": "Path",
+            "name": path,
+            "children": [expression_to_json(base)]
+        }),
+        ExpressionNode::BinaryOp(data) => json!({
+            "type": "BinaryOp",
+            "operator": data.op.as_str(),
+            "children": [expression_to_json(&data.left), expression_to_json(&data.right)]
+        }),
+        ExpressionNode::UnaryOp { op, operand } => json!({
+            "type": "UnaryOp",
+            "operator": op.as_str(),
+            "children": [expression_to_json(operand)]
+        }),
+        ExpressionNode::FunctionCall(data) => json!({
+            "type": "FunctionCall",
+            "name": data.name,
+            "children": data.args.iter().map(expression_to_json).collect::<Vec<_>>()
+        }),
+        ExpressionNode::MethodCall(data) => {
+            let mut children = vec![expression_to_json(&data.base)];
+            children.extend(data.args.iter().map(expression_to_json));
+            json!({
+                "type": "MethodCall",
+                "name": data.method,
+                "children": children
+            })
+        }
+        ExpressionNode::Index { base, index } => json!({
+            "type": "Index",
+            "children": [expression_to_json(base), expression_to_json(index)]
+        }),
+        ExpressionNode::Filter { base, condition } => json!({
+            "type": "Filter",
+            "children": [expression_to_json(base), expression_to_json(condition)]
+        }),
+        ExpressionNode::Union { left, right } => json!({
+            "type": "Union",
+            "operator": "|",
+            "children": [expression_to_json(left), expression_to_json(right)]
+        }),
+        ExpressionNode::TypeCheck {
+            expression,
+            type_name,
+        } => json!({
+            "type": "TypeCheck",
+            "type_name": type_name,
+            "children": [expression_to_json(expression)]
+        }),
+        ExpressionNode::TypeCast {
+            expression,
+            type_name,
+        } => json!({
+            "type": "TypeCast",
+            "type_name": type_name,
+            "children": [expression_to_json(expression)]
+        }),
+        ExpressionNode::Lambda(data) => json!({
+            "type": "Lambda",
+            "params": data.params.to_vec(),
+            "children": [expression_to_json(&data.body)]
+        }),
+        ExpressionNode::Conditional(data) => {
+            let mut children = vec![
+                expression_to_json(&data.condition),
+                expression_to_json(&data.then_expr),
+            ];
+            if let Some(else_expr) = &data.else_expr {
+                children.push(expression_to_json(else_expr));
+            }
+            json!({
+                "type": "Conditional",
+                "children": children
+            })
+        }
+        ExpressionNode::Variable(name) => json!({
+            "type": "Variable",
+            "name": name
+        }),
+    }
+}
+
+/// Serialize a literal AST node, keeping the literal's FHIRPath type
+fn literal_to_json(literal: &LiteralValue) -> Value {
+    let (literal_type, value) = match literal {
+        LiteralValue::Boolean(b) => ("Boolean", json!(b)),
+        LiteralValue::Integer(i) => ("Integer", json!(i)),
+        LiteralValue::Decimal(d) => ("Decimal", json!(d)),
+        LiteralValue::String(s) => ("String", json!(s)),
+        LiteralValue::Date(d) => ("Date", json!(d)),
+        LiteralValue::DateTime(dt) => ("DateTime", json!(dt)),
+        LiteralValue::Time(t) => ("Time", json!(t)),
+        LiteralValue::Quantity { value, unit } => {
+            ("Quantity", json!({ "value": value, "unit": unit }))
+        }
+        LiteralValue::Null => ("Null", Value::Null),
+    };
+
+    json!({
+        "type": "Literal",
+        "literal_type": literal_type,
+        "value": value
+    })
+}
 
-    if length < 20 && function_count <= 1 && operator_count <= 1 {
+/// Assess expression complexity from its structural metrics
+fn assess_complexity(metrics: &ComplexityMetrics) -> String {
+    if metrics.function_count == 0 && metrics.operator_count <= 1 && metrics.ast_depth <= 1 {
         "simple".to_string()
-    } else if length < 50 && function_count <= 2 && operator_count <= 2 {
+    } else if metrics.function_count <= 2 && metrics.operator_count <= 2 && metrics.ast_depth <= 2 {
         "moderate".to_string()
     } else {
         "complex".to_string()
@@ -285,51 +1132,768 @@ fn fhirpath_value_to_collection(value: FhirPathValue) -> Vec<FhirPathValue> {
     }
 }
 
-/// Evaluates FHIRPath expressions against FHIR resources, returning typed results with performance metrics
-pub async fn fhirpath_evaluate(params: EvaluateParams) -> Result<EvaluateResult> {
-    let start_time = Instant::now();
-
-    // Validate expression is not empty
-    if params.expression.trim().is_empty() {
-        return Err(anyhow!("Expression cannot be empty"));
+/// Resolve the resource elements selected by a navigation expression, with their locations
+/// (e.g. `Patient.name[0].given[1]`). Filters and subsetting functions pass the locations of
+/// their input through. Returns `None` for expressions computing new values.
+fn locate_elements(node: &ExpressionNode, resource: &Value) -> Option<Vec<(String, Value)>> {
+    match node {
+        ExpressionNode::Identifier(name) => {
+            let resource_type = resource.get("resourceType")?.as_str()?;
+            let root = vec![(resource_type.to_string(), resource.clone())];
+            if name == resource_type {
+                Some(root)
+            } else {
+                Some(locate_children(root, name))
+            }
+        }
+        ExpressionNode::Path { base, path } => {
+            Some(locate_children(locate_elements(base, resource)?, path))
+        }
+        ExpressionNode::Index { base, index } => match index.as_ref() {
+            ExpressionNode::Literal(LiteralValue::Integer(i)) => {
+                let elements = locate_elements(base, resource)?;
+                Some(
+                    usize::try_from(*i)
+                        .ok()
+                        .and_then(|i| elements.into_iter().nth(i))
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            _ => None,
+        },
+        ExpressionNode::Filter { base, .. } => locate_elements(base, resource),
+        ExpressionNode::MethodCall(data)
+            if SUBSETTING_FUNCTIONS.contains(&data.method.as_str()) =>
+        {
+            let mut elements = locate_elements(&data.base, resource)?;
+            let count = || match data.args.first() {
+                Some(ExpressionNode::Literal(LiteralValue::Integer(n))) => {
+                    Some(usize::try_from(*n).unwrap_or(0))
+                }
+                _ => None,
+            };
+            // Positional subsetting picks its elements here, so equal values at different
+            // locations can't be confused when the results are matched up
+            match data.method.as_str() {
+                "first" => elements.truncate(1),
+                "last" => elements = elements.pop().into_iter().collect(),
+                "tail" => elements = elements.into_iter().skip(1).collect(),
+                "skip" => elements = elements.into_iter().skip(count()?).collect(),
+                "take" => elements.truncate(count()?),
+                _ => {}
+            }
+            Some(elements)
+        }
+        _ => None,
     }
+}
 
-    // Create context variables if provided (skip for now due to complexity)
-    if params.context.is_some() {
-        return Err(anyhow!(
-            "Context variables not yet supported in this implementation"
-        ));
-    }
+fn locate_children(elements: Vec<(String, Value)>, name: &str) -> Vec<(String, Value)> {
+    elements
+        .into_iter()
+        .filter_map(|(path, element)| element.get(name).map(|child| (path, child.clone())))
+        .flat_map(|(path, child)| match child {
+            Value::Array(items) => items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| (format!("{path}.{name}[{i}]"), item))
+                .collect::<Vec<_>>(),
+            child => vec![(format!("{path}.{name}"), child)],
+        })
+        .collect()
+}
 
-    let _parse_start = Instant::now();
-    let eval_start = Instant::now();
+/// Assign each result value the location of the element it came from, in result order.
+/// Values that cannot be matched keep a `result[i]` placeholder and are listed as computed,
+/// as do values a filter kept from several elements equal to them, whose location is
+/// ambiguous.
+fn result_paths(
+    ast: Option<&ExpressionNode>,
+    resource: &Value,
+    values: &[Value],
+) -> (Vec<String>, Vec<usize>) {
+    let mut located = ast
+        .and_then(|ast| locate_elements(ast, resource))
+        .unwrap_or_default();
 
-    // Use the shared engine configured with proper provider
-    let engine = crate::fhirpath_engine::get_shared_engine().await?;
-    let result = engine
-        .evaluate(&params.expression, params.resource.clone())
-        .await;
+    // Plain navigation yields exactly the engine's elements, in the same order
+    let positional = located.len() == values.len();
 
-    let eval_time = eval_start.elapsed();
-    let parse_time = _parse_start.elapsed();
+    let mut paths = Vec::with_capacity(values.len());
+    let mut computed = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        let found = if positional {
+            Some(0)
+        } else {
+            let mut matches = located
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, element))| element == value)
+                .map(|(index, _)| index);
+            matches.next().filter(|_| matches.next().is_none())
+        };
+        match found {
+            Some(index) => paths.push(located.remove(index).0),
+            None => {
+                paths.push(format!("result[{i}]"));
+                computed.push(i);
+            }
+        }
+    }
 
-    let (values, types, diagnostics) = match result {
-        Ok(fhir_value) => {
-            let collection = fhirpath_value_to_collection(fhir_value);
+    (paths, computed)
+}
 
-            let values: Vec<Value> = collection.iter().map(fhirpath_value_to_json).collect();
+/// Convert an element path such as `Patient.name[0].family` into a JSON Pointer into the
+/// resource (`/name/0/family`). The root resource type becomes the empty pointer.
+fn json_pointer(path: &str) -> String {
+    path.split('.')
+        .skip(1)
+        .flat_map(|segment| match segment.strip_suffix(']') {
+            Some(indexed) => match indexed.split_once('[') {
+                Some((name, index)) => vec![name, index],
+                None => vec![segment],
+            },
+            None => vec![segment],
+        })
+        .map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
 
-            let types: Vec<String> = collection.iter().map(get_type_description).collect();
+/// RFC 6902 operations that check and then replace each located value, for clients writing
+/// changes back. Computed values have no location in the resource and are left out.
+fn json_patch_template(paths: &[String], values: &[Value]) -> Vec<Value> {
+    paths
+        .iter()
+        .zip(values)
+        .filter(|(path, _)| !path.starts_with("result["))
+        .flat_map(|(path, value)| {
+            let pointer = json_pointer(path);
+            [
+                json!({"op": "test", "path": pointer, "value": value}),
+                json!({"op": "replace", "path": pointer, "value": value}),
+            ]
+        })
+        .collect()
+}
+
+/// Error returned when the evaluated `resource` is not a FHIR resource object
+#[derive(Debug, thiserror::Error)]
+#[error("resource must be a FHIR resource object with a string resourceType, got {found}")]
+pub struct InvalidResource {
+    /// What was supplied instead, e.g. "an array"
+    pub found: &'static str,
+}
+
+/// Error returned when `root_type` does not name a type of the FHIR model
+#[derive(Debug, thiserror::Error)]
+#[error("root_type '{root_type}' is not a known FHIR type")]
+pub struct UnknownRootType {
+    pub root_type: String,
+}
+
+/// Require `root_type` to be a type name and, when the model provider has the FHIR
+/// specification loaded, a type it defines
+async fn check_root_type(
+    provider: &dyn ModelProvider,
+    root_type: &str,
+) -> Result<(), UnknownRootType> {
+    let well_formed = root_type
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic())
+        && root_type.chars().all(|c| c.is_ascii_alphanumeric());
+    let known = well_formed
+        && (provider.get_type_reflection(root_type).await.is_some()
+            // Without the specification types can't be looked up, so any name is accepted
+            || provider.get_type_reflection("Patient").await.is_none());
+
+    if known {
+        Ok(())
+    } else {
+        Err(UnknownRootType {
+            root_type: root_type.to_string(),
+        })
+    }
+}
+
+/// `ast` with paths starting at `root_type` starting at the evaluation's input instead, as
+/// FHIRPath allows an expression to name the type of its context (`HumanName.given` against
+/// a `HumanName`). The engine only recognizes that for resources, by their `resourceType`,
+/// which elements evaluated against a `root_type` don't have. Function arguments are
+/// evaluated against other inputs, so only the expression's own paths are anchored.
+fn anchor_root_type(ast: &ExpressionNode, root_type: &str) -> ExpressionNode {
+    let mut ast = ast.clone();
+    anchor_paths(&mut ast, root_type);
+    ast
+}
+
+fn anchor_paths(node: &mut ExpressionNode, root_type: &str) {
+    match node {
+        ExpressionNode::Identifier(name) if name == root_type => {
+            *node = ExpressionNode::Variable("this".to_string());
+        }
+        ExpressionNode::Path { base, .. }
+        | ExpressionNode::Index { base, .. }
+        | ExpressionNode::Filter { base, .. } => anchor_paths(base, root_type),
+        ExpressionNode::MethodCall(call) => anchor_paths(&mut call.base, root_type),
+        ExpressionNode::BinaryOp(op) => {
+            anchor_paths(&mut op.left, root_type);
+            anchor_paths(&mut op.right, root_type);
+        }
+        ExpressionNode::Union { left, right } => {
+            anchor_paths(left, root_type);
+            anchor_paths(right, root_type);
+        }
+        ExpressionNode::UnaryOp { operand, .. } => anchor_paths(operand, root_type),
+        ExpressionNode::TypeCheck { expression, .. }
+        | ExpressionNode::TypeCast { expression, .. } => anchor_paths(expression, root_type),
+        _ => {}
+    }
+}
+
+/// Require `resource` to be a JSON object with a string `resourceType`, so callers get a
+/// precise error instead of an opaque engine failure
+fn check_resource_object(resource: &Value) -> Result<(), InvalidResource> {
+    let found = match resource {
+        Value::Object(object) => match object.get("resourceType") {
+            Some(Value::String(_)) => return Ok(()),
+            Some(_) => "an object with a non-string resourceType",
+            None => "an object without resourceType",
+        },
+        Value::Array(_) => "an array",
+        Value::String(_) => "a string",
+        Value::Number(_) => "a number",
+        Value::Bool(_) => "a boolean",
+        Value::Null => "null",
+    };
+    Err(InvalidResource { found })
+}
+
+/// Reject resources nested deeper than the shared security provider's `max_resource_depth`
+/// or larger than its `max_resource_size` before they reach the engine. Depth goes first:
+/// serializing for the size check recurses into the value.
+fn enforce_resource_size(resource: &Value) -> Result<()> {
+    let validator = crate::security::shared_security_provider().validator();
+    validator.check_resource_depth(resource)?;
+    validator.check_resource_size(resource)?;
+    Ok(())
+}
+
+/// Resource type recorded on tool spans and per-type metrics
+pub(crate) fn resource_type(resource: &Value) -> &str {
+    resource
+        .get("resourceType")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown")
+}
+
+tokio::task_local! {
+    /// Correlation ID of the request a tool call serves
+    static CORRELATION_ID: String;
+}
+
+/// Run `future` with `correlation_id` attached to the tool calls it makes, so their spans and
+/// diagnostics can be matched to the request
+pub async fn with_correlation_id<F: std::future::Future>(
+    correlation_id: String,
+    future: F,
+) -> F::Output {
+    CORRELATION_ID.scope(correlation_id, future).await
+}
+
+/// Correlation ID of the request being served, if any
+pub fn current_correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(String::clone).ok()
+}
+
+/// Record the outcome of a tool call on the current tool span
+fn record_span_result(result_count: Option<usize>, duration: Duration) {
+    let span = Span::current();
+    if let Some(count) = result_count {
+        span.record("result_count", count);
+    }
+    span.record("duration_ms", duration.as_secs_f64() * 1000.0);
+}
+
+/// Error returned when an evaluation exceeds its time limit
+#[derive(Debug, thiserror::Error)]
+#[error("Evaluation timed out after {timeout_ms} ms")]
+pub struct EvaluationTimeout {
+    pub timeout_ms: u64,
+}
+
+async fn evaluate_with_timeout(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    expression: &str,
+    resource: Value,
+    timeout_ms: u64,
+) -> Result<FhirPathValue> {
+    let model_provider = engine.model_provider().clone();
+    evaluate_with_provider_timeout(engine, expression, resource, model_provider, timeout_ms).await
+}
+
+/// Aborts the evaluation task when dropped, e.g. because the client that asked for the
+/// evaluation disconnected and its request future was dropped
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn evaluate_with_provider_timeout(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    expression: &str,
+    resource: Value,
+    model_provider: Arc<dyn ModelProvider>,
+    timeout_ms: u64,
+) -> Result<FhirPathValue> {
+    let expression = expression.to_string();
+    let evaluation = async move {
+        engine
+            .evaluate_with_provider_in_slot(&expression, resource, model_provider)
+            .await
+    };
+    run_evaluation_task(engine, timeout_ms, evaluation).await
+}
+
+/// Like [`evaluate_with_provider_timeout`], for an expression parsed once and shared by
+/// several evaluations
+async fn evaluate_ast_with_timeout(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    ast: Arc<ExpressionNode>,
+    resource: Value,
+    model_provider: Arc<dyn ModelProvider>,
+    timeout_ms: u64,
+) -> Result<FhirPathValue> {
+    let evaluation = async move {
+        engine
+            .evaluate_ast_with_provider_in_slot(&ast, resource, model_provider)
+            .await
+    };
+    run_evaluation_task(engine, timeout_ms, evaluation).await
+}
+
+/// Run `evaluation` on a separate task so the time limit holds even when evaluation never
+/// yields. The task is aborted once the limit elapses or the returned future is dropped.
+async fn run_evaluation_task(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    timeout_ms: u64,
+    evaluation: impl std::future::Future<Output = Result<FhirPathValue>> + Send + 'static,
+) -> Result<FhirPathValue> {
+    let evaluation = async {
+        // The evaluation slot is held here rather than by the task: an aborted task only stops
+        // at its next yield, which a CPU-bound evaluation may never reach, while a dropped
+        // request should free its slot at once. The task keeps its pooled engine until it
+        // finishes, so abandoned evaluations are still bounded by the pool size.
+        let _permit = engine.acquire_evaluation_permit().await?;
+        // Keep engine log events inside the tool span, and trace() calls in the evaluation's
+        // trace when it has one
+        let evaluation = evaluation.in_current_span();
+        let mut task = AbortOnDrop(match TraceCollector::current() {
+            Some(collector) => tokio::spawn(collector.scope(evaluation)),
+            None => tokio::spawn(evaluation),
+        });
+        (&mut task.0)
+            .await
+            .map_err(|e| anyhow!("Evaluation task failed: {}", e))?
+    };
+
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), evaluation).await {
+        Ok(result) => result,
+        Err(_) => Err(EvaluationTimeout { timeout_ms }.into()),
+    }
+}
+
+/// Functions whose result depends on when they are called
+const CLOCK_FUNCTIONS: &[&str] = &["now", "today", "timeOfDay"];
+
+/// Functions whose result depends on value set expansions, which expire and may change
+const TERMINOLOGY_FUNCTIONS: &[&str] = &["memberOf"];
+
+/// Result cache key covering everything an evaluation depends on: the expression, resource,
+/// root type, FHIR version, output format and the resources `resolve()` may return. The
+/// expression is keyed by its canonical form, so equivalent spellings share an entry.
+/// Expressions reading the clock or value sets, or calling custom functions that aren't
+/// deterministic, have no key, as their results are never reused.
+fn result_cache_key(
+    params: &EvaluateParams,
+    engine: &crate::fhirpath_engine::FhirPathEngineFactory,
+) -> Option<ResultKey> {
+    let expression = match engine.parse(&params.expression) {
+        Ok(ast) => {
+            let functions = ComplexityMetrics::from_ast(&ast).functions;
+            let custom_functions = &engine.config().custom_functions;
+            if CLOCK_FUNCTIONS
+                .iter()
+                .chain(TERMINOLOGY_FUNCTIONS)
+                .any(|volatile| functions.iter().any(|function| function == volatile))
+                || custom_functions.iter().any(|custom| {
+                    !custom.deterministic()
+                        && functions.iter().any(|function| function == custom.name())
+                })
+            {
+                return None;
+            }
+            canonical_expression(&ast)
+        }
+        Err(_) => params.expression.clone(),
+    };
+    let fhir_version = Value::from(engine.config().fhir_version.as_str());
+    let output = if params.lossless {
+        json!("lossless")
+    } else {
+        json!(params.output.unwrap_or_default())
+    };
+    let root_type = json!(params.root_type);
+    let focus_path = json!(params.focus_path);
+    let mut inputs = vec![
+        &params.resource,
+        &root_type,
+        &focus_path,
+        &fhir_version,
+        &output,
+        params.bundle.as_ref().unwrap_or(&Value::Null),
+    ];
+    inputs.extend(params.contained_resources.iter().flatten());
+    Some(ResultKey::new(&expression, &inputs))
+}
+
+/// Evaluate `expression` against `resource`, an element of `root_type` when one is given.
+/// Pure path navigation is answered directly from the resource, bypassing the engine pool.
+async fn evaluate_expression(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    expression: &str,
+    resource: &Value,
+    root_type: Option<&str>,
+    resolver: Arc<ReferenceResolver>,
+    timeout_ms: u64,
+) -> Result<FhirPathValue> {
+    let Some(root_type) = root_type else {
+        let fast_path = engine
+            .parse(expression)
+            .ok()
+            .and_then(|ast| crate::fast_path::evaluate(&ast, resource));
+        return match fast_path {
+            Some(value) => Ok(value),
+            None => {
+                evaluate_with_provider_timeout(
+                    engine,
+                    expression,
+                    resource.clone(),
+                    resolver,
+                    timeout_ms,
+                )
+                .await
+            }
+        };
+    };
+
+    let ast = engine.parse(expression)?;
+    let ast = Arc::new(anchor_root_type(&ast, root_type));
+    match crate::fast_path::evaluate(&ast, resource) {
+        Some(value) => Ok(value),
+        None => {
+            evaluate_ast_with_timeout(engine, ast, resource.clone(), resolver, timeout_ms).await
+        }
+    }
+}
+
+/// Evaluate `expression` against each item `focus_path` selects from `resource` (of
+/// `root_type` when given), all within one `timeout_ms`
+async fn evaluate_in_focus(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    focus_path: &str,
+    expression: &str,
+    resource: &Value,
+    root_type: Option<&str>,
+    resolver: Arc<ReferenceResolver>,
+    timeout_ms: u64,
+) -> Result<FhirPathValue> {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let focus = evaluate_expression(
+        engine,
+        focus_path,
+        resource,
+        root_type,
+        resolver.clone(),
+        timeout_ms,
+    )
+    .await
+    .map_err(|e| anyhow!("focus_path: {}", e))?;
+
+    let mut values = Vec::new();
+    for item in fhirpath_value_to_collection(focus) {
+        let remaining_ms = deadline
+            .saturating_duration_since(Instant::now())
+            .as_millis() as u64;
+        let value = match evaluate_expression(
+            engine,
+            expression,
+            &focus_item_to_json(&item),
+            None,
+            resolver.clone(),
+            remaining_ms.max(1),
+        )
+        .await
+        {
+            Ok(value) => value,
+            // Report the timeout the caller asked for, not what was left of it
+            Err(e) if e.is::<EvaluationTimeout>() => {
+                return Err(EvaluationTimeout { timeout_ms }.into());
+            }
+            Err(e) => return Err(e),
+        };
+        values.extend(fhirpath_value_to_collection(value));
+    }
+    Ok(FhirPathValue::Collection(values.into()))
+}
+
+/// JSON form of a focus item, which elements and resources keep
+fn focus_item_to_json(value: &FhirPathValue) -> Value {
+    match value {
+        FhirPathValue::Resource(_) | FhirPathValue::JsonValue(_) => {
+            serde_json::from_str(&value.to_string()).unwrap_or_else(|_| json!(value.to_string()))
+        }
+        _ => fhirpath_value_to_json(value),
+    }
+}
+
+/// Evaluate without consulting the result cache, storing successful results under
+/// `cache_key` if the evaluation has one
+async fn evaluate_uncached(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    params: &EvaluateParams,
+    cache_key: Option<ResultKey>,
+) -> Result<CachedEvaluation> {
+    let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_TOOL_TIMEOUT_MS);
+    let resolver = Arc::new(ReferenceResolver::new(
+        engine.model_provider().clone(),
+        params.contained_resources.clone().unwrap_or_default(),
+        params.bundle.clone(),
+    ));
+    let result = match &params.focus_path {
+        Some(focus_path) => {
+            evaluate_in_focus(
+                engine,
+                focus_path,
+                &params.expression,
+                &params.resource,
+                params.root_type.as_deref(),
+                resolver.clone(),
+                timeout_ms,
+            )
+            .await
+        }
+        None => {
+            evaluate_expression(
+                engine,
+                &params.expression,
+                &params.resource,
+                params.root_type.as_deref(),
+                resolver.clone(),
+                timeout_ms,
+            )
+            .await
+        }
+    };
+
+    let (diagnostic, error) = match result {
+        Ok(fhir_value) => {
+            let collection = fhirpath_value_to_collection(fhir_value);
+
+            let mut values: Vec<Value> = collection.iter().map(value_converter(params)).collect();
+            if params.lossless {
+                let mut inputs = vec![&params.resource];
+                inputs.extend(params.contained_resources.iter().flatten());
+                inputs.extend(&params.bundle);
+                restore_decimal_digits(&mut values, &decimal_digits(inputs));
+            }
+
+            let types: Vec<String> = collection.iter().map(get_type_description).collect();
+
+            // Unresolvable references evaluate to empty; report them rather than failing
+            let unresolved: Vec<String> = resolver
+                .unresolved()
+                .into_iter()
+                .map(|reference| format!("Could not resolve reference '{reference}'"))
+                .collect();
+
+            let evaluation = CachedEvaluation {
+                values,
+                types,
+                diagnostics: (!unresolved.is_empty()).then_some(unresolved),
+                errors: None,
+            };
+            if let Some(cache_key) = cache_key {
+                engine.result_cache().insert(cache_key, evaluation.clone());
+            }
+            return Ok(evaluation);
+        }
+        // Clients tell these apart by their error code rather than by an empty result
+        Err(e) if e.is::<ServerBusy>() || e.is::<EvaluationTimeout>() => return Err(e),
+        Err(e) => (format!("Evaluation error: {}", e), e),
+    };
+
+    Ok(CachedEvaluation {
+        values: vec![],
+        types: vec![],
+        diagnostics: Some(vec![diagnostic]),
+        errors: Some(vec![EvaluationDiagnostic::from_error(
+            &params.expression,
+            &error,
+        )]),
+    })
+}
 
-            (values, types, None)
+/// Evaluate `params`, reusing the result of an identical earlier evaluation unless the caller
+/// asks for a fresh result, which then replaces the cached one. Traced evaluations always
+/// run, collecting `trace()` calls into `collector`. Also returns whether the result came
+/// from the cache.
+async fn evaluate_cached(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    params: &EvaluateParams,
+    collector: Option<&TraceCollector>,
+) -> (Result<CachedEvaluation>, bool) {
+    let cache_key = result_cache_key(params, engine);
+    let cached = match &cache_key {
+        Some(cache_key) if !params.bypass_cache && collector.is_none() => {
+            engine.result_cache().get(cache_key)
         }
-        Err(e) => {
-            let diagnostics = vec![format!("Evaluation error: {}", e)];
-            (vec![], vec![], Some(diagnostics))
+        _ => None,
+    };
+
+    match (cached, collector) {
+        (Some(cached), _) => (Ok(CachedEvaluation::clone(&cached)), true),
+        (None, Some(collector)) => (
+            collector
+                .clone()
+                .scope(evaluate_uncached(engine, params, cache_key))
+                .await,
+            false,
+        ),
+        (None, None) => (evaluate_uncached(engine, params, cache_key).await, false),
+    }
+}
+
+/// Evaluates FHIRPath expressions against FHIR resources, returning typed results with performance metrics
+///
+/// An expression that matches nothing is not an error: it yields empty `values` with
+/// `evaluation_failed` unset and no `errors`. When the expression fails to parse or to
+/// evaluate, `evaluation_failed` is set and the failure is reported in `errors` and
+/// `diagnostics`. `diagnostics` of a successful evaluation only carry warnings, such as
+/// references that could not be resolved.
+#[tracing::instrument(
+    name = "fhirpath_evaluate",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        resource_type = resource_type(&params.resource),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_evaluate(params: EvaluateParams) -> Result<EvaluateResult> {
+    let start_time = Instant::now();
+
+    // Validate expression is not empty
+    if params.expression.trim().is_empty() {
+        return Err(anyhow!("Expression cannot be empty"));
+    }
+
+    if params.root_type.is_none() {
+        check_resource_object(&params.resource)?;
+    }
+    enforce_resource_size(&params.resource)?;
+    for resource in params.contained_resources.iter().flatten() {
+        enforce_resource_size(resource)?;
+    }
+    if let Some(bundle) = &params.bundle {
+        enforce_resource_size(bundle)?;
+    }
+
+    // Create context variables if provided (skip for now due to complexity)
+    if params.context.is_some() {
+        return Err(anyhow!(
+            "Context variables not yet supported in this implementation"
+        ));
+    }
+
+    let _parse_start = Instant::now();
+    let eval_start = Instant::now();
+
+    // Use the engine for the requested FHIR version (the shared engine by default)
+    let engine =
+        crate::fhirpath_engine::get_engine_for_version(params.fhir_version.as_deref()).await?;
+    if let Some(root_type) = &params.root_type {
+        check_root_type(engine.model_provider().as_ref(), root_type).await?;
+    }
+    let trace_level = params.trace.unwrap_or_default();
+    let collector = (trace_level != TraceLevel::None).then(TraceCollector::new);
+    let (outcome, cache_hit) = evaluate_cached(engine, &params, collector.as_ref()).await;
+    let failed = match &outcome {
+        Ok(evaluation) => evaluation.errors.is_some(),
+        Err(_) => true,
+    };
+    crate::metrics::profile::expression_profiler().record(
+        &params.expression,
+        start_time.elapsed(),
+        failed,
+    );
+    let evaluation = outcome?;
+    let CachedEvaluation {
+        mut values,
+        mut types,
+        diagnostics,
+        errors,
+    } = evaluation;
+
+    // The cache keeps the full result, so each request applies its own limit
+    let max_values = params
+        .max_values
+        .map_or(engine.config().max_result_values, |max| {
+            max.min(engine.config().max_result_values)
+        });
+    let total_count = values.len();
+    let truncated = total_count > max_values;
+    if truncated {
+        values.truncate(max_values);
+        types.truncate(max_values);
+    }
+
+    let trace = match collector {
+        Some(collector) => {
+            let convert = value_converter(&params);
+            let mut trace: Vec<TraceEntry> = collector
+                .take()
+                .into_iter()
+                .map(|(name, value)| TraceEntry::new("trace", name, value, convert, max_values))
+                .collect();
+            if trace_level == TraceLevel::Full && params.focus_path.is_none() {
+                let timeout =
+                    Duration::from_millis(params.timeout_ms.unwrap_or(DEFAULT_TOOL_TIMEOUT_MS));
+                trace.extend(trace_steps(engine, &params, start_time + timeout, max_values).await);
+            }
+            Some(trace)
         }
+        None => None,
     };
 
+    let eval_time = eval_start.elapsed();
+    let parse_time = _parse_start.elapsed();
+
+    // The expression was parsed (and cached) during evaluation, so this is a cache lookup
+    let metrics = engine
+        .parse(&params.expression)
+        .ok()
+        .map(|ast| ComplexityMetrics::from_ast(&ast));
+
     let total_time = start_time.elapsed();
+    record_span_result(Some(total_count), total_time);
 
     Ok(EvaluateResult {
         values,
@@ -338,44 +1902,181 @@ pub async fn fhirpath_evaluate(params: EvaluateParams) -> Result<EvaluateResult>
             execution_time_ms: total_time.as_secs_f64() * 1000.0,
             parse_time_ms: parse_time.as_secs_f64() * 1000.0,
             evaluation_time_ms: eval_time.as_secs_f64() * 1000.0,
+            cache_hit,
         },
         expression_info: ExpressionInfo {
-            parsed: diagnostics.is_none(),
-            complexity: assess_complexity(&params.expression),
-            ast_node_count: None, // Could be implemented if AST provides node count
+            parsed: metrics.is_some(),
+            complexity: metrics
+                .as_ref()
+                .map_or_else(|| "unknown".to_string(), assess_complexity),
+            ast_node_count: metrics.as_ref().map(|m| m.node_count),
         },
         diagnostics,
+        evaluation_failed: errors.is_some(),
+        errors,
+        truncated,
+        total_count: truncated.then_some(total_count),
+        correlation_id: current_correlation_id(),
+        trace,
+    })
+}
+
+/// The value of each intermediate step of `params.expression` evaluated against
+/// `params.resource`, with the same contained resources and bundle, up to the first step
+/// that fails or `deadline`
+async fn trace_steps(
+    engine: &'static crate::fhirpath_engine::FhirPathEngineFactory,
+    params: &EvaluateParams,
+    deadline: Instant,
+    max_values: usize,
+) -> Vec<TraceEntry> {
+    let Ok(mut ast) = engine.parse(&params.expression) else {
+        return Vec::new();
+    };
+    if let Some(root_type) = &params.root_type {
+        ast = Arc::new(anchor_root_type(&ast, root_type));
+    }
+    let mut planned = Vec::new();
+    plan_steps(&ast, &mut planned);
+
+    let resolver: Arc<dyn ModelProvider> = Arc::new(ReferenceResolver::new(
+        engine.model_provider().clone(),
+        params.contained_resources.clone().unwrap_or_default(),
+        params.bundle.clone(),
+    ));
+    let convert = value_converter(params);
+    let mut trace = Vec::with_capacity(planned.len());
+    for step in planned {
+        let remaining_ms = deadline
+            .saturating_duration_since(Instant::now())
+            .as_millis() as u64;
+        if remaining_ms == 0 {
+            break;
+        }
+        let Ok(value) = evaluate_ast_with_timeout(
+            engine,
+            Arc::new(step.node.clone()),
+            params.resource.clone(),
+            resolver.clone(),
+            remaining_ms,
+        )
+        .await
+        else {
+            break;
+        };
+        trace.push(TraceEntry::new(
+            step.operation,
+            step.description,
+            value,
+            convert,
+            max_values,
+        ));
+    }
+    trace
+}
+
+/// Evaluates a FHIRPath expression against a resource read from an allowed FHIR server
+#[tracing::instrument(
+    name = "fhirpath_evaluate_remote",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        resource_ref = %params.resource_ref,
+    )
+)]
+pub async fn fhirpath_evaluate_remote(params: EvaluateRemoteParams) -> Result<EvaluateResult> {
+    evaluate_remote(
+        params,
+        crate::security::shared_security_provider().validator(),
+    )
+    .await
+}
+
+async fn evaluate_remote(
+    params: EvaluateRemoteParams,
+    validator: &crate::security::validation::InputValidator,
+) -> Result<EvaluateResult> {
+    let base = validator.validate_fhir_server_base(&params.fhir_server_base)?;
+    let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_TOOL_TIMEOUT_MS);
+    let resource = crate::remote::fetch_resource(
+        &base,
+        &params.resource_ref,
+        Duration::from_millis(timeout_ms),
+        validator.max_resource_size(),
+    )
+    .await?;
+
+    fhirpath_evaluate(EvaluateParams {
+        expression: params.expression,
+        resource,
+        timeout_ms: params.timeout_ms,
+        fhir_version: params.fhir_version,
+        ..Default::default()
     })
+    .await
 }
 
 /// Parses and validates FHIRPath expressions, providing detailed syntax analysis
+#[tracing::instrument(
+    name = "fhirpath_parse",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        duration_ms = field::Empty,
+    )
+)]
 pub async fn fhirpath_parse(params: ParseParams) -> Result<ParseResult> {
+    let start_time = Instant::now();
+
     // Validate expression is not empty
     if params.expression.trim().is_empty() {
         return Err(anyhow!("Expression cannot be empty"));
     }
 
-    // For now, do basic validation using the engine
     let engine = crate::fhirpath_engine::get_shared_engine().await?;
 
-    // Try to parse by evaluating against an empty resource
-    let test_resource = json!({});
-    let result = engine.evaluate(&params.expression, test_resource).await;
-
-    let (valid, errors) = match result {
-        Ok(_) => (true, vec![]),
-        Err(e) => (false, vec![e.to_string()]),
+    // Syntax only: evaluating against a placeholder resource would also report errors that
+    // depend on the data, such as operand types, for expressions that parse fine
+    let parsed = engine.parse(&params.expression);
+    let errors = match &parsed {
+        Ok(_) => vec![],
+        Err(e) => vec![e.to_string()],
+    };
+    let valid = errors.is_empty();
+    let error_details = match valid {
+        true => Vec::new(),
+        false => ParseDiagnostic::from_expression(&params.expression)
+            .into_iter()
+            .collect(),
+    };
+    let parsed = parsed.ok();
+    let ast = if params.include_ast.unwrap_or(false) {
+        parsed.as_ref().map(|node| expression_to_json(node))
+    } else {
+        None
     };
 
     // Analyze expression for metadata
-    let functions_used = extract_functions(&params.expression);
+    let metrics = parsed
+        .as_ref()
+        .map(|node| ComplexityMetrics::from_ast(node));
+    let functions_used = match &metrics {
+        Some(metrics) => metrics.functions.clone(),
+        None => extract_functions(&params.expression),
+    };
     let token_count = params.expression.split_whitespace().count();
+    record_span_result(None, start_time.elapsed());
 
     Ok(ParseResult {
         valid,
         errors,
+        error_details,
         metadata: ExpressionMetadata {
-            complexity: assess_complexity(&params.expression),
+            complexity: metrics
+                .as_ref()
+                .map_or_else(|| "unknown".to_string(), assess_complexity),
             token_count,
             functions_used,
             evaluation_complexity: if token_count < 5 {
@@ -386,11 +2087,22 @@ pub async fn fhirpath_parse(params: ParseParams) -> Result<ParseResult> {
                 "high".to_string()
             },
         },
-        ast: None, // Could be implemented with detailed AST analysis
+        ast,
     })
 }
 
 /// Extracts data from FHIR resources using FHIRPath with flexible output formatting
+#[tracing::instrument(
+    name = "fhirpath_extract",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        resource_type = resource_type(&params.resource),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
 pub async fn fhirpath_extract(params: ExtractParams) -> Result<ExtractResult> {
     let start_time = Instant::now();
 
@@ -399,11 +2111,20 @@ pub async fn fhirpath_extract(params: ExtractParams) -> Result<ExtractResult> {
         return Err(anyhow!("Expression cannot be empty"));
     }
 
-    // Use the shared engine configured with proper provider
-    let engine = crate::fhirpath_engine::get_shared_engine().await?;
-    let result = engine
-        .evaluate(&params.expression, params.resource.clone())
-        .await;
+    check_resource_object(&params.resource)?;
+    enforce_resource_size(&params.resource)?;
+
+    // Use the engine for the requested FHIR version (the shared engine by default)
+    let engine =
+        crate::fhirpath_engine::get_engine_for_version(params.fhir_version.as_deref()).await?;
+    let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_TOOL_TIMEOUT_MS);
+    let result = evaluate_with_timeout(
+        engine,
+        &params.expression,
+        params.resource.clone(),
+        timeout_ms,
+    )
+    .await;
 
     let execution_time = start_time.elapsed();
 
@@ -415,8 +2136,9 @@ pub async fn fhirpath_extract(params: ExtractParams) -> Result<ExtractResult> {
 
             let value_types: Vec<String> = collection.iter().map(get_type_description).collect();
 
-            // Generate simple paths for now
-            let paths: Vec<String> = (0..values.len()).map(|i| format!("result[{i}]")).collect();
+            // The expression was parsed (and cached) during evaluation
+            let ast = engine.parse(&params.expression).ok();
+            let (paths, computed_paths) = result_paths(ast.as_deref(), &params.resource, &values);
 
             let format = params.format.as_deref().unwrap_or("values");
             let data = match format {
@@ -426,9 +2148,12 @@ pub async fn fhirpath_extract(params: ExtractParams) -> Result<ExtractResult> {
                     "paths": paths
                 }),
                 "paths" => json!(paths),
+                "jsonpatch" => json!(json_patch_template(&paths, &values)),
                 _ => json!(values), // "values" or default
             };
 
+            record_span_result(Some(values.len()), execution_time);
+
             Ok(ExtractResult {
                 data,
                 paths,
@@ -436,15 +2161,98 @@ pub async fn fhirpath_extract(params: ExtractParams) -> Result<ExtractResult> {
                     value_count: values.len(),
                     value_types,
                     execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+                    computed_paths,
                 },
             })
         }
+        Err(e) if e.is::<EvaluationTimeout>() || e.is::<ServerBusy>() => Err(e),
         Err(e) => Err(anyhow!("Extraction failed: {}", e)),
     }
 }
 
+/// Extracts values like [`fhirpath_extract`], sending them through `sender` in chunks of up
+/// to `chunk_size` values instead of assembling one response.
+///
+/// Values are converted to JSON one chunk at a time, and each send waits for room in the
+/// channel, so a slow consumer holds back conversion rather than buffering the whole result.
+#[tracing::instrument(
+    name = "fhirpath_extract_stream",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        resource_type = resource_type(&params.resource),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_extract_stream(
+    params: ExtractParams,
+    chunk_size: usize,
+    sender: tokio::sync::mpsc::Sender<ExtractChunk>,
+) -> Result<ExtractStreamSummary> {
+    let start_time = Instant::now();
+
+    if params.expression.trim().is_empty() {
+        return Err(anyhow!("Expression cannot be empty"));
+    }
+
+    check_resource_object(&params.resource)?;
+    enforce_resource_size(&params.resource)?;
+
+    let engine =
+        crate::fhirpath_engine::get_engine_for_version(params.fhir_version.as_deref()).await?;
+    let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_TOOL_TIMEOUT_MS);
+    let collection = match evaluate_with_timeout(
+        engine,
+        &params.expression,
+        params.resource,
+        timeout_ms,
+    )
+    .await
+    {
+        Ok(value) => fhirpath_value_to_collection(value),
+        Err(e) if e.is::<EvaluationTimeout>() || e.is::<ServerBusy>() => return Err(e),
+        Err(e) => return Err(anyhow!("Extraction failed: {}", e)),
+    };
+
+    let mut chunk_count = 0;
+    for (index, items) in collection.chunks(chunk_size.max(1)).enumerate() {
+        let chunk = ExtractChunk {
+            index,
+            values: items.iter().map(fhirpath_value_to_json).collect(),
+            types: items.iter().map(get_type_description).collect(),
+        };
+        sender
+            .send(chunk)
+            .await
+            .map_err(|_| anyhow!("Extraction stream closed by the client"))?;
+        chunk_count += 1;
+    }
+
+    let execution_time = start_time.elapsed();
+    record_span_result(Some(collection.len()), execution_time);
+
+    Ok(ExtractStreamSummary {
+        value_count: collection.len(),
+        chunk_count,
+        execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+    })
+}
+
 /// Analyzes FHIRPath expressions providing detailed information about syntax, performance, and usage
+#[tracing::instrument(
+    name = "fhirpath_analyze",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        duration_ms = field::Empty,
+    )
+)]
 pub async fn fhirpath_analyze(params: AnalyzeParams) -> Result<AnalyzeResult> {
+    let start_time = Instant::now();
+
     // Validate expression is not empty
     if params.expression.trim().is_empty() {
         return Err(anyhow!("Expression cannot be empty"));
@@ -453,289 +2261,3106 @@ pub async fn fhirpath_analyze(params: AnalyzeParams) -> Result<AnalyzeResult> {
     let expression = &params.expression;
     let options = params.options.unwrap_or_default();
 
-    // Extract functions from the expression
-    let functions = extract_functions(expression);
-
     // Basic syntax analysis
     let syntax_analysis = analyze_syntax(expression).await;
 
+    // Structural metrics from the parsed AST
+    let parsed = crate::fhirpath_engine::get_shared_engine()
+        .await
+        .and_then(|engine| engine.parse(expression))
+        .ok();
+    let metrics = parsed
+        .as_ref()
+        .map(|node| ComplexityMetrics::from_ast(node))
+        .unwrap_or_default();
+
+    // Fall back to scanning the text when the expression does not parse
+    let functions = if parsed.is_some() {
+        metrics.functions.clone()
+    } else {
+        extract_functions(expression)
+    };
+
     // Expression analysis
-    let analysis = analyze_expression_structure(expression, &functions);
+    let analysis = analyze_expression_structure(&metrics, &functions);
 
     // Performance prediction
     let performance = predict_performance(expression, &functions, &analysis);
 
     // Optional AST analysis
     let ast = if options.include_ast.unwrap_or(false) {
-        // For now, return a placeholder - could be enhanced with detailed AST parsing
-        Some(json!({
-            "type": "expression",
-            "raw": expression,
-            "note": "Detailed AST analysis not yet implemented"
-        }))
+        parsed.as_ref().map(|node| expression_to_json(node))
     } else {
         None
     };
 
+    let lints = crate::lint::lint(expression);
+
+    record_span_result(None, start_time.elapsed());
+
     Ok(AnalyzeResult {
         analysis,
         functions,
         performance,
         syntax: syntax_analysis,
         ast,
+        lints,
     })
 }
 
-fn analyze_expression_structure(expression: &str, functions: &[String]) -> ExpressionAnalysis {
-    let path_segments = expression.split('.').count();
-    let function_count = functions.len();
-    let uses_collections = expression.contains('[')
-        || functions
-            .iter()
-            .any(|f| ["where", "select", "all", "any", "distinct"].contains(&f.as_str()));
+/// Compares an expression's results on two versions of a resource
+#[tracing::instrument(
+    name = "fhirpath_diff",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        resource_type = resource_type(&params.left_resource),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_diff(params: DiffParams) -> Result<DiffResult> {
+    let start_time = Instant::now();
 
-    // Determine expression type
-    let expression_type = if functions
-        .iter()
-        .any(|f| ["where", "exists", "empty"].contains(&f.as_str()))
-    {
-        "filter".to_string()
-    } else if functions
-        .iter()
-        .any(|f| ["count", "sum", "avg"].contains(&f.as_str()))
-    {
-        "aggregation".to_string()
-    } else if functions
-        .iter()
-        .any(|f| ["select", "first", "last"].contains(&f.as_str()))
-    {
-        "transformation".to_string()
-    } else {
-        "query".to_string()
-    };
+    // Validate expression is not empty
+    if params.expression.trim().is_empty() {
+        return Err(anyhow!("Expression cannot be empty"));
+    }
 
-    // Calculate complexity
-    let complexity_score =
-        path_segments + (function_count * 2) + if uses_collections { 2 } else { 0 };
-    let complexity = if complexity_score < 3 {
-        "low".to_string()
-    } else if complexity_score < 8 {
-        "medium".to_string()
-    } else {
-        "high".to_string()
+    check_resource_object(&params.left_resource)?;
+    check_resource_object(&params.right_resource)?;
+    enforce_resource_size(&params.left_resource)?;
+    enforce_resource_size(&params.right_resource)?;
+
+    let engine = crate::fhirpath_engine::get_shared_engine().await?;
+    let evaluate = |resource: Value| async {
+        let value = evaluate_with_timeout(
+            engine,
+            &params.expression,
+            resource,
+            DEFAULT_TOOL_TIMEOUT_MS,
+        )
+        .await?;
+        Ok::<_, anyhow::Error>(typed_values(value))
     };
 
-    ExpressionAnalysis {
-        complexity,
-        expression_type,
-        path_segments,
-        function_count,
-        uses_collections,
+    let left = evaluate(params.left_resource.clone())
+        .await
+        .map_err(|e| anyhow!("Evaluation on left resource failed: {}", e))?;
+    let mut only_in_right = evaluate(params.right_resource.clone())
+        .await
+        .map_err(|e| anyhow!("Evaluation on right resource failed: {}", e))?;
+
+    // Match values as multisets so repeated values are compared by count
+    let mut only_in_left = Vec::new();
+    let mut common = Vec::new();
+    for value in left {
+        match only_in_right
+            .iter()
+            .position(|candidate| *candidate == value)
+        {
+            Some(index) => common.push(only_in_right.remove(index)),
+            None => only_in_left.push(value),
+        }
     }
+
+    let execution_time = start_time.elapsed();
+    record_span_result(
+        Some(only_in_left.len() + only_in_right.len() + common.len()),
+        execution_time,
+    );
+
+    Ok(DiffResult {
+        changed: !only_in_left.is_empty() || !only_in_right.is_empty(),
+        only_in_left,
+        only_in_right,
+        common,
+        execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+    })
 }
 
-async fn analyze_syntax(expression: &str) -> SyntaxAnalysis {
-    // Try to parse the expression using the FHIRPath engine to validate syntax
-    let mut errors = Vec::new();
-    let mut warnings = Vec::new();
-    let mut is_valid = true;
+/// Checks whether two expressions produce the same results across sample resources
+#[tracing::instrument(
+    name = "fhirpath_compare",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression_a.len() + params.expression_b.len(),
+        resource_count = params.resources.len(),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_compare(params: CompareParams) -> Result<CompareResult> {
+    let start_time = Instant::now();
 
-    // Basic token counting
-    let token_count = expression.split_whitespace().count()
-        + expression.matches('.').count()
-        + expression.matches('(').count()
-        + expression.matches('[').count();
+    if params.expression_a.trim().is_empty() || params.expression_b.trim().is_empty() {
+        return Err(anyhow!("Expression cannot be empty"));
+    }
+    if params.resources.is_empty() {
+        return Err(anyhow!("At least one resource is required"));
+    }
+    for resource in &params.resources {
+        enforce_resource_size(resource)?;
+    }
 
-    // Try to validate with the engine if available
-    match crate::fhirpath_engine::get_shared_engine().await {
-        Ok(engine) => {
-            // Try parsing with a dummy resource to check syntax
-            let dummy_resource = serde_json::json!({"resourceType": "Patient"});
-            match engine.evaluate(expression, dummy_resource).await {
-                Err(e) => {
-                    is_valid = false;
-                    errors.push(e.to_string());
+    let engine = crate::fhirpath_engine::get_shared_engine().await?;
+    let evaluate = |expression: &str, resource: &Value, index: usize| {
+        let expression = expression.to_string();
+        let resource = resource.clone();
+        async move {
+            evaluate_with_timeout(engine, &expression, resource, DEFAULT_TOOL_TIMEOUT_MS)
+                .await
+                .map(typed_values)
+                .map_err(|e| {
+                    anyhow!(
+                        "Evaluation of '{}' on resource {} failed: {}",
+                        expression,
+                        index,
+                        e
+                    )
+                })
+        }
+    };
+
+    let mut results = Vec::with_capacity(params.resources.len());
+    for (index, resource) in params.resources.iter().enumerate() {
+        let result_a = evaluate(&params.expression_a, resource, index).await?;
+        let result_b = evaluate(&params.expression_b, resource, index).await?;
+        results.push(CompareEntry {
+            index,
+            equivalent: result_a == result_b,
+            result_a,
+            result_b,
+        });
+    }
+
+    let execution_time = start_time.elapsed();
+    record_span_result(Some(results.len()), execution_time);
+
+    Ok(CompareResult {
+        equivalent: results.iter().all(|entry| entry.equivalent),
+        results,
+        execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+    })
+}
+
+/// Evaluates one expression against each of several resources, parsing it only once
+#[tracing::instrument(
+    name = "fhirpath_evaluate_multi",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        resource_count = params.resources.len(),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_evaluate_multi(params: MultiResourceParams) -> Result<MultiResourceResult> {
+    let start_time = Instant::now();
+
+    if params.expression.trim().is_empty() {
+        return Err(anyhow!("Expression cannot be empty"));
+    }
+    if params.resources.is_empty() {
+        return Err(anyhow!("At least one resource is required"));
+    }
+
+    let engine = crate::fhirpath_engine::get_shared_engine().await?;
+    let ast = engine.parse(&params.expression)?;
+
+    let mut results = Vec::with_capacity(params.resources.len());
+    for (index, resource) in params.resources.into_iter().enumerate() {
+        // A resource that can't be evaluated fails only its own entry
+        let outcome = async {
+            check_resource_object(&resource)?;
+            enforce_resource_size(&resource)?;
+            evaluate_ast_with_timeout(
+                engine,
+                ast.clone(),
+                resource,
+                engine.model_provider().clone(),
+                DEFAULT_TOOL_TIMEOUT_MS,
+            )
+            .await
+        }
+        .await;
+        results.push(match outcome {
+            Ok(value) => MultiResourceEntry {
+                index,
+                values: typed_values(value),
+                error: None,
+            },
+            Err(e) => MultiResourceEntry {
+                index,
+                values: Vec::new(),
+                error: Some(e.to_string()),
+            },
+        });
+    }
+
+    let execution_time = start_time.elapsed();
+    record_span_result(Some(results.len()), execution_time);
+
+    Ok(MultiResourceResult {
+        error_count: results.iter().filter(|entry| entry.error.is_some()).count(),
+        results,
+        execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+    })
+}
+
+/// Flattens an evaluation result into values paired with their FHIRPath types
+fn typed_values(value: FhirPathValue) -> Vec<DiffValue> {
+    fhirpath_value_to_collection(value)
+        .iter()
+        .map(|item| DiffValue {
+            value: fhirpath_value_to_json(item),
+            value_type: get_type_description(item),
+        })
+        .collect()
+}
+
+/// Projects a resource into a flat object using one FHIRPath expression per field
+#[tracing::instrument(
+    name = "fhirpath_transform",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        field_count = params.mapping.len(),
+        resource_type = resource_type(&params.resource),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_transform(params: TransformParams) -> Result<TransformResult> {
+    let start_time = Instant::now();
+
+    if let Some(field) = params
+        .mapping
+        .iter()
+        .find_map(|(field, expression)| expression.trim().is_empty().then_some(field))
+    {
+        return Err(anyhow!("Expression for field '{}' cannot be empty", field));
+    }
+
+    check_resource_object(&params.resource)?;
+    enforce_resource_size(&params.resource)?;
+
+    let engine = crate::fhirpath_engine::get_shared_engine().await?;
+    let mut output = serde_json::Map::new();
+    for (field, expression) in &params.mapping {
+        let value = evaluate_with_timeout(
+            engine,
+            expression,
+            params.resource.clone(),
+            DEFAULT_TOOL_TIMEOUT_MS,
+        )
+        .await
+        .map_err(|e| anyhow!("Evaluation of field '{}' failed: {}", field, e))?;
+
+        let mut values: Vec<Value> = fhirpath_value_to_collection(value)
+            .iter()
+            .map(fhirpath_value_to_json)
+            .collect();
+        let value = match values.len() {
+            0 => Value::Null,
+            1 => values.remove(0),
+            _ => Value::Array(values),
+        };
+        output.insert(field.clone(), value);
+    }
+
+    let execution_time = start_time.elapsed();
+    record_span_result(Some(output.len()), execution_time);
+
+    Ok(TransformResult {
+        output,
+        execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+    })
+}
+
+/// Error returned when `fhirpath_bundle_filter` is given something other than a Bundle
+#[derive(Debug, thiserror::Error)]
+#[error("bundle must be a FHIR Bundle, got {found}")]
+pub struct NotABundle {
+    /// What was supplied instead, e.g. "resourceType 'Patient'"
+    pub found: String,
+}
+
+/// One element of a [`ResourceTypePattern`]
+#[derive(Debug, Clone, PartialEq)]
+enum GlobToken {
+    Literal(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnySequence,
+    /// `[...]`, or `[!...]` when negated
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// A resource type name or glob pattern from `fhirpath_bundle_filter`'s `resource_types`
+#[derive(Debug, Clone)]
+struct ResourceTypePattern {
+    tokens: Vec<GlobToken>,
+}
+
+impl ResourceTypePattern {
+    /// Compile `pattern`, which may only hold ASCII letters and digits besides `*`, `?` and
+    /// `[...]` classes
+    fn parse(pattern: &str) -> Result<Self> {
+        let invalid =
+            |reason: String| anyhow!("Invalid resource type pattern '{pattern}': {reason}");
+        if pattern.is_empty() {
+            return Err(invalid("pattern is empty".to_string()));
+        }
+
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                '*' => GlobToken::AnySequence,
+                '?' => GlobToken::AnyChar,
+                '[' => {
+                    let mut class: Vec<char> = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => class.push(c),
+                            None => return Err(invalid("unclosed '['".to_string())),
+                        }
+                    }
+                    let negated = class.first() == Some(&'!');
+                    let members = &class[negated as usize..];
+                    if members.is_empty() {
+                        return Err(invalid("empty character class".to_string()));
+                    }
+
+                    let mut ranges = Vec::new();
+                    let mut i = 0;
+                    while i < members.len() {
+                        let start = members[i];
+                        if !start.is_ascii_alphanumeric() {
+                            return Err(invalid(format!(
+                                "unexpected '{start}' in character class"
+                            )));
+                        }
+                        if members.get(i + 1) == Some(&'-') && i + 2 < members.len() {
+                            let end = members[i + 2];
+                            if !end.is_ascii_alphanumeric() || end < start {
+                                return Err(invalid(format!("invalid range '{start}-{end}'")));
+                            }
+                            ranges.push((start, end));
+                            i += 3;
+                        } else {
+                            ranges.push((start, start));
+                            i += 1;
+                        }
+                    }
+                    GlobToken::Class { negated, ranges }
                 }
-                Ok(_) => {
-                    // Syntax is valid, but add warnings for potential issues
-                    if expression.len() > 100 {
-                        warnings.push(
-                            "Expression is quite long, consider breaking it down".to_string(),
-                        );
+                c if c.is_ascii_alphanumeric() => GlobToken::Literal(c),
+                c => return Err(invalid(format!("unexpected '{c}'"))),
+            });
+        }
+
+        Ok(Self { tokens })
+    }
+
+    /// The type name when the pattern has no wildcards
+    fn exact_name(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|token| match token {
+                GlobToken::Literal(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn matches(&self, resource_type: &str) -> bool {
+        let text: Vec<char> = resource_type.chars().collect();
+        // Positions of `text` reachable after matching each prefix of the pattern
+        let mut reachable = vec![false; text.len() + 1];
+        reachable[0] = true;
+        for token in &self.tokens {
+            let mut next = vec![false; text.len() + 1];
+            for i in 0..=text.len() {
+                if !reachable[i] {
+                    continue;
+                }
+                match token {
+                    GlobToken::AnySequence => next[i..].iter_mut().for_each(|slot| *slot = true),
+                    GlobToken::AnyChar => {
+                        if i < text.len() {
+                            next[i + 1] = true;
+                        }
                     }
-                    if expression.matches('(').count() > 3 {
-                        warnings.push(
-                            "Multiple nested function calls may impact performance".to_string(),
-                        );
+                    GlobToken::Literal(c) => {
+                        if text.get(i) == Some(c) {
+                            next[i + 1] = true;
+                        }
+                    }
+                    GlobToken::Class { negated, ranges } => {
+                        if let Some(c) = text.get(i) {
+                            let in_class =
+                                ranges.iter().any(|(start, end)| (start..=end).contains(&c));
+                            if in_class != *negated {
+                                next[i + 1] = true;
+                            }
+                        }
                     }
                 }
             }
+            reachable = next;
         }
-        Err(_) => {
-            warnings.push("Could not validate syntax - FHIRPath engine not available".to_string());
-        }
+        reachable[text.len()]
     }
+}
 
-    SyntaxAnalysis {
-        is_valid,
-        errors,
-        warnings,
-        token_count,
+/// Collect the resources of `bundle`'s entries, descending into nested Bundles, whose type
+/// matches one of `resource_types`
+fn collect_bundle_resources<'a>(
+    bundle: &'a Value,
+    resource_types: &[ResourceTypePattern],
+    matches: &mut Vec<&'a Value>,
+) {
+    let resources = bundle
+        .get("entry")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.get("resource"));
+    for resource in resources {
+        let resource_type = resource.get("resourceType").and_then(Value::as_str);
+        if resource_type
+            .is_some_and(|found| resource_types.iter().any(|pattern| pattern.matches(found)))
+        {
+            matches.push(resource);
+        }
+        if resource_type == Some("Bundle") {
+            collect_bundle_resources(resource, resource_types, matches);
+        }
     }
 }
 
-fn predict_performance(
-    expression: &str,
-    functions: &[String],
-    analysis: &ExpressionAnalysis,
-) -> PerformancePrediction {
-    let mut complexity_score = 1u8;
-    let mut suggestions = Vec::new();
-
-    // Add complexity based on various factors
-    complexity_score += analysis.path_segments as u8;
-    complexity_score += (analysis.function_count * 2) as u8;
+/// Returns the resources of given types in a Bundle, optionally evaluating an expression on each
+#[tracing::instrument(
+    name = "fhirpath_bundle_filter",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        type_count = params.resource_types.len(),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_bundle_filter(params: BundleFilterParams) -> Result<BundleFilterResult> {
+    let start_time = Instant::now();
 
-    if analysis.uses_collections {
-        complexity_score += 2;
-        suggestions.push(
-            "Consider using specific indexes instead of filtering entire collections".to_string(),
-        );
+    check_resource_object(&params.bundle)?;
+    match resource_type(&params.bundle) {
+        "Bundle" => {}
+        other => {
+            return Err(NotABundle {
+                found: format!("resourceType '{other}'"),
+            }
+            .into());
+        }
     }
-
-    if functions
+    enforce_resource_size(&params.bundle)?;
+    if params.resource_types.is_empty() {
+        return Err(anyhow!("At least one resource type is required"));
+    }
+    let patterns = params
+        .resource_types
         .iter()
-        .any(|f| ["where", "select"].contains(&f.as_str()))
+        .map(|pattern| ResourceTypePattern::parse(pattern))
+        .collect::<Result<Vec<_>>>()?;
+    if params
+        .expression
+        .as_ref()
+        .is_some_and(|expression| expression.trim().is_empty())
     {
-        complexity_score += 1;
+        return Err(anyhow!("Expression cannot be empty"));
     }
 
-    if expression.len() > 50 {
-        complexity_score += 1;
-        suggestions.push("Consider breaking down long expressions into smaller parts".to_string());
+    let mut matches = Vec::new();
+    collect_bundle_resources(&params.bundle, &patterns, &mut matches);
+
+    let mut resources: BTreeMap<String, Vec<Value>> = patterns
+        .iter()
+        .filter_map(ResourceTypePattern::exact_name)
+        .map(|resource_type| (resource_type, Vec::new()))
+        .collect();
+    let evaluation = match &params.expression {
+        Some(expression) => Some((
+            crate::fhirpath_engine::get_shared_engine().await?,
+            expression,
+        )),
+        None => None,
+    };
+    for resource in &matches {
+        let output = match evaluation {
+            Some((engine, expression)) => {
+                let value = evaluate_with_timeout(
+                    engine,
+                    expression,
+                    (*resource).clone(),
+                    DEFAULT_TOOL_TIMEOUT_MS,
+                )
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "Evaluation on {} '{}' failed: {}",
+                        resource_type(resource),
+                        resource.get("id").and_then(Value::as_str).unwrap_or("?"),
+                        e
+                    )
+                })?;
+                Value::Array(
+                    fhirpath_value_to_collection(value)
+                        .iter()
+                        .map(fhirpath_value_to_json)
+                        .collect(),
+                )
+            }
+            None => (*resource).clone(),
+        };
+        resources
+            .entry(resource_type(resource).to_string())
+            .or_default()
+            .push(output);
     }
 
-    // Cap at 10
-    complexity_score = complexity_score.min(10);
+    let execution_time = start_time.elapsed();
+    record_span_result(Some(matches.len()), execution_time);
 
-    let performance_category = match complexity_score {
-        1..=3 => "fast".to_string(),
-        4..=6 => "moderate".to_string(),
-        7..=8 => "slow".to_string(),
-        _ => "very_slow".to_string(),
+    Ok(BundleFilterResult {
+        resources,
+        match_count: matches.len(),
+        execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+    })
+}
+
+/// A sub-expression to evaluate as one trace step
+struct PlannedStep<'a> {
+    node: &'a ExpressionNode,
+    operation: &'static str,
+    description: String,
+    /// Index of the step producing this step's input collection
+    input: Option<usize>,
+}
+
+/// Describe a function or method call, eliding its arguments
+fn describe_call(name: &str, args: usize) -> String {
+    if args == 0 {
+        format!("{name}()")
+    } else {
+        format!("{name}(...)")
+    }
+}
+
+/// List the sub-expressions of `node` in evaluation order, returning the index of the step
+/// producing `node`'s value. Arguments of functions are evaluated per item and are not traced.
+fn plan_steps<'a>(node: &'a ExpressionNode, steps: &mut Vec<PlannedStep<'a>>) -> usize {
+    let (operation, description, input) = match node {
+        ExpressionNode::Identifier(name) => ("navigate", name.clone(), None),
+        ExpressionNode::Path { base, path } => {
+            ("navigate", path.clone(), Some(plan_steps(base, steps)))
+        }
+        ExpressionNode::Index { base, .. } => {
+            ("index", "[...]".to_string(), Some(plan_steps(base, steps)))
+        }
+        ExpressionNode::Filter { base, .. } => (
+            "filter",
+            "where(...)".to_string(),
+            Some(plan_steps(base, steps)),
+        ),
+        ExpressionNode::MethodCall(data) => {
+            let input = plan_steps(&data.base, steps);
+            let operation = if SUBSETTING_FUNCTIONS.contains(&data.method.as_str()) {
+                "filter"
+            } else {
+                "function"
+            };
+            (
+                operation,
+                describe_call(&data.method, data.args.len()),
+                Some(input),
+            )
+        }
+        ExpressionNode::FunctionCall(data) => {
+            ("function", describe_call(&data.name, data.args.len()), None)
+        }
+        ExpressionNode::BinaryOp(data) => {
+            plan_steps(&data.left, steps);
+            plan_steps(&data.right, steps);
+            ("operator", data.op.as_str().to_string(), None)
+        }
+        ExpressionNode::Union { left, right } => {
+            plan_steps(left, steps);
+            plan_steps(right, steps);
+            ("operator", "|".to_string(), None)
+        }
+        ExpressionNode::UnaryOp { op, operand } => {
+            plan_steps(operand, steps);
+            ("operator", op.as_str().to_string(), None)
+        }
+        ExpressionNode::TypeCheck {
+            expression,
+            type_name,
+        } => (
+            "operator",
+            format!("is {type_name}"),
+            Some(plan_steps(expression, steps)),
+        ),
+        ExpressionNode::TypeCast {
+            expression,
+            type_name,
+        } => (
+            "operator",
+            format!("as {type_name}"),
+            Some(plan_steps(expression, steps)),
+        ),
+        ExpressionNode::Literal(_) => ("value", "literal".to_string(), None),
+        ExpressionNode::Variable(name) => ("value", format!("%{name}"), None),
+        ExpressionNode::Conditional(_) => ("function", "iif(...)".to_string(), None),
+        ExpressionNode::Lambda(_) => ("function", "lambda".to_string(), None),
     };
 
-    if suggestions.is_empty() {
-        suggestions.push("Expression looks well-optimized".to_string());
+    steps.push(PlannedStep {
+        node,
+        operation,
+        description,
+        input,
+    });
+    steps.len() - 1
+}
+
+/// Traces an expression's evaluation, reporting the collection size after each path
+/// navigation, function application and operator.
+///
+/// The engine does not expose intermediate results, so each step's sub-expression is
+/// evaluated on its own against the resource.
+#[tracing::instrument(
+    name = "fhirpath_explain",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        resource_type = resource_type(&params.resource),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_explain(params: ExplainParams) -> Result<ExplainResult> {
+    explain(params, DEFAULT_TOOL_TIMEOUT_MS).await
+}
+
+/// Evaluate the steps of `params.expression`, all within one `timeout_ms`
+async fn explain(params: ExplainParams, timeout_ms: u64) -> Result<ExplainResult> {
+    let start_time = Instant::now();
+    let deadline = start_time + Duration::from_millis(timeout_ms);
+
+    if params.expression.trim().is_empty() {
+        return Err(anyhow!("Expression cannot be empty"));
     }
 
-    PerformancePrediction {
-        complexity_score,
-        performance_category,
-        suggestions,
+    enforce_resource_size(&params.resource)?;
+
+    let engine = crate::fhirpath_engine::get_shared_engine().await?;
+    let ast = engine.parse(&params.expression)?;
+
+    let mut planned = Vec::new();
+    plan_steps(&ast, &mut planned);
+
+    let mut steps: Vec<ExplainStep> = Vec::with_capacity(planned.len());
+    let mut values = Vec::new();
+    for (index, planned) in planned.iter().enumerate() {
+        let remaining_ms = deadline
+            .saturating_duration_since(Instant::now())
+            .as_millis() as u64;
+        let result = match evaluate_ast_with_timeout(
+            engine,
+            Arc::new(planned.node.clone()),
+            params.resource.clone(),
+            engine.model_provider().clone(),
+            remaining_ms.max(1),
+        )
+        .await
+        {
+            Ok(result) => result,
+            // Report the timeout of the whole request, not what was left of it
+            Err(e) if e.is::<EvaluationTimeout>() => {
+                return Err(EvaluationTimeout { timeout_ms }.into());
+            }
+            Err(e) => {
+                return Err(anyhow!(
+                    "Step {} ({}) failed: {}",
+                    index,
+                    planned.description,
+                    e
+                ));
+            }
+        };
+        values = fhirpath_value_to_collection(result)
+            .iter()
+            .map(fhirpath_value_to_json)
+            .collect();
+
+        steps.push(ExplainStep {
+            step: index,
+            operation: planned.operation.to_string(),
+            description: planned.description.clone(),
+            input_count: planned.input.map(|input| steps[input].output_count),
+            output_count: values.len(),
+        });
+    }
+
+    let execution_time = start_time.elapsed();
+    record_span_result(Some(values.len()), execution_time);
+
+    Ok(ExplainResult {
+        steps,
+        values,
+        execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+    })
+}
+
+/// Functions the engine evaluates itself rather than through its registry:
+/// name, category, description, minimum and maximum arity
+const ENGINE_LAMBDA_FUNCTIONS: &[(&str, &str, &str, usize, Option<usize>)] = &[
+    (
+        "where",
+        "collection",
+        "Returns the items of the collection for which the criteria is true",
+        1,
+        Some(1),
+    ),
+    (
+        "select",
+        "collection",
+        "Evaluates the projection for each item and flattens the results",
+        1,
+        Some(1),
+    ),
+    (
+        "all",
+        "collection",
+        "Returns true if the criteria is true for every item in the collection",
+        1,
+        Some(1),
+    ),
+    (
+        "repeat",
+        "collection",
+        "Repeatedly evaluates the projection on its own results, collecting every item",
+        1,
+        Some(1),
+    ),
+    (
+        "aggregate",
+        "collection",
+        "Folds the collection using $this and $total, starting from an optional initial value",
+        1,
+        Some(2),
+    ),
+    (
+        "sort",
+        "collection",
+        "Sorts the collection, optionally by one or more key expressions",
+        0,
+        None,
+    ),
+    (
+        "iif",
+        "logical",
+        "Returns the true result if the criterion is true, otherwise the optional false result",
+        2,
+        Some(3),
+    ),
+];
+
+/// Names of the functions registered by one operation group
+async fn group_functions(
+    register: impl AsyncFnOnce(&FhirPathRegistry) -> octofhir_fhirpath::Result<()>,
+) -> Vec<String> {
+    let registry = FhirPathRegistry::new();
+    match register(&registry).await {
+        Ok(()) => {
+            registry
+                .list_operations_by_type(OperationType::Function)
+                .await
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Category of each registered function, from the operation group that registers it
+async fn function_categories() -> &'static HashMap<String, &'static str> {
+    static CATEGORIES: tokio::sync::OnceCell<HashMap<String, &'static str>> =
+        tokio::sync::OnceCell::const_new();
+
+    CATEGORIES
+        .get_or_init(|| async {
+            let groups = [
+                (
+                    "collection",
+                    group_functions(CollectionOperations::register_all).await,
+                ),
+                (
+                    "string",
+                    group_functions(StringOperations::register_all).await,
+                ),
+                (
+                    "datetime",
+                    group_functions(DateTimeOperations::register_all).await,
+                ),
+                ("fhir", group_functions(FhirOperations::register_all).await),
+                (
+                    "utility",
+                    group_functions(UtilityOperations::register_all).await,
+                ),
+                (
+                    "conversion",
+                    group_functions(ConversionOperations::register_all).await,
+                ),
+                ("math", group_functions(MathOperations::register_all).await),
+                ("type", group_functions(TypeOperations::register_all).await),
+                ("cda", group_functions(CdaOperations::register_all).await),
+                (
+                    "logical",
+                    group_functions(LogicalOperations::register_all).await,
+                ),
+            ];
+
+            groups
+                .into_iter()
+                .flat_map(|(category, names)| names.into_iter().map(move |name| (name, category)))
+                .collect()
+        })
+        .await
+}
+
+/// Lists the functions supported by the engine, with their arity and category
+///
+/// Functions are read from the engine's registry, so new engine versions expose new
+/// functions without changes here. Functions registered on the engine configuration are
+/// listed under the `custom` category.
+#[tracing::instrument(
+    name = "fhirpath_functions",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        result_count = field::Empty,
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_functions(params: FunctionsParams) -> Result<FunctionsResult> {
+    list_functions(crate::fhirpath_engine::get_shared_engine().await?, params).await
+}
+
+async fn list_functions(
+    engine: &crate::fhirpath_engine::FhirPathEngineFactory,
+    params: FunctionsParams,
+) -> Result<FunctionsResult> {
+    let start_time = Instant::now();
+
+    let registry = engine.registry().await?;
+    let categories = function_categories().await;
+    let custom_functions = &engine.config().custom_functions;
+
+    let mut functions = Vec::new();
+    for name in registry
+        .list_operations_by_type(OperationType::Function)
+        .await
+    {
+        let Some(metadata) = registry.get_metadata(&name).await else {
+            continue;
+        };
+        let function = match &metadata.specific {
+            OperationSpecificMetadata::Function(function) => function.clone(),
+            OperationSpecificMetadata::Operator(_) => FunctionMetadata::default(),
+        };
+        let parameters = &metadata.types.parameters;
+
+        let custom = custom_functions
+            .iter()
+            .find(|function| function.name() == name);
+        let category = match custom {
+            Some(_) => CUSTOM_FUNCTION_CATEGORY,
+            None => categories.get(&name).copied().unwrap_or("other"),
+        };
+        functions.push(FunctionInfo {
+            category: category.to_string(),
+            description: metadata.basic.description.clone(),
+            min_arity: parameters
+                .iter()
+                .filter(|parameter| !parameter.optional)
+                .count(),
+            max_arity: (!metadata.types.variadic).then_some(parameters.len()),
+            supports_lambda: function.supports_lambda || registry.is_lambda_function(&name).await,
+            deterministic: custom.map_or(function.deterministic, |custom| custom.deterministic()),
+            name,
+        });
+    }
+
+    for &(name, category, description, min_arity, max_arity) in ENGINE_LAMBDA_FUNCTIONS {
+        if registry.is_lambda_function(name).await && !registry.contains(name).await {
+            functions.push(FunctionInfo {
+                name: name.to_string(),
+                category: category.to_string(),
+                description: description.to_string(),
+                min_arity,
+                max_arity,
+                supports_lambda: true,
+                deterministic: true,
+            });
+        }
+    }
+
+    if let Some(category) = &params.category {
+        functions.retain(|function| function.category.eq_ignore_ascii_case(category));
+    }
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    record_span_result(Some(functions.len()), start_time.elapsed());
+
+    Ok(FunctionsResult {
+        total_count: functions.len(),
+        functions,
+    })
+}
+
+/// Precedence of unary `-` and `not`
+const UNARY_PRECEDENCE: u8 = 11;
+/// Precedence of `.` and `[]`, the tightest binding
+const POSTFIX_PRECEDENCE: u8 = 12;
+
+/// Binding strength of an operator as the engine's parser applies it; higher binds tighter.
+/// Unlike the specification, the parser ranks `is` and `as` below equality and `|` below
+/// comparisons, so formatting follows the parser to keep the meaning of the expression.
+fn operator_precedence(op: BinaryOperator) -> u8 {
+    match op {
+        BinaryOperator::Implies => 1,
+        BinaryOperator::Or | BinaryOperator::Xor => 2,
+        BinaryOperator::And => 3,
+        BinaryOperator::In | BinaryOperator::Contains => 4,
+        BinaryOperator::Is => 5,
+        BinaryOperator::Equal
+        | BinaryOperator::NotEqual
+        | BinaryOperator::Equivalent
+        | BinaryOperator::NotEquivalent => 6,
+        BinaryOperator::LessThan
+        | BinaryOperator::LessThanOrEqual
+        | BinaryOperator::GreaterThan
+        | BinaryOperator::GreaterThanOrEqual => 7,
+        BinaryOperator::Union => 8,
+        BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Concatenate => 9,
+        BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::IntegerDivide
+        | BinaryOperator::Modulo => 10,
     }
 }
 
-impl Default for AnalysisOptions {
-    fn default() -> Self {
-        Self {
-            include_ast: Some(false),
-            include_performance: Some(true),
-            include_functions: Some(true),
-        }
+/// Calendar duration units, written without quotes in quantities such as `4 days`
+const CALENDAR_UNITS: &[&str] = &[
+    "year",
+    "years",
+    "month",
+    "months",
+    "week",
+    "weeks",
+    "day",
+    "days",
+    "hour",
+    "hours",
+    "minute",
+    "minutes",
+    "second",
+    "seconds",
+    "millisecond",
+    "milliseconds",
+];
+
+/// Words that can't name an element or variable without backticks
+const RESERVED_WORDS: &[&str] = &[
+    "and", "as", "contains", "div", "false", "implies", "in", "is", "mod", "or", "true", "xor",
+];
+
+/// Writes a parsed expression back as text
+struct ExpressionFormatter {
+    style: FormatStyle,
+    /// Parenthesize every operand that is an operation, not only where precedence needs it
+    parenthesize_all: bool,
+}
+
+impl ExpressionFormatter {
+    fn format(&self, node: &ExpressionNode) -> String {
+        let mut text = String::new();
+        self.write(node, &mut text);
+        text
+    }
+
+    fn precedence(node: &ExpressionNode) -> u8 {
+        match node {
+            ExpressionNode::BinaryOp(data) => operator_precedence(data.op),
+            ExpressionNode::Union { .. } => operator_precedence(BinaryOperator::Union),
+            ExpressionNode::TypeCheck { .. } | ExpressionNode::TypeCast { .. } => {
+                operator_precedence(BinaryOperator::Is)
+            }
+            ExpressionNode::Lambda(_) => 0,
+            // `5.toString()` would read as a decimal
+            ExpressionNode::UnaryOp { .. }
+            | ExpressionNode::Literal(
+                LiteralValue::Integer(_) | LiteralValue::Decimal(_) | LiteralValue::Quantity { .. },
+            ) => UNARY_PRECEDENCE,
+            _ => POSTFIX_PRECEDENCE,
+        }
+    }
+
+    /// Write `node` as an operand of an operator binding with `precedence`
+    fn write_operand(&self, node: &ExpressionNode, precedence: u8, text: &mut String) {
+        let own = Self::precedence(node);
+        let parenthesize = if self.parenthesize_all {
+            own < POSTFIX_PRECEDENCE
+        } else {
+            own < precedence
+        };
+        if parenthesize {
+            text.push('(');
+            self.write(node, text);
+            text.push(')');
+        } else {
+            self.write(node, text);
+        }
+    }
+
+    fn write_operator(&self, operator: &str, text: &mut String) {
+        if self.style == FormatStyle::Compact && !operator.starts_with(char::is_alphabetic) {
+            text.push_str(operator);
+        } else {
+            text.push(' ');
+            text.push_str(operator);
+            text.push(' ');
+        }
+    }
+
+    fn write_arguments<'a>(
+        &self,
+        args: impl IntoIterator<Item = &'a ExpressionNode>,
+        text: &mut String,
+    ) {
+        let separator = match self.style {
+            FormatStyle::Standard => ", ",
+            FormatStyle::Compact => ",",
+        };
+        text.push('(');
+        for (i, arg) in args.into_iter().enumerate() {
+            if i > 0 {
+                text.push_str(separator);
+            }
+            self.write(arg, text);
+        }
+        text.push(')');
+    }
+
+    fn write(&self, node: &ExpressionNode, text: &mut String) {
+        match node {
+            ExpressionNode::Literal(literal) => write_literal(literal, text),
+            ExpressionNode::Identifier(name) => write_identifier(name, text),
+            ExpressionNode::Variable(name) => {
+                text.push(match name.as_str() {
+                    "this" | "index" | "total" => '$',
+                    _ => '%',
+                });
+                write_identifier(name, text);
+            }
+            ExpressionNode::Path { base, path } => {
+                self.write_operand(base, POSTFIX_PRECEDENCE, text);
+                text.push('.');
+                write_identifier(path, text);
+            }
+            ExpressionNode::BinaryOp(data) => {
+                let precedence = operator_precedence(data.op);
+                // `implies` is right associative, everything else left associative
+                let (left, right) = if data.op == BinaryOperator::Implies {
+                    (precedence + 1, precedence)
+                } else {
+                    (precedence, precedence + 1)
+                };
+                self.write_operand(&data.left, left, text);
+                self.write_operator(data.op.as_str(), text);
+                self.write_operand(&data.right, right, text);
+            }
+            ExpressionNode::UnaryOp { op, operand } => {
+                text.push_str(op.as_str());
+                if *op == UnaryOperator::Not {
+                    text.push(' ');
+                }
+                self.write_operand(operand, UNARY_PRECEDENCE, text);
+            }
+            ExpressionNode::FunctionCall(data) => {
+                text.push_str(&data.name);
+                self.write_arguments(&data.args, text);
+            }
+            ExpressionNode::MethodCall(data) => {
+                self.write_operand(&data.base, POSTFIX_PRECEDENCE, text);
+                text.push('.');
+                text.push_str(&data.method);
+                self.write_arguments(&data.args, text);
+            }
+            ExpressionNode::Index { base, index } => {
+                self.write_operand(base, POSTFIX_PRECEDENCE, text);
+                text.push('[');
+                self.write(index, text);
+                text.push(']');
+            }
+            ExpressionNode::Filter { base, condition } => {
+                self.write_operand(base, POSTFIX_PRECEDENCE, text);
+                text.push_str(".where");
+                self.write_arguments([condition.as_ref()], text);
+            }
+            ExpressionNode::Union { left, right } => {
+                let precedence = operator_precedence(BinaryOperator::Union);
+                self.write_operand(left, precedence, text);
+                self.write_operator("|", text);
+                self.write_operand(right, precedence + 1, text);
+            }
+            ExpressionNode::TypeCheck {
+                expression,
+                type_name,
+            }
+            | ExpressionNode::TypeCast {
+                expression,
+                type_name,
+            } => {
+                self.write_operand(expression, operator_precedence(BinaryOperator::Is), text);
+                let operator = match node {
+                    ExpressionNode::TypeCheck { .. } => "is",
+                    _ => "as",
+                };
+                self.write_operator(operator, text);
+                text.push_str(type_name);
+            }
+            ExpressionNode::Lambda(data) => {
+                match data.params.as_slice() {
+                    [param] => text.push_str(param),
+                    params => {
+                        text.push('(');
+                        text.push_str(&params.join(", "));
+                        text.push(')');
+                    }
+                }
+                text.push_str(" => ");
+                self.write(&data.body, text);
+            }
+            ExpressionNode::Conditional(data) => {
+                text.push_str("iif");
+                let mut args = vec![&data.condition, &data.then_expr];
+                args.extend(data.else_expr.as_deref());
+                self.write_arguments(args, text);
+            }
+        }
+    }
+}
+
+fn write_identifier(name: &str, text: &mut String) {
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED_WORDS.contains(&name);
+    if plain {
+        text.push_str(name);
+    } else {
+        text.push('`');
+        text.push_str(name);
+        text.push('`');
+    }
+}
+
+fn write_string(value: &str, text: &mut String) {
+    text.push('\'');
+    for c in value.chars() {
+        match c {
+            '\'' => text.push_str("\\'"),
+            '\\' => text.push_str("\\\\"),
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            '\t' => text.push_str("\\t"),
+            c => text.push(c),
+        }
+    }
+    text.push('\'');
+}
+
+fn write_literal(literal: &LiteralValue, text: &mut String) {
+    match literal {
+        LiteralValue::Boolean(b) => text.push_str(if *b { "true" } else { "false" }),
+        LiteralValue::Integer(i) => text.push_str(&i.to_string()),
+        LiteralValue::Decimal(d) => text.push_str(d),
+        LiteralValue::String(s) => write_string(s, text),
+        LiteralValue::Date(value) | LiteralValue::DateTime(value) | LiteralValue::Time(value) => {
+            if !value.starts_with('@') {
+                text.push('@');
+            }
+            text.push_str(value);
+        }
+        LiteralValue::Quantity { value, unit } => {
+            text.push_str(value);
+            text.push(' ');
+            if CALENDAR_UNITS.contains(&unit.as_str()) {
+                text.push_str(unit);
+            } else {
+                write_string(unit, text);
+            }
+        }
+        LiteralValue::Null => text.push_str("{}"),
+    }
+}
+
+/// Render `ast` in `style`, checking that the text parses back to the same tree. Parentheses
+/// are added where precedence needs them, or around every operation when that isn't enough.
+fn format_expression(ast: &ExpressionNode, style: FormatStyle) -> Option<String> {
+    [false, true].into_iter().find_map(|parenthesize_all| {
+        let formatted = ExpressionFormatter {
+            style,
+            parenthesize_all,
+        }
+        .format(ast);
+        octofhir_fhirpath::parse(&formatted)
+            .is_ok_and(|reparsed| reparsed == *ast)
+            .then_some(formatted)
+    })
+}
+
+/// Formats an expression canonically: one space around operators and after commas, no
+/// space inside parentheses, and parentheses only where they change the meaning
+///
+/// Expressions that don't parse are returned unchanged with the parse error.
+#[tracing::instrument(
+    name = "fhirpath_format",
+    skip_all,
+    fields(
+        correlation_id = current_correlation_id(),
+        expression_length = params.expression.len(),
+        duration_ms = field::Empty,
+    )
+)]
+pub async fn fhirpath_format(params: FormatParams) -> Result<FormatResult> {
+    let start_time = Instant::now();
+    let formatted = octofhir_fhirpath::parse(&params.expression)
+        .map_err(|e| e.to_string())
+        .and_then(|ast| {
+            format_expression(&ast, params.style.unwrap_or_default()).ok_or_else(|| {
+                "the expression can't be written back without changing its meaning".to_string()
+            })
+        });
+    record_span_result(None, start_time.elapsed());
+
+    Ok(match formatted {
+        Ok(formatted) => FormatResult {
+            changed: formatted != params.expression,
+            formatted,
+            error: None,
+        },
+        Err(error) => FormatResult {
+            formatted: params.expression,
+            changed: false,
+            error: Some(error),
+        },
+    })
+}
+
+fn analyze_expression_structure(
+    metrics: &ComplexityMetrics,
+    functions: &[String],
+) -> ExpressionAnalysis {
+    let path_segments = metrics.path_segments;
+    let function_count = metrics.function_count;
+    let uses_collections = metrics.index_count > 0
+        || metrics.predicate_count > 0
+        || functions
+            .iter()
+            .any(|f| ["where", "select", "all", "any", "distinct"].contains(&f.as_str()));
+
+    // Determine expression type
+    let expression_type = if functions
+        .iter()
+        .any(|f| ["where", "exists", "empty"].contains(&f.as_str()))
+    {
+        "filter".to_string()
+    } else if functions
+        .iter()
+        .any(|f| ["count", "sum", "avg"].contains(&f.as_str()))
+    {
+        "aggregation".to_string()
+    } else if functions
+        .iter()
+        .any(|f| ["select", "first", "last"].contains(&f.as_str()))
+    {
+        "transformation".to_string()
+    } else {
+        "query".to_string()
+    };
+
+    // Calculate complexity; each level of nested function calls weighs like a function call
+    let complexity_score = path_segments
+        + (function_count * 2)
+        + (metrics.ast_depth.saturating_sub(1) * 2)
+        + if uses_collections { 2 } else { 0 };
+    let complexity = if complexity_score < 3 {
+        "low".to_string()
+    } else if complexity_score < 8 {
+        "medium".to_string()
+    } else {
+        "high".to_string()
+    };
+
+    ExpressionAnalysis {
+        complexity,
+        expression_type,
+        path_segments,
+        function_count,
+        predicate_count: metrics.predicate_count,
+        ast_depth: metrics.ast_depth,
+        uses_collections,
+    }
+}
+
+async fn analyze_syntax(expression: &str) -> SyntaxAnalysis {
+    // Try to parse the expression using the FHIRPath engine to validate syntax
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut is_valid = true;
+
+    // Basic token counting
+    let token_count = expression.split_whitespace().count()
+        + expression.matches('.').count()
+        + expression.matches('(').count()
+        + expression.matches('[').count();
+
+    // Try to validate with the engine if available
+    match crate::fhirpath_engine::get_shared_engine().await {
+        Ok(engine) => {
+            // Try parsing with a dummy resource to check syntax
+            let dummy_resource = serde_json::json!({"resourceType": "Patient"});
+            match engine.evaluate(expression, dummy_resource).await {
+                Err(e) => {
+                    is_valid = false;
+                    errors.push(e.to_string());
+                }
+                Ok(_) => {
+                    // Syntax is valid, but add warnings for potential issues
+                    if expression.len() > 100 {
+                        warnings.push(
+                            "Expression is quite long, consider breaking it down".to_string(),
+                        );
+                    }
+                    if expression.matches('(').count() > 3 {
+                        warnings.push(
+                            "Multiple nested function calls may impact performance".to_string(),
+                        );
+                    }
+                }
+            }
+        }
+        Err(_) => {
+            warnings.push("Could not validate syntax - FHIRPath engine not available".to_string());
+        }
+    }
+
+    SyntaxAnalysis {
+        is_valid,
+        errors,
+        warnings,
+        token_count,
+    }
+}
+
+fn predict_performance(
+    expression: &str,
+    functions: &[String],
+    analysis: &ExpressionAnalysis,
+) -> PerformancePrediction {
+    let mut complexity_score = 1u8;
+    let mut suggestions = Vec::new();
+
+    // Add complexity based on various factors
+    complexity_score += analysis.path_segments as u8;
+    complexity_score += (analysis.function_count * 2) as u8;
+
+    if analysis.ast_depth > 2 {
+        complexity_score += 1;
+        suggestions.push(
+            "Deeply nested function calls are evaluated once per item at each level".to_string(),
+        );
+    }
+
+    if analysis.uses_collections {
+        complexity_score += 2;
+        suggestions.push(
+            "Consider using specific indexes instead of filtering entire collections".to_string(),
+        );
+    }
+
+    if functions
+        .iter()
+        .any(|f| ["where", "select"].contains(&f.as_str()))
+    {
+        complexity_score += 1;
+    }
+
+    if expression.len() > 50 {
+        complexity_score += 1;
+        suggestions.push("Consider breaking down long expressions into smaller parts".to_string());
+    }
+
+    // Cap at 10
+    complexity_score = complexity_score.min(10);
+
+    let performance_category = match complexity_score {
+        1..=3 => "fast".to_string(),
+        4..=6 => "moderate".to_string(),
+        7..=8 => "slow".to_string(),
+        _ => "very_slow".to_string(),
+    };
+
+    if suggestions.is_empty() {
+        suggestions.push("Expression looks well-optimized".to_string());
+    }
+
+    PerformancePrediction {
+        complexity_score,
+        performance_category,
+        suggestions,
+    }
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            include_ast: Some(false),
+            include_performance: Some(true),
+            include_functions: Some(true),
+        }
+    }
+}
+
+/// Helper function to extract function names from FHIRPath expression
+fn extract_functions(expression: &str) -> Vec<String> {
+    let mut functions = Vec::new();
+    let function_names = [
+        "where",
+        "select",
+        "first",
+        "last",
+        "tail",
+        "skip",
+        "take",
+        "single",
+        "exists",
+        "empty",
+        "not",
+        "all",
+        "any",
+        "count",
+        "distinct",
+        "isDistinct",
+        "subsetOf",
+        "supersetOf",
+        "intersect",
+        "exclude",
+        "union",
+        "combine",
+        "contains",
+        "in",
+        "indexOf",
+        "substring",
+        "startsWith",
+        "endsWith",
+        "matches",
+        "replaceMatches",
+        "replace",
+        "length",
+        "toInteger",
+        "toString",
+        "toDecimal",
+        "toDateTime",
+        "toTime",
+        "convertsToInteger",
+        "convertsToDecimal",
+        "convertsToDateTime",
+        "convertsToTime",
+        "iif",
+        "trace",
+        "now",
+        "today",
+        "timeOfDay",
+    ];
+
+    for func in function_names {
+        if expression.contains(&format!("{func}(")) {
+            functions.push(func.to_string());
+        }
+    }
+
+    functions.sort();
+    functions.dedup();
+    functions
+}
+
+/// Default time allowed for a tool call when the request does not set `timeout_ms`
+pub const DEFAULT_TOOL_TIMEOUT_MS: u64 = 5000;
+
+/// Failure of a tool call, mapped to a JSON-RPC error code and HTTP status. The codes are
+/// part of the API and don't change between releases.
+#[derive(Debug, thiserror::Error)]
+pub enum ToolError {
+    /// -32602, HTTP 400
+    #[error("Invalid parameters for {tool}: {message}")]
+    InvalidParams { tool: String, message: String },
+    /// -32601, HTTP 404
+    #[error("Unknown tool: {0}")]
+    UnknownTool(String),
+    /// -32000, HTTP 422
+    #[error("{0}")]
+    EvaluationFailed(String),
+    /// -32001, HTTP 413
+    #[error("FHIR resource too large: {size} > {max} bytes")]
+    ResourceTooLarge { size: usize, max: usize },
+    /// -32003, HTTP 504. The tool and timeout are in the error data.
+    #[error("evaluation timed out")]
+    Timeout { tool: String, timeout_ms: u64 },
+    /// -32004, HTTP 403
+    #[error("Not allowed to call {0}")]
+    Forbidden(String),
+    /// -32005, HTTP 503
+    #[error("Server busy: {tool} waited {waited_ms}ms for an evaluation slot")]
+    ServerBusy { tool: String, waited_ms: u64 },
+}
+
+impl ToolError {
+    /// JSON-RPC error code. Server-defined codes start at -32000; -32002 is skipped
+    /// because MCP uses it for "resource not found".
+    pub fn code(&self) -> i32 {
+        match self {
+            ToolError::InvalidParams { .. } => -32602,
+            ToolError::UnknownTool(_) => -32601,
+            ToolError::EvaluationFailed(_) => -32000,
+            ToolError::ResourceTooLarge { .. } => -32001,
+            ToolError::Timeout { .. } => -32003,
+            ToolError::Forbidden(_) => -32004,
+            ToolError::ServerBusy { .. } => -32005,
+        }
+    }
+
+    /// HTTP status returned by the REST tool endpoint
+    pub fn http_status(&self) -> u16 {
+        match self {
+            ToolError::InvalidParams { .. } => 400,
+            ToolError::UnknownTool(_) => 404,
+            ToolError::EvaluationFailed(_) => 422,
+            ToolError::ResourceTooLarge { .. } => 413,
+            ToolError::Timeout { .. } => 504,
+            ToolError::Forbidden(_) => 403,
+            ToolError::ServerBusy { .. } => 503,
+        }
+    }
+
+    /// Details sent alongside the message
+    pub fn data(&self) -> Option<Value> {
+        match self {
+            ToolError::Timeout { tool, timeout_ms } => {
+                Some(json!({"tool": tool, "timeout_ms": timeout_ms}))
+            }
+            _ => None,
+        }
+    }
+
+    /// The error with its message scrubbed by [`RequestSanitizer::sanitize_diagnostic`]
+    fn sanitized(self, production: bool) -> Self {
+        match self {
+            ToolError::InvalidParams { tool, message } => ToolError::InvalidParams {
+                tool,
+                message: RequestSanitizer::sanitize_diagnostic(&message, production),
+            },
+            ToolError::EvaluationFailed(message) => ToolError::EvaluationFailed(
+                RequestSanitizer::sanitize_diagnostic(&message, production),
+            ),
+            error => error,
+        }
+    }
+}
+
+impl From<ToolError> for rmcp::ErrorData {
+    fn from(error: ToolError) -> Self {
+        rmcp::ErrorData::new(
+            rmcp::model::ErrorCode(error.code()),
+            error.to_string(),
+            error.data(),
+        )
+    }
+}
+
+/// Check that a caller with `scope` may call the tool `name`
+pub fn authorize_tool(name: &str, scope: &ToolScope) -> Result<(), ToolError> {
+    if scope.allows(name) {
+        Ok(())
+    } else {
+        Err(ToolError::Forbidden(name.to_string()))
+    }
+}
+
+fn parse_tool_params<T: serde::de::DeserializeOwned>(
+    tool: &str,
+    arguments: Value,
+) -> Result<T, ToolError> {
+    serde_json::from_value(arguments).map_err(|e| ToolError::InvalidParams {
+        tool: tool.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Reject an empty expression, or one the security configuration doesn't accept: too long
+/// or deep, or calling a blacklisted or disallowed function
+pub fn check_expression(tool: &str, expression: &str) -> Result<(), ToolError> {
+    if expression.trim().is_empty() {
+        return Err(ToolError::InvalidParams {
+            tool: tool.to_string(),
+            message: "expression cannot be empty".to_string(),
+        });
+    }
+    crate::security::shared_security_provider()
+        .validator()
+        .validate_fhirpath_expression(expression)
+        .map(drop)
+        .map_err(|e| ToolError::InvalidParams {
+            tool: tool.to_string(),
+            message: e.to_string(),
+        })
+}
+
+/// Checks of `fhirpath_extract` arguments, shared with the streaming extraction route
+pub fn check_extract_params(params: &ExtractParams) -> Result<(), ToolError> {
+    const TOOL: &str = "fhirpath_extract";
+    check_expression(TOOL, &params.expression)?;
+    check_fhir_version(TOOL, params.fhir_version.as_deref())?;
+    check_resource_size(TOOL, &params.resource)
+}
+
+fn check_fhir_version(tool: &str, fhir_version: Option<&str>) -> Result<(), ToolError> {
+    match fhir_version {
+        Some(version) if !crate::fhirpath_engine::SUPPORTED_FHIR_VERSIONS.contains(&version) => {
+            Err(ToolError::InvalidParams {
+                tool: tool.to_string(),
+                message: format!(
+                    "unknown FHIR version '{}', supported versions: {}",
+                    version,
+                    crate::fhirpath_engine::SUPPORTED_FHIR_VERSIONS.join(", ")
+                ),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_resource_size(tool: &str, resource: &Value) -> Result<(), ToolError> {
+    let validator = crate::security::shared_security_provider().validator();
+    validator
+        .check_resource_depth(resource)
+        .map_err(|e| ToolError::InvalidParams {
+            tool: tool.to_string(),
+            message: e.to_string(),
+        })?;
+    validator
+        .check_resource_size(resource)
+        .map_err(|ResourceTooLarge { size, max }| ToolError::ResourceTooLarge { size, max })?;
+    Ok(())
+}
+
+fn tool_error(tool: &str, error: anyhow::Error) -> ToolError {
+    if let Some(timeout) = error.downcast_ref::<EvaluationTimeout>() {
+        return ToolError::Timeout {
+            tool: tool.to_string(),
+            timeout_ms: timeout.timeout_ms,
+        };
+    }
+    if let Some(ServerBusy { waited_ms }) = error.downcast_ref::<ServerBusy>() {
+        return ToolError::ServerBusy {
+            tool: tool.to_string(),
+            waited_ms: *waited_ms,
+        };
+    }
+    if let Some(ResourceTooLarge { size, max }) = error.downcast_ref::<ResourceTooLarge>() {
+        return ToolError::ResourceTooLarge {
+            size: *size,
+            max: *max,
+        };
+    }
+    if error.is::<InvalidResource>()
+        || error.is::<ResourceTooDeep>()
+        || error.is::<UnknownRootType>()
+        || error.is::<NotABundle>()
+        || error.is::<FhirServerNotAllowed>()
+        || error.is::<InvalidResourceRef>()
+    {
+        return ToolError::InvalidParams {
+            tool: tool.to_string(),
+            message: error.to_string(),
+        };
+    }
+    ToolError::EvaluationFailed(error.to_string())
+}
+
+async fn run_with_timeout<T>(
+    tool: &str,
+    timeout_ms: u64,
+    future: impl std::future::Future<Output = Result<T>>,
+) -> Result<T, ToolError> {
+    tokio::time::timeout(Duration::from_millis(timeout_ms), future)
+        .await
+        .map_err(|_| ToolError::Timeout {
+            tool: tool.to_string(),
+            timeout_ms,
+        })?
+        .map_err(|e| tool_error(tool, e))
+}
+
+fn tool_result_to_json<T: Serialize>(result: T) -> Result<Value, ToolError> {
+    serde_json::to_value(result)
+        .map_err(|e| ToolError::EvaluationFailed(format!("Serialization failed: {e}")))
+}
+
+/// A deprecated tool, or parameter of a tool, and what supersedes it
+#[derive(Debug, Clone, Copy)]
+pub struct Deprecation {
+    /// Name of the tool
+    pub tool: &'static str,
+    /// The deprecated parameter, or `None` when the whole tool is deprecated
+    pub parameter: Option<&'static str>,
+    /// What to use instead
+    pub replacement: &'static str,
+}
+
+impl Deprecation {
+    /// The warning given to clients that still use it
+    pub fn message(&self) -> String {
+        match self.parameter {
+            Some(parameter) => format!(
+                "Parameter '{parameter}' of {} is deprecated; use {} instead",
+                self.tool, self.replacement
+            ),
+            None => format!(
+                "Tool {} is deprecated; use {} instead",
+                self.tool, self.replacement
+            ),
+        }
+    }
+}
+
+/// Tools and parameters still accepted but due to be removed
+pub const DEPRECATIONS: &[Deprecation] = &[];
+
+/// Warnings for the tool or parameters a call uses that are among `deprecations`
+pub fn deprecation_warnings(
+    deprecations: &[Deprecation],
+    name: &str,
+    arguments: &Value,
+) -> Vec<String> {
+    deprecations
+        .iter()
+        .filter(|deprecation| deprecation.tool == name)
+        .filter(|deprecation| {
+            deprecation
+                .parameter
+                .is_none_or(|parameter| arguments.get(parameter).is_some())
+        })
+        .map(Deprecation::message)
+        .collect()
+}
+
+/// Fields of tool results carrying diagnostics, as messages or objects with a `message`
+const DIAGNOSTIC_FIELDS: &[&str] = &["diagnostics", "errors", "error_details"];
+
+/// Scrub the diagnostics of a tool result with [`RequestSanitizer::sanitize_diagnostic`]
+fn sanitize_diagnostics(result: &mut Value, production: bool) {
+    fn sanitize(value: &mut Value, production: bool) {
+        match value {
+            Value::String(message) => {
+                *message = RequestSanitizer::sanitize_diagnostic(message, production);
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| sanitize(item, production)),
+            Value::Object(fields) => {
+                if let Some(message) = fields.get_mut("message") {
+                    sanitize(message, production);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(result) = result.as_object_mut() {
+        for field in DIAGNOSTIC_FIELDS {
+            if let Some(diagnostics) = result.get_mut(*field) {
+                sanitize(diagnostics, production);
+            }
+        }
+    }
+}
+
+/// Run a tool by name with JSON arguments, shared by the MCP and REST transports.
+/// Calls using deprecated tools or parameters still succeed, with the deprecations
+/// listed under `warnings` in the result. In production, diagnostics and error messages are
+/// sanitized.
+pub async fn call_tool(name: &str, arguments: Value) -> Result<Value, ToolError> {
+    let production = crate::security::shared_security_provider().production();
+    let warnings = deprecation_warnings(DEPRECATIONS, name, &arguments);
+    let mut result = dispatch_tool(name, arguments)
+        .await
+        .map_err(|error| error.sanitized(production))?;
+    sanitize_diagnostics(&mut result, production);
+    if !warnings.is_empty()
+        && let Some(result) = result.as_object_mut()
+    {
+        result.insert("warnings".to_string(), json!(warnings));
+    }
+    Ok(result)
+}
+
+async fn dispatch_tool(name: &str, arguments: Value) -> Result<Value, ToolError> {
+    match name {
+        "fhirpath_evaluate" => {
+            let params: EvaluateParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            if let Some(focus_path) = &params.focus_path {
+                check_expression(name, focus_path)?;
+            }
+            check_fhir_version(name, params.fhir_version.as_deref())?;
+            check_resource_size(name, &params.resource)?;
+            let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_TOOL_TIMEOUT_MS);
+            tool_result_to_json(
+                run_with_timeout(name, timeout_ms, fhirpath_evaluate(params)).await?,
+            )
+        }
+        "fhirpath_parse" => {
+            let params: ParseParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_parse(params)).await?,
+            )
+        }
+        "fhirpath_extract" => {
+            let params: ExtractParams = parse_tool_params(name, arguments)?;
+            check_extract_params(&params)?;
+            let result = fhirpath_extract(params)
+                .await
+                .map_err(|e| tool_error(name, e))?;
+            tool_result_to_json(result)
+        }
+        "fhirpath_analyze" => {
+            let params: AnalyzeParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_analyze(params)).await?,
+            )
+        }
+        "fhirpath_explain" => {
+            let params: ExplainParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            check_resource_size(name, &params.resource)?;
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_explain(params)).await?,
+            )
+        }
+        "fhirpath_transform" => {
+            let params: TransformParams = parse_tool_params(name, arguments)?;
+            for expression in params.mapping.values() {
+                check_expression(name, expression)?;
+            }
+            check_resource_size(name, &params.resource)?;
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_transform(params)).await?,
+            )
+        }
+        "fhirpath_functions" => {
+            let params: FunctionsParams = parse_tool_params(name, arguments)?;
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_functions(params)).await?,
+            )
+        }
+        "fhirpath_diff" => {
+            let params: DiffParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            check_resource_size(name, &params.left_resource)?;
+            check_resource_size(name, &params.right_resource)?;
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_diff(params)).await?,
+            )
+        }
+        "fhirpath_compare" => {
+            let params: CompareParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression_a)?;
+            check_expression(name, &params.expression_b)?;
+            for resource in &params.resources {
+                check_resource_size(name, resource)?;
+            }
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_compare(params)).await?,
+            )
+        }
+        "fhirpath_evaluate_multi" => {
+            let params: MultiResourceParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            for resource in &params.resources {
+                check_resource_size(name, resource)?;
+            }
+            tool_result_to_json(
+                run_with_timeout(
+                    name,
+                    DEFAULT_TOOL_TIMEOUT_MS,
+                    fhirpath_evaluate_multi(params),
+                )
+                .await?,
+            )
+        }
+        "fhirpath_evaluate_remote" => {
+            let params: EvaluateRemoteParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            check_fhir_version(name, params.fhir_version.as_deref())?;
+            let result = fhirpath_evaluate_remote(params)
+                .await
+                .map_err(|e| tool_error(name, e))?;
+            tool_result_to_json(result)
+        }
+        "fhirpath_bundle_filter" => {
+            let params: BundleFilterParams = parse_tool_params(name, arguments)?;
+            if let Some(expression) = &params.expression {
+                check_expression(name, expression)?;
+            }
+            check_resource_size(name, &params.bundle)?;
+            tool_result_to_json(
+                run_with_timeout(
+                    name,
+                    DEFAULT_TOOL_TIMEOUT_MS,
+                    fhirpath_bundle_filter(params),
+                )
+                .await?,
+            )
+        }
+        "fhirpath_format" => {
+            let params: FormatParams = parse_tool_params(name, arguments)?;
+            check_expression(name, &params.expression)?;
+            tool_result_to_json(
+                run_with_timeout(name, DEFAULT_TOOL_TIMEOUT_MS, fhirpath_format(params)).await?,
+            )
+        }
+        _ => Err(ToolError::UnknownTool(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_fhirpath_evaluate_basic() {
+        let params = EvaluateParams {
+            expression: "Patient.name.given".to_string(),
+            resource: json!({
+                "resourceType": "Patient",
+                "name": [
+                    {
+                        "given": ["John", "Q"],
+                        "family": "Doe"
+                    }
+                ]
+            }),
+            ..Default::default()
+        };
+
+        let result = fhirpath_evaluate(params).await;
+        assert!(result.is_ok());
+
+        let eval_result = result.unwrap();
+        assert_eq!(eval_result.expression_info.complexity, "simple");
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_evaluate_error_position() {
+        let expression = "Patient.active + 1";
+        let result = fhirpath_evaluate(EvaluateParams {
+            expression: expression.to_string(),
+            resource: json!({"resourceType": "Patient", "active": true}),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let errors = result.errors.unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, crate::diagnostics::TYPE_MISMATCH);
+        let (start, end) = (errors[0].start.unwrap(), errors[0].end.unwrap());
+        assert_eq!(&expression[start..end], "+");
+        // The flattened message is still reported for older clients
+        assert!(result.diagnostics.unwrap()[0].starts_with("Evaluation error: "));
+    }
+
+    #[tokio::test]
+    async fn test_empty_result_is_not_an_error() {
+        let evaluate = |expression: &str| EvaluateParams {
+            expression: expression.to_string(),
+            resource: json!({"resourceType": "Observation", "status": "final"}),
+            ..Default::default()
+        };
+
+        // Nothing matches, which is a successful evaluation
+        let empty = fhirpath_evaluate(evaluate("Patient.name")).await.unwrap();
+        assert!(empty.values.is_empty());
+        assert!(!empty.evaluation_failed);
+        assert!(empty.expression_info.parsed);
+        assert!(empty.diagnostics.is_none());
+        assert!(empty.errors.is_none());
+
+        let unparsable = fhirpath_evaluate(evaluate("Patient.name.where("))
+            .await
+            .unwrap();
+        assert!(unparsable.values.is_empty());
+        assert!(unparsable.evaluation_failed);
+        assert!(!unparsable.expression_info.parsed);
+        assert!(unparsable.diagnostics.is_some());
+        assert!(unparsable.errors.is_some());
+
+        // An expression that parses but fails to evaluate is still reported as parsed
+        let failed = fhirpath_evaluate(evaluate("status + 1")).await.unwrap();
+        assert!(failed.evaluation_failed);
+        assert!(failed.expression_info.parsed);
+        assert!(failed.errors.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_non_object_resources_rejected() {
+        let cases = [
+            (json!([{"resourceType": "Patient"}]), "an array"),
+            (json!(42), "a number"),
+            (json!({"id": "p1"}), "an object without resourceType"),
+        ];
+
+        for (resource, found) in cases {
+            let expected = format!(
+                "resource must be a FHIR resource object with a string resourceType, got {found}"
+            );
+
+            let err = fhirpath_evaluate(EvaluateParams {
+                expression: "id".to_string(),
+                resource: resource.clone(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+            assert_eq!(err.to_string(), expected);
+
+            let err = fhirpath_extract(ExtractParams {
+                expression: "id".to_string(),
+                resource,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_with_root_type() {
+        let evaluate = |root_type: Option<&str>| EvaluateParams {
+            expression: "given.first()".to_string(),
+            resource: json!({"given": ["A", "B"]}),
+            root_type: root_type.map(str::to_string),
+            ..Default::default()
+        };
+
+        let result = fhirpath_evaluate(evaluate(Some("HumanName")))
+            .await
+            .unwrap();
+        assert_eq!(result.values, vec![json!("A")]);
+        assert!(result.diagnostics.is_none());
+
+        // The expression may start with the root type, as it may with a resource's type
+        let named = |root_type: &str, expression: &str| EvaluateParams {
+            expression: expression.to_string(),
+            ..evaluate(Some(root_type))
+        };
+        let result = fhirpath_evaluate(named("HumanName", "HumanName.given.last()"))
+            .await
+            .unwrap();
+        assert_eq!(result.values, vec![json!("B")]);
+        let result = fhirpath_evaluate(named(
+            "HumanName",
+            "HumanName.given.where(HumanName.exists()).count()",
+        ))
+        .await
+        .unwrap();
+        assert_eq!(result.values, vec![json!(0)]);
+        let result = fhirpath_evaluate(named("Address", "HumanName.given"))
+            .await
+            .unwrap();
+        assert!(result.values.is_empty());
+
+        // Without a root type the fragment is still rejected
+        let err = fhirpath_evaluate(evaluate(None)).await.unwrap_err();
+        assert!(err.is::<InvalidResource>());
+
+        let err = fhirpath_evaluate(evaluate(Some("Human Name")))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "root_type 'Human Name' is not a known FHIR type"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repeated_evaluation_hits_result_cache() {
+        let evaluate = |family: &str| EvaluateParams {
+            expression: "Patient.name.family".to_string(),
+            resource: json!({
+                "resourceType": "Patient",
+                "id": "result-cache",
+                "name": [{"family": family}]
+            }),
+            ..Default::default()
+        };
+
+        let first = fhirpath_evaluate(evaluate("Cached")).await.unwrap();
+        assert!(!first.performance.cache_hit);
+
+        let repeated = fhirpath_evaluate(evaluate("Cached")).await.unwrap();
+        assert!(repeated.performance.cache_hit);
+        assert_eq!(repeated.values, first.values);
+        assert_eq!(repeated.types, first.types);
+
+        let changed = fhirpath_evaluate(evaluate("Changed")).await.unwrap();
+        assert!(!changed.performance.cache_hit);
+        assert_eq!(changed.values, vec![json!("Changed")]);
+    }
+
+    #[tokio::test]
+    async fn test_equivalent_expressions_share_cached_result() {
+        let evaluate = |expression: &str| {
+            fhirpath_evaluate(EvaluateParams {
+                expression: expression.to_string(),
+                resource: json!({
+                    "resourceType": "Patient",
+                    "id": "canonical-cache-key",
+                    "name": [{"family": "Doe", "given": ["Jane"]}]
+                }),
+                ..Default::default()
+            })
+        };
+
+        let first = evaluate("Patient.name.family").await.unwrap();
+        assert!(!first.performance.cache_hit);
+        let spaced = evaluate("Patient .name . family").await.unwrap();
+        assert!(spaced.performance.cache_hit);
+        assert_eq!(spaced.values, vec![json!("Doe")]);
+
+        let different = evaluate("Patient.name.given").await.unwrap();
+        assert!(!different.performance.cache_hit);
+        assert_eq!(different.values, vec![json!("Jane")]);
+    }
+
+    #[tokio::test]
+    async fn test_clock_expressions_are_not_cached() {
+        for expression in ["now()", "Patient.birthDate < today()", "timeOfDay().hour()"] {
+            let params = || EvaluateParams {
+                expression: expression.to_string(),
+                resource: json!({"resourceType": "Patient", "id": "clock", "birthDate": "1990-01-01"}),
+                ..Default::default()
+            };
+            let engine = crate::fhirpath_engine::get_shared_engine().await.unwrap();
+            assert!(
+                result_cache_key(&params(), engine).is_none(),
+                "{expression}"
+            );
+
+            fhirpath_evaluate(params()).await.unwrap();
+            let again = fhirpath_evaluate(params()).await.unwrap();
+            assert!(!again.performance.cache_hit, "{expression}");
+        }
+    }
+
+    /// Terminology whose only value set can be replaced, as a server's may be updated
+    struct ChangingTerminology(std::sync::Mutex<Arc<crate::ValueSet>>);
+
+    #[async_trait::async_trait]
+    impl crate::TerminologyResolver for ChangingTerminology {
+        async fn value_set(&self, _url: &str) -> Result<Option<Arc<crate::ValueSet>>> {
+            Ok(Some(self.0.lock().unwrap().clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_member_of_expressions_are_not_cached() {
+        let codes =
+            |code: &str| Arc::new(crate::ValueSet::new([("http://example.org/codes", code)]));
+        let terminology = Arc::new(ChangingTerminology(std::sync::Mutex::new(codes("a"))));
+        let config = crate::fhirpath_engine::FhirEngineConfig {
+            terminology: Some(terminology.clone()),
+            ..Default::default()
+        };
+        let engine: &'static _ = Box::leak(Box::new(
+            crate::fhirpath_engine::FhirPathEngineFactory::with_config(config)
+                .await
+                .unwrap(),
+        ));
+        let params = EvaluateParams {
+            expression: "code.memberOf('http://example.org/ValueSet/codes')".to_string(),
+            resource: json!({
+                "resourceType": "Observation",
+                "status": "final",
+                "code": {"coding": [{"system": "http://example.org/codes", "code": "a"}]}
+            }),
+            ..Default::default()
+        };
+        assert!(result_cache_key(&params, engine).is_none());
+
+        let (first, cache_hit) = evaluate_cached(engine, &params, None).await;
+        assert_eq!(first.unwrap().values, vec![json!(true)]);
+        assert!(!cache_hit);
+
+        *terminology.0.lock().unwrap() = codes("b");
+        let (again, cache_hit) = evaluate_cached(engine, &params, None).await;
+        assert_eq!(again.unwrap().values, vec![json!(false)]);
+        assert!(!cache_hit);
+    }
+
+    #[tokio::test]
+    async fn test_bypass_cache_recomputes_and_refreshes_entry() {
+        let evaluate = |bypass_cache: bool| EvaluateParams {
+            expression: "Patient.name.family".to_string(),
+            resource: json!({
+                "resourceType": "Patient",
+                "id": "bypass-cache",
+                "name": [{"family": "Fresh"}]
+            }),
+            bypass_cache,
+            ..Default::default()
+        };
+
+        // A stale entry, as if the result had since changed
+        let engine = crate::fhirpath_engine::get_shared_engine().await.unwrap();
+        engine.result_cache().insert(
+            result_cache_key(&evaluate(false), engine).unwrap(),
+            CachedEvaluation {
+                values: vec![json!("Stale")],
+                types: vec!["string".to_string()],
+                diagnostics: None,
+                errors: None,
+            },
+        );
+
+        let cached = fhirpath_evaluate(evaluate(false)).await.unwrap();
+        assert!(cached.performance.cache_hit);
+        assert_eq!(cached.values, vec![json!("Stale")]);
+
+        let fresh = fhirpath_evaluate(evaluate(true)).await.unwrap();
+        assert!(!fresh.performance.cache_hit);
+        assert_eq!(fresh.values, vec![json!("Fresh")]);
+
+        let refreshed = fhirpath_evaluate(evaluate(false)).await.unwrap();
+        assert!(refreshed.performance.cache_hit);
+        assert_eq!(refreshed.values, vec![json!("Fresh")]);
+    }
+
+    #[tokio::test]
+    async fn test_evaluation_trace_levels() {
+        let evaluate = |trace: Option<TraceLevel>| EvaluateParams {
+            expression: "Patient.name.family.trace('families').first()".to_string(),
+            resource: json!({
+                "resourceType": "Patient",
+                "name": [{"family": "Smith"}, {"family": "Jones"}]
+            }),
+            trace,
+            ..Default::default()
+        };
+        let families = TraceEntry {
+            operation: "trace".to_string(),
+            name: "families".to_string(),
+            values: vec![json!("Smith"), json!("Jones")],
+            total_count: None,
+        };
+
+        let result = fhirpath_evaluate(evaluate(None)).await.unwrap();
+        assert_eq!(result.trace, None);
+
+        let result = fhirpath_evaluate(evaluate(Some(TraceLevel::Summary)))
+            .await
+            .unwrap();
+        assert_eq!(result.values, vec![json!("Smith")]);
+        assert_eq!(result.trace, Some(vec![families.clone()]));
+
+        let trace = fhirpath_evaluate(evaluate(Some(TraceLevel::Full)))
+            .await
+            .unwrap()
+            .trace
+            .unwrap();
+        assert_eq!(trace[0], families);
+        let steps: Vec<_> = trace[1..]
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.values.len()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ("Patient", 1),
+                ("name", 2),
+                ("family", 2),
+                ("trace(...)", 2),
+                ("first()", 1)
+            ]
+        );
+
+        // Traced values are cut to max_values like the result
+        let result = fhirpath_evaluate(EvaluateParams {
+            max_values: Some(1),
+            ..evaluate(Some(TraceLevel::Full))
+        })
+        .await
+        .unwrap();
+        let trace = result.trace.unwrap();
+        assert_eq!(trace[0].values, vec![json!("Smith")]);
+        assert_eq!(trace[0].total_count, Some(2));
+        assert!(trace.iter().all(|entry| entry.values.len() <= 1));
+        assert_eq!(trace.last().unwrap().total_count, None);
+
+        // Steps resolve references against the request's contained resources
+        let trace = fhirpath_evaluate(EvaluateParams {
+            expression: "Patient.managingOrganization.resolve().name".to_string(),
+            resource: json!({
+                "resourceType": "Patient",
+                "managingOrganization": {"reference": "#org"}
+            }),
+            contained_resources: Some(vec![
+                json!({"resourceType": "Organization", "id": "org", "name": "Acme"}),
+            ]),
+            trace: Some(TraceLevel::Full),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .trace
+        .unwrap();
+        assert_eq!(trace.last().unwrap().values, vec![json!("Acme")]);
+
+        // Steps share the request's deadline rather than each getting a full time limit
+        let engine = crate::fhirpath_engine::get_shared_engine().await.unwrap();
+        let params = evaluate(Some(TraceLevel::Full));
+        let steps = trace_steps(engine, &params, Instant::now() + Duration::from_secs(5), 10);
+        assert_eq!(steps.await.len(), 5);
+        let steps = trace_steps(engine, &params, Instant::now(), 10);
+        assert!(steps.await.is_empty());
+    }
+
+    /// A Patient whose serialized JSON is exactly `size` bytes
+    fn patient_of_size(size: usize) -> Value {
+        let base = json!({"resourceType": "Patient", "id": ""});
+        let padding = size - serde_json::to_vec(&base).unwrap().len();
+        json!({"resourceType": "Patient", "id": "x".repeat(padding)})
+    }
+
+    #[tokio::test]
+    async fn test_resource_size_limit() {
+        let max = crate::security::validation::ValidationConfig::default().max_resource_size;
+        let evaluate = |resource: Value| EvaluateParams {
+            expression: "Patient.id.length()".to_string(),
+            resource,
+            ..Default::default()
+        };
+
+        let under = patient_of_size(max);
+        assert_eq!(serde_json::to_vec(&under).unwrap().len(), max);
+        let result = fhirpath_evaluate(evaluate(under)).await.unwrap();
+        assert!(result.diagnostics.is_none());
+
+        let over = patient_of_size(max + 1);
+        let err = fhirpath_evaluate(evaluate(over.clone())).await.unwrap_err();
+        assert!(err.to_string().contains("FHIR resource too large"));
+
+        let err = fhirpath_extract(ExtractParams {
+            expression: "Patient.id".to_string(),
+            resource: over,
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            tool_error("fhirpath_extract", err),
+            ToolError::ResourceTooLarge { .. }
+        ));
+    }
+
+    #[test]
+    fn test_server_busy_maps_to_service_unavailable() {
+        let err = tool_error("fhirpath_evaluate", ServerBusy { waited_ms: 10 }.into());
+        assert!(matches!(err, ToolError::ServerBusy { waited_ms: 10, .. }));
+        assert_eq!(err.http_status(), 503);
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_extract_paths() {
+        let resource = json!({
+            "resourceType": "Patient",
+            "name": [
+                {"given": ["John", "Q"], "family": "Doe"},
+                {"given": ["Johnny"], "use": "nickname"}
+            ]
+        });
+        let extract = |expression: &str| ExtractParams {
+            expression: expression.to_string(),
+            resource: resource.clone(),
+            format: Some("paths".to_string()),
+            ..Default::default()
+        };
+
+        let result = fhirpath_extract(extract("Patient.name.given"))
+            .await
+            .unwrap();
+        assert_eq!(
+            result.data,
+            json!([
+                "Patient.name[0].given[0]",
+                "Patient.name[0].given[1]",
+                "Patient.name[1].given[0]"
+            ])
+        );
+        assert!(result.metadata.computed_paths.is_empty());
+
+        let result = fhirpath_extract(extract("Patient.name.where(use = 'nickname').given"))
+            .await
+            .unwrap();
+        assert_eq!(result.paths, vec!["Patient.name[1].given[0]"]);
+
+        let result = fhirpath_extract(extract("Patient.name.given.count()"))
+            .await
+            .unwrap();
+        assert_eq!(result.paths, vec!["result[0]"]);
+        assert_eq!(result.metadata.computed_paths, vec![0]);
+
+        let result = fhirpath_extract(ExtractParams {
+            format: Some("jsonpatch".to_string()),
+            ..extract("Patient.name.family")
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            result.data,
+            json!([
+                {"op": "test", "path": "/name/0/family", "value": "Doe"},
+                {"op": "replace", "path": "/name/0/family", "value": "Doe"}
+            ])
+        );
+        assert_eq!(json_pointer("Patient.name[1].given[0]"), "/name/1/given/0");
+        assert_eq!(json_pointer("Patient"), "");
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_extract_paths_of_duplicate_values() {
+        let resource = json!({
+            "resourceType": "Patient",
+            "name": [{"given": ["Ann", "Bob", "Ann"]}]
+        });
+        let paths = |expression: &str| {
+            let params = ExtractParams {
+                expression: expression.to_string(),
+                resource: resource.clone(),
+                format: Some("paths".to_string()),
+                ..Default::default()
+            };
+            async move { fhirpath_extract(params).await.unwrap() }
+        };
+
+        assert_eq!(
+            paths("Patient.name.given.last()").await.paths,
+            vec!["Patient.name[0].given[2]"]
+        );
+        assert_eq!(
+            paths("Patient.name.given.skip(1)").await.paths,
+            vec!["Patient.name[0].given[1]", "Patient.name[0].given[2]"]
+        );
+
+        // Either `Ann` could be the one kept, so neither gets a location
+        let result = paths("Patient.name.given.where($this = 'Ann')").await;
+        assert_eq!(result.paths, vec!["result[0]", "result[1]"]);
+        assert_eq!(result.metadata.computed_paths, vec![0, 1]);
+        assert_eq!(
+            paths("Patient.name.given.where($this = 'Bob')").await.paths,
+            vec!["Patient.name[0].given[1]"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_typed_output() {
+        let evaluate = |expression: &str, output: Option<OutputFormat>| EvaluateParams {
+            expression: expression.to_string(),
+            resource: json!({"resourceType": "Patient", "birthDate": "2020"}),
+            output,
+            ..Default::default()
+        };
+
+        let plain = fhirpath_evaluate(evaluate("10 'mg'", None)).await.unwrap();
+        assert_eq!(plain.values, vec![json!({"value": 10.0, "unit": "mg"})]);
+        let typed = fhirpath_evaluate(evaluate("10 'mg'", Some(OutputFormat::Typed)))
+            .await
+            .unwrap();
+        assert_eq!(
+            typed.values,
+            vec![json!({
+                "type": "Quantity",
+                "value": 10.0,
+                "unit": "mg",
+                "system": "http://unitsofmeasure.org",
+                "code": "mg"
+            })]
+        );
+
+        let plain = fhirpath_evaluate(evaluate("@2020", Some(OutputFormat::Plain)))
+            .await
+            .unwrap();
+        assert_eq!(plain.values, vec![json!("2020")]);
+        let typed = fhirpath_evaluate(evaluate("@2020", Some(OutputFormat::Typed)))
+            .await
+            .unwrap();
+        assert_eq!(
+            typed.values,
+            vec![json!({"type": "date", "value": "2020", "precision": "year"})]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_with_focus_path() {
+        // `ofType(Patient)` would read better, but the engine evaluates its type argument
+        // as a path and always yields empty
+        const PATIENTS: &str = "Bundle.entry.resource.where(resourceType = 'Patient')";
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "collection",
+            "entry": [
+                {"resource": {"resourceType": "Patient", "name": [{"family": "Smith"}, {"family": "Jones"}]}},
+                {"resource": {"resourceType": "Observation", "status": "final"}},
+                {"resource": {"resourceType": "Patient", "name": [{"family": "Brown"}]}}
+            ]
+        });
+        let evaluate = |expression: &str, focus_path: Option<&str>| EvaluateParams {
+            expression: expression.to_string(),
+            resource: bundle.clone(),
+            focus_path: focus_path.map(str::to_string),
+            ..Default::default()
+        };
+
+        let result = fhirpath_evaluate(evaluate("name.family", Some(PATIENTS)))
+            .await
+            .unwrap();
+        assert_eq!(
+            result.values,
+            vec![json!("Smith"), json!("Jones"), json!("Brown")]
+        );
+
+        // The expression is evaluated per focus item rather than on the collection
+        let result = fhirpath_evaluate(evaluate("name.count()", Some(PATIENTS)))
+            .await
+            .unwrap();
+        assert_eq!(result.values, vec![json!(2), json!(1)]);
+
+        // Without a focus the expression starts from the Bundle and finds nothing
+        let result = fhirpath_evaluate(evaluate("name.family", None))
+            .await
+            .unwrap();
+        assert!(result.values.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lossless_output() {
+        let evaluate = |expression: &str, lossless: bool| EvaluateParams {
+            expression: expression.to_string(),
+            resource: json!({"resourceType": "Patient", "birthDate": "2020-05"}),
+            lossless,
+            ..Default::default()
+        };
+
+        let result = fhirpath_evaluate(evaluate("3.14159265358979", true))
+            .await
+            .unwrap();
+        assert_eq!(
+            result.values,
+            vec![json!({"type": "decimal", "value": "3.14159265358979"})]
+        );
+        // Trailing zeros are significant digits too
+        let result = fhirpath_evaluate(evaluate("1.50 'mg'", true))
+            .await
+            .unwrap();
+        assert_eq!(result.values[0]["value"], "1.50");
+
+        let result = fhirpath_evaluate(evaluate("@2020-05", true)).await.unwrap();
+        assert_eq!(
+            result.values,
+            vec![json!({"type": "date", "value": "2020-05", "precision": "month"})]
+        );
+
+        // Decimals read from the resource keep the digits of its JSON text, which the
+        // engine's binary floating point loses
+        let observation: Value = serde_json::from_str(
+            r#"{"resourceType": "Observation", "status": "final", "code": {"text": "dose"},
+                "valueQuantity": {"value": 1.50, "unit": "mg"},
+                "extension": [{"url": "http://example.org/ratio",
+                               "valueDecimal": 0.12345678901234567890}]}"#,
+        )
+        .unwrap();
+        let evaluate_observation = |expression: &str| EvaluateParams {
+            expression: expression.to_string(),
+            resource: observation.clone(),
+            lossless: true,
+            ..Default::default()
+        };
+        let result = fhirpath_evaluate(evaluate_observation("Observation.valueQuantity"))
+            .await
+            .unwrap();
+        assert_eq!(result.values[0]["value"], "1.50");
+        assert_eq!(result.values[0]["unit"], "mg");
+        let result = fhirpath_evaluate(evaluate_observation("Observation.extension.valueDecimal"))
+            .await
+            .unwrap();
+        assert_eq!(
+            result.values,
+            vec![json!({"type": "decimal", "value": "0.12345678901234567890"})]
+        );
+
+        // The lossy form is cached separately
+        let result = fhirpath_evaluate(evaluate("3.14159265358979", false))
+            .await
+            .unwrap();
+        assert!(result.values[0].is_number());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_reference_from_bundle() {
+        let evaluate = |reference: &str| EvaluateParams {
+            expression: "Observation.subject.resolve().name.family".to_string(),
+            resource: json!({
+                "resourceType": "Observation",
+                "status": "final",
+                "code": {"text": "weight"},
+                "subject": {"reference": reference}
+            }),
+            bundle: Some(json!({
+                "resourceType": "Bundle",
+                "type": "collection",
+                "entry": [{
+                    "fullUrl": "https://example.org/fhir/Patient/p1",
+                    "resource": {
+                        "resourceType": "Patient",
+                        "id": "p1",
+                        "name": [{"family": "Chalmers"}]
+                    }
+                }]
+            })),
+            ..Default::default()
+        };
+
+        let result = fhirpath_evaluate(evaluate("Patient/p1")).await.unwrap();
+        assert_eq!(result.values, vec![json!("Chalmers")]);
+        assert!(result.diagnostics.is_none());
+
+        let result = fhirpath_evaluate(evaluate("Patient/missing"))
+            .await
+            .unwrap();
+        assert!(result.values.is_empty());
+        assert!(result.expression_info.parsed);
+        assert_eq!(
+            result.diagnostics,
+            Some(vec![
+                "Could not resolve reference 'Patient/missing'".to_string()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_diff() {
+        let patient = |given: Value| json!({"resourceType": "Patient", "name": [{"given": given, "family": "Doe"}]});
+        let result = fhirpath_diff(DiffParams {
+            expression: "Patient.name.given".to_string(),
+            left_resource: patient(json!(["John"])),
+            right_resource: patient(json!(["John", "Q"])),
+        })
+        .await
+        .unwrap();
+
+        assert!(result.changed);
+        assert!(result.only_in_left.is_empty());
+        assert_eq!(result.only_in_right.len(), 1);
+        assert_eq!(result.only_in_right[0].value, json!("Q"));
+        assert_eq!(result.only_in_right[0].value_type, "string");
+        assert_eq!(result.common.len(), 1);
+        assert_eq!(result.common[0].value, json!("John"));
+
+        let err = fhirpath_diff(DiffParams {
+            expression: "Patient.name.given".to_string(),
+            left_resource: patient(json!(["John"])),
+            right_resource: json!("Patient"),
+        })
+        .await
+        .unwrap_err();
+        assert!(err.is::<InvalidResource>());
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_compare() {
+        let patient = |given: Value| json!({"resourceType": "Patient", "name": [{"given": given}]});
+        let resources = vec![
+            patient(json!(["Peter", "James"])),
+            patient(json!(["Jim"])),
+            json!({"resourceType": "Patient", "id": "nameless"}),
+        ];
+
+        let result = fhirpath_compare(CompareParams {
+            expression_a: "Patient.name.given.first()".to_string(),
+            expression_b: "Patient.name.given[0]".to_string(),
+            resources: resources.clone(),
+        })
+        .await
+        .unwrap();
+
+        assert!(result.equivalent);
+        assert_eq!(result.results.len(), 3);
+        assert!(result.results.iter().all(|entry| entry.equivalent));
+        assert_eq!(result.results[0].result_a[0].value, json!("Peter"));
+        assert!(result.results[2].result_a.is_empty());
+
+        let result = fhirpath_compare(CompareParams {
+            expression_a: "Patient.name.given.first()".to_string(),
+            expression_b: "Patient.name.given.last()".to_string(),
+            resources,
+        })
+        .await
+        .unwrap();
+
+        assert!(!result.equivalent);
+        assert!(!result.results[0].equivalent);
+        assert!(result.results[1].equivalent);
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_evaluate_multi() {
+        let patient = |active: Value| json!({"resourceType": "Patient", "active": active});
+        let result = fhirpath_evaluate_multi(MultiResourceParams {
+            expression: "Patient.active = true".to_string(),
+            resources: vec![
+                patient(json!(true)),
+                patient(json!(false)),
+                patient(json!(true)),
+            ],
+        })
+        .await
+        .unwrap();
+
+        let answers: Vec<Value> = result
+            .results
+            .iter()
+            .map(|entry| entry.values[0].value.clone())
+            .collect();
+        assert_eq!(answers, vec![json!(true), json!(false), json!(true)]);
+        assert_eq!(result.error_count, 0);
+
+        // A resource that isn't one fails only its own entry
+        let result = fhirpath_evaluate_multi(MultiResourceParams {
+            expression: "Patient.active = true".to_string(),
+            resources: vec![json!("not a resource"), patient(json!(true))],
+        })
+        .await
+        .unwrap();
+        assert_eq!(result.error_count, 1);
+        assert!(result.results[0].error.is_some());
+        assert_eq!(result.results[1].values[0].value, json!(true));
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_explain() {
+        let result = fhirpath_explain(ExplainParams {
+            expression: "Patient.name.where(use='official').family".to_string(),
+            resource: json!({
+                "resourceType": "Patient",
+                "name": [
+                    {"use": "official", "family": "Doe"},
+                    {"use": "nickname", "family": "Jo"},
+                    {"use": "maiden", "family": "Smith"}
+                ]
+            }),
+        })
+        .await
+        .unwrap();
+
+        let steps: Vec<(&str, &str, Option<usize>, usize)> = result
+            .steps
+            .iter()
+            .map(|step| {
+                (
+                    step.operation.as_str(),
+                    step.description.as_str(),
+                    step.input_count,
+                    step.output_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ("navigate", "Patient", None, 1),
+                ("navigate", "name", Some(1), 3),
+                ("filter", "where(...)", Some(3), 1),
+                ("navigate", "family", Some(1), 1),
+            ]
+        );
+        assert_eq!(result.values, vec![json!("Doe")]);
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_functions() {
+        let result = fhirpath_functions(FunctionsParams::default())
+            .await
+            .unwrap();
+        assert_eq!(result.total_count, result.functions.len());
+        let function = |name: &str| {
+            result
+                .functions
+                .iter()
+                .find(|function| function.name == name)
+                .unwrap_or_else(|| panic!("{name} should be listed"))
+        };
+
+        for name in ["where", "select"] {
+            let lambda = function(name);
+            assert_eq!(lambda.category, "collection");
+            assert_eq!((lambda.min_arity, lambda.max_arity), (1, Some(1)));
+            assert!(lambda.supports_lambda);
+        }
+
+        let count = function("count");
+        assert_eq!(count.category, "collection");
+        assert_eq!((count.min_arity, count.max_arity), (0, Some(0)));
+        assert!(!count.supports_lambda);
+        assert!(!count.description.is_empty());
+
+        let strings = fhirpath_functions(FunctionsParams {
+            category: Some("string".to_string()),
+        })
+        .await
+        .unwrap();
+        assert!(
+            strings
+                .functions
+                .iter()
+                .any(|function| function.name == "substring")
+        );
+        assert!(
+            strings
+                .functions
+                .iter()
+                .all(|function| function.category == "string")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nondeterministic_custom_functions_are_not_cached() {
+        let calls = Arc::new(std::sync::atomic::AtomicI64::new(0));
+        let counter = calls.clone();
+        let risk_score = crate::FnFunction::new("riskScore", 0, move |_input, _args| {
+            Ok(FhirPathValue::Integer(
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            ))
+        })
+        .with_deterministic(false);
+        let half = crate::FnFunction::new("half", 1, |_input, args| match &args[0] {
+            FhirPathValue::Integer(value) => Ok(FhirPathValue::Integer(value / 2)),
+            other => Err(anyhow!(
+                "half() needs an integer, got {}",
+                other.type_name()
+            )),
+        });
+        let config = crate::fhirpath_engine::FhirEngineConfig::default()
+            .with_custom_function(risk_score)
+            .with_custom_function(half);
+        let engine: &'static _ = Box::leak(Box::new(
+            crate::fhirpath_engine::FhirPathEngineFactory::with_config(config)
+                .await
+                .unwrap(),
+        ));
+        let params = |expression: &str| EvaluateParams {
+            expression: expression.to_string(),
+            resource: json!({"resourceType": "Patient", "id": "custom"}),
+            ..Default::default()
+        };
+
+        assert!(result_cache_key(&params("half(4)"), engine).is_some());
+        assert!(result_cache_key(&params("riskScore() + 1"), engine).is_none());
+        let (first, _) = evaluate_cached(engine, &params("riskScore()"), None).await;
+        let (again, cache_hit) = evaluate_cached(engine, &params("riskScore()"), None).await;
+        assert!(!cache_hit);
+        assert_ne!(first.unwrap().values, again.unwrap().values);
+
+        let custom = list_functions(
+            engine,
+            FunctionsParams {
+                category: Some(CUSTOM_FUNCTION_CATEGORY.to_string()),
+            },
+        )
+        .await
+        .unwrap();
+        let deterministic: Vec<_> = custom
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.deterministic))
+            .collect();
+        assert_eq!(deterministic, vec![("half", true), ("riskScore", false)]);
     }
-}
 
-/// Helper function to extract function names from FHIRPath expression
-fn extract_functions(expression: &str) -> Vec<String> {
-    let mut functions = Vec::new();
-    let function_names = [
-        "where",
-        "select",
-        "first",
-        "last",
-        "tail",
-        "skip",
-        "take",
-        "single",
-        "exists",
-        "empty",
-        "not",
-        "all",
-        "any",
-        "count",
-        "distinct",
-        "isDistinct",
-        "subsetOf",
-        "supersetOf",
-        "intersect",
-        "exclude",
-        "union",
-        "combine",
-        "contains",
-        "in",
-        "indexOf",
-        "substring",
-        "startsWith",
-        "endsWith",
-        "matches",
-        "replaceMatches",
-        "replace",
-        "length",
-        "toInteger",
-        "toString",
-        "toDecimal",
-        "toDateTime",
-        "toTime",
-        "convertsToInteger",
-        "convertsToDecimal",
-        "convertsToDateTime",
-        "convertsToTime",
-        "iif",
-        "trace",
-        "now",
-        "today",
-        "timeOfDay",
-    ];
+    #[tokio::test]
+    async fn test_fhirpath_format() {
+        let format = |expression: &str, style: Option<FormatStyle>| {
+            fhirpath_format(FormatParams {
+                expression: expression.to_string(),
+                style,
+            })
+        };
 
-    for func in function_names {
-        if expression.contains(&format!("{func}(")) {
-            functions.push(func.to_string());
+        let result = format("Patient.name.where(use='official' )", None)
+            .await
+            .unwrap();
+        assert_eq!(result.formatted, "Patient.name.where(use = 'official')");
+        assert!(result.changed);
+        assert!(result.error.is_none());
+
+        let result = format(&result.formatted, None).await.unwrap();
+        assert!(!result.changed);
+
+        for (expression, formatted) in [
+            ("(1+2)*3", "(1 + 2) * 3"),
+            ("1+(2*3)", "1 + 2 * 3"),
+            ("a implies (b implies c)", "a implies b implies c"),
+            (
+                "iif(active,name.given.first( ),{})",
+                "iif(active, name.given.first(), {})",
+            ),
+            (
+                "birthDate<@2000-01-01 and deceased.not()",
+                "birthDate < @2000-01-01 and deceased.not()",
+            ),
+            (
+                "value > 5 'mg'|value<4 days",
+                "value > 5 'mg' | value < 4 days",
+            ),
+            ("name.select($this.given)[0]", "name.select($this.given)[0]"),
+            ("%resource.`div`", "%resource.`div`"),
+            (
+                "family.where($this='O\\'Brien')",
+                "family.where($this = 'O\\'Brien')",
+            ),
+        ] {
+            let result = format(expression, None).await.unwrap();
+            assert_eq!(result.formatted, formatted, "formatting {expression}");
         }
-    }
 
-    functions.sort();
-    functions.dedup();
-    functions
-}
+        let result = format(
+            "Patient.name.where(use = 'official' and given.exists())",
+            Some(FormatStyle::Compact),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            result.formatted,
+            "Patient.name.where(use='official' and given.exists())"
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+        let result = format("Patient.name.where(", None).await.unwrap();
+        assert_eq!(result.formatted, "Patient.name.where(");
+        assert!(!result.changed);
+        assert!(result.error.is_some());
+    }
 
     #[tokio::test]
-    async fn test_fhirpath_evaluate_basic() {
-        let params = EvaluateParams {
-            expression: "Patient.name.given".to_string(),
+    async fn test_fhirpath_transform() {
+        let mapping = HashMap::from([
+            ("familyName".to_string(), "Patient.name.family".to_string()),
+            (
+                "phone".to_string(),
+                "Patient.telecom.where(system='phone').value".to_string(),
+            ),
+            ("given".to_string(), "Patient.name.given".to_string()),
+            ("deceased".to_string(), "Patient.deceased".to_string()),
+        ]);
+        let result = fhirpath_transform(TransformParams {
             resource: json!({
                 "resourceType": "Patient",
-                "name": [
-                    {
-                        "given": ["John", "Q"],
-                        "family": "Doe"
-                    }
+                "name": [{"family": "Doe", "given": ["John", "Q"]}],
+                "telecom": [
+                    {"system": "phone", "value": "555-1234"},
+                    {"system": "email", "value": "john@example.com"}
                 ]
             }),
-            context: None,
-            timeout_ms: None,
+            mapping,
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            Value::Object(result.output),
+            json!({
+                "familyName": "Doe",
+                "phone": "555-1234",
+                "given": ["John", "Q"],
+                "deceased": null
+            })
+        );
+
+        let err = fhirpath_transform(TransformParams {
+            resource: json!(["Patient"]),
+            mapping: HashMap::from([("id".to_string(), "id".to_string())]),
+        })
+        .await
+        .unwrap_err();
+        assert!(err.is::<InvalidResource>());
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_evaluate_with_fhir_version() {
+        let params = EvaluateParams {
+            expression: "Patient.id".to_string(),
+            resource: json!({"resourceType": "Patient", "id": "r5"}),
+            fhir_version: Some("R5".to_string()),
+            ..Default::default()
         };
 
-        let result = fhirpath_evaluate(params).await;
-        assert!(result.is_ok());
+        let result = fhirpath_evaluate(params).await.unwrap();
+        assert_eq!(result.values, vec![json!("r5")]);
 
-        let eval_result = result.unwrap();
-        assert_eq!(eval_result.expression_info.complexity, "simple");
+        let params = EvaluateParams {
+            expression: "Patient.id".to_string(),
+            resource: json!({"resourceType": "Patient", "id": "r3"}),
+            fhir_version: Some("STU3".to_string()),
+            ..Default::default()
+        };
+
+        let err = fhirpath_evaluate(params).await.unwrap_err();
+        assert!(err.to_string().contains("Supported versions"));
     }
 
     #[tokio::test]
@@ -752,6 +5377,111 @@ mod tests {
         assert!(parse_result.valid || !parse_result.errors.is_empty()); // Either valid or has error info
     }
 
+    #[tokio::test]
+    async fn test_fhirpath_parse_checks_syntax_only() {
+        // Syntactically fine, but each fails once evaluated
+        for expression in [
+            "1 + 'a'",
+            "'abc'.substring('x')",
+            "Patient.name.unknownFunction()",
+        ] {
+            let result = fhirpath_parse(ParseParams {
+                expression: expression.to_string(),
+                include_ast: None,
+            })
+            .await
+            .unwrap();
+            assert!(result.valid, "{expression}: {:?}", result.errors);
+            assert!(result.errors.is_empty());
+        }
+
+        let result = fhirpath_parse(ParseParams {
+            expression: "Patient.name.where(".to_string(),
+            include_ast: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_parse_locates_errors() {
+        let parse = |expression: &str| {
+            fhirpath_parse(ParseParams {
+                expression: expression.to_string(),
+                include_ast: None,
+            })
+        };
+
+        let result = parse("Patient.name.where(").await.unwrap();
+        let details = &result.error_details[0];
+        assert_eq!(details.position, Some(19));
+        assert_eq!(
+            details.suggestion.as_deref(),
+            Some("Close the '(' at position 18 with ')'")
+        );
+
+        let result = parse("Patient..name").await.unwrap();
+        let details = &result.error_details[0];
+        assert_eq!((details.position, details.end), (Some(8), Some(9)));
+        assert_eq!(details.expected, ["identifier"]);
+        assert_eq!(details.suggestion.as_deref(), Some("Remove the extra '.'"));
+
+        let result = parse("Patient.name").await.unwrap();
+        assert!(result.error_details.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_parse_includes_ast() {
+        let params = ParseParams {
+            expression: "Patient.name.where(use='official')".to_string(),
+            include_ast: Some(true),
+        };
+
+        let result = fhirpath_parse(params).await.unwrap();
+        let ast = result
+            .ast
+            .expect("AST should be present when include_ast is true");
+
+        assert_eq!(ast["type"], "MethodCall");
+        assert_eq!(ast["name"], "where");
+
+        let children = ast["children"].as_array().unwrap();
+        assert_eq!(children[0]["type"], "Path");
+        assert_eq!(children[0]["name"], "name");
+
+        let condition = &children[1];
+        assert_eq!(condition["type"], "BinaryOp");
+        assert_eq!(condition["operator"], "=");
+        assert_eq!(condition["children"][0]["name"], "use");
+        assert_eq!(condition["children"][1]["value"], "official");
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_parse_omits_ast_by_default() {
+        let params = ParseParams {
+            expression: "Patient.name".to_string(),
+            include_ast: None,
+        };
+
+        let result = fhirpath_parse(params).await.unwrap();
+        assert!(result.ast.is_none());
+    }
+
+    #[test]
+    fn test_expression_to_json_indexer() {
+        let ast = octofhir_fhirpath::parse("Patient.name[0].given").unwrap();
+        let json = expression_to_json(&ast);
+
+        assert_eq!(json["type"], "Path");
+        assert_eq!(json["name"], "given");
+        let index = &json["children"][0];
+        assert_eq!(index["type"], "Index");
+        assert_eq!(index["children"][1]["literal_type"], "Integer");
+        assert_eq!(index["children"][1]["value"], 0);
+    }
+
     #[tokio::test]
     async fn test_fhirpath_extract_structured() {
         let params = ExtractParams {
@@ -766,6 +5496,7 @@ mod tests {
                 ]
             }),
             format: Some("structured".to_string()),
+            ..Default::default()
         };
 
         let result = fhirpath_extract(params).await;
@@ -784,15 +5515,417 @@ mod tests {
         assert!(functions.contains(&"first".to_string()));
     }
 
+    fn metrics(expression: &str) -> ComplexityMetrics {
+        ComplexityMetrics::from_ast(&octofhir_fhirpath::parse(expression).unwrap())
+    }
+
     #[test]
     fn test_complexity_assessment() {
-        assert_eq!(assess_complexity("name"), "simple");
-        assert_eq!(assess_complexity("Patient.name.given.first()"), "moderate");
+        assert_eq!(assess_complexity(&metrics("name")), "simple");
+        assert_eq!(
+            assess_complexity(&metrics("Patient.name.given.first()")),
+            "moderate"
+        );
         assert_eq!(
-            assess_complexity(
+            assess_complexity(&metrics(
                 "Patient.name.where(use = 'official').given.first() and Patient.birthDate < today()"
-            ),
+            )),
             "complex"
         );
     }
+
+    #[test]
+    fn test_complexity_metrics_path() {
+        let m = metrics("a.b.c");
+        assert_eq!(m.ast_depth, 1);
+        assert_eq!(m.path_segments, 3);
+        assert_eq!(m.function_count, 0);
+    }
+
+    #[test]
+    fn test_complexity_metrics_nested_where() {
+        let m = metrics("a.where(b.where(c))");
+        assert_eq!(m.ast_depth, 2);
+        assert_eq!(m.function_count, 2);
+        assert_eq!(m.predicate_count, 2);
+        assert_eq!(m.path_segments, 3);
+        assert_eq!(m.functions, vec!["where".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_analyze_uses_ast() {
+        let params = AnalyzeParams {
+            expression: "Patient.name.where(given.where(startsWith('J')))".to_string(),
+            options: Some(AnalysisOptions {
+                include_ast: Some(true),
+                ..AnalysisOptions::default()
+            }),
+        };
+
+        let result = fhirpath_analyze(params).await.unwrap();
+        assert_eq!(result.analysis.function_count, 3);
+        assert_eq!(result.analysis.ast_depth, 3);
+        assert_eq!(result.analysis.path_segments, 3);
+        assert_eq!(result.ast.unwrap()["type"], "MethodCall");
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_analyze_lints() {
+        let analyze = |expression: &str| AnalyzeParams {
+            expression: expression.to_string(),
+            options: None,
+        };
+
+        let result = fhirpath_analyze(analyze("Patient.name.where(true)"))
+            .await
+            .unwrap();
+        assert_eq!(result.lints.len(), 1);
+        assert_eq!(result.lints[0].rule, crate::lint::REDUNDANT_FILTER);
+        assert_eq!((result.lints[0].start, result.lints[0].end), (13, 24));
+
+        let result = fhirpath_analyze(analyze("Patient.telecom.where(system = 'phone') = '555'"))
+            .await
+            .unwrap();
+        let rules: Vec<&str> = result.lints.iter().map(|lint| lint.rule.as_str()).collect();
+        assert_eq!(rules, vec![crate::lint::COLLECTION_COMPARISON]);
+
+        let result = fhirpath_analyze(analyze("Patient.name.given.first()"))
+            .await
+            .unwrap();
+        assert!(result.lints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_evaluate_remote() {
+        use axum::{Json, Router, extract::Path, http::StatusCode, routing::get};
+
+        // A FHIR server knowing two Patients, one of them too large to accept
+        let fhir_server = Router::new()
+            .route(
+                "/fhir/Patient/{id}",
+                get(|Path(id): Path<String>| async move {
+                    match id.as_str() {
+                        "123" => Ok(Json(json!({
+                            "resourceType": "Patient",
+                            "id": "123",
+                            "name": [{"family": "Remote"}]
+                        }))),
+                        "large" => Ok(Json(json!({
+                            "resourceType": "Patient",
+                            "id": "large",
+                            "text": {"div": "x".repeat(64 * 1024)}
+                        }))),
+                        _ => Err(StatusCode::NOT_FOUND),
+                    }
+                }),
+            )
+            .route(
+                "/fhir/Observation/{id}",
+                get(|| async {
+                    // Chunked, without a Content-Length to check up front
+                    let chunks = (0..64).map(|i| {
+                        let chunk = if i == 0 {
+                            r#"{"resourceType": "Observation", "note": ""#.to_string()
+                        } else {
+                            "y".repeat(1024)
+                        };
+                        Ok::<_, std::convert::Infallible>(chunk)
+                    });
+                    axum::body::Body::from_stream(futures_util::stream::iter(chunks))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/fhir", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, fhir_server).await });
+
+        let validator = crate::security::validation::InputValidator::new(
+            crate::security::validation::ValidationConfig {
+                allowed_fhir_servers: vec![base.clone()],
+                max_resource_size: 16 * 1024,
+                ..crate::security::validation::ValidationConfig::default()
+            },
+        );
+        let evaluate = |base: &str, resource_ref: &str| {
+            evaluate_remote(
+                EvaluateRemoteParams {
+                    fhir_server_base: base.to_string(),
+                    resource_ref: resource_ref.to_string(),
+                    expression: "Patient.name.family".to_string(),
+                    timeout_ms: None,
+                    fhir_version: None,
+                },
+                &validator,
+            )
+        };
+
+        let result = evaluate(&base, "Patient/123").await.unwrap();
+        assert_eq!(result.values, vec![json!("Remote")]);
+
+        let err = evaluate(&base, "Patient/missing").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to fetch Patient/missing from {base}: server returned 404 Not Found")
+        );
+
+        // Oversized responses are refused whether or not their length is announced
+        for resource_ref in ["Patient/large", "Observation/streamed"] {
+            let err = evaluate(&base, resource_ref).await.unwrap_err();
+            let too_large = err.downcast_ref::<ResourceTooLarge>().unwrap();
+            assert_eq!(too_large.max, 16 * 1024);
+            assert!(too_large.size <= 64 * 1024 + 1024, "{resource_ref}");
+            assert_eq!(tool_error("fhirpath_evaluate_remote", err).code(), -32001);
+        }
+
+        // Servers off the allow list are never contacted
+        let err = evaluate("http://169.254.169.254/latest", "Patient/123")
+            .await
+            .unwrap_err();
+        assert!(err.is::<FhirServerNotAllowed>());
+        assert_eq!(tool_error("fhirpath_evaluate_remote", err).code(), -32602);
+    }
+
+    #[test]
+    fn test_diagnostics_sanitized_in_production() {
+        let message = "Evaluation error: cannot open /etc/octofhir/model.json";
+        let result = json!({
+            "values": ["/etc/hosts"],
+            "diagnostics": [message],
+            "errors": [{"kind": "function", "message": message}],
+        });
+
+        let mut production = result.clone();
+        sanitize_diagnostics(&mut production, true);
+        let scrubbed = "Evaluation error: cannot open <path>";
+        assert_eq!(production["diagnostics"][0], scrubbed);
+        assert_eq!(production["errors"][0]["message"], scrubbed);
+        assert_eq!(production["values"], result["values"]);
+
+        let mut development = result.clone();
+        sanitize_diagnostics(&mut development, false);
+        assert_eq!(development, result);
+
+        let error = ToolError::EvaluationFailed(message.to_string()).sanitized(true);
+        assert_eq!(error.to_string(), scrubbed);
+    }
+
+    /// Deprecation of a parameter no tool has
+    const TEST_DEPRECATION: Deprecation = Deprecation {
+        tool: "fhirpath_parse",
+        parameter: Some("legacy_option"),
+        replacement: "include_ast",
+    };
+
+    #[test]
+    fn test_deprecation_warnings() {
+        let deprecations = [
+            TEST_DEPRECATION,
+            Deprecation {
+                tool: "fhirpath_diff",
+                parameter: None,
+                replacement: "fhirpath_compare",
+            },
+        ];
+        let warning = "Parameter 'legacy_option' of fhirpath_parse is deprecated; \
+                       use include_ast instead";
+
+        let arguments = json!({"expression": "Patient.name", "legacy_option": true});
+        assert_eq!(
+            deprecation_warnings(&deprecations, "fhirpath_parse", &arguments),
+            vec![warning]
+        );
+        let arguments = json!({"expression": "Patient.name"});
+        assert!(deprecation_warnings(&deprecations, "fhirpath_parse", &arguments).is_empty());
+        assert_eq!(
+            deprecation_warnings(&deprecations, "fhirpath_diff", &arguments),
+            vec!["Tool fhirpath_diff is deprecated; use fhirpath_compare instead"]
+        );
+
+        // The tool listing points out the deprecation too
+        let mut tools = crate::server::tool_definitions().unwrap();
+        crate::server::describe_deprecations(&mut tools, &deprecations);
+        let parse = tools.iter().find(|t| t.name == "fhirpath_parse").unwrap();
+        assert!(parse.description.as_deref().unwrap().ends_with(warning));
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_error_codes() {
+        let err = call_tool("fhirpath_unknown", json!({})).await.unwrap_err();
+        assert!(matches!(err, ToolError::UnknownTool(_)));
+        assert_eq!((err.code(), err.http_status()), (-32601, 404));
+
+        let err = call_tool("fhirpath_evaluate", json!({"expression": 42}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ToolError::InvalidParams { .. }));
+        assert_eq!((err.code(), err.http_status()), (-32602, 400));
+
+        let err = call_tool(
+            "fhirpath_extract",
+            json!({"expression": "Patient.name.where(", "resource": {"resourceType": "Patient"}}),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ToolError::EvaluationFailed(_)));
+        assert_eq!((err.code(), err.http_status()), (-32000, 422));
+
+        let large = json!({"resourceType": "Patient", "text": "x".repeat(2 * 1024 * 1024)});
+        let err = call_tool(
+            "fhirpath_evaluate",
+            json!({"expression": "Patient.id", "resource": large}),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ToolError::ResourceTooLarge { .. }));
+        assert_eq!((err.code(), err.http_status()), (-32001, 413));
+
+        let mut deep = json!("leaf");
+        for _ in 0..100 {
+            deep = json!({"extension": [deep]});
+        }
+        let err = call_tool(
+            "fhirpath_evaluate",
+            json!({"expression": "Patient.id", "resource": {"resourceType": "Patient", "text": deep}}),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ToolError::InvalidParams { .. }));
+        assert!(err.to_string().contains("too deeply nested"));
+        assert_eq!((err.code(), err.http_status()), (-32602, 400));
+
+        let err = call_tool(
+            "fhirpath_evaluate",
+            json!({"expression": "Patient.id", "resource": [{"resourceType": "Patient"}]}),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ToolError::InvalidParams { .. }));
+        assert_eq!((err.code(), err.http_status()), (-32602, 400));
+
+        let err = ToolError::Timeout {
+            tool: "fhirpath_evaluate".to_string(),
+            timeout_ms: 10,
+        };
+        assert_eq!((err.code(), err.http_status()), (-32003, 504));
+        assert_eq!(err.to_string(), "evaluation timed out");
+        assert_eq!(
+            err.data(),
+            Some(json!({"tool": "fhirpath_evaluate", "timeout_ms": 10}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_timeout() {
+        let err = run_with_timeout("fhirpath_evaluate", 10, async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(())
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ToolError::Timeout { .. }));
+    }
+
+    #[test]
+    fn test_tool_error_to_mcp_error() {
+        let error: rmcp::ErrorData = ToolError::UnknownTool("nope".to_string()).into();
+        assert_eq!(error.code, rmcp::model::ErrorCode::METHOD_NOT_FOUND);
+        assert_eq!(error.message, "Unknown tool: nope");
+    }
+
+    /// A Bundle that is slow to evaluate but stays under the default resource size limit
+    fn large_bundle() -> Value {
+        let entries: Vec<Value> = (0..9_000)
+            .map(|i| {
+                json!({"resource": {
+                    "resourceType": "Patient",
+                    "id": format!("p{i}"),
+                    "name": [{"given": ["John", "Q"], "family": format!("Doe{i}")}]
+                }})
+            })
+            .collect();
+        json!({"resourceType": "Bundle", "type": "collection", "entry": entries})
+    }
+
+    const SLOW_EXPRESSION: &str =
+        "Bundle.entry.resource.name.where(given.exists() and family.startsWith('Doe')).given";
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fhirpath_evaluate_timeout() {
+        let params = EvaluateParams {
+            expression: SLOW_EXPRESSION.to_string(),
+            resource: large_bundle(),
+            timeout_ms: Some(1),
+            ..Default::default()
+        };
+
+        let err = fhirpath_evaluate(params).await.unwrap_err();
+        assert!(err.is::<EvaluationTimeout>());
+        let err = tool_error("fhirpath_evaluate", err);
+        assert_eq!(err.code(), -32003);
+        assert_eq!(err.to_string(), "evaluation timed out");
+    }
+
+    #[tokio::test]
+    async fn test_fhirpath_evaluate_truncates_large_results() {
+        let params = |max_values| EvaluateParams {
+            expression: "Bundle.entry.resource.name.given".to_string(),
+            resource: large_bundle(),
+            timeout_ms: Some(30_000),
+            max_values,
+            ..Default::default()
+        };
+
+        let result = fhirpath_evaluate(params(Some(5))).await.unwrap();
+        assert_eq!(result.values.len(), 5);
+        assert_eq!(result.types.len(), 5);
+        assert!(result.truncated);
+        assert_eq!(result.total_count, Some(18_000));
+
+        // A request can't raise the limit past the server's
+        let result = fhirpath_evaluate(params(Some(50_000))).await.unwrap();
+        assert_eq!(
+            result.values.len(),
+            crate::fhirpath_engine::DEFAULT_MAX_RESULT_VALUES
+        );
+        assert!(result.truncated);
+        assert_eq!(result.total_count, Some(18_000));
+
+        let mut small = params(None);
+        small.expression = "Bundle.entry.first().resource.id".to_string();
+        let result = fhirpath_evaluate(small).await.unwrap();
+        assert_eq!(result.values, vec![json!("p0")]);
+        assert!(!result.truncated);
+        assert_eq!(result.total_count, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fhirpath_explain_timeout() {
+        let params = ExplainParams {
+            expression: SLOW_EXPRESSION.to_string(),
+            resource: large_bundle(),
+        };
+
+        let err = explain(params, 1).await.unwrap_err();
+        assert!(err.is::<EvaluationTimeout>());
+        assert!(matches!(
+            tool_error("fhirpath_explain", err),
+            ToolError::Timeout { timeout_ms: 1, .. }
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fhirpath_extract_timeout() {
+        let params = ExtractParams {
+            expression: SLOW_EXPRESSION.to_string(),
+            resource: large_bundle(),
+            timeout_ms: Some(1),
+            ..Default::default()
+        };
+
+        let err = fhirpath_extract(params).await.unwrap_err();
+        assert!(err.is::<EvaluationTimeout>());
+        assert!(matches!(
+            tool_error("fhirpath_extract", err),
+            ToolError::Timeout { timeout_ms: 1, .. }
+        ));
+    }
 }
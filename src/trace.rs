@@ -0,0 +1,128 @@
+//! Collection of `trace()` output
+//!
+//! The engine's own `trace()` prints to stdout, which the stdio transport reserves for
+//! protocol messages. Engines built by [`FhirPathEngineFactory`](crate::FhirPathEngineFactory)
+//! use [`TraceOperation`] instead: it records traced values for an evaluation run inside
+//! [`TraceCollector::scope`], and otherwise only logs them.
+
+use async_trait::async_trait;
+use octofhir_fhirpath::registry::operations::EvaluationContext;
+use octofhir_fhirpath::registry::{
+    FhirPathOperation, MetadataBuilder, OperationMetadata, OperationType, TypeConstraint,
+};
+use octofhir_fhirpath::{FhirPathError, FhirPathValue};
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+tokio::task_local! {
+    /// Collector of the evaluation being traced
+    static TRACES: TraceCollector;
+}
+
+/// Values passed to `trace()` during an evaluation, as `(name, value)` in call order
+#[derive(Debug, Clone, Default)]
+pub struct TraceCollector(Arc<Mutex<Vec<(String, FhirPathValue)>>>);
+
+impl TraceCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collector of the evaluation the current task is part of, if it is traced
+    pub fn current() -> Option<Self> {
+        TRACES.try_with(Clone::clone).ok()
+    }
+
+    /// Run `future`, recording the `trace()` calls of its evaluations in this collector
+    pub async fn scope<F: std::future::Future>(self, future: F) -> F::Output {
+        TRACES.scope(self, future).await
+    }
+
+    /// Remove and return the traces recorded so far
+    pub fn take(&self) -> Vec<(String, FhirPathValue)> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+
+    fn record(&self, name: String, value: FhirPathValue) {
+        self.0.lock().unwrap().push((name, value));
+    }
+}
+
+/// `trace(name [, projection])` recording into the current [`TraceCollector`]
+pub(crate) struct TraceOperation {
+    metadata: OperationMetadata,
+}
+
+impl TraceOperation {
+    pub(crate) fn new() -> Self {
+        Self {
+            metadata: MetadataBuilder::new("trace", OperationType::Function)
+                .description(
+                    "Records the input, or the projection of it, under the given name and \
+                     returns the input unchanged",
+                )
+                .example("Patient.name.trace('names').family")
+                .returns(TypeConstraint::Any)
+                .build(),
+        }
+    }
+}
+
+#[async_trait]
+impl FhirPathOperation for TraceOperation {
+    fn identifier(&self) -> &str {
+        "trace"
+    }
+
+    fn operation_type(&self) -> OperationType {
+        OperationType::Function
+    }
+
+    fn metadata(&self) -> &OperationMetadata {
+        &self.metadata
+    }
+
+    async fn evaluate(
+        &self,
+        args: &[FhirPathValue],
+        context: &EvaluationContext,
+    ) -> octofhir_fhirpath::Result<FhirPathValue> {
+        if args.len() > 2 {
+            return Err(FhirPathError::InvalidArgumentCount {
+                function_name: "trace".to_string(),
+                expected: 2,
+                actual: args.len(),
+            });
+        }
+
+        let name = match args.first() {
+            None => "trace".to_string(),
+            Some(FhirPathValue::String(name)) => name.to_string(),
+            Some(FhirPathValue::Collection(items)) if items.len() == 1 => match items.get(0) {
+                Some(FhirPathValue::String(name)) => name.to_string(),
+                _ => {
+                    return Err(FhirPathError::evaluation_error(
+                        "trace() name argument must be a string",
+                    ));
+                }
+            },
+            Some(_) => {
+                return Err(FhirPathError::evaluation_error(
+                    "trace() name argument must be a string",
+                ));
+            }
+        };
+        // The projection argument is already evaluated against the input
+        let value = args.get(1).unwrap_or(&context.input).clone();
+
+        match TraceCollector::current() {
+            Some(collector) => collector.record(name, value),
+            None => debug!("trace({}): {}", name, value),
+        }
+        Ok(context.input.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
@@ -0,0 +1,221 @@
+//! JSON-RPC 2.0 batch requests
+//!
+//! rmcp's streamable HTTP service refuses batches and its stdio service answers each member
+//! on its own, so the transports split batches themselves, dispatch the members concurrently
+//! and answer with one array of responses. Notifications get no entry; a batch holding only
+//! notifications gets no response at all, and an empty batch is an invalid request.
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{HeaderValue, Request, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use futures_util::{StreamExt, future::join_all};
+use rmcp::model::{ClientJsonRpcMessage, ClientRequest, JsonRpcMessage, RequestId};
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use tower::ServiceExt;
+
+/// JSON-RPC error code for a message that isn't a valid request
+pub const INVALID_REQUEST: i32 = -32600;
+/// JSON-RPC error code for a failure inside the server
+pub const INTERNAL_ERROR: i32 = -32603;
+
+/// JSON-RPC error response
+pub fn error_response(id: Value, code: i32, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message},
+    })
+}
+
+/// Response to an empty batch, or to a batch member that isn't a JSON-RPC message
+pub fn invalid_request() -> Value {
+    error_response(Value::Null, INVALID_REQUEST, "Invalid Request")
+}
+
+/// One message of a batch
+pub enum Member {
+    /// A request, answered by a response with the same id
+    Request {
+        id: RequestId,
+        message: ClientJsonRpcMessage,
+    },
+    /// A notification or a response to a server request, which nothing answers
+    Notification(ClientJsonRpcMessage),
+    /// Answered with this error instead of being dispatched
+    Rejected(Value),
+}
+
+impl Member {
+    pub fn parse(member: Value) -> Self {
+        let id = member.get("id").cloned().unwrap_or(Value::Null);
+        match serde_json::from_value::<ClientJsonRpcMessage>(member) {
+            // The MCP lifecycle forbids batching initialization, which would also start a
+            // session the client never hears about
+            Ok(JsonRpcMessage::Request(request))
+                if matches!(request.request, ClientRequest::InitializeRequest(_)) =>
+            {
+                Self::Rejected(error_response(
+                    id,
+                    INVALID_REQUEST,
+                    "initialize must not be part of a batch",
+                ))
+            }
+            Ok(JsonRpcMessage::Request(request)) => Self::Request {
+                id: request.id.clone(),
+                message: JsonRpcMessage::Request(request),
+            },
+            Ok(
+                message @ (JsonRpcMessage::Notification(_)
+                | JsonRpcMessage::Response(_)
+                | JsonRpcMessage::Error(_)),
+            ) => Self::Notification(message),
+            // Nested batches and malformed messages
+            Ok(_) | Err(_) => Self::Rejected(invalid_request()),
+        }
+    }
+}
+
+/// Answer a batch POSTed to the MCP endpoint by passing each member to `service` as a
+/// request of its own, with the batch's headers and extensions
+pub async fn handle_http_batch<S, B>(service: S, parts: Parts, members: Vec<Value>) -> Response
+where
+    S: tower::Service<Request<Body>, Response = axum::http::Response<B>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<axum::BoxError>,
+{
+    if members.is_empty() {
+        return (StatusCode::BAD_REQUEST, axum::Json(invalid_request())).into_response();
+    }
+
+    let responses = join_all(members.into_iter().map(|member| {
+        let service = service.clone();
+        let parts = parts.clone();
+        async move {
+            // The member is forwarded as sent rather than re-serialized from its parsed form
+            let id = match Member::parse(member.clone()) {
+                Member::Request { .. } => Some(member["id"].clone()),
+                Member::Notification(_) => None,
+                Member::Rejected(error) => return Some(error),
+            };
+
+            let mut request = Request::from_parts(parts, Body::from(member.to_string()));
+            request.headers_mut().remove(header::CONTENT_LENGTH);
+            let Ok(response) = service.oneshot(request).await;
+            match id {
+                Some(id) => Some(member_response(id, response).await),
+                None => None,
+            }
+        }
+    }))
+    .await;
+
+    let responses: Vec<Value> = responses.into_iter().flatten().collect();
+    if responses.is_empty() {
+        return StatusCode::ACCEPTED.into_response();
+    }
+    let mut response = axum::Json(responses).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+/// The JSON-RPC response carried by the MCP service's answer to one batch member
+async fn member_response<B>(id: Value, response: axum::http::Response<B>) -> Value
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<axum::BoxError>,
+{
+    let status = response.status();
+    let mut body = Body::new(response.into_body()).into_data_stream();
+
+    if !status.is_success() {
+        let mut message = Vec::new();
+        while let Some(Ok(chunk)) = body.next().await {
+            message.extend_from_slice(&chunk);
+        }
+        let code = if status.is_client_error() {
+            INVALID_REQUEST
+        } else {
+            INTERNAL_ERROR
+        };
+        return error_response(id, code, String::from_utf8_lossy(&message).trim());
+    }
+
+    // Requests are answered over SSE, possibly after progress or log notifications; the
+    // stream may stay open after the response, so stop reading at the response
+    let mut buffer = Vec::new();
+    while let Some(Ok(chunk)) = body.next().await {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+            let event: Vec<u8> = buffer.drain(..end + 2).collect();
+            let text = String::from_utf8_lossy(&event).into_owned();
+            let data = text
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if let Ok(message) = serde_json::from_str::<Value>(&data)
+                && message.get("id") == Some(&id)
+                && (message.get("result").is_some() || message.get("error").is_some())
+            {
+                return message;
+            }
+        }
+    }
+
+    error_response(id, INTERNAL_ERROR, "No response to the request")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_members() {
+        let request = json!({"jsonrpc": "2.0", "id": 7, "method": "tools/list"});
+        assert!(matches!(
+            Member::parse(request),
+            Member::Request {
+                id: RequestId::Number(7),
+                ..
+            }
+        ));
+
+        let notification = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        assert!(matches!(
+            Member::parse(notification),
+            Member::Notification(_)
+        ));
+
+        let Member::Rejected(error) = Member::parse(json!(5)) else {
+            panic!("a number is not a request");
+        };
+        assert_eq!(error, invalid_request());
+
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": "init",
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": {"name": "test-client", "version": "1.0"}
+            }
+        });
+        let Member::Rejected(error) = Member::parse(initialize) else {
+            panic!("initialize can't be batched");
+        };
+        assert_eq!(error["id"], "init");
+        assert_eq!(error["error"]["code"], INVALID_REQUEST);
+    }
+}
@@ -0,0 +1,3533 @@
+//! HTTP transport using the MCP streamable HTTP protocol
+
+use anyhow::Result;
+use axum::{
+    Json, Router,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, DefaultBodyLimit, FromRequest, Path, Query, Request, State},
+    http::{Extensions, HeaderMap, HeaderValue, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, post},
+    serve::ListenerExt,
+};
+use futures_util::StreamExt;
+use rmcp::transport::streamable_http_server::{StreamableHttpServerConfig, StreamableHttpService};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tower::ServiceExt;
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowHeaders, AllowOrigin, CorsLayer},
+    decompression::RequestDecompressionLayer,
+    limit::RequestBodyLimitLayer,
+};
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use crate::config::{CorsConfig, ServerConfig};
+use crate::fhirpath_engine::FhirEngineConfig;
+use crate::metrics::{HealthCheck, MetricsFormat, MetricsProvider, MonitoringConfig};
+use crate::security::{
+    AuditLog, AuditRecord, AuthError, AuthFailureReason, AuthenticatedRequest, RateLimiter,
+    RequestSanitizer, SecurityConfig, SecurityProvider,
+};
+use crate::server::{FhirPathToolServer, ServerLimits};
+use crate::tools::{
+    EvaluateParams, ExtractParams, ToolError, authorize_tool, call_tool, check_expression,
+    check_extract_params, current_correlation_id, fhirpath_evaluate, fhirpath_extract_stream,
+    resource_type, with_correlation_id,
+};
+use crate::transport::batch::handle_http_batch;
+use crate::transport::idempotency::{
+    self, CachedResponse, DEFAULT_IDEMPOTENCY_TTL, IDEMPOTENCY_KEY_HEADER,
+    IDEMPOTENCY_PRUNE_INTERVAL, IDEMPOTENT_REPLAY_HEADER, IdempotencyCache,
+    MAX_IDEMPOTENCY_KEY_LENGTH, Reservation,
+};
+use crate::transport::session::{DEFAULT_SSE_REPLAY_BUFFER, ResumableSessionManager};
+use crate::transport::tls::{TlsListener, load_server_config};
+use crate::transport::versioning::{
+    ACCEPT_VERSION_HEADER, SUPPORTED_API_VERSIONS, negotiate_api_version,
+};
+
+/// Header carrying the request correlation ID
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// Header repeating each warning of a tool result, such as the use of a deprecated parameter
+pub const WARNING_HEADER: &str = "x-mcp-warning";
+
+/// How often idle rate limit buckets are pruned
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Error codes of [`ToolError`] audited for failed bulk evaluation lines
+const INVALID_PARAMS: i32 = -32602;
+const EVALUATION_FAILED: i32 = -32000;
+const RESOURCE_TOO_LARGE: i32 = -32001;
+
+/// Values per `data` event of a streamed extraction, unless the request sets `chunk_size`
+const DEFAULT_STREAM_CHUNK_SIZE: usize = 100;
+
+/// Chunks of a streamed extraction buffered ahead of the client
+const STREAM_BUFFER_CHUNKS: usize = 2;
+
+/// Routes served without authentication
+const PUBLIC_PATHS: &[&str] = &["/health", "/ready", "/version"];
+
+/// Readiness check that stays unhealthy while startup warm-up runs
+const WARMUP_CHECK: &str = "warmup";
+
+/// Route that starts a graceful shutdown; it checks the admin token itself
+const ADMIN_SHUTDOWN_PATH: &str = "/admin/shutdown";
+
+/// Route that zeroes request metrics; it answers 404 unless admin endpoints are enabled and
+/// checks the admin token itself
+const ADMIN_METRICS_RESET_PATH: &str = "/admin/metrics/reset";
+
+/// Route listing per-expression evaluation statistics; it answers 404 unless admin endpoints
+/// are enabled and checks the admin token itself
+const ADMIN_PROFILE_PATH: &str = "/admin/profile/expressions";
+
+/// Allowance on top of `max_resource_size` for the expression and other arguments of a request
+const REQUEST_BODY_OVERHEAD: usize = 64 * 1024;
+
+/// Media type of bulk evaluation request and response bodies
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Default interval between keep-alive comments on SSE streams
+pub const DEFAULT_SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// How long clients turned away under memory pressure are asked to wait before retrying
+const MEMORY_PRESSURE_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// State shared by the HTTP handlers and middleware
+#[derive(Clone)]
+struct HttpState {
+    security: Arc<SecurityProvider>,
+    rate_limiter: Option<RateLimiter>,
+    metrics: Arc<MetricsProvider>,
+    idempotency: Option<IdempotencyCache>,
+    fhir_version: Arc<str>,
+    shutdown: Arc<watch::Sender<bool>>,
+    sse_keep_alive: Duration,
+    admin_endpoints: bool,
+    audit_log: Option<AuditLog>,
+}
+
+/// HTTP transport server using MCP streamable HTTP protocol
+pub struct HttpTransportServer {
+    pub host: String,
+    pub port: u16,
+    security: SecurityConfig,
+    monitoring: MonitoringConfig,
+    compression: bool,
+    cors: Option<CorsConfig>,
+    idempotency_ttl: Duration,
+    sse_replay_buffer: usize,
+    sse_keep_alive: Duration,
+    sse_session_timeout: Option<Duration>,
+    engine: FhirEngineConfig,
+    warmup_expressions: Vec<String>,
+    admin_endpoints: bool,
+    audit_log: Option<AuditLog>,
+    /// Certificate chain and private key PEM files, serving HTTPS when set
+    tls: Option<(PathBuf, PathBuf)>,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl HttpTransportServer {
+    /// Create a new HTTP transport server. Authentication stays disabled until a security
+    /// configuration is supplied with [`with_security`](Self::with_security).
+    pub fn new(host: String, port: u16) -> Self {
+        Self {
+            host,
+            port,
+            security: SecurityConfig {
+                enable_auth: false,
+                ..SecurityConfig::default()
+            },
+            monitoring: MonitoringConfig::default(),
+            compression: true,
+            cors: None,
+            idempotency_ttl: DEFAULT_IDEMPOTENCY_TTL,
+            sse_replay_buffer: DEFAULT_SSE_REPLAY_BUFFER,
+            sse_keep_alive: DEFAULT_SSE_KEEP_ALIVE,
+            sse_session_timeout: None,
+            engine: FhirEngineConfig::default(),
+            warmup_expressions: Vec::new(),
+            admin_endpoints: false,
+            audit_log: None,
+            tls: None,
+            shutdown: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Apply the HTTP settings of a server configuration
+    pub fn with_config(mut self, config: &ServerConfig) -> Result<Self> {
+        self = self
+            .with_compression(config.http_compression)
+            .with_idempotency_ttl(Duration::from_secs(config.idempotency_ttl_seconds))
+            .with_sse_replay_buffer(config.sse_replay_buffer)
+            .with_sse_keep_alive(Duration::from_secs(config.sse_keep_alive_seconds))?
+            .with_sse_session_timeout(
+                config.sse_session_timeout_seconds.map(Duration::from_secs),
+            )?;
+        self.engine = config.engine_config(self.engine);
+        self.monitoring = config.monitoring_config(self.monitoring);
+        self.warmup_expressions = config.warmup_expressions.clone();
+        self.admin_endpoints = config.enable_admin_endpoints;
+        self.security = config.security_config(self.security);
+        if let Some(sink) = &config.audit_log {
+            self = self.with_audit_log(AuditLog::open(sink)?);
+        }
+        if let Some(cors) = &config.cors {
+            self = self.with_cors(cors.clone())?;
+        }
+        if let (Some(cert_path), Some(key_path)) = (&config.tls_cert_path, &config.tls_key_path) {
+            self = self.with_tls(cert_path, key_path);
+        }
+        Ok(self)
+    }
+
+    /// Use the given security configuration for authentication and rate limiting
+    pub fn with_security(mut self, security: SecurityConfig) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Monitor health and shed load according to `monitoring`: tool calls are answered with
+    /// 503 while memory usage is above its critical threshold
+    pub fn with_monitoring(mut self, monitoring: MonitoringConfig) -> Self {
+        self.monitoring = monitoring;
+        self
+    }
+
+    /// Enable or disable gzip/deflate compression of responses and request bodies
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Keep responses to tool calls sent with an `Idempotency-Key` header for `ttl`, replaying
+    /// them for retries with the same key. A zero TTL ignores idempotency keys.
+    pub fn with_idempotency_ttl(mut self, ttl: Duration) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    /// Buffer up to `messages` recent messages per MCP SSE stream, replayed to clients that
+    /// reconnect with a `Last-Event-ID` header. Clients that fell further behind are told
+    /// how many messages they missed by a `lagged` notification.
+    pub fn with_sse_replay_buffer(mut self, messages: usize) -> Self {
+        self.sse_replay_buffer = messages;
+        self
+    }
+
+    /// Send a keep-alive comment on idle SSE streams every `interval`, so proxies with short
+    /// idle timeouts don't close them. Fails if the interval is zero.
+    pub fn with_sse_keep_alive(mut self, interval: Duration) -> Result<Self> {
+        if interval.is_zero() {
+            return Err(anyhow::anyhow!("SSE keep-alive interval must be positive"));
+        }
+        self.sse_keep_alive = interval;
+        Ok(self)
+    }
+
+    /// Close MCP sessions that see no activity for `timeout`; sessions live until the client
+    /// closes them when `None`. Fails if the timeout is zero.
+    pub fn with_sse_session_timeout(mut self, timeout: Option<Duration>) -> Result<Self> {
+        if timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err(anyhow::anyhow!("SSE session timeout must be positive"));
+        }
+        self.sse_session_timeout = timeout;
+        Ok(self)
+    }
+
+    /// Answer cross-origin requests according to `cors`. Fails if the configuration is
+    /// invalid, e.g. credentials combined with a wildcard origin.
+    pub fn with_cors(mut self, cors: CorsConfig) -> Result<Self> {
+        cors.validate()?;
+        self.cors = Some(cors);
+        Ok(self)
+    }
+
+    /// Evaluate `expressions` in the background when the server starts, reporting not-ready on
+    /// `/ready` until they have run
+    pub fn with_warmup_expressions(mut self, expressions: Vec<String>) -> Self {
+        self.warmup_expressions = expressions;
+        self
+    }
+
+    /// Serve administrative endpoints such as `POST /admin/metrics/reset`. They accept only
+    /// the admin token, so they stay unusable without one.
+    pub fn with_admin_endpoints(mut self, enabled: bool) -> Self {
+        self.admin_endpoints = enabled;
+        self
+    }
+
+    /// Record every tool call, over MCP and REST, to `audit_log`
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Serve HTTPS using the PEM certificate chain at `cert_path` and private key at
+    /// `key_path`. The files are read when the server starts.
+    pub fn with_tls(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        self.tls = Some((cert_path.into(), key_path.into()));
+        self
+    }
+
+    /// Receiver that observes `true` once a graceful shutdown has been requested
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Stop accepting connections and let in-flight requests finish, as `POST /admin/shutdown`
+    /// does
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Build the Axum router serving MCP requests, the REST tool endpoint, and the health,
+    /// readiness, version, stats, OpenAPI and admin endpoints
+    pub fn create_router(&self) -> Router {
+        self.build_router(self.create_state())
+    }
+
+    fn create_state(&self) -> HttpState {
+        let rate_limiter = (self.security.rate_limit_per_minute > 0)
+            .then(|| RateLimiter::new(self.security.rate_limit_per_minute));
+
+        HttpState {
+            security: Arc::new(SecurityProvider::new(self.security.clone())),
+            rate_limiter,
+            metrics: Arc::new(MetricsProvider::new(
+                self.monitoring.clone(),
+                crate::VERSION.to_string(),
+            )),
+            idempotency: (!self.idempotency_ttl.is_zero())
+                .then(|| IdempotencyCache::new(self.idempotency_ttl)),
+            fhir_version: self.engine.fhir_version.as_str().into(),
+            shutdown: self.shutdown.clone(),
+            sse_keep_alive: self.sse_keep_alive,
+            admin_endpoints: self.admin_endpoints,
+            audit_log: self.audit_log.clone(),
+        }
+    }
+
+    fn session_manager(&self) -> ResumableSessionManager {
+        ResumableSessionManager::new(self.sse_replay_buffer)
+            .with_session_timeout(self.sse_session_timeout)
+    }
+
+    fn streamable_http_config(&self) -> StreamableHttpServerConfig {
+        StreamableHttpServerConfig {
+            sse_keep_alive: Some(self.sse_keep_alive),
+            ..StreamableHttpServerConfig::default()
+        }
+    }
+
+    /// Largest request body accepted outside bulk evaluation
+    fn request_body_limit(&self) -> usize {
+        self.security
+            .max_resource_size
+            .saturating_add(REQUEST_BODY_OVERHEAD)
+    }
+
+    fn build_router(&self, state: HttpState) -> Router {
+        // Create the streamable HTTP service with resumable local sessions
+        let limits = ServerLimits::new(&self.security, &self.engine.fhir_version);
+        let audit_log = self.audit_log.clone();
+        let service = StreamableHttpService::new(
+            move || {
+                let server = FhirPathToolServer::new().with_limits(limits.clone());
+                Ok(match &audit_log {
+                    Some(audit_log) => server.with_audit_log(audit_log.clone()),
+                    None => server,
+                })
+            },
+            Arc::new(self.session_manager()),
+            self.streamable_http_config(),
+        );
+
+        let mut router = Router::new()
+            .route("/health", get(health))
+            .route("/ready", get(ready))
+            .route("/version", get(version))
+            .route("/stats", get(stats))
+            .route("/metrics", get(handle_metrics))
+            .route("/openapi.json", get(openapi))
+            .route("/mcp/tools/{tool_name}", post(handle_tool_call))
+            .route("/mcp/tools/{tool_name}/schema", get(tool_schema))
+            .route(
+                "/mcp/tools/fhirpath_extract/stream",
+                post(handle_extract_stream),
+            )
+            .route(ADMIN_SHUTDOWN_PATH, post(admin_shutdown))
+            .route(ADMIN_METRICS_RESET_PATH, post(admin_metrics_reset))
+            .route(ADMIN_PROFILE_PATH, get(admin_expression_profile))
+            .fallback(move |request: Request| handle_mcp(service.clone(), request))
+            // Rejects oversized bodies with 413 before they are buffered; replaces axum's
+            // default extractor limit so the configured size applies
+            .layer(RequestBodyLimitLayer::new(self.request_body_limit()))
+            .layer(DefaultBodyLimit::disable())
+            // Bulk evaluation frames its body line by line and bounds each line itself
+            .route("/mcp/bulk/evaluate", post(handle_bulk_evaluate));
+
+        if self.compression {
+            // The default compression predicate skips `text/event-stream`, so SSE responses
+            // are streamed rather than buffered by the encoder
+            router = router
+                .layer(CompressionLayer::new().gzip(true).deflate(true))
+                .layer(RequestDecompressionLayer::new().gzip(true).deflate(true));
+        }
+
+        router = router
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit_middleware,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                memory_admission_middleware,
+            ));
+
+        // Outside authentication so preflight requests, which carry no credentials, succeed
+        if let Some(cors) = &self.cors {
+            router = router.layer(cors_layer(cors));
+        }
+
+        router
+            .layer(middleware::from_fn(correlation_id_middleware))
+            .with_state(state)
+    }
+
+    /// Mark the server not-ready and warm up the shared engine in the background. Failing
+    /// expressions are logged by the warm-up and don't keep the server from becoming ready.
+    async fn start_warm_up(&self, state: &HttpState) -> Option<tokio::task::JoinHandle<()>> {
+        if self.warmup_expressions.is_empty() {
+            return None;
+        }
+
+        state
+            .metrics
+            .update_readiness_check(WARMUP_CHECK, HealthCheck::unhealthy("Warming up"))
+            .await;
+
+        let expressions = self.warmup_expressions.clone();
+        let metrics = state.metrics.clone();
+        Some(tokio::spawn(async move {
+            let start = Instant::now();
+            let message = match crate::fhirpath_engine::get_shared_engine().await {
+                Ok(engine) => {
+                    let warmed = engine.warm_up(&expressions).await;
+                    format!("Warmed up {}/{} expressions", warmed, expressions.len())
+                }
+                Err(e) => {
+                    warn!("Skipping warm-up: {}", e);
+                    "Warm-up skipped".to_string()
+                }
+            };
+            metrics
+                .update_readiness_check(
+                    WARMUP_CHECK,
+                    HealthCheck::healthy(message).with_duration(start.elapsed()),
+                )
+                .await;
+        }))
+    }
+
+    /// Start the HTTP server with MCP streamable HTTP protocol support
+    pub async fn start(&self) -> Result<()> {
+        info!(
+            "Starting MCP HTTP streamable transport server on {}:{}",
+            self.host, self.port
+        );
+
+        // Read the certificate first so a bad one fails startup before anything else runs
+        let tls = match &self.tls {
+            Some((cert_path, key_path)) => Some(load_server_config(cert_path, key_path)?),
+            None => None,
+        };
+
+        // Initialize the shared FHIRPath engine (ignore if already initialized)
+        if let Err(e) =
+            crate::fhirpath_engine::initialize_shared_engine_with_config(self.engine.clone()).await
+        {
+            if !e.to_string().contains("already initialized") {
+                return Err(e);
+            }
+            debug!("FHIRPath engine already initialized");
+        }
+
+        // Tool functions enforce resource limits through the shared security provider
+        if let Err(e) = crate::security::initialize_shared_security(self.security.clone()) {
+            debug!("{}", e);
+        }
+
+        let state = self.create_state();
+        self.start_warm_up(&state).await;
+        if let Some(limiter) = state.rate_limiter.clone() {
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(RATE_LIMIT_PRUNE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    limiter.prune(RATE_LIMIT_PRUNE_INTERVAL).await;
+                }
+            });
+        }
+        if let Some(idempotency) = state.idempotency.clone() {
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(IDEMPOTENCY_PRUNE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    idempotency.prune();
+                }
+            });
+        }
+        let metrics = state.metrics.clone();
+        metrics.start_periodic_persistence().await?;
+        metrics.start_memory_sampling();
+        let router = self.build_router(state);
+
+        let bind_address: SocketAddr = format!("{}:{}", self.host, self.port).parse()?;
+        let listener = tokio::net::TcpListener::bind(bind_address).await?;
+
+        let mut shutdown = self.shutdown_signal();
+        let shutdown_requested = async move {
+            // A dropped sender can't request a shutdown any more, so keep serving
+            if shutdown.wait_for(|requested| *requested).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        };
+        let service = router.into_make_service_with_connect_info::<SocketAddr>();
+        match tls {
+            Some(config) => {
+                info!(
+                    "MCP HTTP streamable server listening on https://{}",
+                    bind_address
+                );
+                // Tapping the listener gives its connections the client address as connect info
+                let listener = TlsListener::new(listener, config)?.tap_io(|_| {});
+                axum::serve(listener, service)
+                    .with_graceful_shutdown(shutdown_requested)
+                    .await?;
+            }
+            None => {
+                info!(
+                    "MCP HTTP streamable server listening on http://{}",
+                    bind_address
+                );
+                axum::serve(listener, service)
+                    .with_graceful_shutdown(shutdown_requested)
+                    .await?;
+            }
+        }
+
+        if let Some(audit_log) = self.audit_log.clone() {
+            tokio::task::spawn_blocking(move || audit_log.flush()).await?;
+        }
+        // Keep the requests served since the last periodic write
+        if let Err(e) = metrics.persist_snapshot().await {
+            warn!("Failed to persist metrics on shutdown: {:#}", e);
+        }
+        info!("MCP HTTP streamable server stopped");
+        Ok(())
+    }
+}
+
+/// Build the CORS layer for a validated configuration
+fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .filter_map(|origin| origin.parse().ok()),
+        )
+    };
+
+    let headers = if !config.allowed_headers.is_empty() {
+        AllowHeaders::list(
+            config
+                .allowed_headers
+                .iter()
+                .filter_map(|name| name.parse().ok()),
+        )
+    } else if config.allow_credentials {
+        // Browsers reject a wildcard together with credentials, so echo what was requested
+        AllowHeaders::mirror_request()
+    } else {
+        AllowHeaders::any()
+    };
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([
+            axum::http::Method::GET,
+            axum::http::Method::POST,
+            axum::http::Method::DELETE,
+            axum::http::Method::OPTIONS,
+        ])
+        .allow_headers(headers)
+        .expose_headers(
+            config
+                .exposed_headers
+                .iter()
+                .filter_map(|name| name.parse().ok())
+                .collect::<Vec<header::HeaderName>>(),
+        )
+        .allow_credentials(config.allow_credentials)
+}
+
+/// Liveness probe: answers whenever the runtime can still schedule requests. Engine and
+/// warm-up state are left to `/ready`, so a slow start never gets the process restarted.
+async fn health() -> Json<Value> {
+    Json(json!({
+        "status": "ok",
+        "version": crate::VERSION,
+    }))
+}
+
+/// Readiness probe: 503 until the engine is up and startup warm-up and any other readiness
+/// checks pass
+async fn ready(State(state): State<HttpState>) -> Response {
+    let readiness = state.metrics.get_readiness_status().await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
+
+/// Build information and the FHIR version served, for confirming which build is running
+async fn version(State(state): State<HttpState>) -> Json<Value> {
+    let build_timestamp = env!("OCTOFHIR_BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|timestamp| timestamp.to_rfc3339());
+
+    Json(json!({
+        "version": crate::VERSION,
+        "git_commit": env!("OCTOFHIR_GIT_COMMIT"),
+        "build_timestamp": build_timestamp,
+        "rustc_version": env!("OCTOFHIR_RUSTC_VERSION"),
+        "fhirpath_version": crate::fhirpath_engine::FHIRPATH_VERSION,
+        "fhir_version": &*state.fhir_version,
+        "api_versions": SUPPORTED_API_VERSIONS,
+    }))
+}
+
+/// Publish the shared engine's current cache counters, if it has started
+async fn refresh_cache_metrics(metrics: &MetricsProvider) {
+    if let Some(engine) = crate::fhirpath_engine::shared_engine_if_initialized() {
+        metrics
+            .record_cache_stats(&engine.expression_cache().stats())
+            .await;
+        metrics
+            .record_result_cache_stats(&engine.result_cache().stats())
+            .await;
+    }
+}
+
+/// Global and per-tool request metrics
+async fn stats(State(state): State<HttpState>, Query(format): Query<FormatQuery>) -> Response {
+    refresh_cache_metrics(&state.metrics).await;
+    json_response(
+        StatusCode::OK,
+        &state.metrics.get_metrics_snapshot().await,
+        format.pretty,
+    )
+}
+
+/// Metrics for scraping, in the OpenMetrics format when the `Accept` header asks for it and
+/// the Prometheus text format otherwise
+async fn handle_metrics(State(state): State<HttpState>, headers: HeaderMap) -> Response {
+    let format = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(MetricsFormat::from_accept)
+        .unwrap_or_default();
+    refresh_cache_metrics(&state.metrics).await;
+    let metrics = state.metrics.get_text_metrics(format).await;
+    ([(header::CONTENT_TYPE, metrics.content_type)], metrics.data).into_response()
+}
+
+/// Response formatting requested through the query string
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    /// Indent the JSON body for reading, e.g. when debugging with curl
+    #[serde(default)]
+    pretty: bool,
+}
+
+/// JSON response serialized compactly, or indented when `pretty` is set
+fn json_response(status: StatusCode, body: &impl serde::Serialize, pretty: bool) -> Response {
+    if !pretty {
+        return (status, Json(body)).into_response();
+    }
+    match serde_json::to_string_pretty(body) {
+        Ok(body) => (status, [(header::CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// Require the admin token as the bearer credential of an administrative request. Other
+/// credentials get 403 even when they authenticate every other route.
+async fn require_admin_token(
+    state: &HttpState,
+    headers: &HeaderMap,
+    path: &str,
+) -> Result<(), Response> {
+    let token = match headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(auth_header) => auth_header.strip_prefix("Bearer ").ok_or(AuthError::new(
+            AuthFailureReason::MalformedHeader,
+            "Invalid authorization header format",
+        )),
+        None => Err(AuthError::new(
+            AuthFailureReason::MissingHeader,
+            "Missing authorization header",
+        )),
+    };
+
+    let token = match token {
+        Ok(token) => token,
+        Err(e) => {
+            warn!(reason = %e.reason, path, "Authentication failed: {}", e);
+            state.metrics.record_auth_failure(e.reason).await;
+            return Err(unauthorized());
+        }
+    };
+
+    if !state.security.authenticator().is_admin_token(token) {
+        let reason = AuthFailureReason::OutOfScope;
+        warn!(reason = %reason, path, "Admin endpoint requested without the admin token");
+        state.metrics.record_auth_failure(reason).await;
+        return Err((StatusCode::FORBIDDEN, Json(json!({ "error": "Forbidden" }))).into_response());
+    }
+
+    Ok(())
+}
+
+/// Begin a graceful shutdown. Only the admin token is accepted.
+async fn admin_shutdown(State(state): State<HttpState>, headers: HeaderMap) -> Response {
+    if let Err(response) = require_admin_token(&state, &headers, ADMIN_SHUTDOWN_PATH).await {
+        return response;
+    }
+
+    info!(
+        "Graceful shutdown requested through {}",
+        ADMIN_SHUTDOWN_PATH
+    );
+    state.shutdown.send_replace(true);
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "status": "shutting_down" })),
+    )
+        .into_response()
+}
+
+/// Zero request, per-tool and custom metrics while keeping uptime. Only the admin token is
+/// accepted.
+async fn admin_metrics_reset(State(state): State<HttpState>, headers: HeaderMap) -> Response {
+    if !state.admin_endpoints {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Err(response) = require_admin_token(&state, &headers, ADMIN_METRICS_RESET_PATH).await {
+        return response;
+    }
+
+    info!("Metrics reset through {}", ADMIN_METRICS_RESET_PATH);
+    state.metrics.reset().await;
+    (StatusCode::OK, Json(json!({ "status": "reset" }))).into_response()
+}
+
+/// Call count, latency and error rate of each recently evaluated expression, the most total
+/// time first. Only the admin token is accepted.
+async fn admin_expression_profile(State(state): State<HttpState>, headers: HeaderMap) -> Response {
+    if !state.admin_endpoints {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Err(response) = require_admin_token(&state, &headers, ADMIN_PROFILE_PATH).await {
+        return response;
+    }
+
+    let expressions = crate::metrics::profile::expression_profiler().profile();
+    Json(json!({ "expressions": expressions })).into_response()
+}
+
+/// OpenAPI description of the REST routes
+async fn openapi(State(state): State<HttpState>) -> Response {
+    let auth_enabled = state.security.authenticator().is_auth_enabled();
+    match crate::transport::openapi::openapi_document(auth_enabled) {
+        Ok(document) => Json(document).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.message })),
+        )
+            .into_response(),
+    }
+}
+
+/// MCP endpoint served by rmcp
+type McpService = StreamableHttpService<FhirPathToolServer, ResumableSessionManager>;
+
+/// Pass MCP requests to rmcp, answering JSON-RPC batches, which rmcp rejects, by sending it
+/// each member separately
+async fn handle_mcp(service: McpService, request: Request) -> Response {
+    if request.method() != Method::POST {
+        let Ok(response) = service.oneshot(request).await;
+        return response.into_response();
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = match Bytes::from_request(Request::from_parts(parts.clone(), body), &()).await {
+        Ok(body) => body,
+        Err(rejection) => return rejection.into_response(),
+    };
+    if body.trim_ascii_start().starts_with(b"[")
+        && let Ok(Value::Array(members)) = serde_json::from_slice(&body)
+    {
+        return handle_http_batch(service, parts, members).await;
+    }
+
+    let Ok(response) = service
+        .oneshot(Request::from_parts(parts, Body::from(body)))
+        .await;
+    response.into_response()
+}
+
+/// Input schema of a single tool, exactly as `tools/list` reports it
+async fn tool_schema(Path(tool_name): Path<String>) -> Response {
+    let tools = match crate::server::tool_definitions() {
+        Ok(tools) => tools,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": e.message })),
+            )
+                .into_response();
+        }
+    };
+
+    match tools.into_iter().find(|tool| tool.name == tool_name) {
+        Some(tool) => Json(Value::Object((*tool.input_schema).clone())).into_response(),
+        None => {
+            let error = ToolError::UnknownTool(tool_name);
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "error": {
+                        "code": error.code(),
+                        "message": error.to_string(),
+                    }
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Run a tool directly over REST, returning the tool result or a JSON-RPC style error.
+///
+/// Requests carrying an `Idempotency-Key` header run at most once per client and key: replays
+/// get the stored response, and a replay while the first request is running gets 409. Reusing
+/// a key for a different tool or different arguments gets 422.
+/// Requests whose `X-MCP-Accept-Version` names no supported response version get 406.
+/// `fhirpath_evaluate` calls with `Cache-Control: no-cache` skip the result cache.
+async fn handle_tool_call(
+    State(state): State<HttpState>,
+    Path(tool_name): Path<String>,
+    Query(format): Query<FormatQuery>,
+    extensions: Extensions,
+    headers: HeaderMap,
+    Json(mut arguments): Json<Value>,
+) -> Response {
+    if let Err(response) = authorize(&state, &extensions, &tool_name).await {
+        return response;
+    }
+
+    let accept_version = headers
+        .get(ACCEPT_VERSION_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let api_version = match negotiate_api_version(accept_version) {
+        Ok(api_version) => api_version,
+        Err(error) => {
+            return (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(json!({
+                    "error": error.to_string(),
+                    "supported_versions": SUPPORTED_API_VERSIONS,
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|key| !key.is_empty());
+
+    let reservation = match (&state.idempotency, idempotency_key) {
+        (Some(_), Some(key)) if key.len() > MAX_IDEMPOTENCY_KEY_LENGTH => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": format!(
+                        "Idempotency key exceeds {MAX_IDEMPOTENCY_KEY_LENGTH} characters"
+                    ),
+                })),
+            )
+                .into_response();
+        }
+        (Some(cache), Some(key)) => match cache.begin(
+            &request_client(&extensions),
+            key,
+            idempotency::fingerprint(&tool_name, &arguments),
+        ) {
+            Reservation::Acquired(guard) => Some(guard),
+            Reservation::InFlight => {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(json!({
+                        "error": "A request with this idempotency key is still in progress",
+                    })),
+                )
+                    .into_response();
+            }
+            Reservation::Mismatch => {
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({
+                        "error": "This idempotency key was used for a different request",
+                    })),
+                )
+                    .into_response();
+            }
+            Reservation::Completed(cached) => {
+                debug!("Replaying response for idempotency key {}", key);
+                let status = StatusCode::from_u16(cached.status)
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                return (
+                    [(IDEMPOTENT_REPLAY_HEADER, "true")],
+                    with_warning_headers(
+                        &cached.body,
+                        json_response(status, &cached.body, format.pretty),
+                    ),
+                )
+                    .into_response();
+            }
+        },
+        _ => None,
+    };
+
+    if tool_name == "fhirpath_evaluate"
+        && requests_no_cache(&headers)
+        && let Some(arguments) = arguments.as_object_mut()
+    {
+        arguments.insert("bypass_cache".to_string(), Value::Bool(true));
+    }
+
+    let subject = extensions
+        .get::<AuthenticatedRequest>()
+        .map(|authenticated| authenticated.subject.clone());
+    let mut call = UnfinishedCall {
+        tool_name: &tool_name,
+        finished: false,
+    };
+    let (status, body) = run_tool_call(&state, &tool_name, arguments, subject, api_version).await;
+    call.finished = true;
+    if let Some(guard) = reservation {
+        guard.complete(CachedResponse {
+            status: status.as_u16(),
+            body: body.clone(),
+        });
+    }
+    with_warning_headers(&body, json_response(status, &body, format.pretty))
+}
+
+/// Add a [`WARNING_HEADER`] for each warning of the tool result in `body`
+fn with_warning_headers(body: &Value, mut response: Response) -> Response {
+    let warnings = body["result"]["warnings"].as_array().into_iter().flatten();
+    for warning in warnings.filter_map(Value::as_str) {
+        if let Ok(value) = HeaderValue::from_str(warning) {
+            response.headers_mut().append(WARNING_HEADER, value);
+        }
+    }
+    response
+}
+
+/// Whether the request's `Cache-Control` header has the `no-cache` directive
+fn requests_no_cache(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+/// Notes a tool call whose handler was dropped before finishing. Hyper drops the handler
+/// when the client disconnects, which cancels the evaluation and frees its slot.
+struct UnfinishedCall<'a> {
+    tool_name: &'a str,
+    finished: bool,
+}
+
+impl Drop for UnfinishedCall<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            debug!("Client disconnected, cancelled {} call", self.tool_name);
+        }
+    }
+}
+
+async fn run_tool_call(
+    state: &HttpState,
+    tool_name: &str,
+    arguments: Value,
+    subject: Option<String>,
+    api_version: &str,
+) -> (StatusCode, Value) {
+    // Tools evaluating a resource are also broken down by its type
+    let resource_type = arguments
+        .get("resource")
+        .map(|resource| resource_type(resource).to_string());
+    let audit = state.audit_log.as_ref().map(|audit_log| {
+        let record = AuditRecord::new(tool_name, &arguments, subject, current_correlation_id());
+        (audit_log, record)
+    });
+    let start_time = Instant::now();
+    let result = call_tool(tool_name, arguments).await;
+    let elapsed = start_time.elapsed();
+    if let Some((audit_log, record)) = audit {
+        audit_log.record(match &result {
+            Ok(_) => record,
+            Err(error) => record.failed(error.code()),
+        });
+    }
+    state
+        .metrics
+        .record_tool_request(tool_name, elapsed, result.is_err());
+    if let Some(resource_type) = resource_type {
+        state
+            .metrics
+            .record_evaluation_by_type(&resource_type, elapsed)
+            .await;
+    }
+
+    match result {
+        Ok(result) => (
+            StatusCode::OK,
+            json!({ "api_version": api_version, "result": result }),
+        ),
+        Err(error) => {
+            warn!("Tool call {} failed: {}", tool_name, error);
+            let status = StatusCode::from_u16(error.http_status())
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let mut body = json!({
+                "api_version": api_version,
+                "error": {
+                    "code": error.code(),
+                    "message": error.to_string(),
+                }
+            });
+            if let Some(data) = error.data() {
+                body["error"]["data"] = data;
+            }
+            (status, body)
+        }
+    }
+}
+
+/// Check that the authenticated caller, if any, may call `tool`
+#[allow(clippy::result_large_err)]
+async fn authorize(state: &HttpState, extensions: &Extensions, tool: &str) -> Result<(), Response> {
+    let Some(authenticated) = extensions.get::<AuthenticatedRequest>() else {
+        return Ok(());
+    };
+
+    if let Err(error) = authorize_tool(tool, &authenticated.scope) {
+        let reason = AuthFailureReason::OutOfScope;
+        warn!(
+            reason = %reason,
+            "{} is not allowed to call {}",
+            authenticated.subject,
+            tool
+        );
+        state.metrics.record_auth_failure(reason).await;
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": {
+                    "code": error.code(),
+                    "message": error.to_string(),
+                }
+            })),
+        )
+            .into_response());
+    }
+
+    Ok(())
+}
+
+/// Response for a tool call refused before it ran, as the REST tool endpoint reports it
+fn tool_error_response(error: &ToolError) -> Response {
+    let status =
+        StatusCode::from_u16(error.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut body = json!({
+        "error": {
+            "code": error.code(),
+            "message": error.to_string(),
+        }
+    });
+    if let Some(data) = error.data() {
+        body["error"]["data"] = data;
+    }
+    (status, Json(body)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamQuery {
+    chunk_size: Option<usize>,
+}
+
+/// Run `fhirpath_extract` and stream its values as SSE `data` events of up to `chunk_size`
+/// values, followed by a `complete` event with a summary, or an `error` event.
+async fn handle_extract_stream(
+    State(state): State<HttpState>,
+    Query(query): Query<StreamQuery>,
+    extensions: Extensions,
+    Json(params): Json<ExtractParams>,
+) -> Response {
+    const TOOL: &str = "fhirpath_extract";
+    if let Err(response) = authorize(&state, &extensions, TOOL).await {
+        return response;
+    }
+    if let Err(error) = check_extract_params(&params) {
+        return tool_error_response(&error);
+    }
+
+    let chunk_size = query.chunk_size.unwrap_or(DEFAULT_STREAM_CHUNK_SIZE).max(1);
+    // A bounded channel keeps extraction from running ahead of a slow client
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER_CHUNKS);
+    let resource_type = resource_type(&params.resource).to_string();
+    let audit = state.audit_log.clone().map(|audit_log| {
+        let subject = extensions
+            .get::<AuthenticatedRequest>()
+            .map(|authenticated| authenticated.subject.clone());
+        let record = AuditRecord::new(
+            TOOL,
+            &json!({ "expression": &params.expression }),
+            subject,
+            current_correlation_id(),
+        );
+        let record = AuditRecord {
+            resource_type: Some(resource_type.clone()),
+            ..record
+        };
+        (audit_log, record)
+    });
+    let start_time = Instant::now();
+    let extraction =
+        tokio::spawn(fhirpath_extract_stream(params, chunk_size, sender).in_current_span());
+
+    let metrics = state.metrics.clone();
+    let production = state.security.production();
+    let events = async_stream::stream! {
+        while let Some(chunk) = receiver.recv().await {
+            yield Event::default().event("data").json_data(chunk);
+        }
+
+        let result = extraction
+            .await
+            .unwrap_or_else(|e| Err(anyhow::anyhow!("Extraction task failed: {}", e)));
+        let elapsed = start_time.elapsed();
+        metrics.record_tool_request(TOOL, elapsed, result.is_err());
+        metrics.record_evaluation_by_type(&resource_type, elapsed).await;
+        if let Some((audit_log, record)) = audit {
+            audit_log.record(match &result {
+                Ok(_) => record,
+                Err(_) => record.failed(EVALUATION_FAILED),
+            });
+        }
+
+        yield match result {
+            Ok(summary) => Event::default().event("complete").json_data(summary),
+            Err(e) => {
+                warn!("Streamed extraction failed: {}", e);
+                let message = RequestSanitizer::sanitize_diagnostic(&e.to_string(), production);
+                Event::default()
+                    .event("error")
+                    .json_data(json!({ "error": { "message": message } }))
+            }
+        };
+    };
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(state.sse_keep_alive))
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct BulkQuery {
+    expression: String,
+}
+
+/// Evaluate `expression` against each resource of an NDJSON body (e.g. a FHIR Bulk Data
+/// export), answering with one NDJSON line per resource. Lines are read and answered as they
+/// arrive, and a line that fails produces an error object instead of ending the response.
+async fn handle_bulk_evaluate(
+    State(state): State<HttpState>,
+    Query(query): Query<BulkQuery>,
+    extensions: Extensions,
+    body: Body,
+) -> Response {
+    const TOOL: &str = "fhirpath_evaluate";
+    if let Err(response) = authorize(&state, &extensions, TOOL).await {
+        return response;
+    }
+    if let Err(error) = check_expression(TOOL, &query.expression) {
+        return tool_error_response(&error);
+    }
+
+    let max_line = state.security.validator().max_resource_size();
+    let expression = query.expression;
+    // Each line is audited as a call of its own, completed with the line's resource type
+    let audit = state.audit_log.as_ref().map(|_| {
+        let subject = extensions
+            .get::<AuthenticatedRequest>()
+            .map(|authenticated| authenticated.subject.clone());
+        AuditRecord::new(
+            TOOL,
+            &json!({ "expression": &expression }),
+            subject,
+            current_correlation_id(),
+        )
+    });
+    let mut chunks = body.into_data_stream();
+
+    let lines = async_stream::stream! {
+        let mut buffer = Vec::new();
+        let mut index = 0;
+        // Set while skipping the rest of a line that exceeded `max_line`
+        let mut oversized = false;
+        let mut finished = false;
+
+        while !finished {
+            let mut pending = Vec::new();
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    for byte_line in chunk.split_inclusive(|byte| *byte == b'\n') {
+                        let complete = byte_line.ends_with(b"\n");
+                        if !oversized {
+                            buffer.extend_from_slice(byte_line);
+                        }
+                        if complete {
+                            if !oversized {
+                                pending.push(Ok(std::mem::take(&mut buffer)));
+                            }
+                            oversized = false;
+                        } else if !oversized && buffer.len() > max_line {
+                            pending.push(Err(()));
+                            buffer.clear();
+                            oversized = true;
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    warn!("Failed to read bulk evaluation body: {}", e);
+                    yield Ok::<_, std::convert::Infallible>(ndjson_line(&json!({
+                        "resourceIndex": index,
+                        "error": { "message": format!("Failed to read request body: {e}") }
+                    })));
+                    break;
+                }
+                None => {
+                    if !oversized {
+                        pending.push(Ok(std::mem::take(&mut buffer)));
+                    }
+                    finished = true;
+                }
+            }
+
+            for line in pending {
+                let line = match line {
+                    Ok(line) if line.trim_ascii().is_empty() => continue,
+                    Ok(line) => Ok(line),
+                    Err(()) => Err(format!(
+                        "FHIR resource too large: line exceeds {max_line} bytes"
+                    )),
+                };
+                let output =
+                    evaluate_bulk_line(&state, &expression, index, line, audit.as_ref()).await;
+                index += 1;
+                yield Ok(ndjson_line(&output));
+            }
+        }
+    };
+
+    (
+        [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+/// Evaluate one NDJSON line, returning its `{resourceIndex, values, types}` result or a
+/// `{resourceIndex, error}` object
+async fn evaluate_bulk_line(
+    state: &HttpState,
+    expression: &str,
+    index: usize,
+    line: Result<Vec<u8>, String>,
+    audit: Option<&AuditRecord>,
+) -> Value {
+    let start_time = Instant::now();
+    // Lines over the size limit arrive as errors
+    let resource = line
+        .map_err(|message| (RESOURCE_TOO_LARGE, message))
+        .and_then(|line| {
+            serde_json::from_slice::<Value>(&line)
+                .map_err(|e| (INVALID_PARAMS, format!("Invalid JSON resource: {e}")))
+        });
+    let resource_type = resource
+        .as_ref()
+        .ok()
+        .map(|resource| resource_type(resource).to_string());
+    let result = match resource {
+        Ok(resource) => fhirpath_evaluate(EvaluateParams {
+            expression: expression.to_string(),
+            resource,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| match result.diagnostics {
+            Some(diagnostics) => Err(diagnostics.join("; ")),
+            None => Ok(result),
+        })
+        .map_err(|message| (EVALUATION_FAILED, message)),
+        Err(error) => Err(error),
+    };
+    let elapsed = start_time.elapsed();
+    if let (Some(audit_log), Some(record)) = (&state.audit_log, audit) {
+        let record = AuditRecord {
+            resource_type: resource_type.clone(),
+            ..record.clone()
+        };
+        audit_log.record(match &result {
+            Ok(_) => record,
+            Err((code, _)) => record.failed(*code),
+        });
+    }
+    state
+        .metrics
+        .record_tool_request("fhirpath_evaluate", elapsed, result.is_err());
+    if let Some(resource_type) = resource_type {
+        state
+            .metrics
+            .record_evaluation_by_type(&resource_type, elapsed)
+            .await;
+    }
+
+    match result {
+        Ok(result) => json!({
+            "resourceIndex": index,
+            "values": result.values,
+            "types": result.types,
+        }),
+        Err((_, message)) => {
+            debug!("Bulk evaluation of line {} failed: {}", index, message);
+            let message =
+                RequestSanitizer::sanitize_diagnostic(&message, state.security.production());
+            json!({ "resourceIndex": index, "error": { "message": message } })
+        }
+    }
+}
+
+fn ndjson_line(value: &Value) -> String {
+    format!("{value}\n")
+}
+
+/// Identify the client of an authenticated request by [`AuthenticatedRequest::client_id`],
+/// otherwise by peer IP address
+fn request_client(extensions: &Extensions) -> String {
+    if let Some(authenticated) = extensions.get::<AuthenticatedRequest>() {
+        return authenticated.client_id();
+    }
+
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| format!("ip:{}", addr.ip()))
+        .unwrap_or_else(|| "ip:unknown".to_string())
+}
+
+/// Run each request inside a span carrying its correlation ID, so tool spans recorded while
+/// handling it share one trace. The ID is taken from `X-Correlation-ID` or generated, and is
+/// echoed back on the response. Generated IDs are written to the request header so the MCP
+/// service, which runs outside this span, can attach them to its tool calls too.
+async fn correlation_id_middleware(mut request: Request, next: Next) -> Response {
+    let correlation_id = request
+        .headers()
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(RequestSanitizer::create_correlation_id);
+
+    if let Ok(value) = correlation_id.parse() {
+        request.headers_mut().insert(CORRELATION_ID_HEADER, value);
+    }
+
+    let span = info_span!(
+        "http_request",
+        correlation_id = %correlation_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+
+    let mut response = with_correlation_id(correlation_id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+    if let Ok(value) = correlation_id.parse() {
+        response.headers_mut().insert(CORRELATION_ID_HEADER, value);
+    }
+    response
+}
+
+/// Require a valid API key or JWT bearer token on every request except [`PUBLIC_PATHS`] and
+/// the admin routes when authentication is enabled. The authenticated request is
+/// stored in the request extensions.
+async fn auth_middleware(
+    State(state): State<HttpState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let authenticator = state.security.authenticator();
+    let path = request.uri().path();
+    if !authenticator.is_auth_enabled()
+        || PUBLIC_PATHS.contains(&path)
+        || path == ADMIN_SHUTDOWN_PATH
+        || path == ADMIN_METRICS_RESET_PATH
+        || path == ADMIN_PROFILE_PATH
+    {
+        return next.run(request).await;
+    }
+
+    let auth_header = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+
+    let result = match auth_header {
+        Some(auth_header) => authenticator.parse_authorization_header(auth_header),
+        None => Err(AuthError::new(
+            AuthFailureReason::MissingHeader,
+            "Missing authorization header",
+        )),
+    };
+
+    match result {
+        Ok(authenticated) => {
+            debug!("Authenticated request from {}", authenticated.subject);
+            request.extensions_mut().insert(authenticated);
+            next.run(request).await
+        }
+        Err(e) => {
+            // The reason is only logged and counted; clients get the same 401 either way
+            warn!(
+                reason = %e.reason,
+                path = %request.uri().path(),
+                "Authentication failed: {}",
+                e
+            );
+            state.metrics.record_auth_failure(e.reason).await;
+            unauthorized()
+        }
+    }
+}
+
+/// The 401 response, identical whatever the reason authentication failed
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(json!({ "error": "Unauthorized" })),
+    )
+        .into_response()
+}
+
+/// Identify the client for rate limiting: the authenticated subject when auth is enabled,
+/// otherwise the peer IP address
+fn client_key(security: &SecurityProvider, request: &Request) -> String {
+    let authenticator = security.authenticator();
+    if authenticator.is_auth_enabled()
+        && let Some(auth_header) = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+        && let Ok(authenticated) = authenticator.parse_authorization_header(auth_header)
+    {
+        return authenticated.client_id();
+    }
+
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| format!("ip:{}", addr.ip()))
+        .unwrap_or_else(|| "ip:unknown".to_string())
+}
+
+async fn rate_limit_middleware(
+    State(state): State<HttpState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limiter) = &state.rate_limiter else {
+        return next.run(request).await;
+    };
+
+    let key = client_key(&state.security, &request);
+    match limiter.check(&key).await {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let retry_after_secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            warn!("Rate limit exceeded for {}", key);
+
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(json!({
+                    "error": "Rate limit exceeded",
+                    "retry_after": retry_after_secs,
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Turn away tool calls while the last memory sample is critical, so the server can recover:
+/// REST tool calls, bulk evaluation, and MCP `tools/call` messages, alone or in a batch. Other
+/// MCP messages such as `ping`, `initialize` and cancellations are still served, as are
+/// health, readiness, metrics and the admin endpoints.
+async fn memory_admission_middleware(
+    State(state): State<HttpState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if request.method() != Method::POST || path.starts_with("/admin/") {
+        return next.run(request).await;
+    }
+    let Some(memory_mb) = state.metrics.health_monitor().critical_memory_usage_mb() else {
+        return next.run(request).await;
+    };
+
+    if !path.starts_with("/mcp/tools/") && path != "/mcp/bulk/evaluate" {
+        // Anything else is an MCP message; read it to see whether it calls a tool. Bodies
+        // too large to inspect are turned away with the tool calls.
+        let (parts, body) = request.into_parts();
+        let limit = state
+            .security
+            .validator()
+            .max_resource_size()
+            .saturating_add(REQUEST_BODY_OVERHEAD);
+        if let Ok(bytes) = axum::body::to_bytes(body, limit).await
+            && !calls_tool(&bytes)
+        {
+            return next
+                .run(Request::from_parts(parts, Body::from(bytes)))
+                .await;
+        }
+        return memory_pressure_response(parts.uri.path(), memory_mb);
+    }
+    memory_pressure_response(path, memory_mb)
+}
+
+/// Whether a JSON-RPC message or batch contains a `tools/call` request
+fn calls_tool(body: &[u8]) -> bool {
+    let is_tool_call = |message: &Value| message["method"] == "tools/call";
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(messages)) => messages.iter().any(is_tool_call),
+        Ok(message) => is_tool_call(&message),
+        Err(_) => false,
+    }
+}
+
+fn memory_pressure_response(path: &str, memory_mb: f64) -> Response {
+    let retry_after_secs = MEMORY_PRESSURE_RETRY_AFTER.as_secs();
+    warn!(
+        "Rejecting {} under memory pressure ({:.1}MB in use)",
+        path, memory_mb
+    );
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, retry_after_secs.to_string())],
+        Json(json!({
+            "error": "Server is under memory pressure",
+            "retry_after": retry_after_secs,
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::ApiKeyConfig;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    fn health_request() -> Request {
+        Request::builder()
+            .uri("/health")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_returns_429() {
+        let security = SecurityConfig {
+            enable_auth: false,
+            rate_limit_per_minute: 3,
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .create_router();
+
+        for _ in 0..3 {
+            let response = router.clone().oneshot(health_request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = router.clone().oneshot(health_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "Rate limit exceeded");
+    }
+
+    async fn post_tool(router: Router, tool: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/mcp/tools/{tool}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_endpoint() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+
+        let (status, body) = post_tool(
+            router.clone(),
+            "fhirpath_evaluate",
+            json!({"expression": "Patient.id", "resource": {"resourceType": "Patient", "id": "p1"}}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["result"]["values"], json!(["p1"]));
+
+        let (status, body) = post_tool(router.clone(), "unknown_tool", json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], -32601);
+
+        let (status, body) = post_tool(router, "fhirpath_parse", json!({})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], -32602);
+    }
+
+    #[tokio::test]
+    async fn test_over_length_expression_is_invalid_params() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        // Longer than the default `max_expression_length`
+        let expression = format!("Patient.id = '{}'", "x".repeat(1000));
+
+        let (status, body) = post_tool(
+            router,
+            "fhirpath_evaluate",
+            json!({"expression": expression, "resource": {"resourceType": "Patient", "id": "p1"}}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], -32602);
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("too long")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_cache_header_bypasses_result_cache() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let arguments = json!({
+            "expression": "Patient.id",
+            "resource": {"resourceType": "Patient", "id": "no-cache-header"}
+        });
+        let call = |cache_control: Option<&str>| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/mcp/tools/fhirpath_evaluate")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(cache_control) = cache_control {
+                request = request.header(header::CACHE_CONTROL, cache_control);
+            }
+            let request = request.body(Body::from(arguments.to_string())).unwrap();
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Value>(&body).unwrap()
+            }
+        };
+
+        call(None).await;
+        let body = call(None).await;
+        assert_eq!(body["result"]["performance"]["cache_hit"], true);
+
+        let body = call(Some("max-age=0, No-Cache")).await;
+        assert_eq!(body["result"]["performance"]["cache_hit"], false);
+        assert_eq!(body["result"]["values"], json!(["no-cache-header"]));
+    }
+
+    #[test]
+    fn test_warning_headers() {
+        let warning = "Parameter 'legacy_option' of fhirpath_parse is deprecated; \
+                       use include_ast instead";
+        let body = json!({"result": {"valid": true, "warnings": [warning]}});
+
+        let response = with_warning_headers(&body, json_response(StatusCode::OK, &body, false));
+        let headers: Vec<_> = response.headers().get_all(WARNING_HEADER).iter().collect();
+        assert_eq!(headers, vec![warning]);
+
+        let body = json!({"result": {"valid": true}});
+        let response = with_warning_headers(&body, json_response(StatusCode::OK, &body, false));
+        assert!(response.headers().get(WARNING_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_api_version() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let call = |accept_version: Option<&str>| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/mcp/tools/fhirpath_parse")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(accept_version) = accept_version {
+                request = request.header(ACCEPT_VERSION_HEADER, accept_version);
+            }
+            let request = request
+                .body(Body::from(json!({"expression": "Patient.id"}).to_string()))
+                .unwrap();
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        let (status, body) = call(None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["api_version"], "1.0");
+        assert_eq!(body["result"]["valid"], true);
+
+        let (status, body) = call(Some("2.0, 1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["api_version"], "1.0");
+
+        let (status, body) = call(Some("2.0")).await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(body["supported_versions"], json!(["1.0"]));
+
+        let (status, body) = post_tool(router, "unknown_tool", json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["api_version"], "1.0");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tool_call_timeout_is_gateway_timeout() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let entries: Vec<Value> = (0..9_000)
+            .map(|i| {
+                json!({"resource": {
+                    "resourceType": "Patient",
+                    "id": format!("p{i}"),
+                    "name": [{"given": ["Jane"], "family": format!("Poe{i}")}]
+                }})
+            })
+            .collect();
+
+        let (status, body) = post_tool(
+            router,
+            "fhirpath_evaluate",
+            json!({
+                "expression": "Bundle.entry.resource.name.where(family.startsWith('Poe')).given",
+                "resource": {"resourceType": "Bundle", "type": "collection", "entry": entries},
+                "timeout_ms": 1
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            body,
+            json!({
+                "api_version": "1.0",
+                "error": {
+                    "code": -32003,
+                    "message": "evaluation timed out",
+                    "data": {"tool": "fhirpath_evaluate", "timeout_ms": 1}
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stats_reports_tool_metrics() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+
+        post_tool(
+            router.clone(),
+            "fhirpath_parse",
+            json!({"expression": "Patient.name"}),
+        )
+        .await;
+
+        let request = Request::builder()
+            .uri("/stats")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["tools"]["fhirpath_parse"]["total_requests"], 1);
+        assert!(body["tools"]["fhirpath_parse"]["p95_response_time_ms"].is_number());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_content_negotiation() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+
+        for (accept, content_type, openmetrics) in [
+            (None, "text/plain; version=0.0.4; charset=utf-8", false),
+            (
+                Some("text/plain; version=0.0.4"),
+                "text/plain; version=0.0.4; charset=utf-8",
+                false,
+            ),
+            (
+                Some("application/openmetrics-text; version=1.0.0"),
+                "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                true,
+            ),
+        ] {
+            let mut builder = Request::builder().uri("/metrics");
+            if let Some(accept) = accept {
+                builder = builder.header(header::ACCEPT, accept);
+            }
+            let response = router
+                .clone()
+                .oneshot(builder.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+
+            assert!(body.contains("octofhir_requests_total "));
+            assert_eq!(body.ends_with("# EOF\n"), openmetrics, "{accept:?}");
+            assert_eq!(
+                body.contains("# TYPE octofhir_requests counter"),
+                openmetrics
+            );
+            assert_eq!(body.contains("# UNIT "), openmetrics);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pretty_json_responses() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let body_text = |uri: &str, body: Option<Value>| {
+            let router = router.clone();
+            let request = match body {
+                Some(body) => Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string())),
+                None => Request::builder().uri(uri).body(Body::empty()),
+            }
+            .unwrap();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+        let arguments = json!({"expression": "Patient.name"});
+
+        let compact = body_text("/mcp/tools/fhirpath_parse", Some(arguments.clone())).await;
+        assert!(!compact.contains('\n'));
+        let pretty = body_text("/mcp/tools/fhirpath_parse?pretty=true", Some(arguments)).await;
+        assert!(pretty.contains("\n  \"result\": {"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap()["result"]["valid"],
+            true
+        );
+
+        assert!(!body_text("/stats", None).await.contains('\n'));
+        let pretty = body_text("/stats?pretty=true", None).await;
+        assert!(pretty.contains("\n  \"performance\": {"));
+        serde_json::from_str::<Value>(&pretty).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stats_reports_evaluations_by_resource_type() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+
+        // The untyped resource is rejected, but still counted
+        for (resource, expected_status) in [
+            (
+                json!({"resourceType": "Patient", "id": "p1"}),
+                StatusCode::OK,
+            ),
+            (
+                json!({"resourceType": "Observation", "id": "o1", "status": "final"}),
+                StatusCode::OK,
+            ),
+            (json!({"id": "untyped"}), StatusCode::BAD_REQUEST),
+        ] {
+            let (status, _) = post_tool(
+                router.clone(),
+                "fhirpath_evaluate",
+                json!({"expression": "id", "resource": resource}),
+            )
+            .await;
+            assert_eq!(status, expected_status);
+        }
+
+        let request = Request::builder()
+            .uri("/stats")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let metrics = &body["custom_metrics"];
+        for resource_type in ["Patient", "Observation", "unknown"] {
+            assert_eq!(
+                metrics[format!("evaluations_by_type_{resource_type}")],
+                1.0,
+                "{resource_type}"
+            );
+            assert!(metrics[format!("evaluation_time_us_by_type_{resource_type}")].is_number());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_rejected() {
+        let security = SecurityConfig {
+            enable_auth: false,
+            max_resource_size: 1024,
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .create_router();
+        let padding = "x".repeat(1024 + REQUEST_BODY_OVERHEAD);
+
+        let body = json!({
+            "expression": "id",
+            "resource": {"resourceType": "Patient", "id": "big", "text": {"div": padding}}
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/tools/fhirpath_evaluate")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let request = Request::builder()
+            .uri("/stats")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["custom_metrics"]["evaluations_by_type_Patient"].is_null());
+
+        // Bulk bodies are only bounded per line
+        let line = json!({"resourceType": "Patient", "id": "small"}).to_string();
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/bulk/evaluate?expression=id")
+            .header(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+            .body(Body::from(
+                vec![line; 2 * REQUEST_BODY_OVERHEAD / 40].join("\n"),
+            ))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_memory_pressure_sheds_evaluations() {
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0).with_monitoring(
+            MonitoringConfig {
+                memory_threshold_mb: 100.0,
+                ..MonitoringConfig::default()
+            },
+        );
+        let router_with_memory = |probe: fn() -> f64| {
+            let state = server.create_state();
+            let metrics = MetricsProvider::new(server.monitoring.clone(), "test".to_string())
+                .with_memory_probe(probe);
+            server.build_router(HttpState {
+                metrics: Arc::new(metrics),
+                ..state
+            })
+        };
+        let evaluate = || {
+            Request::builder()
+                .method("POST")
+                .uri("/mcp/tools/fhirpath_evaluate")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({"expression": "id", "resource": {"resourceType": "Patient", "id": "a"}})
+                        .to_string(),
+                ))
+                .unwrap()
+        };
+
+        // Over 1.5 times the threshold
+        let router = router_with_memory(|| 151.0);
+        let response = router.clone().oneshot(evaluate()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+
+        let mcp = |message: Value| {
+            Request::builder()
+                .method("POST")
+                .uri("/mcp")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::ACCEPT, "application/json, text/event-stream")
+                .body(Body::from(message.to_string()))
+                .unwrap()
+        };
+        let tool_call = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "tools/call",
+            "params": {"name": "fhirpath_parse", "arguments": {"expression": "Patient"}}
+        });
+        let ping = json!({"jsonrpc": "2.0", "id": 1, "method": "ping"});
+        for message in [tool_call.clone(), json!([ping.clone(), tool_call])] {
+            let response = router.clone().oneshot(mcp(message)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        // Messages that start no evaluation reach the MCP service
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": {"name": "test", "version": "1.0"}
+            }
+        });
+        let cancelled = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": {"requestId": 2}
+        });
+        for message in [ping, initialize, cancelled] {
+            let response = router.clone().oneshot(mcp(message.clone())).await.unwrap();
+            assert_ne!(
+                response.status(),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "{message}"
+            );
+        }
+
+        for uri in ["/health", "/metrics"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_ne!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+        }
+
+        // Elevated but not critical
+        let router = router_with_memory(|| 149.0);
+        let response = router.oneshot(evaluate()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ready_after_warm_up() {
+        crate::fhirpath_engine::get_shared_engine().await.unwrap();
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_warmup_expressions(vec!["Patient.name.given".to_string()]);
+        let state = server.create_state();
+        let router = server.build_router(state.clone());
+
+        let get_ready = |router: Router| async move {
+            let request = Request::builder()
+                .uri("/ready")
+                .body(Body::empty())
+                .unwrap();
+            let response = router.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        };
+
+        let (status, body) = get_ready(router.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+
+        let warm_up = server.start_warm_up(&state).await.unwrap();
+        assert!(!state.metrics.get_readiness_status().await.ready);
+        let (status, body) = get_ready(router.clone()).await;
+        if !warm_up.is_finished() {
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(body["ready"], false);
+        }
+
+        warm_up.await.unwrap();
+        let (status, body) = get_ready(router).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["checks"][WARMUP_CHECK]["message"],
+            "Warmed up 1/1 expressions"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_live_but_not_ready_during_warm_up() {
+        crate::fhirpath_engine::get_shared_engine().await.unwrap();
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0);
+        let state = server.create_state();
+        let router = server.build_router(state.clone());
+        state
+            .metrics
+            .update_readiness_check(WARMUP_CHECK, HealthCheck::unhealthy("Warming up"))
+            .await;
+
+        let get = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                router.oneshot(request).await.unwrap().status()
+            }
+        };
+        assert_eq!(get("/health").await, StatusCode::OK);
+        assert_eq!(get("/ready").await, StatusCode::SERVICE_UNAVAILABLE);
+
+        // Degraded system health doesn't take the server out of rotation
+        state
+            .metrics
+            .update_health_check("memory_usage", HealthCheck::degraded("High memory usage"))
+            .await;
+        state
+            .metrics
+            .update_readiness_check(WARMUP_CHECK, HealthCheck::healthy("Warmed up"))
+            .await;
+        assert_eq!(get("/health").await, StatusCode::OK);
+        assert_eq!(get("/ready").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_auth_required_when_enabled() {
+        let security = SecurityConfig {
+            api_keys: vec!["test-key-123".into()],
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .create_router();
+
+        let stats_request = |auth: Option<&str>| {
+            let mut builder = Request::builder().uri("/stats");
+            if let Some(auth) = auth {
+                builder = builder.header(header::AUTHORIZATION, auth);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let response = router.clone().oneshot(stats_request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = router
+            .clone()
+            .oneshot(stats_request(Some("Bearer eyJhbGciOiJIUzI1NiJ9.e30.x")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = router
+            .clone()
+            .oneshot(stats_request(Some("Bearer test-key-123")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router.clone().oneshot(health_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let version_request = Request::builder()
+            .uri("/version")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(version_request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_version_endpoint() {
+        let config = ServerConfig {
+            fhir_version: "R5".to_string(),
+            ..ServerConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_config(&config)
+            .unwrap()
+            .create_router();
+
+        let request = Request::builder()
+            .uri("/version")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["version"], crate::VERSION);
+        assert_eq!(body["fhir_version"], "R5");
+        assert_eq!(body["api_versions"], json!(SUPPORTED_API_VERSIONS));
+        assert!(!body["git_commit"].as_str().unwrap().is_empty());
+        assert!(body["rustc_version"].as_str().unwrap().starts_with("rustc"));
+        assert_eq!(
+            body["fhirpath_version"],
+            crate::fhirpath_engine::FHIRPATH_VERSION
+        );
+        assert!(body["build_timestamp"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_tool_scopes() {
+        let security = SecurityConfig {
+            api_keys: vec![ApiKeyConfig::Scoped {
+                key: "read-only-key".to_string(),
+                tools: vec!["fhirpath_evaluate".to_string()],
+            }],
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .create_router();
+        let tool_request = |tool: &str, body: Value| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("/mcp/tools/{tool}"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, "Bearer read-only-key")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(tool_request(
+                "fhirpath_evaluate",
+                json!({"expression": "Patient.id", "resource": {"resourceType": "Patient", "id": "p1"}}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .oneshot(tool_request(
+                "fhirpath_parse",
+                json!({"expression": "Patient.name"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], -32004);
+    }
+
+    #[tokio::test]
+    async fn test_auth_failure_reason_metrics() {
+        let security = SecurityConfig {
+            api_keys: vec![
+                "full-access-key".into(),
+                ApiKeyConfig::Scoped {
+                    key: "read-only-key".to_string(),
+                    tools: vec!["fhirpath_evaluate".to_string()],
+                },
+            ],
+            jwt_secret: Some("test-secret".to_string()),
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .create_router();
+
+        let token = |exp_offset_secs: i64, secret: &str| {
+            let now = jsonwebtoken::get_current_timestamp() as i64;
+            let claims = crate::security::auth::Claims {
+                sub: "user-42".to_string(),
+                exp: (now + exp_offset_secs) as usize,
+                nbf: None,
+                iat: None,
+                iss: None,
+            };
+            jsonwebtoken::encode(
+                &jsonwebtoken::Header::default(),
+                &claims,
+                &jsonwebtoken::EncodingKey::from_secret(secret.as_ref()),
+            )
+            .unwrap()
+        };
+        let stats_request = |auth: Option<String>| {
+            let mut builder = Request::builder().uri("/stats");
+            if let Some(auth) = auth {
+                builder = builder.header(header::AUTHORIZATION, auth);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let rejected = [
+            None,
+            Some("Basic dGVzdDp0ZXN0".to_string()),
+            Some("Bearer unknown-key-123".to_string()),
+            Some(format!("Bearer {}", token(-3600, "test-secret"))),
+            Some(format!("Bearer {}", token(3600, "other-secret"))),
+        ];
+        for auth in rejected {
+            let response = router.clone().oneshot(stats_request(auth)).await.unwrap();
+            // Every failure looks the same to the client
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body, json!({"error": "Unauthorized"}));
+        }
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/tools/fhirpath_parse")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, "Bearer read-only-key")
+            .body(Body::from(
+                json!({"expression": "Patient.name"}).to_string(),
+            ))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router
+            .oneshot(stats_request(Some("Bearer full-access-key".to_string())))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        for reason in [
+            AuthFailureReason::MissingHeader,
+            AuthFailureReason::MalformedHeader,
+            AuthFailureReason::UnknownApiKey,
+            AuthFailureReason::ExpiredToken,
+            AuthFailureReason::InvalidSignature,
+            AuthFailureReason::OutOfScope,
+        ] {
+            assert_eq!(
+                body["custom_metrics"][format!("auth_failures_{reason}")],
+                1.0,
+                "{reason}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sse_intervals_applied() {
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_sse_keep_alive(Duration::from_secs(5))
+            .unwrap()
+            .with_sse_session_timeout(Some(Duration::from_secs(300)))
+            .unwrap();
+
+        assert_eq!(
+            server.streamable_http_config().sse_keep_alive,
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(server.create_state().sse_keep_alive, Duration::from_secs(5));
+        assert_eq!(
+            server.session_manager().session_config().keep_alive,
+            Some(Duration::from_secs(300))
+        );
+
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0);
+        assert!(server.with_sse_keep_alive(Duration::ZERO).is_err());
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0);
+        assert!(
+            server
+                .with_sse_session_timeout(Some(Duration::ZERO))
+                .is_err()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_https_health_check() {
+        let fixture = |name: &str| {
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/tls")
+                .join(name)
+        };
+
+        let err = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_tls(fixture("missing.pem"), fixture("key.pem"))
+            .start()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("TLS certificate chain"));
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server = Arc::new(
+            HttpTransportServer::new("127.0.0.1".to_string(), port)
+                .with_tls(fixture("cert.pem"), fixture("key.pem")),
+        );
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.start().await }
+        });
+
+        let cert = std::fs::read(fixture("cert.pem")).unwrap();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{port}/health");
+        let mut response = None;
+        for _ in 0..300 {
+            match client.get(&url).send().await {
+                Ok(ok) => {
+                    response = Some(ok);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+        let response = response.expect("HTTPS server did not come up");
+        assert_eq!(response.status(), StatusCode::OK);
+
+        server.shutdown();
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metrics_snapshot_written_on_shutdown() {
+        let dir =
+            std::env::temp_dir().join(format!("octofhir-mcp-metrics-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let snapshot_path = dir.join("metrics.json");
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = ServerConfig {
+            metrics_snapshot_path: Some(snapshot_path.clone()),
+            // Only the shutdown write happens during the test
+            metrics_snapshot_interval_seconds: 3600,
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(
+            HttpTransportServer::new("127.0.0.1".to_string(), port)
+                .with_config(&config)
+                .unwrap(),
+        );
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.start().await }
+        });
+
+        let client = reqwest::Client::new();
+        let url = format!("http://127.0.0.1:{port}/mcp/tools/fhirpath_parse");
+        let mut response = None;
+        for _ in 0..300 {
+            match client
+                .post(&url)
+                .json(&json!({"expression": "Patient.name"}))
+                .send()
+                .await
+            {
+                Ok(ok) => {
+                    response = Some(ok);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+        assert!(
+            response
+                .expect("server did not come up")
+                .status()
+                .is_success()
+        );
+
+        server.shutdown();
+        running.await.unwrap().unwrap();
+
+        let snapshot = crate::metrics::PersistedMetrics::load(&snapshot_path).unwrap();
+        assert!(snapshot.requests.total_requests >= 1);
+        assert_eq!(snapshot.requests.tool_requests["fhirpath_parse"], 1);
+        assert_eq!(
+            snapshot.requests.tool_samples["fhirpath_parse"]
+                .response_times_ms
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_shutdown_requires_admin_token() {
+        let security = SecurityConfig {
+            api_keys: vec!["regular-api-key".into()],
+            admin_token: Some("admin-token-123".to_string()),
+            ..SecurityConfig::default()
+        };
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0).with_security(security);
+        let shutdown = server.shutdown_signal();
+        let router = server.create_router();
+
+        let shutdown_request = |auth: Option<&str>| {
+            let mut builder = Request::builder().method("POST").uri("/admin/shutdown");
+            if let Some(auth) = auth {
+                builder = builder.header(header::AUTHORIZATION, auth);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(shutdown_request(None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = router
+            .clone()
+            .oneshot(shutdown_request(Some("Bearer regular-api-key")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!shutdown.has_changed().unwrap());
+        assert!(!*shutdown.borrow());
+
+        let response = router
+            .oneshot(shutdown_request(Some("Bearer admin-token-123")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(shutdown.has_changed().unwrap());
+        assert!(*shutdown.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_admin_shutdown_disabled_without_admin_token() {
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0);
+        let shutdown = server.shutdown_signal();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/admin/shutdown")
+            .header(header::AUTHORIZATION, "Bearer anything")
+            .body(Body::empty())
+            .unwrap();
+        let response = server.create_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!*shutdown.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_admin_metrics_reset() {
+        let security = SecurityConfig {
+            api_keys: vec!["regular-api-key".into()],
+            admin_token: Some("admin-token-123".to_string()),
+            ..SecurityConfig::default()
+        };
+        let reset_request = |auth: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/admin/metrics/reset")
+                .header(header::AUTHORIZATION, auth)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Disabled unless admin endpoints are explicitly enabled
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security.clone())
+            .create_router();
+        let response = router
+            .oneshot(reset_request("Bearer admin-token-123"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let server = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .with_admin_endpoints(true);
+        let state = server.create_state();
+        let metrics = state.metrics.clone();
+        let router = server.build_router(state);
+
+        metrics.record_tool_request("fhirpath_evaluate", Duration::from_millis(10), false);
+        metrics.record_request(Duration::from_millis(5), true);
+        metrics
+            .increment_custom_metric("bundles_processed", 2)
+            .await;
+        let uptime = metrics.health_monitor().uptime();
+
+        let response = router
+            .clone()
+            .oneshot(reset_request("Bearer regular-api-key"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(metrics.get_performance_metrics().total_requests, 2);
+
+        let response = router
+            .oneshot(reset_request("Bearer admin-token-123"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let snapshot = metrics.get_metrics_snapshot().await;
+        assert_eq!(snapshot.performance.total_requests, 0);
+        assert_eq!(snapshot.performance.error_rate_percent, 0.0);
+        assert!(snapshot.tools.is_empty());
+        assert!(snapshot.custom_metrics.is_empty());
+        assert!(metrics.health_monitor().uptime() >= uptime);
+    }
+
+    #[tokio::test]
+    async fn test_admin_expression_profile() {
+        let security = SecurityConfig {
+            api_keys: vec!["regular-api-key".into()],
+            admin_token: Some("admin-token-123".to_string()),
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .with_admin_endpoints(true)
+            .create_router();
+        let profile_request = |auth: &str| {
+            Request::builder()
+                .uri("/admin/profile/expressions")
+                .header(header::AUTHORIZATION, auth)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The profiler is process-wide, so these expressions are unique to this test
+        let given = "Patient.name.given.where($this = 'ProfiledGiven')";
+        let family = "Patient.name.family.where($this = 'ProfiledFamily')";
+        let patient = json!({"resourceType": "Patient", "name": [{"given": ["Ann"]}]});
+        for (expression, times) in [(given, 3), (family, 2)] {
+            for _ in 0..times {
+                let request = Request::builder()
+                    .method("POST")
+                    .uri("/mcp/tools/fhirpath_evaluate")
+                    .header(header::AUTHORIZATION, "Bearer regular-api-key")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        json!({"expression": format!("  {expression} "), "resource": patient})
+                            .to_string(),
+                    ))
+                    .unwrap();
+                let response = router.clone().oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        }
+
+        let response = router
+            .clone()
+            .oneshot(profile_request("Bearer regular-api-key"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router
+            .oneshot(profile_request("Bearer admin-token-123"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let expressions = body["expressions"].as_array().unwrap();
+        let calls = |expression: &str| {
+            expressions
+                .iter()
+                .find(|profile| profile["expression"] == expression)
+                .map(|profile| {
+                    (
+                        profile["calls"].clone(),
+                        profile["error_rate_percent"].clone(),
+                    )
+                })
+        };
+        assert_eq!(calls(given), Some((json!(3), json!(0.0))));
+        assert_eq!(calls(family), Some((json!(2), json!(0.0))));
+
+        let total_times: Vec<f64> = expressions
+            .iter()
+            .map(|profile| profile["total_time_ms"].as_f64().unwrap())
+            .collect();
+        assert!(total_times.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_openapi_document() {
+        let security = SecurityConfig {
+            api_keys: vec!["test-key-123".into()],
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .create_router();
+
+        let request = Request::builder()
+            .uri("/openapi.json")
+            .header(header::AUTHORIZATION, "Bearer test-key-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let document: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(document["openapi"], "3.1.0");
+        for tool in crate::server::tool_definitions().unwrap() {
+            let operation = &document["paths"][format!("/mcp/tools/{}", tool.name)]["post"];
+            assert!(operation.is_object(), "missing path for {}", tool.name);
+            assert_eq!(operation["security"], json!([{"bearerAuth": []}]));
+        }
+        assert_eq!(document["paths"]["/health"]["get"]["security"], json!([]));
+
+        let schema = &document["components"]["schemas"]["fhirpath_evaluate_input"];
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["properties"]["expression"]["type"], "string");
+        let required = schema["required"].as_array().unwrap();
+        assert!(required.contains(&json!("expression")));
+        assert!(required.contains(&json!("resource")));
+    }
+
+    #[tokio::test]
+    async fn test_tool_schema_endpoint() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let get_schema = |tool: &str| {
+            let request = Request::builder()
+                .uri(format!("/mcp/tools/{tool}/schema"))
+                .body(Body::empty())
+                .unwrap();
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        let (status, schema) = get_schema("fhirpath_evaluate").await;
+        assert_eq!(status, StatusCode::OK);
+        let listed = crate::server::tool_definitions()
+            .unwrap()
+            .into_iter()
+            .find(|tool| tool.name == "fhirpath_evaluate")
+            .unwrap();
+        assert_eq!(schema, Value::Object((*listed.input_schema).clone()));
+        assert_eq!(schema["properties"]["expression"]["type"], "string");
+
+        // The streaming route for fhirpath_extract doesn't shadow its schema
+        let (status, schema) = get_schema("fhirpath_extract").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(schema["properties"]["format"].is_object());
+
+        let (status, body) = get_schema("no_such_tool").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], -32601);
+    }
+
+    #[tokio::test]
+    async fn test_mcp_batch_request() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let post = |session: Option<String>, body: Value| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/mcp")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::ACCEPT, "application/json, text/event-stream");
+            if let Some(session) = session {
+                request = request.header("mcp-session-id", session);
+            }
+            router
+                .clone()
+                .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        };
+
+        let response = post(
+            None,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {},
+                    "clientInfo": {"name": "test-client", "version": "1.0"}
+                }
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let session = response.headers()["mcp-session-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let initialized = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        let response = post(Some(session.clone()), initialized).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "id": "list", "method": "tools/list"},
+            {
+                "jsonrpc": "2.0",
+                "id": "call",
+                "method": "tools/call",
+                "params": {
+                    "name": "fhirpath_evaluate",
+                    "arguments": {
+                        "expression": "Patient.id",
+                        "resource": {"resourceType": "Patient", "id": "p1"}
+                    }
+                }
+            },
+            {"jsonrpc": "2.0", "method": "notifications/roots/list_changed"}
+        ]);
+        let response = post(Some(session.clone()), batch).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let responses: Vec<Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], "list");
+        assert!(responses[0]["result"]["tools"].is_array());
+        assert_eq!(responses[1]["id"], "call");
+        let text = responses[1]["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(text).unwrap()["values"],
+            json!(["p1"])
+        );
+
+        // Notifications alone get no response body, and an empty batch is invalid
+        let notifications =
+            json!([{"jsonrpc": "2.0", "method": "notifications/roots/list_changed"}]);
+        let response = post(Some(session.clone()), notifications).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let response = post(Some(session), json!([])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            error["error"]["code"],
+            crate::transport::batch::INVALID_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_stream_sends_chunks() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let entries: Vec<Value> = (0..100)
+            .map(|i| json!({"resource": {"resourceType": "Patient", "id": format!("p{i}")}}))
+            .collect();
+        let body = json!({
+            "expression": "Bundle.entry.resource.id",
+            "resource": {"resourceType": "Bundle", "type": "collection", "entry": entries},
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/tools/fhirpath_extract/stream?chunk_size=15")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let events: Vec<(&str, Value)> = body
+            .split("\n\n")
+            .filter_map(|event| {
+                let name = event
+                    .lines()
+                    .find_map(|line| line.strip_prefix("event: "))?;
+                let data = event.lines().find_map(|line| line.strip_prefix("data: "))?;
+                Some((name, serde_json::from_str(data).unwrap()))
+            })
+            .collect();
+
+        let chunks: Vec<&Value> = events
+            .iter()
+            .filter(|(name, _)| *name == "data")
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(chunks.len(), 7);
+        let streamed: usize = chunks
+            .iter()
+            .map(|chunk| chunk["values"].as_array().unwrap().len())
+            .sum();
+        assert_eq!(streamed, 100);
+        assert_eq!(chunks[0]["values"][0], "p0");
+
+        let (name, summary) = events.last().unwrap();
+        assert_eq!(*name, "complete");
+        assert_eq!(summary["value_count"], 100);
+        assert_eq!(summary["chunk_count"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_evaluate_ndjson() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let body = [
+            json!({"resourceType": "Patient", "id": "a", "name": [{"given": ["Ann", "Marie"]}]}),
+            json!({"resourceType": "Patient", "id": "b"}),
+        ]
+        .iter()
+        .map(Value::to_string)
+        .chain(std::iter::once("{not json".to_string()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/bulk/evaluate?expression=Patient.name.given")
+            .header(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+            .body(Body::from(body))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            NDJSON_CONTENT_TYPE
+        );
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let lines: Vec<Value> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+
+        assert_eq!(lines[0]["resourceIndex"], 0);
+        assert_eq!(lines[0]["values"], json!(["Ann", "Marie"]));
+        assert_eq!(lines[0]["types"].as_array().unwrap().len(), 2);
+        assert_eq!(lines[1]["resourceIndex"], 1);
+        assert_eq!(lines[1]["values"], json!([]));
+        assert_eq!(lines[2]["resourceIndex"], 2);
+        assert!(
+            lines[2]["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("Invalid JSON")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_stream_checks_expression() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        // Nested deeper than the default `max_expression_depth`
+        let deep = format!(
+            "name{}",
+            ".where(given.exists()".repeat(40) + &")".repeat(40)
+        );
+
+        for expression in [" ", deep.as_str()] {
+            let body = json!({
+                "expression": expression,
+                "resource": {"resourceType": "Patient", "id": "p1"},
+            });
+            let request = Request::builder()
+                .method("POST")
+                .uri("/mcp/tools/fhirpath_extract/stream")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{expression}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"]["code"], -32602);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bulk_evaluate_checks_expression() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let deep = format!(
+            "name{}",
+            ".where(given.exists()".repeat(40) + &")".repeat(40)
+        );
+
+        for expression in ["%20", deep.as_str()] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/mcp/bulk/evaluate?expression={expression}"))
+                .header(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+                .body(Body::from(
+                    json!({"resourceType": "Patient", "id": "a"}).to_string(),
+                ))
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{expression}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bulk_errors_sanitized_in_production() {
+        let read_error = || Err("Failed to read /var/lib/octofhir/bulk.ndjson".to_string());
+        let production = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_config(&ServerConfig {
+                production: true,
+                ..ServerConfig::default()
+            })
+            .unwrap()
+            .create_state();
+        let line = evaluate_bulk_line(&production, "name", 3, read_error(), None).await;
+        assert_eq!(
+            line,
+            json!({"resourceIndex": 3, "error": {"message": "Failed to read <path>"}})
+        );
+
+        let development = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_state();
+        let line = evaluate_bulk_line(&development, "name", 3, read_error(), None).await;
+        assert_eq!(
+            line["error"]["message"],
+            "Failed to read /var/lib/octofhir/bulk.ndjson"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compressed_request_and_response() {
+        use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+        use std::io::{Read, Write};
+
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let given: Vec<String> = (0..500).map(|i| format!("name-{i}")).collect();
+        let body = json!({
+            "expression": "Patient.name.given",
+            "resource": {"resourceType": "Patient", "name": [{"given": given}]},
+        });
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.to_string().as_bytes()).unwrap();
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/tools/fhirpath_evaluate")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::from(encoder.finish().unwrap()))
+            .unwrap();
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let compressed = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut decoded = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert!(compressed.len() < decoded.len());
+        let decoded: Value = serde_json::from_str(&decoded).unwrap();
+        assert_eq!(decoded["result"]["values"].as_array().unwrap().len(), 500);
+
+        // Clients that do not ask for compression get plain responses
+        let (status, body) = post_tool(
+            router,
+            "fhirpath_evaluate",
+            json!({"expression": "Patient.id", "resource": {"resourceType": "Patient", "id": "p1"}}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["result"]["values"], json!(["p1"]));
+    }
+
+    #[tokio::test]
+    async fn test_correlation_id_echoed() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+
+        let request = Request::builder()
+            .uri("/health")
+            .header(CORRELATION_ID_HEADER, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[CORRELATION_ID_HEADER], "abc-123");
+
+        let response = router.oneshot(health_request()).await.unwrap();
+        assert!(response.headers().contains_key(CORRELATION_ID_HEADER));
+    }
+
+    /// Log output written by a test subscriber
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_are_audited() {
+        let security = SecurityConfig {
+            api_keys: vec!["auditor-key".into()],
+            ..SecurityConfig::default()
+        };
+        let sink = CapturedLogs::default();
+        let audit_log = AuditLog::from_writer(sink.clone()).unwrap();
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .with_audit_log(audit_log.clone())
+            .create_router();
+
+        for expression in ["Patient.name.given", "Patient.name.where("] {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/mcp/tools/fhirpath_extract")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, "Bearer auditor-key")
+                .header(CORRELATION_ID_HEADER, "audit-1")
+                .body(Body::from(
+                    json!({
+                        "expression": expression,
+                        "resource": {"resourceType": "Patient", "name": [{"given": ["Confidential"]}]}
+                    })
+                    .to_string(),
+                ))
+                .unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+        audit_log.flush();
+
+        let output = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains("Confidential"));
+        let records: Vec<AuditRecord> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+
+        let record = &records[0];
+        assert!(record.subject.is_some());
+        assert_eq!(record.tool, "fhirpath_extract");
+        assert_eq!(record.expressions, vec!["Patient.name.given"]);
+        assert_eq!(record.resource_type.as_deref(), Some("Patient"));
+        assert_eq!(record.correlation_id.as_deref(), Some("audit-1"));
+        assert!(record.success);
+        assert_eq!(record.error_code, None);
+
+        assert!(!records[1].success);
+        assert_eq!(records[1].error_code, Some(-32000));
+
+        // Streamed extraction is one call; each line of a bulk evaluation is a call of its own
+        let requests = [
+            (
+                "/mcp/tools/fhirpath_extract/stream",
+                "application/json",
+                json!({"expression": "name.given", "resource": {"resourceType": "Patient"}})
+                    .to_string(),
+            ),
+            (
+                "/mcp/bulk/evaluate?expression=id",
+                NDJSON_CONTENT_TYPE,
+                format!(
+                    "{}\nnot json\n",
+                    json!({"resourceType": "Observation", "id": "o1"})
+                ),
+            ),
+        ];
+        for (uri, content_type, body) in requests {
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::AUTHORIZATION, "Bearer auditor-key")
+                .body(Body::from(body))
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+        }
+        audit_log.flush();
+
+        let output = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<AuditRecord> = output
+            .lines()
+            .skip(2)
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.tool.as_str(),
+                    record.resource_type.as_deref(),
+                    record.error_code,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("fhirpath_extract", Some("Patient"), None),
+                ("fhirpath_evaluate", Some("Observation"), None),
+                ("fhirpath_evaluate", None, Some(-32602)),
+            ]
+        );
+        assert!(records.iter().all(|record| record.subject.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_correlation_id_in_tool_logs_and_diagnostics() {
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/tools/fhirpath_evaluate")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(CORRELATION_ID_HEADER, "trace-me-42")
+            .body(Body::from(
+                json!({"expression": "Patient.name.where(", "resource": {"resourceType": "Patient"}})
+                    .to_string(),
+            ))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["result"]["diagnostics"].is_array());
+        assert_eq!(body["result"]["correlation_id"], "trace-me-42");
+
+        // The engine logs from a spawned task, which must still carry the request's ID
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let engine_event = logs
+            .lines()
+            .find(|line| line.contains("Evaluating FHIRPath expression"))
+            .expect("engine log event");
+        let (_, tool_span) = engine_event
+            .split_once("fhirpath_evaluate{")
+            .expect("event inside the tool span");
+        assert!(tool_span.contains("trace-me-42"));
+    }
+
+    #[test]
+    fn test_credentialed_cors_rejects_wildcard_origin() {
+        let cors = CorsConfig {
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        let err = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_cors(cors)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("allowed_origins"));
+    }
+
+    #[tokio::test]
+    async fn test_credentialed_cors_headers() {
+        let cors = CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            allowed_headers: vec!["authorization".to_string(), "content-type".to_string()],
+            exposed_headers: vec![CORRELATION_ID_HEADER.to_string()],
+            allow_credentials: true,
+        };
+        let security = SecurityConfig {
+            api_keys: vec!["test-key-123".into()],
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .with_cors(cors)
+            .unwrap()
+            .create_router();
+
+        // Preflight requests succeed without credentials
+        let preflight = Request::builder()
+            .method("OPTIONS")
+            .uri("/mcp/tools/fhirpath_parse")
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(preflight).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "authorization,content-type"
+        );
+
+        let request = Request::builder()
+            .uri("/stats")
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::AUTHORIZATION, "Bearer test-key-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_EXPOSE_HEADERS],
+            CORRELATION_ID_HEADER
+        );
+
+        // Other origins are not granted access
+        let request = Request::builder()
+            .uri("/health")
+            .header(header::ORIGIN, "https://evil.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_tool_call_replayed() {
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+        let tool_request = |key: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/mcp/tools/fhirpath_evaluate")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(IDEMPOTENCY_KEY_HEADER, key)
+                .body(Body::from(
+                    json!({
+                        "expression": "Patient.id",
+                        "resource": {"resourceType": "Patient", "id": "p1"},
+                    })
+                    .to_string(),
+                ))
+                .unwrap()
+        };
+        let send = |request: Request| {
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let replayed = response.headers().contains_key(IDEMPOTENT_REPLAY_HEADER);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (serde_json::from_slice::<Value>(&body).unwrap(), replayed)
+            }
+        };
+
+        let (first, replayed) = send(tool_request("retry-1")).await;
+        assert!(!replayed);
+        assert_eq!(first["result"]["values"], json!(["p1"]));
+
+        let (second, replayed) = send(tool_request("retry-1")).await;
+        assert!(replayed);
+        assert_eq!(second, first);
+
+        // The same key with other arguments is rejected rather than replayed
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp/tools/fhirpath_evaluate")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(IDEMPOTENCY_KEY_HEADER, "retry-1")
+            .body(Body::from(
+                json!({
+                    "expression": "Patient.id",
+                    "resource": {"resourceType": "Patient", "id": "p2"},
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = Request::builder()
+            .uri("/stats")
+            .body(Body::empty())
+            .unwrap();
+        let (stats, _) = send(request).await;
+        assert_eq!(stats["tools"]["fhirpath_evaluate"]["total_requests"], 1);
+
+        // A different key evaluates again
+        send(tool_request("retry-2")).await;
+        let request = Request::builder()
+            .uri("/stats")
+            .body(Body::empty())
+            .unwrap();
+        let (stats, _) = send(request).await;
+        assert_eq!(stats["tools"]["fhirpath_evaluate"]["total_requests"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_disabled() {
+        let security = SecurityConfig {
+            rate_limit_per_minute: 0,
+            ..SecurityConfig::default()
+        };
+        let router = HttpTransportServer::new("127.0.0.1".to_string(), 0)
+            .with_security(security)
+            .create_router();
+
+        for _ in 0..10 {
+            let response = router.clone().oneshot(health_request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Idempotency keys for retried HTTP tool calls
+//!
+//! A client may send an `Idempotency-Key` header with a tool call. The first request with a
+//! given key runs the tool and stores its response; replays within the TTL get the stored
+//! response without re-evaluating. Keys are scoped to the calling client, and a key reused
+//! for a different tool or different arguments is rejected instead of replayed.
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Header carrying the client-chosen idempotency key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header set on responses served from the idempotency cache
+pub const IDEMPOTENT_REPLAY_HEADER: &str = "idempotent-replayed";
+
+/// Longest accepted idempotency key
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Default time responses to idempotent tool calls are kept for replay
+pub const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(600);
+
+/// How often expired responses are dropped
+pub const IDEMPOTENCY_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Digest of the tool and arguments of a request, compared on replay
+pub type Fingerprint = [u8; 32];
+
+/// Fingerprint of a call to `tool` with `arguments`. Object keys are serialized in sorted
+/// order, so the fingerprint doesn't depend on how the client ordered them.
+pub fn fingerprint(tool: &str, arguments: &Value) -> Fingerprint {
+    let mut hasher = Sha256::new();
+    hasher.update(tool.as_bytes());
+    hasher.update([0]);
+    hasher.update(arguments.to_string().as_bytes());
+    hasher.finalize().into()
+}
+
+/// A completed tool call response
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+#[derive(Debug)]
+enum Entry {
+    InFlight(Fingerprint),
+    Completed {
+        fingerprint: Fingerprint,
+        response: CachedResponse,
+        expires_at: Instant,
+    },
+}
+
+/// Outcome of claiming an idempotency key
+#[derive(Debug)]
+pub enum Reservation {
+    /// The key is new: run the request and store its response through the guard
+    Acquired(ReservationGuard),
+    /// A request with the same key is still running
+    InFlight,
+    /// A request with the same key completed; replay its response
+    Completed(CachedResponse),
+    /// The key was used for a different tool or different arguments
+    Mismatch,
+}
+
+/// Responses of idempotent requests keyed by (client, idempotency key)
+#[derive(Debug, Clone)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<(String, String), Entry>>>,
+}
+
+impl IdempotencyCache {
+    /// Create a cache keeping completed responses for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Claim `key` for `client`, or report the state of an earlier request with the same key.
+    /// `fingerprint` identifies the request, see [`fingerprint`].
+    pub fn begin(&self, client: &str, key: &str, fingerprint: Fingerprint) -> Reservation {
+        let entry_key = (client.to_string(), key.to_string());
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(&entry_key) {
+            Some(Entry::InFlight(earlier)) if *earlier != fingerprint => {
+                return Reservation::Mismatch;
+            }
+            Some(Entry::InFlight(_)) => return Reservation::InFlight,
+            Some(Entry::Completed {
+                fingerprint: earlier,
+                response,
+                expires_at,
+            }) if *expires_at > Instant::now() => {
+                if *earlier != fingerprint {
+                    return Reservation::Mismatch;
+                }
+                return Reservation::Completed(response.clone());
+            }
+            _ => {}
+        }
+
+        entries.insert(entry_key.clone(), Entry::InFlight(fingerprint));
+        Reservation::Acquired(ReservationGuard {
+            cache: self.clone(),
+            key: Some(entry_key),
+            fingerprint,
+        })
+    }
+
+    /// Drop completed responses whose TTL has passed
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.entries.lock().unwrap().retain(|_, entry| match entry {
+            Entry::InFlight(_) => true,
+            Entry::Completed { expires_at, .. } => *expires_at > now,
+        });
+    }
+
+    /// Number of keys currently tracked
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Claim on an idempotency key. Dropping it without completing, e.g. when the request is
+/// cancelled, releases the key so the client can retry.
+#[derive(Debug)]
+pub struct ReservationGuard {
+    cache: IdempotencyCache,
+    key: Option<(String, String)>,
+    fingerprint: Fingerprint,
+}
+
+impl ReservationGuard {
+    /// Store the response to replay for this key
+    pub fn complete(mut self, response: CachedResponse) {
+        if let Some(key) = self.key.take() {
+            let expires_at = Instant::now() + self.cache.ttl;
+            self.cache.entries.lock().unwrap().insert(
+                key,
+                Entry::Completed {
+                    fingerprint: self.fingerprint,
+                    response,
+                    expires_at,
+                },
+            );
+        }
+    }
+}
+
+impl Drop for ReservationGuard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.entries.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_reservation_lifecycle() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60));
+        let request = fingerprint("fhirpath_evaluate", &json!({"expression": "Patient.id"}));
+
+        let Reservation::Acquired(guard) = cache.begin("client-a", "key-1", request) else {
+            panic!("new key should be acquired");
+        };
+        assert!(matches!(
+            cache.begin("client-a", "key-1", request),
+            Reservation::InFlight
+        ));
+        // Keys are scoped per client
+        assert!(matches!(
+            cache.begin("client-b", "key-1", request),
+            Reservation::Acquired(_)
+        ));
+
+        let response = CachedResponse {
+            status: 200,
+            body: json!({"result": 1}),
+        };
+        guard.complete(response.clone());
+        match cache.begin("client-a", "key-1", request) {
+            Reservation::Completed(cached) => assert_eq!(cached, response),
+            other => panic!("expected completed response, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_reused_key_with_different_request_is_rejected() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60));
+        let arguments = json!({"expression": "Patient.id", "resource": {"id": "p1"}});
+        let request = fingerprint("fhirpath_evaluate", &arguments);
+
+        // Key order doesn't change the fingerprint
+        let reordered: Value =
+            serde_json::from_str(r#"{"resource": {"id": "p1"}, "expression": "Patient.id"}"#)
+                .unwrap();
+        assert_eq!(fingerprint("fhirpath_evaluate", &reordered), request);
+
+        let other_arguments = fingerprint(
+            "fhirpath_evaluate",
+            &json!({"expression": "Patient.id", "resource": {"id": "p2"}}),
+        );
+        let other_tool = fingerprint("fhirpath_extract", &arguments);
+
+        let Reservation::Acquired(guard) = cache.begin("client", "key", request) else {
+            panic!("new key should be acquired");
+        };
+        assert!(matches!(
+            cache.begin("client", "key", other_arguments),
+            Reservation::Mismatch
+        ));
+        guard.complete(CachedResponse {
+            status: 200,
+            body: Value::Null,
+        });
+        assert!(matches!(
+            cache.begin("client", "key", other_tool),
+            Reservation::Mismatch
+        ));
+        assert!(matches!(
+            cache.begin("client", "key", request),
+            Reservation::Completed(_)
+        ));
+    }
+
+    #[test]
+    fn test_abandoned_and_expired_keys_are_released() {
+        let cache = IdempotencyCache::new(Duration::ZERO);
+        let request = fingerprint("fhirpath_parse", &json!({"expression": "Patient"}));
+
+        drop(cache.begin("client", "cancelled", request));
+        assert!(cache.is_empty());
+
+        let Reservation::Acquired(guard) = cache.begin("client", "expired", request) else {
+            panic!("new key should be acquired");
+        };
+        guard.complete(CachedResponse {
+            status: 200,
+            body: Value::Null,
+        });
+        assert!(matches!(
+            cache.begin("client", "expired", request),
+            Reservation::Acquired(_)
+        ));
+
+        cache.prune();
+        assert!(cache.is_empty());
+    }
+}
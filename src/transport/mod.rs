@@ -0,0 +1,65 @@
+//! Transport integration using rmcp SDK
+//!
+//! This module provides actual MCP protocol transport implementations
+//! using the official rmcp SDK.
+
+pub mod batch;
+pub mod http;
+pub mod idempotency;
+pub mod openapi;
+pub mod session;
+pub mod stdio;
+pub mod tls;
+pub mod versioning;
+
+pub use http::HttpTransportServer;
+pub use stdio::StdioTransportServer;
+
+/// Factory for creating transport servers
+pub struct TransportFactory;
+
+impl TransportFactory {
+    /// Create an HTTP transport server
+    pub fn create_http(host: &str, port: u16) -> HttpTransportServer {
+        HttpTransportServer::new(host.to_string(), port)
+    }
+
+    /// Create a stdio transport server
+    pub fn create_stdio() -> StdioTransportServer {
+        StdioTransportServer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_http_transport_creation() {
+        let transport = TransportFactory::create_http("127.0.0.1", 3002);
+        assert_eq!(transport.host, "127.0.0.1");
+        assert_eq!(transport.port, 3002);
+    }
+
+    #[tokio::test]
+    async fn test_stdio_transport_creation() {
+        let transport = TransportFactory::create_stdio();
+        // Test that we can create a stdio transport without errors; empty input shuts it down
+        let result = transport.serve(tokio::io::empty(), tokio::io::sink()).await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_factory_methods() {
+        let http_transport = TransportFactory::create_http("localhost", 8080);
+        assert_eq!(http_transport.host, "localhost");
+        assert_eq!(http_transport.port, 8080);
+
+        let stdio_transport = TransportFactory::create_stdio();
+        // Just verify it was created successfully
+        assert_eq!(
+            std::mem::size_of_val(&stdio_transport),
+            std::mem::size_of::<StdioTransportServer>()
+        );
+    }
+}
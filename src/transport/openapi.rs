@@ -0,0 +1,479 @@
+//! OpenAPI description of the HTTP transport's REST routes
+//!
+//! Tool request bodies use the same JSON schemas that `tools/list` advertises, so the
+//! document stays in sync with the tools as they change.
+
+use rmcp::ErrorData;
+use serde_json::{Map, Value, json};
+
+use crate::server::tool_definitions;
+
+/// Name of the bearer security scheme in the document's components
+const BEARER_SCHEME: &str = "bearerAuth";
+
+/// Query parameter asking for an indented JSON response body
+fn pretty_parameter() -> Value {
+    json!({
+        "name": "pretty",
+        "in": "query",
+        "required": false,
+        "description": "Indent the JSON response body",
+        "schema": {"type": "boolean", "default": false}
+    })
+}
+
+/// Build an OpenAPI 3.1 document for the REST routes. When `auth_enabled` is set, every
+/// route except `/health` and `/version` is marked as requiring a bearer token.
+pub fn openapi_document(auth_enabled: bool) -> Result<Value, ErrorData> {
+    let security = |required: bool| {
+        if auth_enabled && required {
+            json!([{ BEARER_SCHEME: [] }])
+        } else {
+            json!([])
+        }
+    };
+
+    let mut schemas = Map::new();
+    schemas.insert(
+        "Error".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "properties": {
+                        "code": {"type": "integer"},
+                        "message": {"type": "string"},
+                        "data": {"type": "object"}
+                    },
+                    "required": ["code", "message"]
+                },
+                "api_version": {"type": "string"}
+            },
+            "required": ["error"]
+        }),
+    );
+
+    let mut paths = Map::new();
+    paths.insert(
+        "/health".to_string(),
+        json!({
+            "get": {
+                "summary": "Liveness check",
+                "operationId": "health",
+                "security": security(false),
+                "responses": {
+                    "200": {
+                        "description": "Server is running",
+                        "content": {"application/json": {"schema": {
+                            "type": "object",
+                            "properties": {
+                                "status": {"type": "string"},
+                                "version": {"type": "string"}
+                            }
+                        }}}
+                    }
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/ready".to_string(),
+        json!({
+            "get": {
+                "summary": "Readiness check; not ready until startup warm-up completes",
+                "operationId": "ready",
+                "security": security(false),
+                "responses": {
+                    "200": {"description": "Server is ready to serve requests"},
+                    "503": {"description": "Server is still warming up or a readiness check fails"}
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/version".to_string(),
+        json!({
+            "get": {
+                "summary": "Build information and the FHIR version served",
+                "operationId": "version",
+                "security": security(false),
+                "responses": {
+                    "200": {
+                        "description": "Build information",
+                        "content": {"application/json": {"schema": {
+                            "type": "object",
+                            "properties": {
+                                "version": {"type": "string"},
+                                "git_commit": {"type": "string"},
+                                "build_timestamp": {"type": ["string", "null"]},
+                                "rustc_version": {"type": "string"},
+                                "fhir_version": {"type": "string"}
+                            }
+                        }}}
+                    }
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/stats".to_string(),
+        json!({
+            "get": {
+                "summary": "Global and per-tool request metrics",
+                "operationId": "stats",
+                "security": security(true),
+                "parameters": [pretty_parameter()],
+                "responses": {
+                    "200": {
+                        "description": "Metrics snapshot",
+                        "content": {"application/json": {"schema": {"type": "object"}}}
+                    }
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/metrics".to_string(),
+        json!({
+            "get": {
+                "summary": "Metrics for scraping",
+                "description": "Answers in the OpenMetrics format when the Accept header \
+                                prefers application/openmetrics-text, and in the Prometheus \
+                                text format otherwise.",
+                "operationId": "metrics",
+                "security": security(true),
+                "responses": {
+                    "200": {
+                        "description": "Metrics in the negotiated text format",
+                        "content": {
+                            "text/plain": {"schema": {"type": "string"}},
+                            "application/openmetrics-text": {"schema": {"type": "string"}}
+                        }
+                    }
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/admin/shutdown".to_string(),
+        json!({
+            "post": {
+                "summary": "Stop accepting connections and drain in-flight requests",
+                "description": "Requires the admin token as a bearer credential; API keys and \
+                                JWTs are refused.",
+                "operationId": "adminShutdown",
+                "security": security(true),
+                "responses": {
+                    "202": {"description": "Graceful shutdown started"},
+                    "401": {"description": "Missing or malformed credentials"},
+                    "403": {"description": "Credential is not the admin token, or no admin \
+                                            token is configured"}
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/admin/metrics/reset".to_string(),
+        json!({
+            "post": {
+                "summary": "Zero request, per-tool and custom metrics, keeping uptime",
+                "description": "Served only when admin endpoints are enabled. Requires the admin \
+                                token as a bearer credential; API keys and JWTs are refused.",
+                "operationId": "adminMetricsReset",
+                "security": security(true),
+                "responses": {
+                    "200": {"description": "Metrics reset"},
+                    "401": {"description": "Missing or malformed credentials"},
+                    "403": {"description": "Credential is not the admin token, or no admin \
+                                            token is configured"},
+                    "404": {"description": "Admin endpoints are disabled"}
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/admin/profile/expressions".to_string(),
+        json!({
+            "get": {
+                "summary": "Call count, latency and error rate per evaluated expression",
+                "description": "Covers the most recently evaluated expressions, sorted by total \
+                                evaluation time. Served only when admin endpoints are enabled. \
+                                Requires the admin token as a bearer credential.",
+                "operationId": "adminExpressionProfile",
+                "security": security(true),
+                "responses": {
+                    "200": {
+                        "description": "Expression statistics",
+                        "content": {"application/json": {"schema": {"type": "object"}}}
+                    },
+                    "401": {"description": "Missing or malformed credentials"},
+                    "403": {"description": "Credential is not the admin token, or no admin \
+                                            token is configured"},
+                    "404": {"description": "Admin endpoints are disabled"}
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/openapi.json".to_string(),
+        json!({
+            "get": {
+                "summary": "This document",
+                "operationId": "openapi",
+                "security": security(true),
+                "responses": {
+                    "200": {
+                        "description": "OpenAPI document",
+                        "content": {"application/json": {"schema": {"type": "object"}}}
+                    }
+                }
+            }
+        }),
+    );
+
+    for tool in tool_definitions()? {
+        let name = tool.name.to_string();
+        let schema_name = format!("{name}_input");
+        let mut schema = Value::Object((*tool.input_schema).clone());
+        lift_definitions(&mut schema, &mut schemas);
+        schemas.insert(schema_name.clone(), schema);
+
+        paths.insert(
+            format!("/mcp/tools/{name}"),
+            json!({
+                "post": {
+                    "summary": tool.description.as_deref().unwrap_or_default(),
+                    "operationId": name,
+                    "security": security(true),
+                    "parameters": [{
+                        "name": "Idempotency-Key",
+                        "in": "header",
+                        "required": false,
+                        "description": "Replay the stored response for retries with the same key",
+                        "schema": {"type": "string", "maxLength": 255}
+                    }, {
+                        "name": "X-MCP-Accept-Version",
+                        "in": "header",
+                        "required": false,
+                        "description": "Response versions the client accepts, most preferred first",
+                        "schema": {"type": "string", "example": "1.0"}
+                    }, pretty_parameter()],
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {
+                            "$ref": format!("#/components/schemas/{schema_name}")
+                        }}}
+                    },
+                    "responses": tool_responses(),
+                }
+            }),
+        );
+    }
+
+    paths.insert(
+        "/mcp/tools/{tool_name}/schema".to_string(),
+        json!({
+            "get": {
+                "summary": "Input JSON Schema of a single tool, as listed by tools/list",
+                "operationId": "tool_schema",
+                "security": security(true),
+                "parameters": [{
+                    "name": "tool_name",
+                    "in": "path",
+                    "required": true,
+                    "schema": {"type": "string"}
+                }],
+                "responses": {
+                    "200": {
+                        "description": "Input schema",
+                        "content": {"application/json": {"schema": {"type": "object"}}}
+                    },
+                    "401": {"description": "Missing or invalid credentials"},
+                    "404": {
+                        "description": "Unknown tool",
+                        "content": {"application/json": {"schema": {
+                            "$ref": "#/components/schemas/Error"
+                        }}}
+                    }
+                }
+            }
+        }),
+    );
+
+    paths.insert(
+        "/mcp/tools/fhirpath_extract/stream".to_string(),
+        json!({
+            "post": {
+                "summary": "Stream fhirpath_extract results as server-sent events",
+                "operationId": "fhirpath_extract_stream",
+                "security": security(true),
+                "parameters": [{
+                    "name": "chunk_size",
+                    "in": "query",
+                    "required": false,
+                    "description": "Values per `data` event (default 100)",
+                    "schema": {"type": "integer", "minimum": 1}
+                }],
+                "requestBody": {
+                    "required": true,
+                    "content": {"application/json": {"schema": {
+                        "$ref": "#/components/schemas/fhirpath_extract_input"
+                    }}}
+                },
+                "responses": {
+                    "200": {
+                        "description": "`data` events with chunks of values, then a `complete` \
+                                        event with a summary or an `error` event",
+                        "content": {"text/event-stream": {"schema": {"type": "string"}}}
+                    },
+                    "403": {"description": "Caller is not allowed to call fhirpath_extract"}
+                }
+            }
+        }),
+    );
+
+    paths.insert(
+        "/mcp/bulk/evaluate".to_string(),
+        json!({
+            "post": {
+                "summary": "Evaluate an expression against each resource of an NDJSON body",
+                "operationId": "bulk_evaluate",
+                "security": security(true),
+                "parameters": [{
+                    "name": "expression",
+                    "in": "query",
+                    "required": true,
+                    "description": "FHIRPath expression to evaluate",
+                    "schema": {"type": "string"}
+                }],
+                "requestBody": {
+                    "required": true,
+                    "content": {"application/x-ndjson": {"schema": {"type": "string"}}}
+                },
+                "responses": {
+                    "200": {
+                        "description": "One `{resourceIndex, values, types}` or \
+                                        `{resourceIndex, error}` line per resource",
+                        "content": {"application/x-ndjson": {"schema": {"type": "string"}}}
+                    },
+                    "403": {"description": "Caller is not allowed to call fhirpath_evaluate"}
+                }
+            }
+        }),
+    );
+
+    let mut components = json!({ "schemas": schemas });
+    if auth_enabled {
+        components["securitySchemes"] = json!({
+            BEARER_SCHEME: {
+                "type": "http",
+                "scheme": "bearer",
+                "description": "API key or HS256-signed JWT"
+            }
+        });
+    }
+
+    Ok(json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "OctoFHIR MCP Server",
+            "version": crate::VERSION,
+            "description": "REST access to the FHIRPath tools served over MCP"
+        },
+        "paths": paths,
+        "components": components,
+    }))
+}
+
+fn tool_responses() -> Value {
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}}
+        })
+    };
+
+    json!({
+        "200": {
+            "description": "Tool result",
+            "content": {"application/json": {"schema": {
+                "type": "object",
+                "properties": {
+                    "api_version": {"type": "string"},
+                    "result": {"type": "object"}
+                },
+                "required": ["api_version", "result"]
+            }}}
+        },
+        "400": error("Invalid tool parameters"),
+        "401": {"description": "Missing or invalid credentials"},
+        "403": error("Caller is not allowed to call this tool"),
+        "404": error("Unknown tool"),
+        "406": {"description": "None of the versions in X-MCP-Accept-Version is supported"},
+        "409": {"description": "A request with the same idempotency key is in progress"},
+        "413": error("FHIR resource too large"),
+        "422": error("Evaluation failed, or the idempotency key was used for a different request"),
+        "429": {"description": "Rate limit exceeded"},
+        "503": error("Server busy, no evaluation slot available"),
+        "504": error("Evaluation timed out (error code -32003)")
+    })
+}
+
+/// Move a schema's local `$defs` into the shared component schemas, rewriting references so
+/// they resolve from the document root
+fn lift_definitions(schema: &mut Value, schemas: &mut Map<String, Value>) {
+    if let Some(Value::Object(definitions)) = schema
+        .as_object_mut()
+        .and_then(|schema| schema.remove("$defs"))
+    {
+        for (name, mut definition) in definitions {
+            rewrite_refs(&mut definition);
+            schemas.insert(name, definition);
+        }
+    }
+    rewrite_refs(schema);
+}
+
+fn rewrite_refs(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match value {
+                    Value::String(reference) if key == "$ref" => {
+                        if let Some(name) = reference.strip_prefix("#/$defs/") {
+                            *reference = format!("#/components/schemas/{name}");
+                        }
+                    }
+                    value => rewrite_refs(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lift_definitions() {
+        let mut schemas = Map::new();
+        let mut schema = json!({
+            "type": "object",
+            "properties": {"format": {"$ref": "#/$defs/Format"}},
+            "$defs": {"Format": {"type": "string", "enum": ["values", "paths"]}}
+        });
+
+        lift_definitions(&mut schema, &mut schemas);
+
+        assert!(schema.get("$defs").is_none());
+        assert_eq!(
+            schema["properties"]["format"]["$ref"],
+            "#/components/schemas/Format"
+        );
+        assert_eq!(schemas["Format"]["enum"], json!(["values", "paths"]));
+    }
+}
@@ -0,0 +1,293 @@
+//! MCP session management for the streamable HTTP transport
+//!
+//! Sessions live in rmcp's [`LocalSessionManager`], which keeps a bounded buffer of recent
+//! SSE messages per stream. When a client reconnects with `Last-Event-ID`, the buffered
+//! messages it missed are replayed before live streaming resumes. The local manager replays
+//! from the given event inclusive, so this wrapper drops events the client already has.
+//!
+//! Messages older than the buffer are lost. A resumed stream then starts with a `lagged`
+//! log notification telling the client how many it missed, so it can reconcile by
+//! re-fetching rather than silently working from partial state.
+
+use futures_util::{Stream, StreamExt, stream};
+use rmcp::{
+    model::{
+        ClientJsonRpcMessage, LoggingLevel, LoggingMessageNotificationParam, Notification,
+        ServerJsonRpcMessage, ServerNotification,
+    },
+    transport::{
+        common::server_side_http::ServerSseMessage,
+        streamable_http_server::{
+            SessionId, SessionManager,
+            session::local::{LocalSessionManager, LocalSessionManagerError, SessionConfig},
+        },
+    },
+};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default number of recent messages buffered per SSE stream for replay
+pub const DEFAULT_SSE_REPLAY_BUFFER: usize = SessionConfig::DEFAULT_CHANNEL_CAPACITY;
+
+/// Session manager replaying only messages newer than a reconnecting client's `Last-Event-ID`
+#[derive(Debug)]
+pub struct ResumableSessionManager {
+    inner: LocalSessionManager,
+}
+
+impl Default for ResumableSessionManager {
+    fn default() -> Self {
+        Self::new(DEFAULT_SSE_REPLAY_BUFFER)
+    }
+}
+
+impl ResumableSessionManager {
+    /// Create a manager buffering up to `replay_buffer` recent messages per stream
+    pub fn new(replay_buffer: usize) -> Self {
+        Self {
+            inner: LocalSessionManager {
+                sessions: Default::default(),
+                session_config: SessionConfig {
+                    channel_capacity: replay_buffer.max(1),
+                    ..SessionConfig::default()
+                },
+            },
+        }
+    }
+
+    /// Close sessions after `timeout` without activity; sessions never expire when `None`
+    pub fn with_session_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.inner.session_config.keep_alive = timeout;
+        self
+    }
+
+    /// Buffering and expiry applied to new sessions
+    pub fn session_config(&self) -> &SessionConfig {
+        &self.inner.session_config
+    }
+}
+
+/// Logger name of the notification announcing messages lost to a full replay buffer
+pub const LAGGED_LOGGER: &str = "lagged";
+
+/// Position of an event in its stream; rmcp event ids are `index` or `index/request`
+fn event_index(event_id: &str) -> Option<usize> {
+    event_id.split('/').next()?.parse().ok()
+}
+
+/// Notification that `missed` messages were dropped from the replay buffer before the
+/// client resumed. It has no event id, so it doesn't move the client's `Last-Event-ID`.
+fn lagged_message(missed: usize) -> ServerSseMessage {
+    let notification = ServerNotification::LoggingMessageNotification(Notification::new(
+        LoggingMessageNotificationParam {
+            level: LoggingLevel::Warning,
+            logger: Some(LAGGED_LOGGER.to_string()),
+            data: json!({ "event": "lagged", "missed": missed }),
+        },
+    ));
+    ServerSseMessage {
+        event_id: None,
+        message: Arc::new(ServerJsonRpcMessage::notification(notification)),
+    }
+}
+
+impl SessionManager for ResumableSessionManager {
+    type Error = LocalSessionManagerError;
+    type Transport = <LocalSessionManager as SessionManager>::Transport;
+
+    async fn create_session(&self) -> Result<(SessionId, Self::Transport), Self::Error> {
+        self.inner.create_session().await
+    }
+
+    async fn initialize_session(
+        &self,
+        id: &SessionId,
+        message: ClientJsonRpcMessage,
+    ) -> Result<ServerJsonRpcMessage, Self::Error> {
+        self.inner.initialize_session(id, message).await
+    }
+
+    async fn has_session(&self, id: &SessionId) -> Result<bool, Self::Error> {
+        self.inner.has_session(id).await
+    }
+
+    async fn close_session(&self, id: &SessionId) -> Result<(), Self::Error> {
+        self.inner.close_session(id).await
+    }
+
+    async fn create_stream(
+        &self,
+        id: &SessionId,
+        message: ClientJsonRpcMessage,
+    ) -> Result<impl Stream<Item = ServerSseMessage> + Send + Sync + 'static, Self::Error> {
+        self.inner.create_stream(id, message).await
+    }
+
+    async fn accept_message(
+        &self,
+        id: &SessionId,
+        message: ClientJsonRpcMessage,
+    ) -> Result<(), Self::Error> {
+        self.inner.accept_message(id, message).await
+    }
+
+    async fn create_standalone_stream(
+        &self,
+        id: &SessionId,
+    ) -> Result<impl Stream<Item = ServerSseMessage> + Send + Sync + 'static, Self::Error> {
+        self.inner.create_standalone_stream(id).await
+    }
+
+    async fn resume(
+        &self,
+        id: &SessionId,
+        last_event_id: String,
+    ) -> Result<impl Stream<Item = ServerSseMessage> + Send + Sync + 'static, Self::Error> {
+        let last_index = event_index(&last_event_id);
+        let stream = self.inner.resume(id, last_event_id).await?;
+
+        // The first message after the client's last one tells whether any were lost
+        let mut expected = last_index.map(|index| index + 1);
+        Ok(stream
+            .filter(move |message| {
+                let index = message.event_id.as_deref().and_then(event_index);
+                let newer = match (last_index, index) {
+                    (Some(last_index), Some(index)) => index > last_index,
+                    _ => true,
+                };
+                std::future::ready(newer)
+            })
+            .flat_map(move |message| {
+                let index = message.event_id.as_deref().and_then(event_index);
+                let lagged = match (expected.take(), index) {
+                    (Some(expected), Some(index)) if index > expected => {
+                        Some(lagged_message(index - expected))
+                    }
+                    _ => None,
+                };
+                stream::iter(lagged.into_iter().chain([message]))
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::transport::Transport;
+
+    fn log_message(data: usize) -> ServerJsonRpcMessage {
+        serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/message",
+            "params": {"level": "info", "data": data}
+        }))
+        .unwrap()
+    }
+
+    fn log_data(message: &ServerSseMessage) -> usize {
+        let message = serde_json::to_value(message.message.as_ref()).unwrap();
+        message["params"]["data"].as_u64().unwrap() as usize
+    }
+
+    /// A session of `manager` that has completed the initialize handshake
+    async fn initialized_session(
+        manager: &Arc<ResumableSessionManager>,
+    ) -> (
+        SessionId,
+        <ResumableSessionManager as SessionManager>::Transport,
+    ) {
+        let (id, mut transport) = manager.create_session().await.unwrap();
+
+        // Answer the initialize request as the MCP service would
+        let initialize = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": {"name": "test-client", "version": "1.0"}
+            }
+        }))
+        .unwrap();
+        let initializing = tokio::spawn({
+            let manager = manager.clone();
+            let id = id.clone();
+            async move { manager.initialize_session(&id, initialize).await }
+        });
+        transport.receive().await.expect("initialize request");
+        let initialized = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "serverInfo": {"name": "test-server", "version": "1.0"}
+            }
+        }))
+        .unwrap();
+        transport.send(initialized).await.unwrap();
+        initializing.await.unwrap().unwrap();
+        (id, transport)
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_missed_messages() {
+        let manager = Arc::new(ResumableSessionManager::new(8));
+        let (id, mut transport) = initialized_session(&manager).await;
+
+        let mut stream = Box::pin(manager.create_standalone_stream(&id).await.unwrap());
+        let mut last_event_id = None;
+        for data in 0..3 {
+            transport.send(log_message(data)).await.unwrap();
+            let message = stream.next().await.unwrap();
+            assert_eq!(log_data(&message), data);
+            last_event_id = message.event_id;
+        }
+
+        // Messages sent while the client is disconnected are buffered
+        drop(stream);
+        for data in 3..6 {
+            transport.send(log_message(data)).await.unwrap();
+        }
+
+        let mut resumed = Box::pin(manager.resume(&id, last_event_id.unwrap()).await.unwrap());
+        for data in 3..6 {
+            assert_eq!(log_data(&resumed.next().await.unwrap()), data);
+        }
+
+        // Live streaming continues after the replay
+        transport.send(log_message(6)).await.unwrap();
+        assert_eq!(log_data(&resumed.next().await.unwrap()), 6);
+    }
+
+    #[tokio::test]
+    async fn test_resume_reports_messages_lost_to_full_buffer() {
+        let manager = Arc::new(ResumableSessionManager::new(2));
+        let (id, mut transport) = initialized_session(&manager).await;
+
+        let mut stream = Box::pin(manager.create_standalone_stream(&id).await.unwrap());
+        transport.send(log_message(0)).await.unwrap();
+        let last_event_id = stream.next().await.unwrap().event_id.unwrap();
+
+        // Only the last two of these fit the buffer
+        drop(stream);
+        for data in 1..6 {
+            transport.send(log_message(data)).await.unwrap();
+        }
+
+        let mut resumed = Box::pin(manager.resume(&id, last_event_id).await.unwrap());
+        let lagged = resumed.next().await.unwrap();
+        assert!(lagged.event_id.is_none());
+        let lagged = serde_json::to_value(lagged.message.as_ref()).unwrap();
+        assert_eq!(lagged["params"]["logger"], LAGGED_LOGGER);
+        assert_eq!(
+            lagged["params"]["data"],
+            json!({"event": "lagged", "missed": 3})
+        );
+
+        assert_eq!(log_data(&resumed.next().await.unwrap()), 4);
+        assert_eq!(log_data(&resumed.next().await.unwrap()), 5);
+    }
+}
@@ -0,0 +1,956 @@
+//! Stdio transport using the MCP stdio protocol
+//!
+//! Messages are newline-delimited JSON-RPC on stdin/stdout. Logging must go to stderr so it
+//! never interleaves with protocol output.
+//!
+//! Requests are multiplexed: input is read continuously and each request is handled on its own
+//! task, so a slow evaluation doesn't hold up requests behind it. Responses are written as they
+//! complete, serialized through a single writer, and may arrive in a different order than the
+//! requests; clients correlate them by id. The responses to a batch are written together as
+//! one array once every request in it has been answered.
+//!
+//! While a tool call runs longer than the progress interval, `notifications/progress` messages
+//! carrying the elapsed time are sent under the request's progress token, or its id when the
+//! client gave none, until the result is written.
+
+use anyhow::{Result, anyhow};
+use rmcp::{
+    RoleServer, ServiceExt,
+    model::{JsonRpcBatchRequestItem, JsonRpcMessage, RequestId},
+    service::{RxJsonRpcMessage, ServerInitializeError, TxJsonRpcMessage},
+    transport::Transport,
+};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{Mutex, watch},
+};
+use tracing::{debug, error, info, warn};
+
+use crate::config::ServerConfig;
+use crate::fhirpath_engine::FhirEngineConfig;
+use crate::security::SecurityConfig;
+use crate::server::{FhirPathToolServer, ServerLimits};
+use crate::transport::batch::{INVALID_REQUEST, Member, error_response, invalid_request};
+
+/// JSON-RPC error code for lines that are not valid JSON
+const PARSE_ERROR: i32 = -32700;
+
+/// Default time between progress notifications for a running tool call
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Stdio transport server using MCP stdio protocol
+pub struct StdioTransportServer {
+    progress_interval: Duration,
+    security: SecurityConfig,
+    engine: FhirEngineConfig,
+}
+
+impl Default for StdioTransportServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StdioTransportServer {
+    /// Create a new stdio transport server
+    pub fn new() -> Self {
+        Self {
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            security: SecurityConfig::default(),
+            engine: FhirEngineConfig::default(),
+        }
+    }
+
+    /// Apply the settings of `config` that concern the stdio transport
+    pub fn with_config(mut self, config: &ServerConfig) -> Self {
+        self.security = config.security_config(self.security);
+        self.engine = config.engine_config(self.engine);
+        self
+    }
+
+    /// Send a `notifications/progress` notification for each tool call still running after
+    /// `interval`, and again every `interval` until it is answered
+    pub fn with_progress_interval(mut self, interval: Duration) -> Self {
+        self.progress_interval = interval;
+        self
+    }
+
+    /// Start the stdio transport server, serving requests until stdin is closed
+    pub async fn start(&self) -> Result<()> {
+        info!("Starting MCP stdio transport server");
+
+        // Initialize the shared FHIRPath engine (ignore if already initialized)
+        if let Err(e) =
+            crate::fhirpath_engine::initialize_shared_engine_with_config(self.engine.clone()).await
+        {
+            if !e.to_string().contains("already initialized") {
+                return Err(e);
+            }
+            debug!("FHIRPath engine already initialized");
+        }
+
+        // Tool functions enforce limits and sanitize diagnostics through the shared provider
+        if let Err(e) = crate::security::initialize_shared_security(self.security.clone()) {
+            debug!("{}", e);
+        }
+
+        self.serve(tokio::io::stdin(), tokio::io::stdout()).await
+    }
+
+    /// Serve MCP requests read from `reader`, writing responses to `writer`
+    pub async fn serve<R, W>(&self, reader: R, writer: W) -> Result<()>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let transport = LineDelimitedTransport::new(reader, writer);
+
+        let server = FhirPathToolServer::new()
+            .with_progress_interval(self.progress_interval)
+            .with_limits(ServerLimits::new(&self.security, &self.engine.fhir_version));
+        let service = match server.serve(transport).await {
+            Ok(service) => service,
+            Err(ServerInitializeError::ConnectionClosed(_))
+            | Err(ServerInitializeError::ExpectedInitializeRequest(None)) => {
+                info!("Stdio input closed before initialization");
+                return Ok(());
+            }
+            Err(e) => return Err(anyhow!("MCP initialization failed: {}", e)),
+        };
+        info!("Stdio transport ready for MCP communication");
+
+        let reason = service.waiting().await?;
+        info!("Stdio transport server shutting down: {:?}", reason);
+        Ok(())
+    }
+}
+
+/// Newline-delimited JSON-RPC framing over an async reader/writer pair.
+///
+/// Lines may end with `\n` or `\r\n`; blank lines and a UTF-8 byte order mark starting a
+/// line are ignored. Lines that are not valid UTF-8 or JSON are answered with a parse error
+/// (id `null`), and JSON that is not a JSON-RPC message with an invalid request error echoing
+/// its `id`; either way the line is skipped, so one bad message does not end the session. End
+/// of input
+/// closes the transport once every request read so far has been answered.
+pub struct LineDelimitedTransport<R, W> {
+    reader: BufReader<R>,
+    writer: Arc<Mutex<W>>,
+    /// Requests received but not yet answered
+    in_flight: Arc<watch::Sender<usize>>,
+    /// Members of a batch not yet handed to the service
+    queued: VecDeque<RxJsonRpcMessage<RoleServer>>,
+    /// Batches with requests still unanswered
+    batches: Arc<std::sync::Mutex<Vec<PendingBatch>>>,
+    eof: bool,
+}
+
+/// Responses gathered for a batch until every request in it has been answered
+struct PendingBatch {
+    waiting: Vec<RequestId>,
+    responses: Vec<Value>,
+}
+
+impl<R, W> LineDelimitedTransport<R, W>
+where
+    R: AsyncRead + Send + Unpin,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            writer: Arc::new(Mutex::new(writer)),
+            in_flight: Arc::new(watch::Sender::new(0)),
+            queued: VecDeque::new(),
+            batches: Arc::default(),
+            eof: false,
+        }
+    }
+
+    /// Queue the members of a batch for the service and start gathering their responses.
+    /// Members that can't be dispatched are answered straight away when nothing else in
+    /// the batch needs an answer.
+    async fn queue_batch(&mut self, members: Vec<Value>) -> std::io::Result<()> {
+        if members.is_empty() {
+            return write_line(&self.writer, invalid_request().to_string()).await;
+        }
+
+        let mut waiting = Vec::new();
+        let mut responses = Vec::new();
+        for member in members {
+            match Member::parse(member) {
+                Member::Request { id, message } => {
+                    waiting.push(id);
+                    self.queued.push_back(message);
+                }
+                Member::Notification(message) => self.queued.push_back(message),
+                Member::Rejected(error) => responses.push(error),
+            }
+        }
+
+        if waiting.is_empty() {
+            if responses.is_empty() {
+                return Ok(());
+            }
+            return write_line(&self.writer, Value::Array(responses).to_string()).await;
+        }
+        self.in_flight.send_modify(|count| *count += waiting.len());
+        self.batches
+            .lock()
+            .unwrap()
+            .push(PendingBatch { waiting, responses });
+        Ok(())
+    }
+}
+
+/// What to write for a message the service sends
+enum Outgoing {
+    /// The message itself
+    Message,
+    /// Nothing yet: the message answers part of a batch still being gathered
+    Held,
+    /// The responses to a batch, now complete
+    Batch(Vec<Value>),
+}
+
+/// Hold `item` back if it answers a request of a pending batch, completing the batch once
+/// it has every response
+fn gather(
+    batches: &std::sync::Mutex<Vec<PendingBatch>>,
+    item: &TxJsonRpcMessage<RoleServer>,
+) -> serde_json::Result<Outgoing> {
+    let id = match item {
+        JsonRpcMessage::Response(response) => &response.id,
+        JsonRpcMessage::Error(error) => &error.id,
+        _ => return Ok(Outgoing::Message),
+    };
+
+    let mut batches = batches.lock().unwrap();
+    let Some((index, position)) = batches.iter().enumerate().find_map(|(index, batch)| {
+        let position = batch.waiting.iter().position(|waiting| waiting == id)?;
+        Some((index, position))
+    }) else {
+        return Ok(Outgoing::Message);
+    };
+
+    let batch = &mut batches[index];
+    batch.waiting.swap_remove(position);
+    batch.responses.push(serde_json::to_value(item)?);
+    if !batch.waiting.is_empty() {
+        return Ok(Outgoing::Held);
+    }
+    Ok(Outgoing::Batch(batches.remove(index).responses))
+}
+
+/// Number of requests in `message` that expect a response
+fn request_count(message: &RxJsonRpcMessage<RoleServer>) -> usize {
+    match message {
+        JsonRpcMessage::Request(_) => 1,
+        JsonRpcMessage::BatchRequest(items) => items
+            .iter()
+            .filter(|item| matches!(item, JsonRpcBatchRequestItem::Request(_)))
+            .count(),
+        _ => 0,
+    }
+}
+
+/// Text of a line read from the input, without its line ending or a leading byte order mark
+fn line_text(bytes: &[u8]) -> Result<&str, std::str::Utf8Error> {
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    std::str::from_utf8(bytes)
+}
+
+async fn write_line<W: AsyncWrite + Unpin>(writer: &Mutex<W>, line: String) -> std::io::Result<()> {
+    let mut writer = writer.lock().await;
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
+
+impl<R, W> Transport<RoleServer> for LineDelimitedTransport<R, W>
+where
+    R: AsyncRead + Send + Unpin,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    type Error = std::io::Error;
+
+    fn send(
+        &mut self,
+        item: TxJsonRpcMessage<RoleServer>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'static {
+        let writer = self.writer.clone();
+        let answers_request =
+            matches!(item, JsonRpcMessage::Response(_) | JsonRpcMessage::Error(_));
+        let in_flight = self.in_flight.clone();
+        let outgoing = gather(&self.batches, &item);
+        async move {
+            let line = match outgoing? {
+                Outgoing::Message => Some(serde_json::to_string(&item)?),
+                Outgoing::Held => None,
+                Outgoing::Batch(responses) => Some(Value::Array(responses).to_string()),
+            };
+            let result = match line {
+                Some(line) => write_line(&writer, line).await,
+                None => Ok(()),
+            };
+            if answers_request {
+                in_flight.send_modify(|count| *count = count.saturating_sub(1));
+            }
+            result
+        }
+    }
+
+    async fn receive(&mut self) -> Option<RxJsonRpcMessage<RoleServer>> {
+        loop {
+            if let Some(message) = self.queued.pop_front() {
+                return Some(message);
+            }
+
+            if self.eof {
+                // Keep the session open until responses to earlier requests are written
+                let mut in_flight = self.in_flight.subscribe();
+                let _ = in_flight.wait_for(|count| *count == 0).await;
+                return None;
+            }
+
+            let mut bytes = Vec::new();
+            match self.reader.read_until(b'\n', &mut bytes).await {
+                Ok(0) => {
+                    debug!("Stdio input reached EOF");
+                    self.eof = true;
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Failed to read from stdio: {}", e);
+                    self.eof = true;
+                    continue;
+                }
+            }
+
+            let message = match line_text(&bytes) {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => serde_json::from_str::<Value>(line).map_err(|e| e.to_string()),
+                Err(e) => Err(format!("invalid UTF-8: {e}")),
+            };
+            let response = match message {
+                Ok(Value::Array(members)) => {
+                    if let Err(e) = self.queue_batch(members).await {
+                        error!("Failed to write batch response: {}", e);
+                        return None;
+                    }
+                    continue;
+                }
+                Ok(message) => {
+                    let id = message.get("id").cloned().unwrap_or(Value::Null);
+                    match serde_json::from_value::<RxJsonRpcMessage<RoleServer>>(message) {
+                        Ok(message) => {
+                            let requests = request_count(&message);
+                            self.in_flight.send_modify(|count| *count += requests);
+                            return Some(message);
+                        }
+                        Err(e) => {
+                            warn!("Received a message that is not JSON-RPC: {}", e);
+                            error_response(id, INVALID_REQUEST, "Invalid Request")
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Received malformed JSON: {}", e);
+                    error_response(Value::Null, PARSE_ERROR, &format!("Parse error: {e}"))
+                }
+            };
+            if let Err(e) = write_line(&self.writer, response.to_string()).await {
+                error!("Failed to write error response: {}", e);
+                return None;
+            }
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.writer.lock().await.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    #[tokio::test]
+    async fn test_stdio_initialize_and_list_tools() {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            StdioTransportServer::new()
+                .serve(server_in, server_out)
+                .await
+        });
+
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": {"name": "test-client", "version": "1.0"}
+            }
+        });
+        let initialized = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        let list_tools = json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"});
+
+        // Split a message across writes to exercise partial line handling
+        let initialize = format!("{initialize}\n");
+        let (head, tail) = initialize.split_at(20);
+        client_in.write_all(head.as_bytes()).await.unwrap();
+        client_in.write_all(tail.as_bytes()).await.unwrap();
+        client_in
+            .write_all(format!("{{not json\n{initialized}\n{list_tools}\n").as_bytes())
+            .await
+            .unwrap();
+
+        let mut lines = BufReader::new(client_out).lines();
+        let mut responses = Vec::new();
+        while responses.len() < 3 {
+            let line = lines.next_line().await.unwrap().expect("response line");
+            responses.push(serde_json::from_str::<Value>(&line).unwrap());
+        }
+
+        let by_id = |id: Value| {
+            responses
+                .iter()
+                .find(|response| response["id"] == id)
+                .unwrap_or_else(|| panic!("missing response for id {id}"))
+        };
+        assert_eq!(by_id(Value::Null)["error"]["code"], PARSE_ERROR);
+        assert!(by_id(json!(1))["result"]["capabilities"]["tools"].is_object());
+        let tools = by_id(json!(2))["result"]["tools"].as_array().unwrap();
+        assert!(tools.iter().any(|tool| tool["name"] == "fhirpath_evaluate"));
+
+        // Closing stdin shuts the server down cleanly
+        drop(client_in);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stdio_batch_requests() {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            StdioTransportServer::new()
+                .serve(server_in, server_out)
+                .await
+        });
+
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": {"name": "test-client", "version": "1.0"}
+            }
+        });
+        let initialized = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        let batch = json!([
+            {"jsonrpc": "2.0", "id": 2, "method": "tools/list"},
+            {"jsonrpc": "2.0", "method": "notifications/roots/list_changed"},
+            {
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "tools/call",
+                "params": {
+                    "name": "fhirpath_evaluate",
+                    "arguments": {
+                        "expression": "Patient.id",
+                        "resource": {"resourceType": "Patient", "id": "p1"}
+                    }
+                }
+            },
+            5
+        ]);
+        let notifications =
+            json!([{"jsonrpc": "2.0", "method": "notifications/roots/list_changed"}]);
+        client_in
+            .write_all(
+                format!("{initialize}\n{initialized}\n{batch}\n{notifications}\n[]\n").as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let mut lines = BufReader::new(client_out).lines();
+        let mut responses = Vec::new();
+        while responses.len() < 3 {
+            let line = lines.next_line().await.unwrap().expect("response line");
+            responses.push(serde_json::from_str::<Value>(&line).unwrap());
+        }
+        drop(client_in);
+        // The all-notification batch is never answered
+        assert!(lines.next_line().await.unwrap().is_none());
+        server.await.unwrap().unwrap();
+
+        assert!(responses.iter().any(|response| response["id"] == 1));
+        // An empty batch is answered by a single error
+        assert!(responses.contains(&invalid_request()));
+
+        let batch = responses
+            .iter()
+            .find_map(Value::as_array)
+            .expect("batch response");
+        assert_eq!(batch.len(), 3);
+        let by_id = |id: Value| batch.iter().find(|response| response["id"] == id).unwrap();
+        assert!(by_id(json!(2))["result"]["tools"].is_array());
+        let text = by_id(json!(3))["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap();
+        let result: Value = serde_json::from_str(text).unwrap();
+        assert_eq!(result["values"], json!(["p1"]));
+        assert_eq!(by_id(Value::Null), &invalid_request());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stdio_fast_request_overtakes_slow_one() {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            StdioTransportServer::new()
+                .serve(server_in, server_out)
+                .await
+        });
+
+        let entries: Vec<Value> = (0..9_000)
+            .map(|i| {
+                json!({"resource": {
+                    "resourceType": "Patient",
+                    "id": format!("p{i}"),
+                    "name": [{"given": ["John", "Q"], "family": format!("Doe{i}")}]
+                }})
+            })
+            .collect();
+        let messages = [
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {},
+                    "clientInfo": {"name": "test-client", "version": "1.0"}
+                }
+            }),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            json!({
+                "jsonrpc": "2.0",
+                "id": "slow",
+                "method": "tools/call",
+                "params": {
+                    "name": "fhirpath_evaluate",
+                    "arguments": {
+                        "expression": "Bundle.entry.resource.name\
+                            .where(given.exists() and family.startsWith('Doe')).given.count()",
+                        "resource": {"resourceType": "Bundle", "type": "collection", "entry": entries}
+                    }
+                }
+            }),
+            json!({"jsonrpc": "2.0", "id": "fast", "method": "tools/list"}),
+        ];
+        // Write from a separate task so a large request can't deadlock against unread output,
+        // then close stdin straight away: pending requests must still be answered
+        let input: String = messages
+            .iter()
+            .map(|message| format!("{message}\n"))
+            .collect();
+        tokio::spawn(async move {
+            client_in.write_all(input.as_bytes()).await.unwrap();
+        });
+
+        let mut lines = BufReader::new(client_out).lines();
+        let mut ids = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let response: Value = serde_json::from_str(&line).unwrap();
+            if response["id"] == "slow" {
+                let text = response["result"]["content"][0]["text"].as_str().unwrap();
+                let result: Value = serde_json::from_str(text).unwrap();
+                assert_eq!(result["values"], json!([18_000]));
+            }
+            ids.push(response["id"].clone());
+        }
+
+        assert_eq!(ids, vec![json!(1), json!("fast"), json!("slow")]);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stdio_progress_before_slow_response() {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            StdioTransportServer::new()
+                .with_progress_interval(Duration::from_millis(20))
+                .serve(server_in, server_out)
+                .await
+        });
+
+        let entries: Vec<Value> = (0..9_000)
+            .map(|i| {
+                json!({"resource": {
+                    "resourceType": "Patient",
+                    "id": format!("p{i}"),
+                    "name": [{"given": ["John"], "family": format!("Roe{i}")}]
+                }})
+            })
+            .collect();
+        let messages = [
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {},
+                    "clientInfo": {"name": "test-client", "version": "1.0"}
+                }
+            }),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            json!({
+                "jsonrpc": "2.0",
+                "id": "slow",
+                "method": "tools/call",
+                "params": {
+                    "name": "fhirpath_evaluate",
+                    "arguments": {
+                        "expression": "Bundle.entry.resource.name\
+                            .where(family.startsWith('Roe') and given.exists()).family.count()",
+                        "resource": {"resourceType": "Bundle", "type": "collection", "entry": entries}
+                    }
+                }
+            }),
+        ];
+        let input: String = messages
+            .iter()
+            .map(|message| format!("{message}\n"))
+            .collect();
+        tokio::spawn(async move {
+            client_in.write_all(input.as_bytes()).await.unwrap();
+        });
+
+        let mut lines = BufReader::new(client_out).lines();
+        let mut progress = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let message: Value = serde_json::from_str(&line).unwrap();
+            if message["method"] == "notifications/progress" {
+                progress.push(message["params"].clone());
+            } else if message["id"] == "slow" {
+                assert!(message["result"].is_object());
+                break;
+            }
+        }
+
+        assert!(!progress.is_empty(), "no progress before the response");
+        assert!(
+            progress
+                .iter()
+                .all(|params| params["progressToken"] == "slow")
+        );
+        assert!(progress[0]["progress"].as_f64().unwrap() > 0.0);
+        server.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stdio_timeout_error_code() {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            StdioTransportServer::new()
+                .serve(server_in, server_out)
+                .await
+        });
+
+        let entries: Vec<Value> = (0..9_000)
+            .map(|i| {
+                json!({"resource": {
+                    "resourceType": "Patient",
+                    "id": format!("p{i}"),
+                    "name": [{"given": ["Jim"], "family": format!("Moe{i}")}]
+                }})
+            })
+            .collect();
+        let messages = [
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {},
+                    "clientInfo": {"name": "test-client", "version": "1.0"}
+                }
+            }),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "tools/call",
+                "params": {
+                    "name": "fhirpath_evaluate",
+                    "arguments": {
+                        "expression": "Bundle.entry.resource.name.where(family.startsWith('Moe')).given",
+                        "resource": {"resourceType": "Bundle", "type": "collection", "entry": entries},
+                        "timeout_ms": 1
+                    }
+                }
+            }),
+        ];
+        let input: String = messages
+            .iter()
+            .map(|message| format!("{message}\n"))
+            .collect();
+        tokio::spawn(async move {
+            client_in.write_all(input.as_bytes()).await.unwrap();
+        });
+
+        let mut lines = BufReader::new(client_out).lines();
+        let response = loop {
+            let line = lines.next_line().await.unwrap().expect("a response");
+            let message: Value = serde_json::from_str(&line).unwrap();
+            if message["id"] == 2 {
+                break message;
+            }
+        };
+
+        assert_eq!(response["error"]["code"], -32003);
+        assert_eq!(response["error"]["message"], "evaluation timed out");
+        assert_eq!(response["error"]["data"]["timeout_ms"], 1);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_stdio_call_tool_structured_content() {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            StdioTransportServer::new()
+                .serve(server_in, server_out)
+                .await
+        });
+
+        let messages = [
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {},
+                    "clientInfo": {"name": "test-client", "version": "1.0"}
+                }
+            }),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "tools/call",
+                "params": {
+                    "name": "fhirpath_evaluate",
+                    "arguments": {
+                        "expression": "Patient.name.given",
+                        "resource": {
+                            "resourceType": "Patient",
+                            "name": [{"given": ["Ada", "King"]}]
+                        }
+                    }
+                }
+            }),
+        ];
+        for message in &messages {
+            client_in
+                .write_all(format!("{message}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+        drop(client_in);
+
+        let mut lines = BufReader::new(client_out).lines();
+        let mut result = Value::Null;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let response: Value = serde_json::from_str(&line).unwrap();
+            if response["id"] == 2 {
+                result = response["result"].clone();
+            }
+        }
+
+        let structured = &result["structuredContent"];
+        assert_eq!(structured["values"], json!(["Ada", "King"]));
+        let text: Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(text["values"], structured["values"]);
+        server.await.unwrap().unwrap();
+    }
+
+    /// Answers the server writes for `input`, sent in one go before stdin is closed
+    async fn responses_to(input: &[u8]) -> Vec<Value> {
+        responses_from(StdioTransportServer::new(), input).await
+    }
+
+    /// Answers `server` writes for `input`, sent in one go before stdin is closed
+    async fn responses_from(server: StdioTransportServer, input: &[u8]) -> Vec<Value> {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move { server.serve(server_in, server_out).await });
+        client_in.write_all(input).await.unwrap();
+        drop(client_in);
+
+        let mut lines = BufReader::new(client_out).lines();
+        let mut responses = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            responses.push(serde_json::from_str(&line).unwrap());
+        }
+        server.await.unwrap().unwrap();
+        responses
+    }
+
+    fn session_input(line_ending: &str) -> String {
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": {"name": "test-client", "version": "1.0"}
+            }
+        });
+        let initialized = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        let list_tools = json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"});
+        [initialize, initialized, list_tools]
+            .iter()
+            .map(|message| format!("{message}{line_ending}"))
+            .collect()
+    }
+
+    fn ids(responses: &[Value]) -> Vec<Value> {
+        responses
+            .iter()
+            .map(|response| response["id"].clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stdio_input_with_byte_order_mark() {
+        let input = format!("\u{FEFF}{}", session_input("\n"));
+        let responses = responses_to(input.as_bytes()).await;
+        assert_eq!(ids(&responses), vec![json!(1), json!(2)]);
+        assert!(responses[1]["result"]["tools"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_stdio_input_with_crlf_line_endings() {
+        let responses = responses_to(session_input("\r\n").as_bytes()).await;
+        assert_eq!(ids(&responses), vec![json!(1), json!(2)]);
+        assert!(responses[1]["result"]["tools"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_stdio_input_with_blank_lines() {
+        let input = format!("\n\r\n{}", session_input("\n  \n\r\n"));
+        let responses = responses_to(input.as_bytes()).await;
+        assert_eq!(ids(&responses), vec![json!(1), json!(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_stdio_invalid_utf8_is_a_parse_error() {
+        let mut input = b"{\"jsonrpc\": \"2.0\", \"id\": \xFF}\n".to_vec();
+        input.extend_from_slice(session_input("\n").as_bytes());
+        let responses = responses_to(&input).await;
+        assert_eq!(ids(&responses), vec![Value::Null, json!(1), json!(2)]);
+        assert_eq!(responses[0]["error"]["code"], PARSE_ERROR);
+        assert!(
+            responses[0]["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("invalid UTF-8")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stdio_json_that_is_not_json_rpc_is_an_invalid_request() {
+        let mut input = String::from("{\"foo\": 1}\n{\"jsonrpc\": \"2.0\", \"id\": 7}\n");
+        input.push_str(&session_input("\n"));
+        let responses = responses_to(input.as_bytes()).await;
+        assert_eq!(
+            ids(&responses),
+            vec![Value::Null, json!(7), json!(1), json!(2)]
+        );
+        for response in &responses[..2] {
+            assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stdio_initialize_advertises_configured_limits() {
+        let server = StdioTransportServer {
+            security: SecurityConfig {
+                max_expression_length: 250,
+                ..SecurityConfig::default()
+            },
+            engine: FhirEngineConfig {
+                fhir_version: "R5".to_string(),
+                ..FhirEngineConfig::default()
+            },
+            ..StdioTransportServer::new()
+        };
+        let responses = responses_from(server, session_input("\n").as_bytes()).await;
+
+        let limits = &responses[0]["result"]["capabilities"]["experimental"]
+            [crate::server::LIMITS_CAPABILITY];
+        assert_eq!(limits["max_expression_length"], 250);
+        assert_eq!(limits["fhir_version"], "R5");
+    }
+
+    #[tokio::test]
+    async fn test_stdio_over_length_expression_is_invalid_params() {
+        // Longer than the default `max_expression_length`
+        let expression = format!("Patient.id = '{}'", "x".repeat(1000));
+        let call = json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "tools/call",
+            "params": {
+                "name": "fhirpath_evaluate",
+                "arguments": {
+                    "expression": expression,
+                    "resource": {"resourceType": "Patient", "id": "p1"}
+                }
+            }
+        });
+        let input = format!("{}{call}\n", session_input("\n"));
+        let responses = responses_to(input.as_bytes()).await;
+
+        let response = responses
+            .iter()
+            .find(|response| response["id"] == 3)
+            .expect("a response to the tool call");
+        assert_eq!(response["error"]["code"], -32602);
+        assert!(
+            response["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("too long")
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! TLS for the HTTP transport
+
+use anyhow::{Context, Result, anyhow};
+use std::{io, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        self,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+    server::TlsStream,
+};
+use tracing::{debug, warn};
+
+/// How long a client may take to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections that completed their handshake and are waiting to be served
+const ACCEPT_BACKLOG: usize = 64;
+
+/// Build the rustls server configuration from a PEM certificate chain and private key
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<rustls::ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| {
+            format!(
+                "Failed to read TLS certificate chain {}",
+                cert_path.display()
+            )
+        })?;
+    if certs.is_empty() {
+        return Err(anyhow!(
+            "TLS certificate file {} contains no certificates",
+            cert_path.display()
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read TLS private key {}", key_path.display()))?;
+
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .with_context(|| {
+        format!(
+            "TLS certificate {} does not match private key {}",
+            cert_path.display(),
+            key_path.display()
+        )
+    })?;
+    Ok(Arc::new(config))
+}
+
+/// Listener serving TLS connections. Handshakes run on their own tasks so a slow client
+/// can't hold up the others.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    acceptor: JoinHandle<()>,
+}
+
+impl TlsListener {
+    /// Accept TCP connections on `listener` and complete their handshakes with `config`
+    pub fn new(listener: TcpListener, config: Arc<rustls::ServerConfig>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, connections) = mpsc::channel(ACCEPT_BACKLOG);
+        let tls = TlsAcceptor::from(config);
+
+        let acceptor = tokio::spawn(async move {
+            loop {
+                let (stream, remote_addr) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        // Usually running out of file descriptors; give connections time to close
+                        warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let tls = tls.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, remote_addr)).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", remote_addr, e),
+                        Err(_) => debug!("TLS handshake with {} timed out", remote_addr),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            connections,
+            acceptor,
+        })
+    }
+}
+
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        // Release the port once the server stops
+        self.acceptor.abort();
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The acceptor only stops when the listener is dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    #[test]
+    fn test_load_server_config_errors() {
+        assert!(load_server_config(&fixture("cert.pem"), &fixture("key.pem")).is_ok());
+
+        let err = load_server_config(&fixture("missing.pem"), &fixture("key.pem")).unwrap_err();
+        assert!(err.to_string().contains("TLS certificate chain"));
+
+        // A key file holds no certificates
+        let err = load_server_config(&fixture("key.pem"), &fixture("key.pem")).unwrap_err();
+        assert!(err.to_string().contains("contains no certificates"));
+
+        let err = load_server_config(&fixture("cert.pem"), &fixture("cert.pem")).unwrap_err();
+        assert!(err.to_string().contains("TLS private key"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Versions of the REST tool call response envelope
+//!
+//! Every tool call response names the `api_version` of its shape. A client may send an
+//! `X-MCP-Accept-Version` header listing the versions it understands, most preferred first;
+//! the server answers in the first one it can serve, or with 406 when it serves none. Minor
+//! versions only add fields, so a request for `1.0` is served by any `1.x`.
+
+/// Header listing the response versions the client accepts, e.g. `1.0, 2.0`
+pub const ACCEPT_VERSION_HEADER: &str = "x-mcp-accept-version";
+
+/// Response envelope versions the server can produce, newest first
+pub const SUPPORTED_API_VERSIONS: &[&str] = &["1.0"];
+
+/// Version served to clients that don't ask for one
+pub const API_VERSION: &str = SUPPORTED_API_VERSIONS[0];
+
+/// None of the requested versions can be served
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Unsupported API version '{requested}', supported versions: {}",
+    SUPPORTED_API_VERSIONS.join(", ")
+)]
+pub struct UnsupportedApiVersion {
+    pub requested: String,
+}
+
+/// `major.minor`, with the minor version defaulting to 0
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    match version.split_once('.') {
+        Some((major, minor)) => Some((major.parse().ok()?, minor.parse().ok()?)),
+        None => Some((version.parse().ok()?, 0)),
+    }
+}
+
+/// Pick the version to answer in from the value of the `X-MCP-Accept-Version` header
+pub fn negotiate_api_version(accept: Option<&str>) -> Result<&'static str, UnsupportedApiVersion> {
+    let Some(accept) = accept.filter(|accept| !accept.trim().is_empty()) else {
+        return Ok(API_VERSION);
+    };
+
+    for requested in accept.split(',').map(str::trim) {
+        if requested == "*" {
+            return Ok(API_VERSION);
+        }
+        let Some((major, minor)) = parse_version(requested) else {
+            continue;
+        };
+        let served = SUPPORTED_API_VERSIONS.iter().find(|supported| {
+            parse_version(supported).is_some_and(|(supported_major, supported_minor)| {
+                supported_major == major && supported_minor >= minor
+            })
+        });
+        if let Some(served) = served {
+            return Ok(served);
+        }
+    }
+
+    Err(UnsupportedApiVersion {
+        requested: accept.trim().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_api_version() {
+        assert_eq!(negotiate_api_version(None).unwrap(), API_VERSION);
+        assert_eq!(negotiate_api_version(Some(" ")).unwrap(), API_VERSION);
+        assert_eq!(negotiate_api_version(Some("1.0")).unwrap(), "1.0");
+        assert_eq!(negotiate_api_version(Some("1")).unwrap(), "1.0");
+        assert_eq!(negotiate_api_version(Some("3.0, v1")).unwrap(), "1.0");
+        assert_eq!(negotiate_api_version(Some("*")).unwrap(), API_VERSION);
+
+        // A newer minor version may rely on fields this server doesn't send
+        let error = negotiate_api_version(Some("1.1")).unwrap_err();
+        assert_eq!(error.requested, "1.1");
+        assert!(negotiate_api_version(Some("2.0, latest")).is_err());
+    }
+}
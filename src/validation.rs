@@ -0,0 +1,188 @@
+//! Server self-validation run by `octofhir-mcp validate`
+//!
+//! FHIRPath checks make real tool calls against the engine with a canned Patient, so a
+//! passing report shows the server evaluates expressions rather than merely starting.
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::time::Instant;
+
+use crate::config::ServerConfig;
+use crate::tools::{EvaluateParams, call_tool, fhirpath_evaluate};
+
+/// Expression evaluated by the FHIRPath checks
+const PROBE_EXPRESSION: &str = "Patient.name.family";
+
+/// Value [`PROBE_EXPRESSION`] must return for the canned Patient
+const PROBE_FAMILY: &str = "Validation";
+
+/// Name of the category holding the FHIRPath checks
+pub const FHIRPATH_CATEGORY: &str = "FHIRPath";
+
+/// Outcome of a single validation check
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationCheck {
+    pub name: String,
+    pub passed: bool,
+    pub message: String,
+    pub duration_ms: f64,
+}
+
+/// Related checks reported together
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationCategory {
+    pub name: String,
+    pub checks: Vec<ValidationCheck>,
+}
+
+impl ValidationCategory {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn check(&self, name: &str) -> Option<&ValidationCheck> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+}
+
+/// Results of all validation checks
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub categories: Vec<ValidationCategory>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.categories.iter().all(ValidationCategory::passed)
+    }
+
+    pub fn category(&self, name: &str) -> Option<&ValidationCategory> {
+        self.categories
+            .iter()
+            .find(|category| category.name == name)
+    }
+}
+
+/// Validate `config` and exercise the FHIRPath tools with live evaluations
+pub async fn validate_server(config: &ServerConfig) -> ValidationReport {
+    let start_time = Instant::now();
+    let configuration = ValidationCategory {
+        name: "Configuration".to_string(),
+        checks: vec![check_result(
+            "config",
+            start_time,
+            config
+                .validate()
+                .map(|()| {
+                    format!(
+                        "FHIR {}, {}:{}",
+                        config.fhir_version, config.host, config.port
+                    )
+                })
+                .map_err(|e| e.to_string()),
+        )],
+    };
+
+    ValidationReport {
+        categories: vec![configuration, validate_fhirpath(config).await],
+    }
+}
+
+async fn validate_fhirpath(config: &ServerConfig) -> ValidationCategory {
+    let mut checks = Vec::new();
+
+    let start_time = Instant::now();
+    let engine =
+        crate::fhirpath_engine::get_engine_for_version(Some(config.fhir_version.as_str())).await;
+    let engine_message = match &engine {
+        Ok(engine) => {
+            let info = engine.get_engine_info().await;
+            Ok(format!(
+                "{}, octofhir-fhirpath v{}",
+                info.schema_provider, info.version
+            ))
+        }
+        Err(e) => Err(format!("Engine initialization failed: {e}")),
+    };
+    checks.push(check_result("engine", start_time, engine_message));
+
+    let start_time = Instant::now();
+    let tools = crate::server::tool_definitions()
+        .map_err(|e| format!("Tool definitions are invalid: {}", e.message))
+        .and_then(|tools| {
+            let names: Vec<String> = tools.iter().map(|tool| tool.name.to_string()).collect();
+            if names.iter().any(|name| name == "fhirpath_evaluate") {
+                Ok(format!("{} tools: {}", names.len(), names.join(", ")))
+            } else {
+                Err("fhirpath_evaluate is not listed".to_string())
+            }
+        });
+    checks.push(check_result("tools", start_time, tools));
+
+    let start_time = Instant::now();
+    let tool_call = call_tool(
+        "fhirpath_evaluate",
+        json!({ "expression": PROBE_EXPRESSION, "resource": probe_patient() }),
+    )
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| expect_probe_values(&result["values"]));
+    checks.push(check_result("tool_execution", start_time, tool_call));
+
+    let start_time = Instant::now();
+    let evaluation = fhirpath_evaluate(EvaluateParams {
+        expression: PROBE_EXPRESSION.to_string(),
+        resource: probe_patient(),
+        fhir_version: Some(config.fhir_version.clone()),
+        ..Default::default()
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| match result.diagnostics {
+        Some(diagnostics) => Err(diagnostics.join("; ")),
+        None => expect_probe_values(&json!(result.values)),
+    });
+    checks.push(check_result("evaluation", start_time, evaluation));
+
+    ValidationCategory {
+        name: FHIRPATH_CATEGORY.to_string(),
+        checks,
+    }
+}
+
+fn probe_patient() -> Value {
+    json!({
+        "resourceType": "Patient",
+        "id": "validation",
+        "name": [{"family": PROBE_FAMILY, "given": ["Server"]}]
+    })
+}
+
+fn expect_probe_values(values: &Value) -> Result<String, String> {
+    if *values == json!([PROBE_FAMILY]) {
+        Ok(format!("{PROBE_EXPRESSION} returned {values}"))
+    } else {
+        Err(format!(
+            "{PROBE_EXPRESSION} returned {values}, expected [\"{PROBE_FAMILY}\"]"
+        ))
+    }
+}
+
+fn check_result(
+    name: &str,
+    start_time: Instant,
+    result: Result<String, String>,
+) -> ValidationCheck {
+    let duration_ms = start_time.elapsed().as_secs_f64() * 1000.0;
+    let (passed, message) = match result {
+        Ok(message) => (true, message),
+        Err(message) => (false, message),
+    };
+
+    ValidationCheck {
+        name: name.to_string(),
+        passed,
+        message,
+        duration_ms,
+    }
+}
@@ -482,6 +482,37 @@ pub mod assertions {
         assert!(has_help, "Metrics should contain HELP comments");
         assert!(has_type, "Metrics should contain TYPE comments");
         assert!(has_metric, "Metrics should contain actual metric values");
+
+        // Response time histogram buckets must be cumulative and end with +Inf
+        assert!(
+            metrics.contains("# TYPE octofhir_response_time_seconds histogram"),
+            "Metrics should contain the response time histogram"
+        );
+        let bucket_counts: Vec<(&str, u64)> = metrics
+            .lines()
+            .filter_map(|line| line.strip_prefix("octofhir_response_time_seconds_bucket{le=\""))
+            .map(|rest| {
+                let (le, count) = rest
+                    .split_once("\"} ")
+                    .unwrap_or_else(|| panic!("Malformed bucket line: {}", rest));
+                (le, count.parse().expect("Bucket count should be an integer"))
+            })
+            .collect();
+        assert_eq!(
+            bucket_counts.last().map(|(le, _)| *le),
+            Some("+Inf"),
+            "Histogram should end with a +Inf bucket"
+        );
+        assert!(
+            bucket_counts.windows(2).all(|pair| pair[0].1 <= pair[1].1),
+            "Histogram buckets should be cumulative"
+        );
+        for suffix in ["_sum", "_count"] {
+            assert!(
+                metrics.contains(&format!("octofhir_response_time_seconds{suffix} ")),
+                "Histogram should contain {suffix}"
+            );
+        }
     }
 }
 
@@ -0,0 +1,69 @@
+//! Dropping an HTTP connection cancels its in-flight evaluation
+//!
+//! Kept in its own test binary so no other test shares the engine's evaluation slots.
+
+use octofhir_mcp::transport::HttpTransportServer;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_disconnect_cancels_evaluation() {
+    let engine = octofhir_mcp::get_shared_engine().await.unwrap();
+    let all_permits = engine.available_evaluation_permits();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let router = HttpTransportServer::new("127.0.0.1".to_string(), 0).create_router();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+
+    let entries: Vec<_> = (0..2_000)
+        .map(|i| json!({"resource": {"resourceType": "Patient", "id": format!("p{i}")}}))
+        .collect();
+    let body = json!({
+        "expression": "Bundle.entry.select(%resource.entry.where(resource.id = 'p1').count())",
+        "resource": {"resourceType": "Bundle", "type": "collection", "entry": entries},
+        "timeout_ms": 120_000
+    })
+    .to_string();
+
+    let start = Instant::now();
+    let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+    client
+        .write_all(
+            format!(
+                "POST /mcp/tools/fhirpath_evaluate HTTP/1.1\r\nHost: {address}\r\n\
+                 Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    while engine.available_evaluation_permits() == all_permits {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "evaluation never started"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    drop(client);
+    let dropped = Instant::now();
+
+    // The evaluation alone takes far longer than this
+    while engine.available_evaluation_permits() < all_permits {
+        assert!(
+            dropped.elapsed() < Duration::from_secs(2),
+            "evaluation slot still held after the client disconnected"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
@@ -6,8 +6,9 @@
 use anyhow::Result;
 use octofhir_mcp::{
     server::{FhirPathToolRouter, demonstrate_tools},
-    tools::{EvaluateParams, ExtractParams, ParseParams},
+    tools::{BundleFilterParams, EvaluateParams, ExtractParams, ParseParams},
     transport::TransportFactory,
+    validation::{FHIRPATH_CATEGORY, validate_server},
 };
 use serde_json::json;
 
@@ -23,8 +24,7 @@ async fn test_sdk_server_initialization() -> Result<()> {
             "resourceType": "Patient",
             "name": [{"family": "Smith", "given": ["John"]}]
         }),
-        context: None,
-        timeout_ms: None,
+        ..Default::default()
     };
 
     let result = router.fhirpath_evaluate(params).await?;
@@ -51,8 +51,7 @@ async fn test_fhirpath_tool_operations() -> Result<()> {
                 "resourceType": "Patient",
                 "birthDate": "1990-01-01"
             }),
-            context: None,
-            timeout_ms: None,
+            ..Default::default()
         })
         .await?;
 
@@ -80,6 +79,7 @@ async fn test_fhirpath_tool_operations() -> Result<()> {
                 ]
             }),
             format: Some("values".to_string()),
+            ..Default::default()
         })
         .await?;
 
@@ -141,8 +141,7 @@ async fn test_complex_fhirpath_expressions() -> Result<()> {
                 }
             ]
         }),
-        context: None,
-        timeout_ms: None,
+        ..Default::default()
     };
 
     let result = router.fhirpath_evaluate(params).await?;
@@ -167,25 +166,26 @@ async fn test_error_handling() -> Result<()> {
     let params = EvaluateParams {
         expression: "invalid().syntax here".to_string(),
         resource: json!({"resourceType": "Patient"}),
-        context: None,
-        timeout_ms: None,
+        ..Default::default()
     };
 
-    let result = router.fhirpath_evaluate(params).await;
-
-    // The evaluation might succeed but return diagnostics, or it might fail
-    // Either way, the router should handle it gracefully
-    match result {
-        Ok(res) => {
-            // If it succeeds, check if there are diagnostics
-            if let Some(diagnostics) = res["diagnostics"].as_array() {
-                assert!(!diagnostics.is_empty());
-            }
-        }
-        Err(_) => {
-            // If it fails, that's also acceptable for invalid syntax
-        }
-    }
+    // A parse failure is reported in the result rather than as an empty match
+    let result = router.fhirpath_evaluate(params).await?;
+    assert_eq!(result["evaluation_failed"], true);
+    assert_eq!(result["expression_info"]["parsed"], false);
+    assert!(!result["diagnostics"].as_array().unwrap().is_empty());
+
+    // An expression matching nothing is a successful, empty evaluation
+    let result = router
+        .fhirpath_evaluate(EvaluateParams {
+            expression: "Patient.name".to_string(),
+            resource: json!({"resourceType": "Observation", "status": "final"}),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(result["values"], json!([]));
+    assert_eq!(result["evaluation_failed"], false);
+    assert!(result["diagnostics"].is_null());
 
     Ok(())
 }
@@ -200,8 +200,7 @@ async fn test_performance_metrics() -> Result<()> {
             "resourceType": "Patient",
             "name": [{"family": "Test"}]
         }),
-        context: None,
-        timeout_ms: None,
+        ..Default::default()
     };
 
     let result = router.fhirpath_evaluate(params).await?;
@@ -218,3 +217,144 @@ async fn test_performance_metrics() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_bundle_filter() -> Result<()> {
+    let router = FhirPathToolRouter;
+    let bundle: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(
+        "tests/fixtures/fhir_resources/bundle.json",
+    )?)?;
+
+    let result = router
+        .fhirpath_bundle_filter(BundleFilterParams {
+            bundle: bundle.clone(),
+            resource_types: vec!["Patient".to_string()],
+            expression: None,
+        })
+        .await?;
+    assert_eq!(result["match_count"], 1);
+    let patients = result["resources"]["Patient"].as_array().unwrap();
+    assert_eq!(patients, &vec![bundle["entry"][0]["resource"].clone()]);
+
+    // Entries of nested Bundles are searched too, and an expression maps each match
+    let outer = json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "entry": [{"resource": bundle}]
+    });
+    let result = router
+        .fhirpath_bundle_filter(BundleFilterParams {
+            bundle: outer,
+            resource_types: vec!["Patient".to_string(), "Practitioner".to_string()],
+            expression: Some("id".to_string()),
+        })
+        .await?;
+    assert_eq!(
+        result["resources"],
+        json!({
+            "Patient": [["example-patient"]],
+            "Practitioner": [["example-practitioner"]]
+        })
+    );
+
+    let not_a_bundle = router
+        .fhirpath_bundle_filter(BundleFilterParams {
+            bundle: json!({"resourceType": "Patient"}),
+            resource_types: vec!["Patient".to_string()],
+            expression: None,
+        })
+        .await;
+    assert!(not_a_bundle.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bundle_filter_glob_patterns() -> Result<()> {
+    let router = FhirPathToolRouter;
+    let bundle = json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "entry": [
+            {"resource": {"resourceType": "Patient", "id": "p1"}},
+            {"resource": {"resourceType": "ServiceRequest", "id": "s1"}},
+            {"resource": {"resourceType": "Observation", "id": "o1"}},
+            {"resource": {"resourceType": "Patient", "id": "p2"}}
+        ]
+    });
+    let filter = |resource_types: &[&str]| {
+        router.fhirpath_bundle_filter(BundleFilterParams {
+            bundle: bundle.clone(),
+            resource_types: resource_types.iter().map(|t| t.to_string()).collect(),
+            expression: Some("id".to_string()),
+        })
+    };
+
+    let result = filter(&["*"]).await?;
+    assert_eq!(result["match_count"], 4);
+    assert_eq!(
+        result["resources"],
+        json!({
+            "Observation": [["o1"]],
+            "Patient": [["p1"], ["p2"]],
+            "ServiceRequest": [["s1"]]
+        })
+    );
+
+    let result = filter(&["Pat*"]).await?;
+    assert_eq!(result["match_count"], 2);
+    assert_eq!(result["resources"], json!({"Patient": [["p1"], ["p2"]]}));
+
+    // Exact names still work and are listed even without matches
+    let result = filter(&["*Request", "Encounter"]).await?;
+    assert_eq!(
+        result["resources"],
+        json!({"Encounter": [], "ServiceRequest": [["s1"]]})
+    );
+
+    let err = filter(&["Pat[ient"]).await.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid resource type pattern 'Pat[ient'")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_validate_server_runs_live_fhirpath_checks() -> Result<()> {
+    let report = validate_server(&octofhir_mcp::ServerConfig::default()).await;
+    assert!(report.passed(), "validation failed: {report:#?}");
+
+    let fhirpath = report
+        .category(FHIRPATH_CATEGORY)
+        .expect("FHIRPath category");
+    for name in ["engine", "tools", "tool_execution", "evaluation"] {
+        assert!(fhirpath.check(name).is_some_and(|check| check.passed));
+    }
+    assert!(
+        fhirpath
+            .check("engine")
+            .unwrap()
+            .message
+            .contains(octofhir_mcp::fhirpath_engine::FHIRPATH_VERSION)
+    );
+    assert!(
+        fhirpath
+            .check("evaluation")
+            .unwrap()
+            .message
+            .contains("[\"Validation\"]")
+    );
+
+    // An unsupported FHIR version is a genuine failure, not a mocked pass
+    let config = octofhir_mcp::ServerConfig {
+        fhir_version: "DSTU2".to_string(),
+        ..Default::default()
+    };
+    let report = validate_server(&config).await;
+    assert!(!report.passed());
+    assert!(!report.category(FHIRPATH_CATEGORY).unwrap().passed());
+
+    Ok(())
+}
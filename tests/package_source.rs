@@ -0,0 +1,69 @@
+//! The HTTP transport loads FHIR packages from the configured package source
+//!
+//! Kept in its own test binary so the shared engine is initialized from this configuration.
+
+use octofhir_mcp::fhirpath_engine::{PackageSource, get_shared_engine};
+use octofhir_mcp::{ServerConfig, transport::TransportFactory};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_served_requests_use_local_dir_packages() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/packages");
+    let config = |package: &str| ServerConfig {
+        host: "127.0.0.1".to_string(),
+        additional_packages: vec![package.to_string()],
+        package_source: PackageSource::LocalDir(fixtures.clone()),
+        ..ServerConfig::default()
+    };
+
+    // A package missing from the directory fails startup instead of being downloaded
+    let err = TransportFactory::create_http("127.0.0.1", 0)
+        .with_config(&config("example.fhir.test@2.0.0"))
+        .unwrap()
+        .start()
+        .await
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("not found in"), "{err:#}");
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server = TransportFactory::create_http("127.0.0.1", port)
+        .with_config(&config("example.fhir.test@1.0.0"))
+        .unwrap();
+    tokio::spawn(async move { server.start().await });
+
+    let client = reqwest::Client::new();
+    let url = format!("http://127.0.0.1:{port}/mcp/tools/fhirpath_evaluate");
+    let body = json!({
+        "expression": "Patient.id",
+        "resource": {"resourceType": "Patient", "id": "local"}
+    });
+    let started = Instant::now();
+    let response = loop {
+        match client.post(&url).json(&body).send().await {
+            Ok(response) => break response,
+            Err(_) if started.elapsed() < Duration::from_secs(60) => {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(e) => panic!("server never started: {e}"),
+        }
+    };
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["result"]["values"], json!(["local"]));
+
+    let engine = get_shared_engine().await.unwrap();
+    assert_eq!(
+        engine.config().package_source,
+        PackageSource::LocalDir(fixtures.clone())
+    );
+    assert_eq!(
+        engine.config().additional_packages,
+        vec!["example.fhir.test@1.0.0"]
+    );
+}